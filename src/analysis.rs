@@ -0,0 +1,1027 @@
+use crate::ast::{Expr, Function, PrintItem, Program, Stmt, StmtKind};
+use crate::cfg::{Cfg, NodeKind, ENTRY, EXIT};
+use crate::diag::{Diagnostics, Warning};
+use crate::source;
+use crate::symbols::Span;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// expressions a node evaluates
+pub fn expressions<'a>(kind: NodeKind<'a>) -> Vec<&'a Expr> {
+    match kind {
+        NodeKind::Statement(stmt) => match &stmt.kind {
+            StmtKind::Print { items, .. } => items
+                .iter()
+                .filter_map(|item| match item {
+                    PrintItem::Value(expr) => Some(expr),
+                    _ => None,
+                })
+                .collect(),
+            StmtKind::Let(_, expr)
+            | StmtKind::Call(expr)
+            | StmtKind::Return(Some(expr))
+            | StmtKind::Exit(expr) => vec![expr],
+            _ => Vec::new(),
+        },
+        NodeKind::Condition(_, expr) | NodeKind::Select(_, expr) => vec![expr],
+        NodeKind::Case(case) => case
+            .items
+            .iter()
+            .flat_map(|item| item.expressions())
+            .collect(),
+        NodeKind::Entry | NodeKind::Exit | NodeKind::Join(_) => Vec::new(),
+    }
+}
+
+// variables a node assigns by LET or INPUT
+pub fn assignments<'a>(kind: NodeKind<'a>) -> Vec<&'a str> {
+    match kind {
+        NodeKind::Statement(stmt) => match &stmt.kind {
+            StmtKind::Let(name, _) => vec![name],
+            StmtKind::Input { targets, .. } => targets.iter().map(String::as_str).collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+// FUNCTIONs of this program a node calls
+fn calls<'a>(program: &Program, kind: NodeKind<'a>) -> Vec<&'a str> {
+    let mut names = Vec::new();
+    for expr in expressions(kind) {
+        expr.visit(&mut |expr| {
+            if let Expr::Call(module, name, _) = expr {
+                if *module == program.module {
+                    names.push(name.as_str());
+                }
+            }
+        });
+    }
+    names
+}
+
+// variables a node reads
+pub fn reads<'a>(kind: NodeKind<'a>) -> Vec<&'a str> {
+    let mut names = Vec::new();
+    for expr in expressions(kind) {
+        expr.visit(&mut |expr| {
+            if let Expr::Variable(name) = expr {
+                names.push(name.as_str());
+            }
+        });
+    }
+    names
+}
+
+// globals each FUNCTION may assign, itself or through the functions it calls
+fn assigned_globals(program: &Program) -> HashMap<&str, HashSet<&str>> {
+    function_globals(program, assignments)
+}
+
+// globals each FUNCTION may read, itself or through the functions it calls
+fn read_globals(program: &Program) -> HashMap<&str, HashSet<&str>> {
+    function_globals(program, reads)
+}
+
+// globals any FUNCTION reads or assigns, the ones that have to stay in
+// memory rather than in SSA values
+pub fn shared_globals(program: &Program) -> HashSet<&str> {
+    let assigned = assigned_globals(program);
+    let read = read_globals(program);
+    assigned
+        .into_values()
+        .chain(read.into_values())
+        .flatten()
+        .collect()
+}
+
+// the globals among the names each FUNCTION's nodes give, callees included
+fn function_globals<'a>(
+    program: &'a Program,
+    names: fn(NodeKind<'a>) -> Vec<&'a str>,
+) -> HashMap<&'a str, HashSet<&'a str>> {
+    let mut found: HashMap<&str, HashSet<&str>> = HashMap::new();
+    let mut callees: HashMap<&str, Vec<&str>> = HashMap::new();
+
+    for function in &program.functions {
+        let cfg = Cfg::build(&function.body, function.span);
+        let globals = cfg
+            .nodes
+            .iter()
+            .flat_map(|node| names(node.kind))
+            .filter(|name| !is_local(function, name))
+            .collect();
+        found.insert(&function.name, globals);
+        callees.insert(
+            &function.name,
+            cfg.nodes
+                .iter()
+                .flat_map(|node| calls(program, node.kind))
+                .collect(),
+        );
+    }
+
+    //grow each set with its callees' until nothing changes
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (function, called) in &callees {
+            for callee in called {
+                let extra: Vec<&str> = found[callee]
+                    .difference(&found[function])
+                    .copied()
+                    .collect();
+                if !extra.is_empty() {
+                    found.get_mut(function).unwrap().extend(extra);
+                    changed = true;
+                }
+            }
+        }
+    }
+    found
+}
+
+// a variable, with the FUNCTION it is local to (None for globals)
+type Variable<'a> = (Option<&'a str>, &'a str);
+
+// how each variable of a program is used
+struct Usage<'a> {
+    //where each variable is assigned, in source order per body
+    assigned: HashMap<Variable<'a>, Vec<Span>>,
+    read: HashSet<Variable<'a>>,
+    //assigned by INPUT, which needs somewhere to put the value
+    input: HashSet<Variable<'a>>,
+}
+
+fn usage(program: &Program) -> Usage<'_> {
+    let mut usage = Usage {
+        assigned: HashMap::new(),
+        read: HashSet::new(),
+        input: HashSet::new(),
+    };
+
+    let mut bodies = vec![(None, Cfg::build(&program.body, Span { file: 0, line: 1 }))];
+    for function in &program.functions {
+        bodies.push((Some(function), Cfg::build(&function.body, function.span)));
+    }
+
+    for (function, cfg) in bodies {
+        let variable = |name| match function {
+            Some(function) if is_local(function, name) => (Some(function.name.as_str()), name),
+            _ => (None, name),
+        };
+        for node in &cfg.nodes {
+            for name in assignments(node.kind) {
+                usage
+                    .assigned
+                    .entry(variable(name))
+                    .or_default()
+                    .push(node.span);
+                if let NodeKind::Statement(Stmt {
+                    kind: StmtKind::Input { .. },
+                    ..
+                }) = node.kind
+                {
+                    usage.input.insert(variable(name));
+                }
+            }
+            usage
+                .read
+                .extend(reads(node.kind).into_iter().map(variable));
+        }
+    }
+    usage
+}
+
+pub fn is_local(function: &Function, name: &str) -> bool {
+    function.parameters.iter().any(|local| local == name)
+        || function.locals.iter().any(|local| local == name)
+}
+
+// variables assigned but never read, values overwritten before anything
+// reads them, and names that only differ in case
+pub fn check_variables(program: &Program, diagnostics: &mut Diagnostics) {
+    let usage = usage(program);
+    let lines = |spans: &[Span]| -> String {
+        let lines: Vec<String> = spans
+            .iter()
+            .map(|&span| source::describe(&program.files, span))
+            .collect();
+        lines.join(", ")
+    };
+
+    let variables = program
+        .globals
+        .iter()
+        .map(|name| (None, name.as_str()))
+        .chain(program.functions.iter().flat_map(|function| {
+            function
+                .locals
+                .iter()
+                .map(|name| (Some(function.name.as_str()), name.as_str()))
+        }));
+    for variable in variables {
+        if usage.read.contains(&variable) {
+            continue;
+        }
+        let spans = &usage.assigned[&variable];
+        let mut message = format!("Variable {} is assigned but never read", variable.1);
+        if spans.len() > 1 {
+            message.push_str(&format!(
+                "\n  note: also assigned on {}",
+                lines(&spans[1..])
+            ));
+        }
+        diagnostics.warn(&program.files, Warning::UnreadVariable, spans[0], message);
+    }
+
+    let callee_reads = read_globals(program);
+    let globals: Vec<&str> = program.globals.iter().map(String::as_str).collect();
+    let cfg = Cfg::build(&program.body, Span { file: 0, line: 1 });
+    let read = |name: &str| usage.read.contains(&(None, name));
+    check_overwritten(program, &cfg, &globals, &callee_reads, &read, diagnostics);
+
+    for function in &program.functions {
+        let cfg = Cfg::build(&function.body, function.span);
+        let locals: Vec<&str> = function
+            .parameters
+            .iter()
+            .chain(&function.locals)
+            .map(String::as_str)
+            .collect();
+        let read = |name: &str| usage.read.contains(&(Some(function.name.as_str()), name));
+        check_overwritten(program, &cfg, &locals, &HashMap::new(), &read, diagnostics);
+    }
+
+    //first place each spelling is assigned, parameters where they are declared
+    let mut first: HashMap<&str, Span> = HashMap::new();
+    let declared = usage
+        .assigned
+        .iter()
+        .map(|(&(_, name), spans)| (name, spans[0]))
+        .chain(program.functions.iter().flat_map(|function| {
+            function
+                .parameters
+                .iter()
+                .map(|name| (name.as_str(), function.span))
+        }));
+    for (name, span) in declared {
+        let earliest = first.entry(name).or_insert(span);
+        if (span.file, span.line) < (earliest.file, earliest.line) {
+            *earliest = span;
+        }
+    }
+
+    let mut spellings: HashMap<String, Vec<(&str, Span)>> = HashMap::new();
+    for (&name, &span) in &first {
+        spellings
+            .entry(name.to_lowercase())
+            .or_default()
+            .push((name, span));
+    }
+    let mut clashes: Vec<Vec<(&str, Span)>> = spellings
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .collect();
+    for names in &mut clashes {
+        names.sort_by_key(|(name, span)| (span.file, span.line, *name));
+    }
+    clashes.sort_by_key(|names| (names[0].1.file, names[0].1.line));
+
+    for names in clashes {
+        let (original, original_span) = names[0];
+        for &(name, span) in &names[1..] {
+            diagnostics.warn(
+                &program.files,
+                Warning::CaseMismatch,
+                span,
+                format!(
+                    "Variable {} differs only in case from {}\n  note: {} is first assigned on {}",
+                    name,
+                    original,
+                    original,
+                    source::describe(&program.files, original_span)
+                ),
+            );
+        }
+    }
+}
+
+// LETs whose value every path overwrites before reading it
+fn check_overwritten(
+    program: &Program,
+    cfg: &Cfg,
+    tracked: &[&str],
+    callee_reads: &HashMap<&str, HashSet<&str>>,
+    is_read: &dyn Fn(&str) -> bool,
+    diagnostics: &mut Diagnostics,
+) {
+    let index: HashMap<&str, usize> = tracked
+        .iter()
+        .enumerate()
+        .map(|(index, &name)| (name, index))
+        .collect();
+    let set_of = |names: &mut dyn Iterator<Item = &str>| {
+        let mut set = vec![false; tracked.len()];
+        for name in names {
+            if let Some(&index) = index.get(name) {
+                set[index] = true;
+            }
+        }
+        set
+    };
+
+    //a call reads whatever globals the function may read
+    let uses: Vec<Vec<bool>> = cfg
+        .nodes
+        .iter()
+        .map(|node| {
+            let called = calls(program, node.kind)
+                .into_iter()
+                .filter_map(|function| callee_reads.get(function))
+                .flatten()
+                .copied();
+            set_of(&mut reads(node.kind).into_iter().chain(called))
+        })
+        .collect();
+    let defs: Vec<Vec<bool>> = cfg
+        .nodes
+        .iter()
+        .map(|node| set_of(&mut assignments(node.kind).into_iter()))
+        .collect();
+
+    //read later on some path out of each node
+    let mut live_out = vec![vec![false; tracked.len()]; cfg.nodes.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in (0..cfg.nodes.len()).rev() {
+            let mut set = vec![false; tracked.len()];
+            for &(successor, _) in &cfg.nodes[node].successors {
+                for (index, live) in set.iter_mut().enumerate() {
+                    *live |= uses[successor][index]
+                        || (live_out[successor][index] && !defs[successor][index]);
+                }
+            }
+            if set != live_out[node] {
+                live_out[node] = set;
+                changed = true;
+            }
+        }
+    }
+
+    let reachable = reachable_from(cfg, &[ENTRY], &HashSet::new());
+    for (node, step) in cfg.nodes.iter().enumerate() {
+        let NodeKind::Statement(Stmt {
+            kind: StmtKind::Let(name, _),
+            ..
+        }) = step.kind
+        else {
+            continue;
+        };
+        let Some(&variable) = index.get(name.as_str()) else {
+            continue;
+        };
+        //never read at all is reported on its own
+        if live_out[node][variable] || !is_read(name) || !reachable.contains(&node) {
+            continue;
+        }
+
+        //the next assignment along some path, with no read before it
+        let mut queue: VecDeque<usize> = cfg.nodes[node]
+            .successors
+            .iter()
+            .map(|&(successor, _)| successor)
+            .collect();
+        let mut seen: HashSet<usize> = queue.iter().copied().collect();
+        let mut overwrite = None;
+        while let Some(next) = queue.pop_front() {
+            if defs[next][variable] {
+                overwrite = Some(cfg.nodes[next].span);
+                break;
+            }
+            for &(successor, _) in &cfg.nodes[next].successors {
+                if seen.insert(successor) {
+                    queue.push_back(successor);
+                }
+            }
+        }
+
+        if let Some(overwrite) = overwrite {
+            diagnostics.warn(
+                &program.files,
+                Warning::OverwrittenValue,
+                step.span,
+                format!(
+                    "Value assigned to {} is overwritten before it is read\n  note: overwritten on {}",
+                    name,
+                    source::describe(&program.files, overwrite)
+                ),
+            );
+        }
+    }
+}
+
+// leave variables nothing reads out of the program. their LETs only keep
+// the calls they make, INPUT targets stay as somewhere to put the value.
+pub fn drop_unread_variables(program: &mut Program) {
+    let usage = usage(program);
+    let unread =
+        |variable: Variable| !usage.read.contains(&variable) && !usage.input.contains(&variable);
+
+    let globals: HashSet<String> = program
+        .globals
+        .iter()
+        .filter(|name| unread((None, name)))
+        .cloned()
+        .collect();
+    let locals: Vec<HashSet<String>> = program
+        .functions
+        .iter()
+        .map(|function| {
+            function
+                .locals
+                .iter()
+                .filter(|name| unread((Some(function.name.as_str()), name)))
+                .cloned()
+                .collect()
+        })
+        .collect();
+    drop(usage);
+
+    program.globals.retain(|name| !globals.contains(name));
+    drop_stores(&mut program.body, &|name| globals.contains(name));
+    for (function, locals) in program.functions.iter_mut().zip(locals) {
+        function.locals.retain(|name| !locals.contains(name));
+        let parameters = function.parameters.clone();
+        let owned: Vec<String> = function.locals.clone();
+        drop_stores(&mut function.body, &|name| {
+            locals.contains(name)
+                || (globals.contains(name)
+                    && !parameters.iter().chain(&owned).any(|local| local == name))
+        });
+    }
+}
+
+fn drop_stores(body: &mut Vec<Stmt>, dropped: &dyn Fn(&str) -> bool) {
+    for mut stmt in std::mem::take(body) {
+        match &mut stmt.kind {
+            StmtKind::Let(name, value) if dropped(name) => {
+                let mut calls = Vec::new();
+                outermost_calls(value, &mut calls);
+                body.extend(calls.into_iter().map(|call| Stmt {
+                    kind: StmtKind::Call(call),
+                    span: stmt.span,
+                }));
+                continue;
+            }
+            StmtKind::If { body, .. }
+            | StmtKind::While { body, .. }
+            | StmtKind::Do { body, .. } => drop_stores(body, dropped),
+            StmtKind::Select { cases, default, .. } => {
+                for case in cases {
+                    drop_stores(&mut case.body, dropped);
+                }
+                if let Some(body) = default {
+                    drop_stores(body, dropped);
+                }
+            }
+            _ => {}
+        }
+        body.push(stmt);
+    }
+}
+
+// calls in an expression, leaving out the ones that are another call's arguments
+fn outermost_calls(expr: &Expr, calls: &mut Vec<Expr>) {
+    match expr {
+        Expr::Call(..) => calls.push(expr.clone()),
+        Expr::Unary(_, operand) => outermost_calls(operand, calls),
+        Expr::Binary(left, _, right) => {
+            outermost_calls(left, calls);
+            outermost_calls(right, calls);
+        }
+        Expr::Number(_) | Expr::Variable(_) | Expr::InputEof => {}
+    }
+}
+
+// labels nobody jumps to, code nothing reaches and loops that never run
+pub fn check_control_flow(program: &Program, diagnostics: &mut Diagnostics) {
+    let mut bodies = vec![Cfg::build(&program.body, Span { file: 0, line: 1 })];
+    for function in &program.functions {
+        bodies.push(Cfg::build(&function.body, function.span));
+    }
+
+    for cfg in &bodies {
+        let gotos: HashSet<&str> = cfg
+            .nodes
+            .iter()
+            .filter_map(|node| match node.kind {
+                NodeKind::Statement(Stmt {
+                    kind: StmtKind::Goto(label),
+                    ..
+                }) => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        let reachable = reachable_from(cfg, &[ENTRY], &HashSet::new());
+        //each unreachable stretch is reported once, at its first node
+        let mut reported = HashSet::new();
+
+        for (index, node) in cfg.nodes.iter().enumerate() {
+            if let NodeKind::Statement(Stmt {
+                kind: StmtKind::Label(label),
+                ..
+            }) = node.kind
+            {
+                if !gotos.contains(label.as_str()) {
+                    diagnostics.warn(
+                        &program.files,
+                        Warning::UnusedLabel,
+                        node.span,
+                        format!("Label {} is never jumped to", label),
+                    );
+                }
+            }
+
+            if let NodeKind::Condition(
+                Stmt {
+                    kind: StmtKind::While { .. },
+                    ..
+                },
+                condition,
+            ) = node.kind
+            {
+                if condition.constant_value() == Some(0.0) {
+                    diagnostics.warn(
+                        &program.files,
+                        Warning::WhileFalse,
+                        node.span,
+                        "WHILE condition is always false, the loop never runs".into(),
+                    );
+                }
+            }
+
+            if index != EXIT && !reachable.contains(&index) && !reported.contains(&index) {
+                diagnostics.warn(
+                    &program.files,
+                    Warning::UnreachableCode,
+                    node.span,
+                    "Unreachable code".into(),
+                );
+                reported.extend(reachable_from(cfg, &[index], &reachable));
+            }
+        }
+    }
+}
+
+// nodes reached from the start nodes, not going through the ones in stop
+pub fn reachable_from(cfg: &Cfg, start: &[usize], stop: &HashSet<usize>) -> HashSet<usize> {
+    let mut reached: HashSet<usize> = start.iter().copied().collect();
+    let mut queue: VecDeque<usize> = start.iter().copied().collect();
+    while let Some(node) = queue.pop_front() {
+        for &(successor, _) in &cfg.nodes[node].successors {
+            if !stop.contains(&successor) && reached.insert(successor) {
+                queue.push_back(successor);
+            }
+        }
+    }
+    reached
+}
+
+// what a call to each FUNCTION does to this program's globals, callees
+// included
+#[derive(Default, PartialEq)]
+struct Summary<'a> {
+    //assigned on every path through the function
+    assigns: HashSet<&'a str>,
+    //read on some path before the function assigns them
+    reads: HashSet<&'a str>,
+}
+
+fn summaries(program: &Program) -> HashMap<&str, Summary<'_>> {
+    let bodies: Vec<(&Function, Cfg, Vec<&str>)> = program
+        .functions
+        .iter()
+        .map(|function| {
+            let globals = program
+                .globals
+                .iter()
+                .map(String::as_str)
+                .filter(|name| !is_local(function, name))
+                .collect();
+            (function, Cfg::build(&function.body, function.span), globals)
+        })
+        .collect();
+
+    //assigning starts out as everything and reading as nothing, so
+    //recursive calls settle
+    let mut summaries: HashMap<&str, Summary> = bodies
+        .iter()
+        .map(|(function, _, globals)| {
+            let summary = Summary {
+                assigns: globals.iter().copied().collect(),
+                reads: HashSet::new(),
+            };
+            (function.name.as_str(), summary)
+        })
+        .collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (function, cfg, globals) in &bodies {
+            let (_, into) = definitely_assigned(program, cfg, globals, &[], &summaries);
+            let mut summary = Summary::default();
+            for (index, &name) in globals.iter().enumerate() {
+                if into[EXIT][index] {
+                    summary.assigns.insert(name);
+                }
+            }
+            for (node, step) in cfg.nodes.iter().enumerate() {
+                for (variable, _) in node_reads(program, step.kind, globals, &summaries) {
+                    if !into[node][variable] {
+                        summary.reads.insert(globals[variable]);
+                    }
+                }
+            }
+            if summary != summaries[function.name.as_str()] {
+                summaries.insert(&function.name, summary);
+                changed = true;
+            }
+        }
+    }
+    summaries
+}
+
+// every variable read must have been assigned on each path that reaches
+// it. a call counts as assigning the globals the function assigns on every
+// path through it, and as reading the ones it may read before that.
+pub fn check_definite_assignment(program: &Program) {
+    let summaries = summaries(program);
+
+    let cfg = Cfg::build(&program.body, Span { file: 0, line: 1 });
+    let globals: Vec<&str> = program.globals.iter().map(String::as_str).collect();
+    check_body(program, &cfg, &globals, &[], &summaries);
+
+    for function in &program.functions {
+        let cfg = Cfg::build(&function.body, function.span);
+        let tracked: Vec<&str> = function
+            .parameters
+            .iter()
+            .chain(&function.locals)
+            .map(String::as_str)
+            .collect();
+        //calls can't reach a function's locals
+        check_body(
+            program,
+            &cfg,
+            &tracked,
+            &function.parameters,
+            &HashMap::new(),
+        );
+    }
+}
+
+// for each node, the tracked variables it assigns, calls included, and the
+// ones assigned on every path into it
+fn definitely_assigned(
+    program: &Program,
+    cfg: &Cfg,
+    tracked: &[&str],
+    parameters: &[String],
+    summaries: &HashMap<&str, Summary>,
+) -> (Vec<Vec<bool>>, Vec<Vec<bool>>) {
+    let index: HashMap<&str, usize> = tracked
+        .iter()
+        .enumerate()
+        .map(|(index, &name)| (name, index))
+        .collect();
+
+    let assigns: Vec<Vec<bool>> = cfg
+        .nodes
+        .iter()
+        .map(|node| {
+            let mut set = vec![false; tracked.len()];
+            let called = calls(program, node.kind)
+                .into_iter()
+                .filter_map(|function| summaries.get(function))
+                .flat_map(|summary| &summary.assigns)
+                .copied();
+            for name in assignments(node.kind).into_iter().chain(called) {
+                if let Some(&index) = index.get(name) {
+                    set[index] = true;
+                }
+            }
+            set
+        })
+        .collect();
+
+    //assigned on every path into each node. everything starts assigned so
+    //loops settle, and nodes nothing reaches keep it
+    let predecessors = cfg.predecessors();
+    let mut into = vec![vec![true; tracked.len()]; cfg.nodes.len()];
+    into[ENTRY] = tracked
+        .iter()
+        .map(|name| parameters.iter().any(|parameter| parameter == name))
+        .collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in 0..cfg.nodes.len() {
+            if node == ENTRY || predecessors[node].is_empty() {
+                continue;
+            }
+            let mut set = vec![true; tracked.len()];
+            for &predecessor in &predecessors[node] {
+                for (index, assigned) in set.iter_mut().enumerate() {
+                    *assigned &= into[predecessor][index] || assigns[predecessor][index];
+                }
+            }
+            if set != into[node] {
+                into[node] = set;
+                changed = true;
+            }
+        }
+    }
+    (assigns, into)
+}
+
+// the tracked variables a node reads, in order, each with the FUNCTION
+// whose call reads it (None when the node reads it itself)
+fn node_reads<'a>(
+    program: &Program,
+    kind: NodeKind<'a>,
+    tracked: &[&str],
+    summaries: &HashMap<&str, Summary>,
+) -> Vec<(usize, Option<&'a str>)> {
+    let mut found = Vec::new();
+    for expr in expressions(kind) {
+        expr.visit(&mut |expr| match expr {
+            Expr::Variable(name) => {
+                if let Some(variable) = tracked.iter().position(|tracked| tracked == name) {
+                    found.push((variable, None));
+                }
+            }
+            Expr::Call(module, name, _) if *module == program.module => {
+                let Some(summary) = summaries.get(name.as_str()) else {
+                    return;
+                };
+                for (variable, tracked) in tracked.iter().enumerate() {
+                    if summary.reads.contains(tracked) {
+                        found.push((variable, Some(name.as_str())));
+                    }
+                }
+            }
+            _ => {}
+        });
+    }
+    found
+}
+
+fn check_body(
+    program: &Program,
+    cfg: &Cfg,
+    tracked: &[&str],
+    parameters: &[String],
+    summaries: &HashMap<&str, Summary>,
+) {
+    let (assigns, into) = definitely_assigned(program, cfg, tracked, parameters, summaries);
+
+    for (node, step) in cfg.nodes.iter().enumerate() {
+        let unassigned = node_reads(program, step.kind, tracked, summaries)
+            .into_iter()
+            .find(|&(variable, _)| !into[node][variable]);
+
+        if let Some((variable, function)) = unassigned {
+            let path = unassigned_path(cfg, &assigns, node, variable);
+            let path: Vec<String> = path
+                .into_iter()
+                .map(|span| source::describe(&program.files, span))
+                .collect();
+            let mut message = format!(
+                "Variable {} may be used before assignment\n  path: {}",
+                tracked[variable],
+                path.join(" -> ")
+            );
+            if let Some(function) = function {
+                message.push_str(&format!("\n  note: FUNCTION {} reads it", function));
+            }
+            source::abort_at(&program.files, step.span, message)
+        }
+    }
+}
+
+// lines of a way into target that never assigns the variable
+fn unassigned_path(cfg: &Cfg, assigns: &[Vec<bool>], target: usize, variable: usize) -> Vec<Span> {
+    let mut came_from = vec![None; cfg.nodes.len()];
+    let mut queue = VecDeque::from([ENTRY]);
+    let mut seen = HashSet::from([ENTRY]);
+
+    while let Some(node) = queue.pop_front() {
+        if node == target {
+            break;
+        }
+        if assigns[node][variable] {
+            continue;
+        }
+        for &(successor, _) in &cfg.nodes[node].successors {
+            if seen.insert(successor) {
+                came_from[successor] = Some(node);
+                queue.push_back(successor);
+            }
+        }
+    }
+
+    let mut path = Vec::new();
+    let mut node = Some(target);
+    while let Some(current) = node {
+        if !matches!(cfg.nodes[current].kind, NodeKind::Entry | NodeKind::Join(_)) {
+            let span = cfg.nodes[current].span;
+            if path.last() != Some(&span) {
+                path.push(span);
+            }
+        }
+        node = came_from[current];
+    }
+    path.reverse();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parse::Parser;
+    use std::path::PathBuf;
+
+    fn parse(source: &str) -> Program {
+        let lexer = Lexer::new(source.to_string(), PathBuf::from("test.teeny"));
+        Parser::new(lexer).program()
+    }
+
+    #[test]
+    #[should_panic(expected = "line 5. Variable x may be used before assignment")]
+    fn assigned_on_one_branch_only() {
+        check_definite_assignment(&parse(
+            "INPUT c\nIF c > 0 THEN\nLET x = 1\nENDIF\nPRINT x\n",
+        ));
+    }
+
+    #[test]
+    fn assigned_in_every_case() {
+        check_definite_assignment(&parse(
+            "INPUT c\nSELECT CASE c\nCASE 1\nLET x = 1\nCASE 2 TO 4\nLET x = 2\n\
+             CASE ELSE\nLET x = 3\nENDSELECT\nPRINT x\n",
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "line 9. Variable x may be used before assignment")]
+    fn assigned_in_a_loop_that_may_not_run() {
+        check_definite_assignment(&parse(
+            "INPUT c\nDO\nLET y = c\nLOOP UNTIL c > 0\nPRINT y\n\
+             WHILE c > 0 REPEAT\nLET x = c\nENDWHILE\nPRINT x\n",
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "line 5. Variable x may be used before assignment")]
+    fn goto_past_the_assignment() {
+        check_definite_assignment(&parse(
+            "GOTO skip\nLET x = 1\nLABEL skip\nLET y = 2\nPRINT x + y\n",
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "line 11. Variable g may be used before assignment")]
+    fn call_that_only_sometimes_assigns() {
+        check_definite_assignment(&parse(
+            "INPUT c\nIF c > 100 THEN\nLET g = 0\nENDIF\n\
+             FUNCTION f()\nIF c > 0 THEN\nLET g = 1\nENDIF\nENDFUNCTION\n\
+             CALL f()\nPRINT g\n",
+        ));
+    }
+
+    #[test]
+    fn call_that_always_assigns() {
+        check_definite_assignment(&parse(
+            "INPUT c\nIF c > 100 THEN\nLET g = 0\nENDIF\n\
+             FUNCTION f()\nLET g = 2\nIF c > 0 THEN\nLET g = 1\nENDIF\nENDFUNCTION\n\
+             FUNCTION h()\nCALL f()\nENDFUNCTION\n\
+             CALL h()\nPRINT g\n",
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "line 4. Variable g may be used before assignment")]
+    fn call_that_reads_before_assignment() {
+        check_definite_assignment(&parse(
+            "FUNCTION f()\nPRINT g\nENDFUNCTION\nCALL f()\nLET g = 1\nCALL f()\n",
+        ));
+    }
+
+    #[test]
+    fn call_that_assigns_before_reading() {
+        check_definite_assignment(&parse(
+            "INPUT c\nIF c > 0 THEN\nLET g = 2\nENDIF\n\
+             FUNCTION f()\nLET g = 1\nPRINT g\nENDFUNCTION\n\
+             FUNCTION h()\nCALL f()\nPRINT g\nENDFUNCTION\n\
+             CALL h()\n",
+        ));
+    }
+
+    // the warnings a check reports, with the flags given
+    fn warnings(
+        check: fn(&Program, &mut Diagnostics),
+        source: &str,
+        flags: &[&str],
+    ) -> Vec<String> {
+        let mut diagnostics = Diagnostics::new();
+        for flag in flags {
+            assert!(diagnostics.flag(flag), "{}", flag);
+        }
+        check(&parse(source), &mut diagnostics);
+        diagnostics.reported().to_vec()
+    }
+
+    #[test]
+    fn unused_labels_unreachable_code_and_loops_that_never_run() {
+        let source = "LABEL top\nLABEL used\nWHILE 1 > 2 REPEAT\nPRINT 1\nENDWHILE\nGOTO used\n\
+                      PRINT 2\nPRINT 3\n";
+        assert_eq!(
+            warnings(check_control_flow, source, &[]),
+            [
+                "Warning in test.teeny on line 1. Label top is never jumped to [-Wunused-label]",
+                "Warning in test.teeny on line 3. WHILE condition is always false, \
+                 the loop never runs [-Wwhile-false]",
+                "Warning in test.teeny on line 7. Unreachable code [-Wunreachable-code]",
+            ]
+        );
+    }
+
+    #[test]
+    fn warnings_switched_off_by_name() {
+        let source = "LABEL top\nEND\nPRINT 1\n";
+        assert_eq!(
+            warnings(check_control_flow, source, &["-Wno-unused-label"]),
+            ["Warning in test.teeny on line 3. Unreachable code [-Wunreachable-code]"]
+        );
+        let flags = [
+            "-Wno-unreachable-code",
+            "-Wno-unused-label",
+            "-Wunused-label",
+        ];
+        assert_eq!(
+            warnings(check_control_flow, source, &flags),
+            ["Warning in test.teeny on line 1. Label top is never jumped to [-Wunused-label]"]
+        );
+        assert!(!Diagnostics::new().flag("-Wno-such-warning"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Error: 1 warning(s) treated as errors because of -Werror")]
+    fn warnings_as_errors() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.flag("-Werror");
+        check_control_flow(&parse("GOTO end\nLABEL end\n"), &mut diagnostics);
+        diagnostics.finish();
+        check_control_flow(&parse("END\nPRINT 1\n"), &mut diagnostics);
+        diagnostics.finish();
+    }
+
+    #[test]
+    fn unread_overwritten_and_case_mismatched_variables() {
+        let source =
+            "LET a = 1\nLET a = 2\nPRINT a\nLET u = 1\nINPUT c\nIF c > 0 THEN\nLET u = 2\nENDIF\n\
+                      LET Total = 1\nLET total = Total + 1\nPRINT total\n";
+        assert_eq!(
+            warnings(check_variables, source, &[]),
+            [
+                "Warning in test.teeny on line 4. Variable u is assigned but never read \
+                 [-Wunread-variable]\n  note: also assigned on line 7",
+                "Warning in test.teeny on line 1. Value assigned to a is overwritten before \
+                 it is read [-Woverwritten-value]\n  note: overwritten on line 2",
+                "Warning in test.teeny on line 10. Variable total differs only in case from \
+                 Total [-Wcase-mismatch]\n  note: Total is first assigned on line 9",
+            ]
+        );
+    }
+
+    #[test]
+    fn unread_variables_leave_their_calls_behind() {
+        let mut program = parse(
+            "FUNCTION f()\nPRINT 1\nRETURN 2\nENDFUNCTION\n\
+             LET u = f() + 1\nLET k = 3\nPRINT k\nINPUT w\n",
+        );
+        drop_unread_variables(&mut program);
+
+        assert_eq!(program.globals, ["k", "w"]);
+        let StmtKind::Call(call) = &program.body[0].kind else {
+            panic!("{:?}", program.body[0]);
+        };
+        assert_eq!(call.to_string(), "f()");
+        assert_eq!(program.body.len(), 4);
+    }
+}
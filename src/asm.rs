@@ -0,0 +1,745 @@
+use crate::backend::{self, using_format, write_if_changed, Backend, InputPolicy, ModuleFiles};
+use crate::ir::{BinOp, BlockId, Function, FunctionKind, Module, Op, Operand, Terminator, Type};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+// x86-64 assembly for the GNU assembler, System V calling convention.
+// every value lives in its own 8 byte stack slot below %rbp, each
+// instruction loads what it needs into %xmm0/%xmm1 or %eax, works it out
+// and stores the result back. floats use scalar SSE, printing and input go
+// through libc.
+
+// print and input helpers, in the main program's file only. modules call
+// them and share tt_column and tt_eof with it.
+const RUNTIME: &str = r#"
+    .text
+    .globl tt_print_string
+tt_print_string:
+    pushq %rbp
+    movq %rsp, %rbp
+    movq %rdi, %rsi
+    leaq .Lformat_string(%rip), %rdi
+    xorl %eax, %eax
+    call printf@PLT
+    addl %eax, tt_column(%rip)
+    popq %rbp
+    ret
+
+    # nan without its sign, which depends on how it was made and which printf
+    # would write as "-nan". only %xmm0 and %eax change
+tt_unsigned_nan:
+    ucomiss %xmm0, %xmm0
+    jnp .Lunsigned_nan_done
+    movd %xmm0, %eax
+    andl $0x7fffffff, %eax
+    movd %eax, %xmm0
+.Lunsigned_nan_done:
+    ret
+
+    .globl tt_print_number
+tt_print_number:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $48, %rsp
+    call tt_unsigned_nan
+    movss %xmm0, -4(%rbp)
+    # whole numbers a long can hold print without a fraction
+    ucomiss .Lminus_long_limit(%rip), %xmm0
+    jb .Lprint_number_shortest
+    movss .Llong_limit(%rip), %xmm1
+    ucomiss %xmm0, %xmm1
+    jbe .Lprint_number_shortest
+    cvttss2siq %xmm0, %rax
+    cvtsi2ssq %rax, %xmm1
+    ucomiss %xmm0, %xmm1
+    jne .Lprint_number_shortest
+    jp .Lprint_number_shortest
+    movq %rax, %rcx
+    leaq .Lformat_long(%rip), %rdx
+    movl $32, %esi
+    leaq -48(%rbp), %rdi
+    xorl %eax, %eax
+    call snprintf@PLT
+    jmp .Lprint_number_done
+    # shortest representation that reads back as the same float
+.Lprint_number_shortest:
+    movl $1, -8(%rbp)
+.Lprint_number_precision:
+    cvtss2sd -4(%rbp), %xmm0
+    movl -8(%rbp), %ecx
+    leaq .Lformat_general(%rip), %rdx
+    movl $32, %esi
+    leaq -48(%rbp), %rdi
+    movl $1, %eax
+    call snprintf@PLT
+    xorl %esi, %esi
+    leaq -48(%rbp), %rdi
+    call strtof@PLT
+    ucomiss -4(%rbp), %xmm0
+    jne .Lprint_number_next
+    jnp .Lprint_number_done
+.Lprint_number_next:
+    addl $1, -8(%rbp)
+    cmpl $9, -8(%rbp)
+    jle .Lprint_number_precision
+.Lprint_number_done:
+    leaq -48(%rbp), %rdi
+    call tt_print_string
+    leave
+    ret
+
+    .globl tt_print_using
+tt_print_using:
+    pushq %rbp
+    movq %rsp, %rbp
+    call tt_unsigned_nan
+    cvtss2sd %xmm0, %xmm0
+    movl $1, %eax
+    call printf@PLT
+    addl %eax, tt_column(%rip)
+    popq %rbp
+    ret
+
+    .globl tt_print_tab
+tt_print_tab:
+    pushq %rbp
+    movq %rsp, %rbp
+.Lprint_tab_space:
+    movl $32, %edi
+    call putchar@PLT
+    addl $1, tt_column(%rip)
+    movl tt_column(%rip), %eax
+    cltd
+    movl $14, %ecx
+    idivl %ecx
+    testl %edx, %edx
+    jne .Lprint_tab_space
+    popq %rbp
+    ret
+
+    .globl tt_print_newline
+tt_print_newline:
+    pushq %rbp
+    movq %rsp, %rbp
+    movl $10, %edi
+    call putchar@PLT
+    movl $0, tt_column(%rip)
+    popq %rbp
+    ret
+
+    # tt_input(float *target, const char *prompt, const char *location)
+    .globl tt_input
+tt_input:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $32, %rsp
+    movq %rdi, -8(%rbp)
+    movq %rsi, -16(%rbp)
+    movq %rdx, -24(%rbp)
+.Linput_again:
+    movq -16(%rbp), %rdi
+    call tt_print_string
+    movq stdout@GOTPCREL(%rip), %rax
+    movq (%rax), %rdi
+    call fflush@PLT
+    movq -8(%rbp), %rsi
+    leaq .Lformat_float(%rip), %rdi
+    xorl %eax, %eax
+    call scanf@PLT
+    movl $0, tt_column(%rip)
+    cmpl $1, %eax
+    je .Linput_done
+    movq -8(%rbp), %rcx
+    movl $0, (%rcx)
+    cmpl $-1, %eax
+    jne .Linput_invalid
+    movl $1, tt_eof(%rip)
+    jmp .Linput_done
+.Linput_invalid:
+"#;
+
+// what the runtime does when INPUT reads something that isn't a number,
+// spliced in after .Linput_invalid
+fn input_policy_code(policy: InputPolicy) -> &'static str {
+    match policy {
+        InputPolicy::Zero => {
+            r#"    leaq .Lformat_skip(%rip), %rdi
+    xorl %eax, %eax
+    call scanf@PLT
+    jmp .Linput_done
+"#
+        }
+        InputPolicy::Reprompt => {
+            r#".Linput_discard:
+    call getchar@PLT
+    cmpl $10, %eax
+    je .Linput_redo
+    cmpl $-1, %eax
+    jne .Linput_discard
+.Linput_redo:
+    leaq .Lredo(%rip), %rdi
+    call puts@PLT
+    jmp .Linput_again
+"#
+        }
+        InputPolicy::Error => {
+            r#"    movq -24(%rbp), %rdx
+    leaq .Linvalid(%rip), %rsi
+    movq stderr@GOTPCREL(%rip), %rax
+    movq (%rax), %rdi
+    xorl %eax, %eax
+    call fprintf@PLT
+    movl $1, %edi
+    call exit@PLT
+"#
+        }
+    }
+}
+
+const RUNTIME_END: &str = r#".Linput_done:
+    leave
+    ret
+
+    .data
+    .globl tt_column
+tt_column:
+    .long 0
+    .globl tt_eof
+tt_eof:
+    .long 0
+
+    .section .rodata
+.Lformat_string:
+    .string "%s"
+.Lformat_long:
+    .string "%ld"
+.Lformat_general:
+    .string "%.*g"
+.Lformat_float:
+    .string "%f"
+.Lformat_skip:
+    .string "%*s"
+.Lredo:
+    .string "?Redo from start"
+.Linvalid:
+    .string "Invalid input at %s\n"
+    .align 4
+    # 2^63 and -2^63, the range of a long
+.Llong_limit:
+    .long 0x5f000000
+.Lminus_long_limit:
+    .long 0xdf000000
+"#;
+
+//floats go in %xmm0-%xmm7, any more on the stack
+const FLOAT_ARGUMENT_REGISTERS: usize = 8;
+
+pub struct AsmEmitter {
+    text: String,
+    //globals, the init guard
+    data: String,
+    //literal floats and strings
+    rodata: String,
+    full_path: String,
+    module: Option<String>,
+    //label of each float constant by its bits, and of each string
+    floats: HashMap<u32, String>,
+    strings: HashMap<String, String>,
+    //stack slot offset of each value of the function being emitted, and
+    //of the shadow slot the jumps into a phi's block fill in
+    slots: HashMap<usize, i64>,
+    phi_slots: HashMap<usize, i64>,
+    //label prefix of the function being emitted
+    function_label: String,
+    function_count: usize,
+    pub input_policy: InputPolicy,
+}
+
+impl AsmEmitter {
+    pub fn new(full_path: String) -> AsmEmitter {
+        AsmEmitter {
+            text: String::new(),
+            data: String::new(),
+            rodata: String::new(),
+            full_path,
+            module: None,
+            floats: HashMap::new(),
+            strings: HashMap::new(),
+            slots: HashMap::new(),
+            phi_slots: HashMap::new(),
+            function_label: String::new(),
+            function_count: 0,
+            input_policy: InputPolicy::Zero,
+        }
+    }
+
+    pub fn full_path(&self) -> &str {
+        &self.full_path
+    }
+
+    fn line(&mut self, code: String) {
+        self.text.push_str("    ");
+        self.text.push_str(&code);
+        self.text.push('\n');
+    }
+
+    fn label(&mut self, label: String) {
+        self.text.push_str(&label);
+        self.text.push_str(":\n");
+    }
+
+    fn float_constant(&mut self, value: f32) -> String {
+        let count = self.floats.len();
+        let label = self
+            .floats
+            .entry(value.to_bits())
+            .or_insert_with(|| format!(".Lfloat{}", count))
+            .clone();
+        if self.floats.len() > count {
+            self.rodata.push_str(&format!(
+                "    .align 4\n{}:\n    .long {:#x}\n",
+                label,
+                value.to_bits()
+            ));
+        }
+        label
+    }
+
+    fn string_constant(&mut self, text: &str) -> String {
+        if let Some(label) = self.strings.get(text) {
+            return label.clone();
+        }
+        let label = format!(".Lstring{}", self.strings.len());
+        self.rodata.push_str(&format!(
+            "{}:\n    .string \"{}\"\n",
+            label,
+            asm_escape(text)
+        ));
+        self.strings.insert(text.into(), label.clone());
+        label
+    }
+
+    fn function(&mut self, module: &Option<String>, function: &Function) {
+        self.function_label = format!(".Lf{}", self.function_count);
+        self.function_count += 1;
+
+        //a slot for every value and every phi's shadow
+        self.slots.clear();
+        self.phi_slots.clear();
+        let mut offset = 0;
+        let results = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .filter_map(|inst| inst.result.map(|result| (result, &inst.op)));
+        for (result, op) in function
+            .parameters
+            .iter()
+            .map(|p| (*p, None))
+            .chain(results.map(|(result, op)| (result, Some(op))))
+        {
+            offset += 8;
+            self.slots.insert(result.0, offset);
+            if let Some(Op::Phi(_)) = op {
+                offset += 8;
+                self.phi_slots.insert(result.0, offset);
+            }
+        }
+        let frame = (offset + 15) / 16 * 16;
+
+        let symbol = match function.kind {
+            FunctionKind::Main => "main".to_string(),
+            FunctionKind::Init => format!("{}__init", module.as_ref().unwrap()),
+            FunctionKind::Function { .. } => asm_function_name(module, &function.name),
+        };
+        let global = !matches!(function.kind, FunctionKind::Function { exported: false });
+        self.text.push('\n');
+        if global {
+            self.line(format!(".globl {}", symbol));
+        }
+        self.line(format!(".type {}, @function", symbol));
+        self.label(symbol);
+        self.line("pushq %rbp".into());
+        self.line("movq %rsp, %rbp".into());
+        if frame > 0 {
+            self.line(format!("subq ${}, %rsp", frame));
+        }
+
+        if function.kind == FunctionKind::Init {
+            //the module's statements run once, when it is first imported
+            self.data
+                .push_str("    .align 4\n.Linitialized:\n    .long 0\n");
+            self.line("cmpl $0, .Linitialized(%rip)".into());
+            self.line(format!("je {}_start", self.function_label));
+            self.line("leave".into());
+            self.line("ret".into());
+            self.label(format!("{}_start", self.function_label));
+            self.line("movl $1, .Linitialized(%rip)".into());
+        }
+
+        for (index, parameter) in function.parameters.iter().enumerate() {
+            let slot = self.slots[&parameter.0];
+            if index < FLOAT_ARGUMENT_REGISTERS {
+                self.line(format!("movss %xmm{}, -{}(%rbp)", index, slot));
+            } else {
+                let above = 16 + 8 * (index - FLOAT_ARGUMENT_REGISTERS);
+                self.line(format!("movss {}(%rbp), %xmm0", above));
+                self.line(format!("movss %xmm0, -{}(%rbp)", slot));
+            }
+        }
+
+        for (index, block) in function.blocks.iter().enumerate() {
+            self.label(format!("{}_b{}", self.function_label, index));
+            for inst in &block.insts {
+                self.instruction(function, inst.result.map(|result| result.0), &inst.op);
+            }
+            for successor in block.terminator.successors() {
+                for inst in &function.blocks[successor].insts {
+                    let Op::Phi(incoming) = &inst.op else {
+                        continue;
+                    };
+                    let (_, value) = incoming.iter().find(|(from, _)| *from == index).unwrap();
+                    let shadow = self.phi_slots[&inst.result.unwrap().0];
+                    self.store(function, value, format!("-{}(%rbp)", shadow));
+                }
+            }
+            self.terminator(function, index, &block.terminator);
+        }
+    }
+
+    fn slot(&self, value: usize) -> String {
+        format!("-{}(%rbp)", self.slots[&value])
+    }
+
+    // an operand in %xmm<register>, %eax for ints or %rax for strings
+    fn load(&mut self, function: &Function, operand: &Operand, register: usize) {
+        match operand {
+            Operand::Value(value) => {
+                let slot = self.slot(value.0);
+                self.load_slot(function.types[value.0], slot, register)
+            }
+            Operand::Float(value) => {
+                let label = self.float_constant(*value);
+                self.line(format!("movss {}(%rip), %xmm{}", label, register))
+            }
+            Operand::Int(value) => self.line(format!("movl ${}, %eax", value)),
+            Operand::String(text) => {
+                let label = self.string_constant(text);
+                self.line(format!("leaq {}(%rip), %rax", label))
+            }
+        }
+    }
+
+    fn load_slot(&mut self, ty: Type, slot: String, register: usize) {
+        match ty {
+            Type::Float => self.line(format!("movss {}, %xmm{}", slot, register)),
+            Type::Int => self.line(format!("movl {}, %eax", slot)),
+            Type::String => self.line(format!("movq {}, %rax", slot)),
+        }
+    }
+
+    // the other way, from %xmm0, %eax or %rax
+    fn store_slot(&mut self, ty: Type, target: String) {
+        match ty {
+            Type::Float => self.line(format!("movss %xmm0, {}", target)),
+            Type::Int => self.line(format!("movl %eax, {}", target)),
+            Type::String => self.line(format!("movq %rax, {}", target)),
+        }
+    }
+
+    // copy an operand to memory
+    fn store(&mut self, function: &Function, operand: &Operand, target: String) {
+        self.load(function, operand, 0);
+        self.store_slot(function.operand_type(operand), target);
+    }
+
+    fn instruction(&mut self, function: &Function, result: Option<usize>, op: &Op) {
+        let target = result.map(|result| self.slot(result));
+        match op {
+            Op::Copy(operand) => self.store(function, operand, target.unwrap()),
+            Op::Neg(operand) => {
+                self.load(function, operand, 0);
+                let sign = self.float_constant(-0.0);
+                self.line(format!("movss {}(%rip), %xmm1", sign));
+                self.line("xorps %xmm1, %xmm0".into());
+                self.line(format!("movss %xmm0, {}", target.unwrap()));
+            }
+            Op::Binary(op, left, right) => {
+                self.load(function, left, 0);
+                self.load(function, right, 1);
+                let instruction = match op {
+                    BinOp::Add => "addss",
+                    BinOp::Sub => "subss",
+                    BinOp::Mul => "mulss",
+                    BinOp::Div => "divss",
+                    _ => {
+                        self.comparison(*op);
+                        self.line(format!("movl %eax, {}", target.unwrap()));
+                        return;
+                    }
+                };
+                self.line(format!("{} %xmm1, %xmm0", instruction));
+                self.line(format!("movss %xmm0, {}", target.unwrap()));
+            }
+            Op::IntToFloat(operand) => {
+                self.load(function, operand, 0);
+                self.line("cvtsi2ssl %eax, %xmm0".into());
+                self.line(format!("movss %xmm0, {}", target.unwrap()));
+            }
+            //cvttss2si gives INT_MIN for nan and numbers out of range,
+            //which is right only for the ones below it
+            Op::FloatToInt(operand) => {
+                self.load(function, operand, 0);
+                self.line("cvttss2si %xmm0, %eax".into());
+                let limit = self.float_constant(2147483648.0);
+                self.line("movl $2147483647, %ecx".into());
+                self.line(format!("ucomiss {}(%rip), %xmm0", limit));
+                self.line("cmovael %ecx, %eax".into());
+                self.line("xorl %ecx, %ecx".into());
+                self.line("ucomiss %xmm0, %xmm0".into());
+                self.line("cmovpl %ecx, %eax".into());
+                self.line(format!("movl %eax, {}", target.unwrap()));
+            }
+            //the jumps into the block have filled in the shadow
+            Op::Phi(_) => {
+                let value = result.unwrap();
+                let shadow = format!("-{}(%rbp)", self.phi_slots[&value]);
+                let ty = function.types[value];
+                self.load_slot(ty, shadow, 0);
+                self.store_slot(ty, target.unwrap());
+            }
+            Op::Load(name) => {
+                self.line(format!("movss {}(%rip), %xmm0", global_label(name)));
+                self.line(format!("movss %xmm0, {}", target.unwrap()));
+            }
+            Op::Store(name, operand) => {
+                self.store(function, operand, format!("{}(%rip)", global_label(name)))
+            }
+            Op::Call(module, name, args) => {
+                //arguments past the registers go on the stack, last
+                //first, keeping %rsp 16 byte aligned for the call
+                let extra = args.len().saturating_sub(FLOAT_ARGUMENT_REGISTERS);
+                let padding = extra % 2;
+                if padding == 1 {
+                    self.line("subq $8, %rsp".into());
+                }
+                for arg in args[FLOAT_ARGUMENT_REGISTERS.min(args.len())..]
+                    .iter()
+                    .rev()
+                {
+                    self.load(function, arg, 0);
+                    self.line("subq $8, %rsp".into());
+                    self.line("movss %xmm0, (%rsp)".into());
+                }
+                for (index, arg) in args.iter().take(FLOAT_ARGUMENT_REGISTERS).enumerate() {
+                    self.load(function, arg, index);
+                }
+                self.line(format!("call {}", asm_function_name(module, name)));
+                if extra > 0 {
+                    self.line(format!("addq ${}, %rsp", 8 * (extra + padding)));
+                }
+                self.line(format!("movss %xmm0, {}", target.unwrap()));
+            }
+            Op::Eof => {
+                self.line("movl tt_eof(%rip), %eax".into());
+                self.line(format!("movl %eax, {}", target.unwrap()));
+            }
+            Op::Input { prompt, location } => {
+                let location = self.string_constant(location);
+                self.line(format!("leaq {}(%rip), %rdx", location));
+                self.load(function, prompt, 0);
+                self.line("movq %rax, %rsi".into());
+                self.line(format!("leaq {}, %rdi", target.unwrap()));
+                self.line("call tt_input".into());
+            }
+            Op::PrintString(text) => {
+                self.load(function, text, 0);
+                self.line("movq %rax, %rdi".into());
+                self.line("call tt_print_string".into());
+            }
+            Op::PrintNumber(value) => {
+                self.load(function, value, 0);
+                self.line("call tt_print_number".into());
+            }
+            Op::PrintUsing(picture, value) => {
+                let format = self.string_constant(&using_format(picture));
+                self.load(function, value, 0);
+                self.line(format!("leaq {}(%rip), %rdi", format));
+                self.line("call tt_print_using".into());
+            }
+            Op::PrintTab => self.line("call tt_print_tab".into()),
+            Op::PrintNewline => self.line("call tt_print_newline".into()),
+            Op::Import(name) => self.line(format!("call {}__init", name)),
+        }
+    }
+
+    // compare %xmm0 with %xmm1 and leave 1 or 0 in %eax. ucomiss sets the
+    // parity flag when either is NaN, which compares unequal and unordered.
+    fn comparison(&mut self, op: BinOp) {
+        match op {
+            BinOp::Gt | BinOp::Ge => self.line("ucomiss %xmm1, %xmm0".into()),
+            _ => self.line("ucomiss %xmm0, %xmm1".into()),
+        }
+        match op {
+            BinOp::Gt | BinOp::Lt => self.line("seta %al".into()),
+            BinOp::Ge | BinOp::Le => self.line("setae %al".into()),
+            BinOp::Eq => {
+                self.line("sete %al".into());
+                self.line("setnp %cl".into());
+                self.line("andb %cl, %al".into());
+            }
+            _ => {
+                self.line("setne %al".into());
+                self.line("setp %cl".into());
+                self.line("orb %cl, %al".into());
+            }
+        }
+        self.line("movzbl %al, %eax".into());
+    }
+
+    fn terminator(&mut self, function: &Function, block: BlockId, terminator: &Terminator) {
+        let next = block + 1;
+        match terminator {
+            Terminator::Jump(target) if *target == next => {}
+            Terminator::Jump(target) => {
+                self.line(format!("jmp {}_b{}", self.function_label, target))
+            }
+            Terminator::Branch(condition, then, otherwise) => {
+                self.load(function, condition, 0);
+                self.line("testl %eax, %eax".into());
+                if *then == next {
+                    self.line(format!("je {}_b{}", self.function_label, otherwise));
+                } else {
+                    self.line(format!("jne {}_b{}", self.function_label, then));
+                    if *otherwise != next {
+                        self.line(format!("jmp {}_b{}", self.function_label, otherwise));
+                    }
+                }
+            }
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    self.load(function, value, 0);
+                }
+                self.line("leave".into());
+                self.line("ret".into());
+            }
+            Terminator::Exit(status) => {
+                self.load(function, status, 0);
+                self.line("movl %eax, %edi".into());
+                self.line("call exit@PLT".into());
+            }
+            Terminator::Stop(message) => {
+                let message = self.string_constant(&format!("{}\n", message));
+                self.line("movq stderr@GOTPCREL(%rip), %rax".into());
+                self.line("movq (%rax), %rsi".into());
+                self.line(format!("leaq {}(%rip), %rdi", message));
+                self.line("call fputs@PLT".into());
+                self.line("movl $1, %edi".into());
+                self.line("call exit@PLT".into());
+            }
+        }
+    }
+}
+
+impl ModuleFiles for AsmEmitter {
+    // emitter for a module's own .s file, next to this one's output
+    fn for_module(&self, name: &str) -> AsmEmitter {
+        let full_path = Path::new(&self.full_path).with_file_name(format!("{}.s", name));
+        let mut emitter = AsmEmitter::new(full_path.display().to_string());
+        emitter.module = Some(name.into());
+        emitter.input_policy = self.input_policy;
+        emitter
+    }
+
+    fn program(&mut self, module: &Module) {
+        for name in &module.globals {
+            self.data.push_str(&format!(
+                "    .align 4\n{}:\n    .long 0\n",
+                global_label(name)
+            ));
+        }
+        for function in &module.functions {
+            self.function(&module.name, function);
+        }
+    }
+
+    fn write_file(&mut self) -> String {
+        let mut value = String::new();
+        if self.module.is_none() {
+            value.push_str(RUNTIME);
+            value.push_str(input_policy_code(self.input_policy));
+            value.push_str(RUNTIME_END);
+        }
+        value.push_str("\n    .text");
+        value.push_str(&self.text);
+        if !self.data.is_empty() {
+            value.push_str("\n    .data\n");
+            value.push_str(&self.data);
+        }
+        if !self.rodata.is_empty() {
+            value.push_str("\n    .section .rodata\n");
+            value.push_str(&self.rodata);
+        }
+        value.push_str("\n    .section .note.GNU-stack,\"\",@progbits\n");
+        write_if_changed(Path::new(&self.full_path), &value);
+        self.full_path.clone()
+    }
+}
+
+impl Backend for AsmEmitter {
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String> {
+        backend::emit_files(self, modules, program)
+    }
+
+    fn finish(&self, files: &[String]) -> Option<String> {
+        match build(files, "out") {
+            Ok(()) => Some("Built out".into()),
+            Err(error) => Some(format!("Build failed, {}", error)),
+        }
+    }
+}
+
+// the symbol of a FUNCTION, the same name the C backend gives it
+fn asm_function_name(module: &Option<String>, name: &str) -> String {
+    match module {
+        Some(module) => format!("{}__{}", module, name),
+        None => format!("tt_fn_{}", name),
+    }
+}
+
+// globals are local labels, so no variable name can clash with a symbol
+fn global_label(name: &str) -> String {
+    format!(".Lglobal_{}", name)
+}
+
+// strings go in as they are, like the C backend writes them
+fn asm_escape(text: &str) -> String {
+    text.replace('\n', "\\n")
+}
+
+// assemble each .s file with as and link them with libc through cc into
+// an executable, giving the step that failed if one did
+pub fn build(sources: &[String], output: &str) -> Result<(), String> {
+    let mut objects = Vec::new();
+    for source in sources {
+        let object = Path::new(source).with_extension("o").display().to_string();
+        run(Command::new("as").arg(source).arg("-o").arg(&object))?;
+        objects.push(object);
+    }
+    run(Command::new("cc").arg("-o").arg(output).args(&objects))
+}
+
+// run a command, or say what it was and why it didn't work
+fn run(command: &mut Command) -> Result<(), String> {
+    let step = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ");
+    match command.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} failed with {}", step, status)),
+        Err(error) => Err(format!("could not run {}: {}", step, error)),
+    }
+}
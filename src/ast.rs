@@ -0,0 +1,270 @@
+use crate::lex::TokenType;
+use crate::source::SourceFile;
+use crate::symbols::{Span, Type};
+use std::fmt;
+
+// expression tree built by the parser, lowered to IR by lower.rs
+#[derive(Debug, Clone)]
+pub enum Expr {
+    //literal, kept as written in the source
+    Number(String),
+    Variable(String),
+    //EOF builtin
+    InputEof,
+    //FUNCTION call, with the module it lives in (None for the main program)
+    Call(Option<String>, String, Vec<Expr>),
+    //+ or - in front of a primary
+    Unary(TokenType, Box<Expr>),
+    //arithmetic and comparison operators
+    Binary(Box<Expr>, TokenType, Box<Expr>),
+}
+
+impl Expr {
+    // literal for a value worked out by the compiler, written so it reads
+    // back as exactly the same f32
+    pub fn number(value: f32) -> Expr {
+        Expr::Number(format!("{:?}", value))
+    }
+
+    // call f on this expression and every expression inside it
+    pub fn visit<'a>(&'a self, f: &mut dyn FnMut(&'a Expr)) {
+        f(self);
+        match self {
+            Expr::Number(_) | Expr::Variable(_) | Expr::InputEof => {}
+            Expr::Call(_, _, args) => args.iter().for_each(|arg| arg.visit(f)),
+            Expr::Unary(_, operand) => operand.visit(f),
+            Expr::Binary(left, _, right) => {
+                left.visit(f);
+                right.visit(f);
+            }
+        }
+    }
+
+    // value of the expression if it is made only of literals, worked out
+    // in f32 so it matches what the generated C computes with floats
+    pub fn constant_value(&self) -> Option<f32> {
+        match self {
+            Expr::Number(text) => text.parse().ok(),
+            Expr::Variable(_) | Expr::InputEof | Expr::Call(..) => None,
+            Expr::Unary(op, operand) => {
+                let value = operand.constant_value()?;
+                match op {
+                    TokenType::MINUS => Some(-value),
+                    _ => Some(value),
+                }
+            }
+            Expr::Binary(left, op, right) => {
+                let left = left.constant_value()?;
+                let right = right.constant_value()?;
+                let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+
+                match op {
+                    TokenType::PLUS => Some(left + right),
+                    TokenType::MINUS => Some(left - right),
+                    TokenType::ASTERISK => Some(left * right),
+                    TokenType::SLASH => Some(left / right),
+                    TokenType::EQEQ => Some(truth(left == right)),
+                    TokenType::NOTEQ => Some(truth(left != right)),
+                    TokenType::LT => Some(truth(left < right)),
+                    TokenType::LTEQ => Some(truth(left <= right)),
+                    TokenType::GT => Some(truth(left > right)),
+                    TokenType::GTEQ => Some(truth(left >= right)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+// teeny tiny source for an expression, parenthesised where it needs it
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(text) => write!(f, "{}", text),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::InputEof => write!(f, "EOF"),
+            Expr::Call(module, name, args) => {
+                if let Some(module) = module {
+                    write!(f, "{}.", module)?;
+                }
+                let args: Vec<String> = args.iter().map(Expr::to_string).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Expr::Unary(op, operand) => match **operand {
+                Expr::Unary(..) | Expr::Binary(..) => {
+                    write!(f, "{}({})", operator_text(op), operand)
+                }
+                _ => write!(f, "{}{}", operator_text(op), operand),
+            },
+            Expr::Binary(left, op, right) => {
+                if matches!(**left, Expr::Binary(_, ref inner, _) if precedence(inner) < precedence(op))
+                {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", operator_text(op))?;
+                if matches!(**right, Expr::Binary(_, ref inner, _) if precedence(inner) <= precedence(op))
+                {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+        }
+    }
+}
+
+pub fn operator_text(op: &TokenType) -> &'static str {
+    match op {
+        TokenType::PLUS => "+",
+        TokenType::MINUS => "-",
+        TokenType::ASTERISK => "*",
+        TokenType::SLASH => "/",
+        TokenType::EQEQ => "==",
+        TokenType::NOTEQ => "!=",
+        TokenType::LT => "<",
+        TokenType::LTEQ => "<=",
+        TokenType::GT => ">",
+        TokenType::GTEQ => ">=",
+        _ => unreachable!("{:?} is not an operator", op),
+    }
+}
+
+// binding strength of a binary operator, higher binds tighter
+pub fn precedence(op: &TokenType) -> u8 {
+    match op {
+        TokenType::ASTERISK | TokenType::SLASH => 4,
+        TokenType::PLUS | TokenType::MINUS => 3,
+        TokenType::LT | TokenType::LTEQ | TokenType::GT | TokenType::GTEQ => 2,
+        _ => 1,
+    }
+}
+
+// a parsed file, the main program or one MODULE
+#[derive(Debug)]
+pub struct Program {
+    //None for the main program
+    pub module: Option<String>,
+    //files read for this program, spans index into it
+    pub files: Vec<SourceFile>,
+    //top level variables, in the order they are first assigned
+    pub globals: Vec<String>,
+    pub functions: Vec<Function>,
+    //top level statements, run by main or by the module's init
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub span: Span,
+    pub exported: bool,
+    //what it returns, parameters and locals are floats
+    pub ty: Type,
+    pub parameters: Vec<String>,
+    //variables first assigned inside the function
+    pub locals: Vec<String>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    //where the statement starts
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    Print {
+        //USING picture, like "###.##"
+        using: Option<String>,
+        items: Vec<PrintItem>,
+        newline: bool,
+    },
+    If {
+        condition: Expr,
+        body: Vec<Stmt>,
+    },
+    While {
+        condition: Expr,
+        body: Vec<Stmt>,
+    },
+    Do {
+        body: Vec<Stmt>,
+        condition: LoopCondition,
+        //the LOOP line
+        end: Span,
+    },
+    Select {
+        subject: Expr,
+        cases: Vec<Case>,
+        //CASE ELSE
+        default: Option<Vec<Stmt>>,
+    },
+    Break,
+    Continue,
+    Label(String),
+    Goto(String),
+    Let(String, Expr),
+    Input {
+        //shown before the first value only
+        prompt: String,
+        targets: Vec<String>,
+        //where the INPUT is, for runtime messages
+        location: String,
+    },
+    //CALL of a function whose result isn't needed
+    Call(Expr),
+    Return(Option<Expr>),
+    //runs the module's top level statements
+    Import(String),
+    End,
+    Stop {
+        location: String,
+        message: Option<String>,
+    },
+    Exit(Expr),
+}
+
+#[derive(Debug, Clone)]
+pub enum LoopCondition {
+    //bare LOOP
+    Forever,
+    Until(Expr),
+    While(Expr),
+}
+
+#[derive(Debug, Clone)]
+pub enum PrintItem {
+    Text(String),
+    Value(Expr),
+    //"," between items
+    Tab,
+}
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub items: Vec<CaseItem>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum CaseItem {
+    //IS followed by a comparison operator
+    Is(TokenType, Expr),
+    //low TO high
+    Range(Expr, Expr),
+    Value(Expr),
+}
+
+impl CaseItem {
+    pub fn expressions(&self) -> Vec<&Expr> {
+        match self {
+            CaseItem::Is(_, value) | CaseItem::Value(value) => vec![value],
+            CaseItem::Range(low, high) => vec![low, high],
+        }
+    }
+}
@@ -0,0 +1,595 @@
+use crate::asm::AsmEmitter;
+use crate::emit::Emitter;
+use crate::ir::{BinOp, BlockId, Function, Inst, Module, Op, Operand, Terminator, Value};
+use crate::js::JsEmitter;
+use crate::llvm::LlvmEmitter;
+use crate::python::PythonEmitter;
+use crate::qbe::QbeEmitter;
+use crate::rust::RustEmitter;
+use crate::structure::{self, Condition, Loop, Statement};
+use crate::variables::{Source, Variables};
+use crate::wasm::WasmEmitter;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// an output --emit can choose. it gets the IR of the modules the program
+// imports, each before the modules that import it, and of the program
+pub trait Backend {
+    // write the output, giving the files written
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String>;
+    // anything left to do once the files are written, and a line telling
+    // the user what comes next
+    fn finish(&self, _files: &[String]) -> Option<String> {
+        None
+    }
+    // true for output on stdout, where nothing else may go
+    fn writes_stdout(&self) -> bool {
+        false
+    }
+}
+
+// what INPUT does when the text typed in is not a number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputPolicy {
+    // set the variable to 0 and skip the bad word
+    Zero,
+    // throw the rest of the line away and ask again
+    Reprompt,
+    // stop the program, reporting the INPUT line
+    Error,
+}
+
+impl InputPolicy {
+    pub fn from_name(name: &str) -> Option<InputPolicy> {
+        match name {
+            "zero" => Some(InputPolicy::Zero),
+            "reprompt" => Some(InputPolicy::Reprompt),
+            "error" => Some(InputPolicy::Error),
+            _ => None,
+        }
+    }
+}
+
+// leave files that would not change alone, so builds that go by
+// modification time only recompile the translation units that changed
+pub fn write_if_changed(path: &Path, value: &str) {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == value) {
+        return;
+    }
+    fs::write(path, value).unwrap();
+}
+
+// printf format for a USING picture, "###.##" prints like "%6.2f"
+pub fn using_format(picture: &str) -> String {
+    let fraction = picture
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len());
+    format!("%{}.{}f", picture.len(), fraction)
+}
+
+// true for an expression hooks wrote with nothing outside brackets or
+// quotes that an operator next to it could split, where binary operators
+// have a space each side
+pub fn simple(expression: &str) -> bool {
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for byte in expression.bytes() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            _ if quoted => {}
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth -= 1,
+            b' ' if depth == 0 => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+// true if an operator, with its spaces, joins anything outside brackets and
+// quotes in an expression hooks wrote
+pub fn joins(expression: &str, operator: &str) -> bool {
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, byte) in expression.bytes().enumerate() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            _ if quoted => {}
+            b'(' | b'[' => depth += 1,
+            b')' | b']' => depth -= 1,
+            _ if depth == 0 && expression[index..].starts_with(operator) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+// makes a backend, with the input policy to build into it
+pub type Constructor = fn(InputPolicy) -> Box<dyn Backend>;
+
+// the backends --emit can choose by name
+pub fn by_name(name: &str) -> Option<Constructor> {
+    let constructor: Constructor = match name {
+        "c" => |input_policy| {
+            let mut emitter = Emitter::new("out.c".to_string());
+            emitter.input_policy = input_policy;
+            Box::new(emitter)
+        },
+        "ir" => |_| Box::new(IrText),
+        "asm" => |input_policy| {
+            let mut emitter = AsmEmitter::new("out.s".to_string());
+            emitter.input_policy = input_policy;
+            Box::new(emitter)
+        },
+        "llvm" => |input_policy| {
+            let mut emitter = LlvmEmitter::new("out.ll".to_string());
+            emitter.input_policy = input_policy;
+            Box::new(emitter)
+        },
+        "qbe" => |input_policy| {
+            let mut emitter = QbeEmitter::new("out.ssa".to_string());
+            emitter.input_policy = input_policy;
+            Box::new(emitter)
+        },
+        "wasm" => |input_policy| {
+            let mut emitter = WasmEmitter::new("out.wat".to_string());
+            emitter.input_policy = input_policy;
+            Box::new(emitter)
+        },
+        "js" => |input_policy| {
+            let mut emitter = JsEmitter::new("out.js".to_string());
+            emitter.input_policy = input_policy;
+            Box::new(emitter)
+        },
+        "python" => |input_policy| {
+            let mut emitter = PythonEmitter::new("out.py".to_string());
+            emitter.input_policy = input_policy;
+            Box::new(emitter)
+        },
+        "rust" => |input_policy| {
+            let mut emitter = RustEmitter::new("main.rs".to_string());
+            emitter.input_policy = input_policy;
+            Box::new(emitter)
+        },
+        _ => return None,
+    };
+    Some(constructor)
+}
+
+// the IR as text on stdout
+struct IrText;
+
+impl Backend for IrText {
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String> {
+        for module in modules.iter().chain([program]) {
+            print!("{}", module);
+        }
+        Vec::new()
+    }
+
+    fn writes_stdout(&self) -> bool {
+        true
+    }
+}
+
+// a backend that writes a file for each module, like C's translation units
+pub trait ModuleFiles {
+    // the emitter for an imported module's own file, next to this one's
+    fn for_module(&self, name: &str) -> Self
+    where
+        Self: Sized;
+    fn program(&mut self, module: &Module);
+    // write the file, giving its path
+    fn write_file(&mut self) -> String;
+}
+
+// each module in a file of its own, the program's first
+pub fn emit_files<E: ModuleFiles>(
+    emitter: &mut E,
+    modules: &[Module],
+    program: &Module,
+) -> Vec<String> {
+    let mut files = Vec::new();
+    for module in modules {
+        let mut module_emitter = emitter.for_module(module.name.as_ref().unwrap());
+        module_emitter.program(module);
+        files.push(module_emitter.write_file());
+    }
+    emitter.program(program);
+    files.insert(0, emitter.write_file());
+    files
+}
+
+// a code generator written as hooks, for output that reads like source
+// code. generate() turns each function's values back into variables (see
+// variables.rs) and its CFG back into if, while and do (see structure.rs),
+// then calls the hooks in order: the start and end of the program and of
+// each function, a statement hook for each instruction that does something
+// and an expression hook for each value, which gives back its text. a
+// value used once, straight after it is worked out, is written into the
+// expression that uses it. a new target only has to say what each hook
+// writes.
+pub trait Generator {
+    // names a variable can't have, which get a '_' on the end
+    fn reserved(&self) -> &'static [&'static str] {
+        &[]
+    }
+    // false for languages without goto, where a function that needs one
+    // runs as a state machine (see the dispatch hooks)
+    fn has_goto(&self) -> bool {
+        true
+    }
+    fn start_program(&mut self, _module: &Module, _variables: &[Variables]) {}
+    fn end_program(&mut self, _module: &Module) {}
+    fn start_function(&mut self, function: &Function, variables: &Variables);
+    fn end_function(&mut self, function: &Function, variables: &Variables);
+
+    //expressions
+    fn constant(&mut self, constant: &Operand) -> String;
+    // comparisons give an int, 1 or 0
+    fn binary(&mut self, op: BinOp, left: String, right: String) -> String;
+    // a comparison as the test of an if or a loop
+    fn compare(&mut self, op: BinOp, left: String, right: String) -> String {
+        self.binary(op, left, right)
+    }
+    // any other int as a test, true unless it is 0
+    fn truth(&mut self, value: String) -> String {
+        value
+    }
+    fn neg(&mut self, operand: String) -> String;
+    fn not(&mut self, condition: String) -> String;
+    // the second only worked out if the first is true, or false
+    fn and(&mut self, first: String, second: String) -> String;
+    fn or(&mut self, first: String, second: String) -> String;
+    fn int_to_float(&mut self, operand: String) -> String;
+    fn float_to_int(&mut self, operand: String) -> String;
+    fn load(&mut self, name: &str) -> String;
+    fn call(&mut self, module: &Option<String>, name: &str, args: Vec<String>) -> String;
+    fn eof(&mut self) -> String;
+
+    //statements
+    fn assign(&mut self, variable: &str, value: String);
+    // an expression whose value nothing reads
+    fn evaluate(&mut self, value: String);
+    fn store(&mut self, name: &str, value: String);
+    fn input(&mut self, variable: &str, prompt: String, location: &str);
+    fn print_string(&mut self, text: String);
+    fn print_number(&mut self, value: String);
+    fn print_using(&mut self, picture: &str, value: String);
+    fn print_tab(&mut self);
+    fn print_newline(&mut self);
+    fn import(&mut self, module: &str);
+
+    //control flow
+    fn start_if(&mut self, condition: String);
+    fn start_else_if(&mut self, condition: String);
+    fn start_else(&mut self);
+    fn end_if(&mut self);
+    // a loop only break leaves
+    fn start_loop(&mut self);
+    fn start_while(&mut self, condition: String);
+    // the end of either
+    fn end_loop(&mut self);
+    // the condition is the one end_do gets, for a continue to test
+    fn start_do(&mut self, condition: String);
+    fn end_do(&mut self, condition: String);
+    fn break_loop(&mut self);
+    fn continue_loop(&mut self);
+    fn label(&mut self, block: BlockId);
+    fn goto(&mut self, block: BlockId);
+    fn ret(&mut self, value: Option<String>);
+    fn exit(&mut self, status: String);
+    fn stop(&mut self, message: &str);
+
+    //state machines, where goto sets the case to run next
+    fn start_dispatch(&mut self, _entry: BlockId) {
+        unreachable!("only a generator without goto has a dispatch");
+    }
+    fn start_case(&mut self, _block: BlockId) {}
+    fn end_case(&mut self) {}
+    fn end_dispatch(&mut self) {}
+}
+
+// run a module's IR through a generator
+pub fn generate(generator: &mut dyn Generator, module: &Module) {
+    let variables: Vec<Variables> = module
+        .functions
+        .iter()
+        .map(|function| Variables::new(function, generator.reserved()))
+        .collect();
+    generator.start_program(module, &variables);
+    for (function, variables) in module.functions.iter().zip(&variables) {
+        let mut walker = Walker {
+            generator: &mut *generator,
+            function,
+            variables,
+            definitions: function
+                .blocks
+                .iter()
+                .flat_map(|block| &block.insts)
+                .filter_map(|inst| inst.result.map(|result| (result, inst)))
+                .collect(),
+        };
+        walker.generator.start_function(function, variables);
+        let mut statements = match walker.generator.has_goto() {
+            true => structure::function(function, variables),
+            false => structure::function_without_goto(function, variables),
+        };
+        //a function that returns nothing can just end
+        if let Some(&Statement::End(block)) = statements.last() {
+            if matches!(function.blocks[block].terminator, Terminator::Return(None)) {
+                statements.pop();
+            }
+        }
+        walker.statements(&statements);
+        walker.generator.end_function(function, variables);
+    }
+    generator.end_program(module);
+}
+
+fn is_comparison(op: BinOp) -> bool {
+    !matches!(op, BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div)
+}
+
+fn branch_condition(function: &Function, block: BlockId) -> Option<&Operand> {
+    match &function.blocks[block].terminator {
+        Terminator::Branch(condition, ..) => Some(condition),
+        _ => None,
+    }
+}
+
+struct Walker<'a> {
+    generator: &'a mut dyn Generator,
+    function: &'a Function,
+    variables: &'a Variables,
+    //the instruction behind each value, for the ones written inline
+    definitions: HashMap<Value, &'a Inst>,
+}
+
+impl Walker<'_> {
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Block(block) => {
+                for inst in &self.function.blocks[*block].insts {
+                    if !self.variables.is_silent(inst) {
+                        self.instruction(inst);
+                    }
+                }
+            }
+            Statement::Copies(from, to) => {
+                for (variable, source) in self.variables.copies(self.function, *from, *to) {
+                    let value = match source {
+                        Source::Operand(operand) => self.operand(operand),
+                        Source::Variable(name) => name.to_string(),
+                    };
+                    self.generator.assign(variable, value);
+                }
+            }
+            Statement::If(condition, then, otherwise) => {
+                if then.is_empty() && otherwise.is_empty() {
+                    //nothing depends on the condition, but it may call
+                    match condition {
+                        Condition::Test { block, .. } => {
+                            if let Some(Operand::Value(value)) =
+                                branch_condition(self.function, *block)
+                            {
+                                if self.variables.is_inline(*value) {
+                                    let value = self.operand(&Operand::Value(*value));
+                                    self.generator.evaluate(value);
+                                }
+                            }
+                        }
+                        _ => {
+                            let condition = self.condition(condition);
+                            self.generator.start_if(condition);
+                            self.generator.end_if();
+                        }
+                    }
+                    return;
+                }
+                let condition = self.condition(condition);
+                self.generator.start_if(condition);
+                self.statements(then);
+                let mut otherwise = otherwise;
+                loop {
+                    match &otherwise[..] {
+                        [] => break,
+                        [Statement::If(condition, then, rest)] if !then.is_empty() => {
+                            let condition = self.condition(condition);
+                            self.generator.start_else_if(condition);
+                            self.statements(then);
+                            otherwise = rest;
+                        }
+                        _ => {
+                            self.generator.start_else();
+                            self.statements(otherwise);
+                            break;
+                        }
+                    }
+                }
+                self.generator.end_if();
+            }
+            Statement::Loop(Loop::Forever, body) => {
+                self.generator.start_loop();
+                self.statements(body);
+                self.generator.end_loop();
+            }
+            Statement::Loop(Loop::While(condition), body) => {
+                let condition = self.condition(condition);
+                self.generator.start_while(condition);
+                self.statements(body);
+                self.generator.end_loop();
+            }
+            Statement::Loop(Loop::DoWhile(condition), body) => {
+                let test = self.condition(condition);
+                self.generator.start_do(test);
+                self.statements(body);
+                let condition = self.condition(condition);
+                self.generator.end_do(condition);
+            }
+            Statement::Break => self.generator.break_loop(),
+            Statement::Continue => self.generator.continue_loop(),
+            Statement::Goto(block) => self.generator.goto(*block),
+            Statement::Label(block) => self.generator.label(*block),
+            Statement::Dispatch(cases) => {
+                self.generator.start_dispatch(cases[0].0);
+                for (block, body) in cases {
+                    self.generator.start_case(*block);
+                    self.statements(body);
+                    self.generator.end_case();
+                }
+                self.generator.end_dispatch();
+            }
+            Statement::End(block) => match &self.function.blocks[*block].terminator {
+                Terminator::Return(value) => {
+                    let value = value.as_ref().map(|value| self.operand(value));
+                    self.generator.ret(value);
+                }
+                Terminator::Exit(status) => {
+                    let status = self.operand(status);
+                    self.generator.exit(status);
+                }
+                Terminator::Stop(message) => self.generator.stop(message),
+                Terminator::Jump(_) | Terminator::Branch(..) => unreachable!(),
+            },
+        }
+    }
+
+    // the test of a branch, or its opposite. == and != are each other's
+    // opposite even for NaN, the other comparisons are not
+    fn condition(&mut self, condition: &Condition) -> String {
+        let (block, negated) = match condition {
+            Condition::Test { block, negated } => (*block, *negated),
+            Condition::And(first, second) => {
+                let first = self.condition(first);
+                let second = self.condition(second);
+                return self.generator.and(first, second);
+            }
+            Condition::Or(first, second) => {
+                let first = self.condition(first);
+                let second = self.condition(second);
+                return self.generator.or(first, second);
+            }
+        };
+        let operand = branch_condition(self.function, block).unwrap();
+        let comparison = match operand {
+            Operand::Value(value) if self.variables.is_inline(*value) => {
+                match &self.definitions[value].op {
+                    Op::Binary(op, left, right) if is_comparison(*op) => Some((*op, left, right)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let Some((op, left, right)) = comparison else {
+            let value = self.operand(operand);
+            let test = self.generator.truth(value);
+            return match negated {
+                true => self.generator.not(test),
+                false => test,
+            };
+        };
+        let op = match (negated, op) {
+            (true, BinOp::Eq) => BinOp::Ne,
+            (true, BinOp::Ne) => BinOp::Eq,
+            _ => op,
+        };
+        let left = self.operand(left);
+        let right = self.operand(right);
+        let test = self.generator.compare(op, left, right);
+        match negated && !matches!(op, BinOp::Eq | BinOp::Ne) {
+            true => self.generator.not(test),
+            false => test,
+        }
+    }
+
+    fn operand(&mut self, operand: &Operand) -> String {
+        match operand {
+            Operand::Value(value) if self.variables.is_inline(*value) => {
+                let op = &self.definitions[value].op;
+                self.expression(op)
+            }
+            Operand::Value(value) => self.variables.name(*value).unwrap().to_string(),
+            constant => self.generator.constant(constant),
+        }
+    }
+
+    fn expression(&mut self, op: &Op) -> String {
+        match op {
+            Op::Copy(operand) => self.operand(operand),
+            Op::Neg(operand) => {
+                let operand = self.operand(operand);
+                self.generator.neg(operand)
+            }
+            Op::Binary(op, left, right) => {
+                let left = self.operand(left);
+                let right = self.operand(right);
+                self.generator.binary(*op, left, right)
+            }
+            Op::IntToFloat(operand) => {
+                let operand = self.operand(operand);
+                self.generator.int_to_float(operand)
+            }
+            Op::FloatToInt(operand) => {
+                let operand = self.operand(operand);
+                self.generator.float_to_int(operand)
+            }
+            Op::Load(name) => self.generator.load(name),
+            Op::Call(module, name, args) => {
+                let args = args.iter().map(|arg| self.operand(arg)).collect();
+                self.generator.call(module, name, args)
+            }
+            Op::Eof => self.generator.eof(),
+            _ => unreachable!("{} is not an expression", op),
+        }
+    }
+
+    fn instruction(&mut self, inst: &Inst) {
+        match &inst.op {
+            Op::Store(name, operand) => {
+                let value = self.operand(operand);
+                self.generator.store(name, value);
+            }
+            Op::Input { prompt, location } => {
+                let prompt = self.operand(prompt);
+                let variable = self.variables.name(inst.result.unwrap()).unwrap();
+                self.generator.input(variable, prompt, location);
+            }
+            Op::PrintString(text) => {
+                let text = self.operand(text);
+                self.generator.print_string(text);
+            }
+            Op::PrintNumber(value) => {
+                let value = self.operand(value);
+                self.generator.print_number(value);
+            }
+            Op::PrintUsing(picture, value) => {
+                let value = self.operand(value);
+                self.generator.print_using(picture, value);
+            }
+            Op::PrintTab => self.generator.print_tab(),
+            Op::PrintNewline => self.generator.print_newline(),
+            Op::Import(name) => self.generator.import(name),
+            op => {
+                let value = self.expression(op);
+                match inst.result.and_then(|result| self.variables.name(result)) {
+                    Some(variable) => self.generator.assign(variable, value),
+                    None => self.generator.evaluate(value),
+                }
+            }
+        }
+    }
+}
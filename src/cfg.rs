@@ -0,0 +1,530 @@
+use crate::ast::{Case, CaseItem, Expr, LoopCondition, PrintItem, Program, Stmt, StmtKind};
+use crate::lex::TokenType;
+use crate::symbols::Span;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// one step of a program body: a simple statement or the test of a compound one
+#[derive(Debug, Clone, Copy)]
+pub enum NodeKind<'a> {
+    Entry,
+    Exit,
+    //PRINT, LET, GOTO, ... anything that isn't IF, WHILE, DO or SELECT
+    Statement(&'a Stmt),
+    //test of an IF, WHILE or DO ... LOOP, with the statement it belongs
+    //to. leaves by a True and a False edge
+    Condition(&'a Stmt, &'a Expr),
+    //where a DO loop starts over
+    Join(&'a Stmt),
+    //SELECT CASE subject, worked out once
+    Select(&'a Stmt, &'a Expr),
+    //test of one CASE, leaves by True into its body and False to the next
+    Case(&'a Case),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    //falling through to the next statement
+    Next,
+    True,
+    False,
+    //GOTO, BREAK, CONTINUE and statements that leave the body
+    Jump,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node<'a> {
+    pub kind: NodeKind<'a>,
+    pub span: Span,
+    pub successors: Vec<(usize, Edge)>,
+}
+
+// control-flow graph of one body, the top level or a FUNCTION, with a
+// node per statement
+pub struct Cfg<'a> {
+    //in source order, entry and exit first
+    pub nodes: Vec<Node<'a>>,
+}
+
+pub const ENTRY: usize = 0;
+pub const EXIT: usize = 1;
+
+// a run of nodes that only the first is jumped to and only the last
+// leaves, entered at the top and left at the bottom
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    //indices into the Cfg's nodes, in the order they run
+    pub nodes: Vec<usize>,
+    //indices of other blocks
+    pub successors: Vec<(usize, Edge)>,
+}
+
+// edges that still need somewhere to go, joined to the next node added
+type Open = Vec<(usize, Edge)>;
+
+struct Loop {
+    //None for DO, whose test comes after its body
+    continue_target: Option<usize>,
+    continues: Open,
+    breaks: Open,
+}
+
+struct Builder<'a> {
+    nodes: Vec<Node<'a>>,
+    labels: HashMap<&'a str, usize>,
+    gotos: Vec<(usize, &'a str)>,
+    loops: Vec<Loop>,
+}
+
+impl<'a> Cfg<'a> {
+    // labels a GOTO names must be in the body, the parser checks that
+    pub fn build(body: &'a [Stmt], span: Span) -> Cfg<'a> {
+        let mut builder = Builder {
+            nodes: Vec::new(),
+            labels: HashMap::new(),
+            gotos: Vec::new(),
+            loops: Vec::new(),
+        };
+        builder.add(NodeKind::Entry, span, Vec::new());
+        builder.add(NodeKind::Exit, span, Vec::new());
+
+        let open = builder.block(body, vec![(ENTRY, Edge::Next)]);
+        builder.link(open, EXIT);
+
+        for (node, label) in std::mem::take(&mut builder.gotos) {
+            let target = builder.labels[label];
+            builder.link(vec![(node, Edge::Jump)], target);
+        }
+
+        Cfg {
+            nodes: builder.nodes,
+        }
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut predecessors = vec![Vec::new(); self.nodes.len()];
+        for (index, node) in self.nodes.iter().enumerate() {
+            for &(successor, _) in &node.successors {
+                if !predecessors[successor].contains(&index) {
+                    predecessors[successor].push(index);
+                }
+            }
+        }
+        predecessors
+    }
+
+    // group the nodes into basic blocks. the entry and exit nodes get
+    // blocks of their own, ENTRY and EXIT again.
+    pub fn basic_blocks(&self) -> Vec<BasicBlock> {
+        let predecessors = self.predecessors();
+        let is_leader: Vec<bool> = (0..self.nodes.len())
+            .map(|node| {
+                let [predecessor] = predecessors[node][..] else {
+                    return true;
+                };
+                node == EXIT
+                    || predecessor == ENTRY
+                    || self.nodes[predecessor].successors.len() != 1
+                    || matches!(
+                        self.nodes[node].kind,
+                        NodeKind::Statement(Stmt {
+                            kind: StmtKind::Label(_),
+                            ..
+                        })
+                    )
+            })
+            .collect();
+
+        let mut blocks = Vec::new();
+        let mut block_of = vec![0; self.nodes.len()];
+        for leader in (0..self.nodes.len()).filter(|&node| is_leader[node]) {
+            let mut nodes = vec![leader];
+            let mut last = leader;
+            while let [(next, _)] = self.nodes[last].successors[..] {
+                if is_leader[next] {
+                    break;
+                }
+                nodes.push(next);
+                last = next;
+            }
+            for &node in &nodes {
+                block_of[node] = blocks.len();
+            }
+            blocks.push(BasicBlock {
+                nodes,
+                successors: Vec::new(),
+            });
+        }
+
+        for block in &mut blocks {
+            let last = *block.nodes.last().unwrap();
+            block.successors = self.nodes[last]
+                .successors
+                .iter()
+                .map(|&(node, edge)| (block_of[node], edge))
+                .collect();
+        }
+        blocks
+    }
+
+    // the basic blocks as a Graphviz cluster, names prefixed so several
+    // bodies can share a graph
+    pub fn write_dot(&self, out: &mut String, prefix: &str, title: &str) {
+        writeln!(out, "  subgraph cluster_{} {{", prefix).unwrap();
+        writeln!(out, "    label=\"{}\";", dot_escape(title)).unwrap();
+
+        let blocks = self.basic_blocks();
+        for (index, block) in blocks.iter().enumerate() {
+            let lines: String = block
+                .nodes
+                .iter()
+                .map(|&node| {
+                    let node = &self.nodes[node];
+                    match node.kind {
+                        NodeKind::Entry | NodeKind::Exit => describe(node.kind),
+                        _ => format!("{}: {}", node.span.line, describe(node.kind)),
+                    }
+                })
+                .map(|line| dot_escape(&line) + "\\l")
+                .collect();
+            writeln!(out, "    {}_{} [label=\"{}\"];", prefix, index, lines).unwrap();
+        }
+
+        for (index, block) in blocks.iter().enumerate() {
+            for (successor, edge) in &block.successors {
+                let attributes = match edge {
+                    Edge::Next => "",
+                    Edge::True => " [label=\"true\"]",
+                    Edge::False => " [label=\"false\"]",
+                    Edge::Jump => " [style=dashed]",
+                };
+                writeln!(
+                    out,
+                    "    {}_{} -> {}_{}{};",
+                    prefix, index, prefix, successor, attributes
+                )
+                .unwrap();
+            }
+        }
+        writeln!(out, "  }}").unwrap();
+    }
+}
+
+// Graphviz DOT for a program, a cluster for the top level and one per FUNCTION
+pub fn program_dot(program: &Program) -> String {
+    let mut out = String::new();
+    let title = program.module.as_deref().unwrap_or("main");
+    writeln!(out, "digraph {} {{", title).unwrap();
+    writeln!(out, "  node [shape=box, fontname=\"monospace\"];").unwrap();
+
+    Cfg::build(&program.body, Span { file: 0, line: 1 }).write_dot(&mut out, title, title);
+    for function in &program.functions {
+        Cfg::build(&function.body, function.span).write_dot(
+            &mut out,
+            &format!("{}_{}", title, function.name),
+            &format!("FUNCTION {}", function.name),
+        );
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// a node as the line of source it stands for
+pub fn describe(kind: NodeKind) -> String {
+    match kind {
+        NodeKind::Entry => "entry".into(),
+        NodeKind::Exit => "exit".into(),
+        NodeKind::Join(_) => "DO".into(),
+        NodeKind::Condition(stmt, condition) => match &stmt.kind {
+            StmtKind::If { .. } => format!("IF {} THEN", condition),
+            StmtKind::While { .. } => format!("WHILE {} REPEAT", condition),
+            StmtKind::Do {
+                condition: LoopCondition::Until(_),
+                ..
+            } => format!("LOOP UNTIL {}", condition),
+            _ => format!("LOOP WHILE {}", condition),
+        },
+        NodeKind::Select(_, subject) => format!("SELECT CASE {}", subject),
+        NodeKind::Case(case) => {
+            let items: Vec<String> = case
+                .items
+                .iter()
+                .map(|item| match item {
+                    CaseItem::Is(TokenType::EQEQ, value) => format!("IS = {}", value),
+                    CaseItem::Is(op, value) => {
+                        format!("IS {} {}", crate::ast::operator_text(op), value)
+                    }
+                    CaseItem::Range(low, high) => format!("{} TO {}", low, high),
+                    CaseItem::Value(value) => value.to_string(),
+                })
+                .collect();
+            format!("CASE {}", items.join(", "))
+        }
+        NodeKind::Statement(stmt) => match &stmt.kind {
+            StmtKind::Print {
+                using,
+                items,
+                newline,
+            } => {
+                let mut text = String::from("PRINT");
+                if let Some(picture) = using {
+                    text.push_str(&format!(" USING \"{}\";", picture));
+                }
+                let mut separator = " ";
+                for item in items {
+                    match item {
+                        PrintItem::Tab => {
+                            text.push(',');
+                            separator = " ";
+                            continue;
+                        }
+                        PrintItem::Text(value) => {
+                            text.push_str(&format!("{}\"{}\"", separator, value))
+                        }
+                        PrintItem::Value(value) => {
+                            text.push_str(&format!("{}{}", separator, value))
+                        }
+                    }
+                    separator = "; ";
+                }
+                if !newline && !matches!(items.last(), Some(PrintItem::Tab)) {
+                    text.push(';');
+                }
+                text
+            }
+            StmtKind::Let(name, value) => format!("LET {} = {}", name, value),
+            StmtKind::Input {
+                prompt, targets, ..
+            } => {
+                if prompt.is_empty() {
+                    format!("INPUT {}", targets.join(", "))
+                } else {
+                    format!("INPUT \"{}\"; {}", prompt, targets.join(", "))
+                }
+            }
+            StmtKind::Label(name) => format!("LABEL {}", name),
+            StmtKind::Goto(name) => format!("GOTO {}", name),
+            StmtKind::Break => "BREAK".into(),
+            StmtKind::Continue => "CONTINUE".into(),
+            StmtKind::Call(call) => format!("CALL {}", call),
+            StmtKind::Return(None) => "RETURN".into(),
+            StmtKind::Return(Some(value)) => format!("RETURN {}", value),
+            StmtKind::Import(name) => format!("IMPORT {}", name),
+            StmtKind::End => "END".into(),
+            StmtKind::Stop { message: None, .. } => "STOP".into(),
+            StmtKind::Stop {
+                message: Some(message),
+                ..
+            } => format!("STOP \"{}\"", message),
+            StmtKind::Exit(value) => format!("EXIT {}", value),
+            //compound statements are split into the nodes above
+            _ => String::new(),
+        },
+    }
+}
+
+impl<'a> Builder<'a> {
+    fn add(&mut self, kind: NodeKind<'a>, span: Span, open: Open) -> usize {
+        self.nodes.push(Node {
+            kind,
+            span,
+            successors: Vec::new(),
+        });
+        let node = self.nodes.len() - 1;
+        self.link(open, node);
+        node
+    }
+
+    fn link(&mut self, open: Open, target: usize) {
+        for (node, edge) in open {
+            let successors = &mut self.nodes[node].successors;
+            if !successors.contains(&(target, edge)) {
+                successors.push((target, edge));
+            }
+        }
+    }
+
+    fn block(&mut self, body: &'a [Stmt], mut open: Open) -> Open {
+        for stmt in body {
+            open = self.statement(stmt, open);
+        }
+        open
+    }
+
+    // add a statement reached by the open edges, giving back the edges
+    // leaving it for whatever follows
+    fn statement(&mut self, stmt: &'a Stmt, open: Open) -> Open {
+        match &stmt.kind {
+            StmtKind::If { condition, body } => {
+                let test = self.add(NodeKind::Condition(stmt, condition), stmt.span, open);
+                let mut open = self.block(body, vec![(test, Edge::True)]);
+                open.push((test, Edge::False));
+                open
+            }
+            StmtKind::While { condition, body } => {
+                let test = self.add(NodeKind::Condition(stmt, condition), stmt.span, open);
+                self.loops.push(Loop {
+                    continue_target: Some(test),
+                    continues: Vec::new(),
+                    breaks: Vec::new(),
+                });
+                let end = self.block(body, vec![(test, Edge::True)]);
+                self.link(end, test);
+
+                let mut open = self.loops.pop().unwrap().breaks;
+                open.push((test, Edge::False));
+                open
+            }
+            StmtKind::Do {
+                body,
+                condition,
+                end,
+            } => {
+                let head = self.add(NodeKind::Join(stmt), stmt.span, open);
+                self.loops.push(Loop {
+                    continue_target: None,
+                    continues: Vec::new(),
+                    breaks: Vec::new(),
+                });
+                let mut open = self.block(body, vec![(head, Edge::Next)]);
+                let lp = self.loops.pop().unwrap();
+                open.extend(lp.continues);
+
+                let mut after = lp.breaks;
+                match condition {
+                    LoopCondition::Forever => self.link(open, head),
+                    LoopCondition::Until(condition) => {
+                        let test = self.add(NodeKind::Condition(stmt, condition), *end, open);
+                        self.link(vec![(test, Edge::False)], head);
+                        after.push((test, Edge::True));
+                    }
+                    LoopCondition::While(condition) => {
+                        let test = self.add(NodeKind::Condition(stmt, condition), *end, open);
+                        self.link(vec![(test, Edge::True)], head);
+                        after.push((test, Edge::False));
+                    }
+                }
+                after
+            }
+            StmtKind::Select {
+                subject,
+                cases,
+                default,
+            } => {
+                let select = self.add(NodeKind::Select(stmt, subject), stmt.span, open);
+                let mut next = vec![(select, Edge::Next)];
+                let mut after = Vec::new();
+
+                for case in cases {
+                    let test = self.add(NodeKind::Case(case), case.span, next);
+                    after.extend(self.block(&case.body, vec![(test, Edge::True)]));
+                    next = vec![(test, Edge::False)];
+                }
+                match default {
+                    Some(body) => after.extend(self.block(body, next)),
+                    None => after.extend(next),
+                }
+                after
+            }
+            StmtKind::Label(name) => {
+                let node = self.add(NodeKind::Statement(stmt), stmt.span, open);
+                self.labels.insert(name, node);
+                vec![(node, Edge::Next)]
+            }
+            StmtKind::Goto(name) => {
+                let node = self.add(NodeKind::Statement(stmt), stmt.span, open);
+                self.gotos.push((node, name));
+                Vec::new()
+            }
+            StmtKind::Break => {
+                let node = self.add(NodeKind::Statement(stmt), stmt.span, open);
+                self.loops
+                    .last_mut()
+                    .unwrap()
+                    .breaks
+                    .push((node, Edge::Jump));
+                Vec::new()
+            }
+            StmtKind::Continue => {
+                let node = self.add(NodeKind::Statement(stmt), stmt.span, open);
+                match self.loops.last().unwrap().continue_target {
+                    Some(target) => self.link(vec![(node, Edge::Jump)], target),
+                    None => self
+                        .loops
+                        .last_mut()
+                        .unwrap()
+                        .continues
+                        .push((node, Edge::Jump)),
+                }
+                Vec::new()
+            }
+            StmtKind::Return(_) | StmtKind::End | StmtKind::Stop { .. } | StmtKind::Exit(_) => {
+                let node = self.add(NodeKind::Statement(stmt), stmt.span, open);
+                self.link(vec![(node, Edge::Jump)], EXIT);
+                Vec::new()
+            }
+            _ => {
+                let node = self.add(NodeKind::Statement(stmt), stmt.span, open);
+                vec![(node, Edge::Next)]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parse::Parser;
+    use std::path::PathBuf;
+
+    fn parse(source: &str) -> Program {
+        let lexer = Lexer::new(source.to_string(), PathBuf::from("test.teeny"));
+        Parser::new(lexer).program()
+    }
+
+    #[test]
+    fn dot_has_a_cluster_per_body() {
+        let program = parse(
+            "LET n = 3\nWHILE n > 0 REPEAT\nPRINT \"n\"\nLET n = n - 1\nENDWHILE\n\
+             FUNCTION f(x)\nIF x > 1 THEN\nRETURN x\nENDIF\nRETURN 0\nENDFUNCTION\n",
+        );
+        assert_eq!(
+            program_dot(&program),
+            r#"digraph main {
+  node [shape=box, fontname="monospace"];
+  subgraph cluster_main {
+    label="main";
+    main_0 [label="entry\l"];
+    main_1 [label="exit\l"];
+    main_2 [label="1: LET n = 3\l"];
+    main_3 [label="2: WHILE n > 0 REPEAT\l"];
+    main_4 [label="3: PRINT \"n\"\l4: LET n = n - 1\l"];
+    main_0 -> main_2;
+    main_2 -> main_3;
+    main_3 -> main_4 [label="true"];
+    main_3 -> main_1 [label="false"];
+    main_4 -> main_3;
+  }
+  subgraph cluster_main_f {
+    label="FUNCTION f";
+    main_f_0 [label="entry\l"];
+    main_f_1 [label="exit\l"];
+    main_f_2 [label="7: IF x > 1 THEN\l"];
+    main_f_3 [label="8: RETURN x\l"];
+    main_f_4 [label="10: RETURN 0\l"];
+    main_f_0 -> main_f_2;
+    main_f_2 -> main_f_3 [label="true"];
+    main_f_2 -> main_f_4 [label="false"];
+    main_f_3 -> main_f_1 [style=dashed];
+    main_f_4 -> main_f_1 [style=dashed];
+  }
+}
+"#
+        );
+    }
+}
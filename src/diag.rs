@@ -0,0 +1,111 @@
+use crate::source::{self, SourceFile};
+use crate::symbols::Span;
+use std::collections::HashSet;
+use strum::IntoEnumIterator;
+use strum_macros::{EnumIter, EnumString, IntoStaticStr};
+
+// things worth pointing out that still compile, each with a -W flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, EnumString, IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Warning {
+    //LABEL no GOTO jumps to
+    UnusedLabel,
+    //statements no path from the start reaches
+    UnreachableCode,
+    //WHILE whose condition is constant and false
+    WhileFalse,
+    //variable assigned but never read
+    UnreadVariable,
+    //LET whose value is always overwritten before it is read
+    OverwrittenValue,
+    //variables whose names differ only in case, likely a typo
+    CaseMismatch,
+}
+
+pub struct Diagnostics {
+    enabled: HashSet<Warning>,
+    //-Werror, fail the compile if anything was reported
+    pub warnings_are_errors: bool,
+    //every warning reported, as printed
+    reported: Vec<String>,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Diagnostics {
+        Diagnostics::new()
+    }
+}
+
+impl Diagnostics {
+    // every warning starts out enabled
+    pub fn new() -> Diagnostics {
+        Diagnostics {
+            enabled: Warning::iter().collect(),
+            warnings_are_errors: false,
+            reported: Vec::new(),
+        }
+    }
+
+    // handle -Wname, -Wno-name and -Werror, false if flag is none of them
+    pub fn flag(&mut self, flag: &str) -> bool {
+        let Some(name) = flag.strip_prefix("-W") else {
+            return false;
+        };
+        if name == "error" {
+            self.warnings_are_errors = true;
+            return true;
+        }
+        let (name, enable) = match name.strip_prefix("no-") {
+            Some(name) => (name, false),
+            None => (name, true),
+        };
+        let Ok(warning) = name.parse::<Warning>() else {
+            return false;
+        };
+        if enable {
+            self.enabled.insert(warning);
+        } else {
+            self.enabled.remove(&warning);
+        }
+        true
+    }
+
+    pub fn warn(&mut self, files: &[SourceFile], warning: Warning, span: Span, message: String) {
+        if !self.enabled.contains(&warning) {
+            return;
+        }
+        //the flag goes on the first line, before any notes
+        let name: &str = warning.into();
+        let (first, notes) = message.split_once('\n').unwrap_or((&message, ""));
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!("\n{}", notes)
+        };
+        let text = format!(
+            "Warning in {} on line {}. {} [-W{}]{}{}",
+            files[span.file].path.display(),
+            span.line,
+            first,
+            name,
+            notes,
+            source::include_chain(files, span.file)
+        );
+        eprintln!("{}", text);
+        self.reported.push(text);
+    }
+
+    pub fn reported(&self) -> &[String] {
+        &self.reported
+    }
+
+    // with -Werror, stop here if anything was reported
+    pub fn finish(&self) {
+        if self.warnings_are_errors && !self.reported.is_empty() {
+            panic!(
+                "Error: {} warning(s) treated as errors because of -Werror",
+                self.reported.len()
+            );
+        }
+    }
+}
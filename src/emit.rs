@@ -1,37 +1,795 @@
-use std::{fs::File, io::Write};
+use crate::backend::{
+    self, using_format, write_if_changed, Backend, Generator, InputPolicy, ModuleFiles,
+};
+use crate::ir::{BinOp, BlockId, Function, FunctionKind, Module, Operand, Type};
+use crate::variables::Variables;
+use std::collections::HashSet;
+use std::path::Path;
+
+// state shared by the runtime helpers of every translation unit, defined by
+// the main program and borrowed by modules. tt_column tracks the output
+// column so that "," in PRINT can pad to the next 14 character print zone,
+// tt_eof is what the EOF builtin reads.
+const RUNTIME_STATE: &str = "int tt_column = 0;\nint tt_eof = 0;\n";
+const MODULE_RUNTIME_STATE: &str = "extern int tt_column;\nextern int tt_eof;\n";
+
+// C helpers used by PRINT
+const PRINT_RUNTIME: &str = r#"
+
+static void tt_print_string(const char *text) {
+    tt_column += printf("%s", text);
+}
+
+// nan without its sign, which depends on how it was made and which printf
+// would write as "-nan"
+static float tt_unsigned_nan(float n) {
+    if (n != n) {
+        union {
+            float number;
+            unsigned bits;
+        } nan = {n};
+        nan.bits &= 0x7fffffff;
+        return nan.number;
+    }
+    return n;
+}
+
+static void tt_print_number(float n) {
+    char buf[32];
+    n = tt_unsigned_nan(n);
+    // whole numbers a long can hold print without a fraction. LONG_MIN is
+    // a power of two, so it and -LONG_MIN are exact floats and every float
+    // from one up to the other converts to a long
+    if (n >= (float)LONG_MIN && n < -(float)LONG_MIN && n == (float)(long)n) {
+        snprintf(buf, sizeof buf, "%ld", (long)n);
+    } else {
+        // shortest representation that reads back as the same float
+        for (int precision = 1; precision <= 9; precision++) {
+            snprintf(buf, sizeof buf, "%.*g", precision, n);
+            if (strtof(buf, NULL) == n) {
+                break;
+            }
+        }
+    }
+    tt_print_string(buf);
+}
+
+static void tt_print_using(const char *format, float n) {
+    tt_column += printf(format, tt_unsigned_nan(n));
+}
+
+static void tt_print_tab(void) {
+    do {
+        putchar(' ');
+        tt_column++;
+    } while (tt_column % 14 != 0);
+}
+
+static void tt_print_newline(void) {
+    putchar('\n');
+    tt_column = 0;
+}
+"#;
+
+// C helper used by EXIT. a cast is undefined for nan and for numbers out
+// of range, which go to 0 and the nearest int instead
+const INT_RUNTIME: &str = r#"
+static int tt_int(float n) {
+    if (n != n) {
+        return 0;
+    }
+    if (n <= (float)INT_MIN) {
+        return INT_MIN;
+    }
+    if (n >= -(float)INT_MIN) {
+        return INT_MAX;
+    }
+    return (int)n;
+}
+"#;
+
+// C helper used by INPUT, with the handling of unreadable input spliced in
+macro_rules! input_runtime {
+    ($on_invalid:literal) => {
+        concat!(
+            r#"
+static void tt_input(float *target, const char *prompt, const char *location) {
+    for (;;) {
+        tt_print_string(prompt);
+        fflush(stdout);
+        int read = scanf("%f", target);
+        tt_column = 0;
+        if (read == 1) {
+            return;
+        }
+        *target = 0;
+        if (read == EOF) {
+            tt_eof = 1;
+            return;
+        }
+"#,
+            $on_invalid,
+            r#"    }
+}
+"#
+        )
+    };
+}
+
+// the C helper for INPUT, for what it does with text that is not a number
+fn input_runtime(policy: InputPolicy) -> &'static str {
+    match policy {
+        InputPolicy::Zero => input_runtime!(
+            r#"        scanf("%*s");
+        return;
+"#
+        ),
+        InputPolicy::Reprompt => input_runtime!(
+            r#"        int c;
+        while ((c = getchar()) != '\n' && c != EOF) {
+        }
+        puts("?Redo from start");
+"#
+        ),
+        InputPolicy::Error => input_runtime!(
+            r#"        fprintf(stderr, "Invalid input at %s\n", location);
+        exit(1);
+"#
+        ),
+    }
+}
+
 pub struct Emitter {
-    code: String,
+    //includes
+    prelude: String,
+    //runtime helpers the program uses
+    runtime_code: String,
+    //globals and function prototypes
+    declarations: String,
+    //finished function definitions
+    functions: String,
+    //locals and body of the function being emitted
     header: String,
+    code: String,
+    //how deep the body's statements are nested
+    indent: usize,
+    //prototypes for the .h file of a module
+    interface: String,
     full_path: String,
+    module: Option<String>,
+    runtime: HashSet<&'static str>,
+    //modules whose header is already included
+    imported: HashSet<String>,
+    pub input_policy: InputPolicy,
 }
 
 impl Emitter {
     pub fn new(full_path: String) -> Emitter {
-        Emitter {
-            code: String::new(),
+        let mut emitter = Emitter {
+            prelude: String::new(),
+            runtime_code: String::new(),
+            declarations: String::new(),
+            functions: String::new(),
             header: String::new(),
+            code: String::new(),
+            indent: 0,
+            interface: String::new(),
             full_path,
-        }
+            module: None,
+            runtime: HashSet::new(),
+            imported: HashSet::new(),
+            input_policy: InputPolicy::Zero,
+        };
+        emitter.prelude_line("#include <stdio.h>".into());
+        emitter.prelude_line("#include <stdlib.h>".into());
+        emitter.prelude_line("#include <limits.h>".into());
+
+        emitter
     }
 
-    pub fn emit(&mut self, code: String) {
-        self.code.push_str(&code);
+    pub fn full_path(&self) -> &str {
+        &self.full_path
     }
 
-    pub fn emit_line(&mut self, code: String) {
+    fn emit_line(&mut self, code: String) {
+        self.code.push_str(&"    ".repeat(self.indent));
         self.code.push_str(&code);
         self.code.push('\n');
     }
 
-    pub fn header_line(&mut self, code: String) {
+    fn header_line(&mut self, code: String) {
+        self.header.push_str("    ");
         self.header.push_str(&code);
         self.header.push('\n');
     }
 
-    pub fn write_file(&mut self) {
-        let mut file = File::create(self.full_path.clone()).unwrap();
-        let value = self.header.clone() + self.code.clone().as_str();
+    fn prelude_line(&mut self, code: String) {
+        self.prelude.push_str(&code);
+        self.prelude.push('\n');
+    }
+
+    fn declaration_line(&mut self, code: String) {
+        self.declarations.push_str(&code);
+        self.declarations.push('\n');
+    }
+
+    fn interface_line(&mut self, code: String) {
+        self.interface.push_str(&code);
+        self.interface.push('\n');
+    }
+
+    // add a block of runtime helpers to the prelude, only the first time
+    fn require_runtime(&mut self, runtime: &'static str) {
+        if self.runtime.insert(runtime) {
+            self.runtime_code.push_str(runtime);
+        }
+    }
+
+    fn require_input_runtime(&mut self) {
+        //tt_input prints its prompt through the print helpers
+        self.require_runtime(PRINT_RUNTIME);
+        self.require_runtime(input_runtime(self.input_policy));
+    }
+}
+
+// C for a module's IR, written by the hooks generate() calls
+impl Generator for Emitter {
+    fn reserved(&self) -> &'static [&'static str] {
+        RESERVED
+    }
+
+    fn start_program(&mut self, module: &Module, variables: &[Variables]) {
+        for name in &module.globals {
+            self.declaration_line(format!("static {} {};", c_type(Type::Float), c_name(name)));
+        }
+
+        for (function, variables) in module.functions.iter().zip(variables) {
+            if let FunctionKind::Function { exported } = function.kind {
+                let signature = c_signature(&module.name, function, variables);
+                if exported {
+                    self.interface_line(format!("{};", signature));
+                } else {
+                    self.declaration_line(format!("static {};", signature));
+                }
+            }
+        }
+    }
+
+    fn start_function(&mut self, function: &Function, variables: &Variables) {
+        self.indent = 1;
+        for (name, ty) in variables.locals() {
+            self.header_line(format!("{} {};", c_type(ty), name));
+        }
+
+        if function.kind == FunctionKind::Init {
+            //the module's statements run once, when it is first imported
+            self.header_line("static int initialized = 0;".into());
+            self.start_if("initialized".into());
+            self.ret(None);
+            self.end_if();
+            self.emit_line("initialized = 1;".into());
+        }
+    }
+
+    // wrap the current body up as a function, ready for the next one
+    fn end_function(&mut self, function: &Function, variables: &Variables) {
+        let signature = match function.kind {
+            FunctionKind::Main => "int main(void)".into(),
+            FunctionKind::Init => {
+                let signature = format!("void {}__init(void)", self.module.as_ref().unwrap());
+                self.interface_line(format!("{};", signature));
+                signature
+            }
+            FunctionKind::Function { exported: true } => {
+                c_signature(&self.module, function, variables)
+            }
+            FunctionKind::Function { exported: false } => {
+                format!("static {}", c_signature(&self.module, function, variables))
+            }
+        };
+        let header = std::mem::take(&mut self.header);
+        let code = std::mem::take(&mut self.code);
+        self.functions
+            .push_str(&format!("{} {{\n{}{}}}\n", signature, header, code));
+    }
+
+    fn constant(&mut self, constant: &Operand) -> String {
+        c_constant(constant)
+    }
+
+    fn binary(&mut self, op: BinOp, left: String, right: String) -> String {
+        let precedence = c_precedence(op);
+        //comparisons of comparisons get brackets, even where C doesn't
+        //need them
+        let comparison = precedence < c_precedence(BinOp::Add);
+        let left = if c_expression_precedence(&left) < precedence
+            || comparison && c_expression_precedence(&left) <= c_precedence(BinOp::Lt)
+        {
+            format!("({})", left)
+        } else {
+            left
+        };
+        let right = if c_expression_precedence(&right) <= precedence {
+            format!("({})", right)
+        } else {
+            right
+        };
+        format!("{} {} {}", left, c_operator(op), right)
+    }
+
+    fn neg(&mut self, operand: String) -> String {
+        //keep "- -1" from turning into "--1"
+        if operand.starts_with('-') || c_expression_precedence(&operand) < UNARY {
+            format!("-({})", operand)
+        } else {
+            format!("-{}", operand)
+        }
+    }
+
+    fn not(&mut self, condition: String) -> String {
+        format!("!{}", c_unary_operand(condition))
+    }
+
+    fn and(&mut self, first: String, second: String) -> String {
+        let bracket = |operand: String| match c_expression_precedence(&operand) < AND {
+            true => format!("({})", operand),
+            false => operand,
+        };
+        format!("{} && {}", bracket(first), bracket(second))
+    }
+
+    fn or(&mut self, first: String, second: String) -> String {
+        //&& inside || gets brackets too, the way cc -Wall would like
+        let bracket = |operand: String| match c_expression_precedence(&operand) {
+            OR => operand,
+            precedence if precedence <= AND => format!("({})", operand),
+            _ => operand,
+        };
+        format!("{} || {}", bracket(first), bracket(second))
+    }
+
+    fn int_to_float(&mut self, operand: String) -> String {
+        format!("(float){}", c_unary_operand(operand))
+    }
+
+    fn float_to_int(&mut self, operand: String) -> String {
+        self.require_runtime(INT_RUNTIME);
+        format!("tt_int({})", operand)
+    }
+
+    fn load(&mut self, name: &str) -> String {
+        c_name(name)
+    }
+
+    fn call(&mut self, module: &Option<String>, name: &str, args: Vec<String>) -> String {
+        format!("{}({})", c_function_name(module, name), args.join(", "))
+    }
+
+    fn eof(&mut self) -> String {
+        self.require_input_runtime();
+        "tt_eof".into()
+    }
+
+    fn assign(&mut self, variable: &str, value: String) {
+        self.emit_line(format!("{} = {};", variable, value));
+    }
+
+    fn evaluate(&mut self, value: String) {
+        self.emit_line(format!("{};", value));
+    }
+
+    fn store(&mut self, name: &str, value: String) {
+        self.emit_line(format!("{} = {};", c_name(name), value));
+    }
+
+    fn input(&mut self, variable: &str, prompt: String, location: &str) {
+        self.require_input_runtime();
+        self.emit_line(format!(
+            "tt_input(&{}, {}, \"{}\");",
+            variable, prompt, location
+        ));
+    }
+
+    fn print_string(&mut self, text: String) {
+        self.require_runtime(PRINT_RUNTIME);
+        self.emit_line(format!("tt_print_string({});", text));
+    }
+
+    fn print_number(&mut self, value: String) {
+        self.require_runtime(PRINT_RUNTIME);
+        self.emit_line(format!("tt_print_number({});", value));
+    }
+
+    fn print_using(&mut self, picture: &str, value: String) {
+        self.require_runtime(PRINT_RUNTIME);
+        self.emit_line(format!(
+            "tt_print_using(\"{}\", {});",
+            using_format(picture),
+            value
+        ));
+    }
+
+    fn print_tab(&mut self) {
+        self.require_runtime(PRINT_RUNTIME);
+        self.emit_line("tt_print_tab();".into());
+    }
+
+    fn print_newline(&mut self) {
+        self.require_runtime(PRINT_RUNTIME);
+        self.emit_line("tt_print_newline();".into());
+    }
+
+    fn import(&mut self, module: &str) {
+        if self.imported.insert(module.into()) {
+            self.prelude_line(format!("#include \"{}.h\"", module));
+        }
+        self.emit_line(format!("{}__init();", module));
+    }
+
+    fn start_if(&mut self, condition: String) {
+        self.emit_line(format!("if ({}) {{", c_condition(condition)));
+        self.indent += 1;
+    }
+
+    fn start_else_if(&mut self, condition: String) {
+        self.indent -= 1;
+        self.emit_line(format!("}} else if ({}) {{", c_condition(condition)));
+        self.indent += 1;
+    }
+
+    fn start_else(&mut self) {
+        self.indent -= 1;
+        self.emit_line("} else {".into());
+        self.indent += 1;
+    }
+
+    fn end_if(&mut self) {
+        self.indent -= 1;
+        self.emit_line("}".into());
+    }
+
+    fn start_loop(&mut self) {
+        self.emit_line("for (;;) {".into());
+        self.indent += 1;
+    }
+
+    fn start_while(&mut self, condition: String) {
+        self.emit_line(format!("while ({}) {{", c_condition(condition)));
+        self.indent += 1;
+    }
+
+    fn end_loop(&mut self) {
+        self.indent -= 1;
+        self.emit_line("}".into());
+    }
+
+    fn start_do(&mut self, _condition: String) {
+        self.emit_line("do {".into());
+        self.indent += 1;
+    }
+
+    fn end_do(&mut self, condition: String) {
+        self.indent -= 1;
+        self.emit_line(format!("}} while ({});", c_condition(condition)));
+    }
+
+    fn break_loop(&mut self) {
+        self.emit_line("break;".into());
+    }
+
+    fn continue_loop(&mut self) {
+        self.emit_line("continue;".into());
+    }
+
+    fn label(&mut self, block: BlockId) {
+        self.emit_line(format!("block_{}: ;", block));
+    }
+
+    fn goto(&mut self, block: BlockId) {
+        self.emit_line(format!("goto block_{};", block));
+    }
+
+    fn ret(&mut self, value: Option<String>) {
+        match value {
+            Some(value) => self.emit_line(format!("return {};", value)),
+            None => self.emit_line("return;".into()),
+        }
+    }
+
+    fn exit(&mut self, status: String) {
+        self.emit_line(format!("exit({});", status));
+    }
+
+    fn stop(&mut self, message: &str) {
+        self.emit_line(format!("fputs(\"{}\\n\", stderr);", message));
+        self.emit_line("exit(1);".into());
+    }
+}
+
+// a translation unit per module
+impl ModuleFiles for Emitter {
+    fn for_module(&self, name: &str) -> Emitter {
+        let full_path = Path::new(&self.full_path).with_file_name(format!("{}.c", name));
+        let mut emitter = Emitter::new(full_path.display().to_string());
+        emitter.module = Some(name.into());
+        emitter.input_policy = self.input_policy;
+        emitter.prelude_line(format!("#include \"{}.h\"", name));
+        emitter
+    }
+
+    fn program(&mut self, module: &Module) {
+        backend::generate(self, module);
+    }
+
+    fn write_file(&mut self) -> String {
+        let state = match self.module {
+            Some(_) => MODULE_RUNTIME_STATE,
+            None => RUNTIME_STATE,
+        };
+        let value = self.prelude.clone()
+            + state
+            + &self.runtime_code
+            + &self.declarations
+            + &self.functions;
+        write_if_changed(Path::new(&self.full_path), &value);
+
+        if let Some(module) = &self.module {
+            let guard = format!("TT_MODULE_{}_H", module.to_uppercase());
+            let value = format!(
+                "#ifndef {}\n#define {}\n{}#endif\n",
+                guard, guard, self.interface
+            );
+            write_if_changed(&Path::new(&self.full_path).with_extension("h"), &value);
+        }
+        self.full_path.clone()
+    }
+}
+
+impl Backend for Emitter {
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String> {
+        backend::emit_files(self, modules, program)
+    }
+
+    //a program with modules is more than one translation unit
+    fn finish(&self, files: &[String]) -> Option<String> {
+        (files.len() > 1).then(|| format!("Build with: cc {}", files.join(" ")))
+    }
+}
+
+fn c_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "float",
+        Type::Int => "int",
+        Type::String => "const char *",
+    }
+}
+
+// names C or the headers it includes already use, and names the code in a
+// function uses, that a variable can't take. teeny tiny names have no
+// underscores, so none of the ones with one can clash.
+const RESERVED: &[&str] = &[
+    "auto",
+    "break",
+    "case",
+    "char",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+    "main",
+    "exit",
+    "fputs",
+    "stderr",
+    "stdin",
+    "stdout",
+    "initialized",
+    "NULL",
+    "EOF",
+    "BUFSIZ",
+    "linux",
+    "unix",
+];
+
+// C name of a global, out of the way of C's own names like a variable's
+fn c_name(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.into()
+    }
+}
+
+// C name of a FUNCTION, prefixed with its module so modules never clash.
+// teeny tiny names have no underscores, so these can't hit a variable.
+fn c_function_name(module: &Option<String>, name: &str) -> String {
+    match module {
+        Some(module) => format!("{}__{}", module, name),
+        None => format!("tt_fn_{}", name),
+    }
+}
+
+fn c_signature(module: &Option<String>, function: &Function, variables: &Variables) -> String {
+    let parameters: Vec<String> = function
+        .parameters
+        .iter()
+        .map(|parameter| {
+            format!(
+                "{} {}",
+                c_type(function.types[parameter.0]),
+                variables.name(*parameter).unwrap()
+            )
+        })
+        .collect();
+    format!(
+        "{} {}({})",
+        function.returns.map_or("void", c_type),
+        c_function_name(module, &function.name),
+        if parameters.is_empty() {
+            "void".into()
+        } else {
+            parameters.join(", ")
+        }
+    )
+}
+
+// how tightly C binds each operator, higher is tighter
+fn c_precedence(op: BinOp) -> u8 {
+    match op {
+        BinOp::Mul | BinOp::Div => 13,
+        BinOp::Add | BinOp::Sub => 12,
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 10,
+        BinOp::Eq | BinOp::Ne => 9,
+    }
+}
+
+const AND: u8 = 5;
+const OR: u8 = 4;
+//casts, negation and !
+const UNARY: u8 = 14;
+//names, constants, calls and anything in brackets
+const PRIMARY: u8 = 15;
+
+// the precedence of the loosest operator outside brackets in an expression
+// the hooks wrote, where binary operators have a space each side
+fn c_expression_precedence(expression: &str) -> u8 {
+    let mut depth = 0;
+    let mut lowest = PRIMARY;
+    let bytes = expression.as_bytes();
+    for (index, &byte) in bytes.iter().enumerate() {
+        match byte {
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            b' ' if depth == 0 => {
+                let operator = expression[index + 1..].split(' ').next().unwrap();
+                let op = match operator {
+                    "&&" => {
+                        lowest = lowest.min(AND);
+                        continue;
+                    }
+                    "||" => {
+                        lowest = lowest.min(OR);
+                        continue;
+                    }
+                    "*" => BinOp::Mul,
+                    "/" => BinOp::Div,
+                    "+" => BinOp::Add,
+                    "-" => BinOp::Sub,
+                    "<" => BinOp::Lt,
+                    "<=" => BinOp::Le,
+                    ">" => BinOp::Gt,
+                    ">=" => BinOp::Ge,
+                    "==" => BinOp::Eq,
+                    "!=" => BinOp::Ne,
+                    _ => continue,
+                };
+                lowest = lowest.min(c_precedence(op));
+            }
+            _ => {}
+        }
+    }
+    if lowest == PRIMARY && (expression.starts_with('-') || expression.starts_with('!')) {
+        //a cast starts with '(' too, but binds as tightly as - does
+        return UNARY;
+    }
+    lowest
+}
+
+// an operand for a cast, - or !
+fn c_unary_operand(operand: String) -> String {
+    if c_expression_precedence(&operand) < UNARY {
+        format!("({})", operand)
+    } else {
+        operand
+    }
+}
+
+// the condition of an if or a loop, which has brackets of its own
+fn c_condition(condition: String) -> String {
+    match condition
+        .strip_prefix('(')
+        .and_then(|inner| inner.strip_suffix(')'))
+    {
+        Some(inner) if c_expression_precedence(&condition) == PRIMARY && balanced(inner) => {
+            inner.into()
+        }
+        _ => condition,
+    }
+}
+
+// true when every bracket in the text closes the one before it
+fn balanced(text: &str) -> bool {
+    let mut depth = 0;
+    for byte in text.bytes() {
+        match byte {
+            b'(' => depth += 1,
+            b')' if depth == 0 => return false,
+            b')' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+fn c_operator(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        BinOp::Ge => ">=",
+    }
+}
+
+fn c_constant(constant: &Operand) -> String {
+    match constant {
+        Operand::Value(_) => unreachable!("{} is not a constant", constant),
+        Operand::Float(value) => c_float_literal(*value),
+        Operand::Int(value) => value.to_string(),
+        Operand::String(text) => format!("\"{}\"", text),
+    }
+}
 
-        file.write_all(value.as_bytes()).unwrap();
+// C float constant, so 7/2 divides as floats rather than ints
+fn c_float_literal(value: f32) -> String {
+    if value.is_nan() {
+        "(0.0f / 0.0f)".into()
+    } else if value.is_infinite() && value > 0.0 {
+        "(1.0f / 0.0f)".into()
+    } else if value.is_infinite() {
+        "(-1.0f / 0.0f)".into()
+    } else {
+        //{:?} always has a '.' or an exponent, and reads back as the same f32
+        format!("{:?}f", value)
     }
 }
@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// three-address code in SSA form. lower.rs builds it from a Program,
+// passes.rs rewrites it and the backends turn it into their own output.
+// each value is assigned exactly once, where control flow joins a phi
+// picks the value for the edge that was taken.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Float,
+    //comparison results, EOF and exit codes
+    Int,
+    String,
+}
+
+// a value defined by an instruction or a parameter, an index into
+// Function::types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub usize);
+
+// blocks are indexes into Function::blocks, the entry block is 0
+pub type BlockId = usize;
+
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Value(Value),
+    Float(f32),
+    Int(i32),
+    String(String),
+}
+
+// constants are the same only if they have the same bits. 0.0 and -0.0
+// print and divide differently, so == on the f32 would merge them.
+impl PartialEq for Operand {
+    fn eq(&self, other: &Operand) -> bool {
+        match (self, other) {
+            (Operand::Value(a), Operand::Value(b)) => a == b,
+            (Operand::Float(a), Operand::Float(b)) => a.to_bits() == b.to_bits(),
+            (Operand::Int(a), Operand::Int(b)) => a == b,
+            (Operand::String(a), Operand::String(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    //comparisons take floats and give an int, 1 or 0
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Copy(Operand),
+    Neg(Operand),
+    Binary(BinOp, Operand, Operand),
+    IntToFloat(Operand),
+    //truncates like a C cast, but saturates where the cast is undefined:
+    //nan is 0 and numbers out of range the nearest int, like Rust's as
+    FloatToInt(Operand),
+    //first in its block, one operand per predecessor
+    Phi(Vec<(BlockId, Operand)>),
+    //variables that live in memory, globals the FUNCTIONs share
+    Load(String),
+    Store(String, Operand),
+    //FUNCTION call, with the module it lives in (None for this program)
+    Call(Option<String>, String, Vec<Operand>),
+    //EOF builtin
+    Eof,
+    //reads a number, showing prompt first. location is for runtime messages.
+    Input { prompt: Operand, location: String },
+    PrintString(Operand),
+    PrintNumber(Operand),
+    //USING picture, like "###.##"
+    PrintUsing(String, Operand),
+    //"," in PRINT, pads to the next print zone
+    PrintTab,
+    PrintNewline,
+    //runs a module's top level statements, the first time only
+    Import(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Inst {
+    pub result: Option<Value>,
+    pub op: Op,
+}
+
+#[derive(Debug, Clone)]
+pub enum Terminator {
+    Jump(BlockId),
+    //int condition, taken when not 0
+    Branch(Operand, BlockId, BlockId),
+    Return(Option<Operand>),
+    //END and EXIT, stop the program with an int status
+    Exit(Operand),
+    //STOP, the message goes to stderr and the status is 1
+    Stop(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    //the main program's statements
+    Main,
+    //a module's top level statements
+    Init,
+    Function { exported: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub kind: FunctionKind,
+    pub parameters: Vec<Value>,
+    //None for a module's init
+    pub returns: Option<Type>,
+    pub blocks: Vec<Block>,
+    //type of every value, indexed by Value
+    pub types: Vec<Type>,
+    //the source variable a value holds, for backends that print code
+    pub names: HashMap<Value, String>,
+}
+
+// one Program, the main program or a MODULE
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub name: Option<String>,
+    //variables kept in memory because FUNCTIONs share them
+    pub globals: Vec<String>,
+    pub functions: Vec<Function>,
+}
+
+impl Op {
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Op::Copy(operand)
+            | Op::Neg(operand)
+            | Op::IntToFloat(operand)
+            | Op::FloatToInt(operand)
+            | Op::Store(_, operand)
+            | Op::Input {
+                prompt: operand, ..
+            }
+            | Op::PrintString(operand)
+            | Op::PrintNumber(operand)
+            | Op::PrintUsing(_, operand) => vec![operand],
+            Op::Binary(_, left, right) => vec![left, right],
+            Op::Phi(incoming) => incoming.iter().map(|(_, operand)| operand).collect(),
+            Op::Call(_, _, args) => args.iter().collect(),
+            Op::Load(_) | Op::Eof | Op::PrintTab | Op::PrintNewline | Op::Import(_) => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Op::Copy(operand)
+            | Op::Neg(operand)
+            | Op::IntToFloat(operand)
+            | Op::FloatToInt(operand)
+            | Op::Store(_, operand)
+            | Op::Input {
+                prompt: operand, ..
+            }
+            | Op::PrintString(operand)
+            | Op::PrintNumber(operand)
+            | Op::PrintUsing(_, operand) => vec![operand],
+            Op::Binary(_, left, right) => vec![left, right],
+            Op::Phi(incoming) => incoming.iter_mut().map(|(_, operand)| operand).collect(),
+            Op::Call(_, _, args) => args.iter_mut().collect(),
+            Op::Load(_) | Op::Eof | Op::PrintTab | Op::PrintNewline | Op::Import(_) => Vec::new(),
+        }
+    }
+
+    // no side effects and the same result for the same operands, so it can
+    // be moved, shared or dropped freely. float math never traps.
+    pub fn is_pure(&self) -> bool {
+        matches!(
+            self,
+            Op::Copy(_) | Op::Neg(_) | Op::Binary(..) | Op::IntToFloat(_) | Op::FloatToInt(_)
+        )
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(_, then, otherwise) => vec![*then, *otherwise],
+            Terminator::Return(_) | Terminator::Exit(_) | Terminator::Stop(_) => Vec::new(),
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch(_, then, otherwise) => vec![then, otherwise],
+            Terminator::Return(_) | Terminator::Exit(_) | Terminator::Stop(_) => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::Branch(condition, ..) => vec![condition],
+            Terminator::Return(Some(value)) | Terminator::Exit(value) => vec![value],
+            Terminator::Jump(_) | Terminator::Return(None) | Terminator::Stop(_) => Vec::new(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Terminator::Branch(condition, ..) => vec![condition],
+            Terminator::Return(Some(value)) | Terminator::Exit(value) => vec![value],
+            Terminator::Jump(_) | Terminator::Return(None) | Terminator::Stop(_) => Vec::new(),
+        }
+    }
+}
+
+impl Function {
+    pub fn new_value(&mut self, ty: Type) -> Value {
+        self.types.push(ty);
+        Value(self.types.len() - 1)
+    }
+
+    pub fn operand_type(&self, operand: &Operand) -> Type {
+        match operand {
+            Operand::Value(value) => self.types[value.0],
+            Operand::Float(_) => Type::Float,
+            Operand::Int(_) => Type::Int,
+            Operand::String(_) => Type::String,
+        }
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (block, data) in self.blocks.iter().enumerate() {
+            for successor in data.terminator.successors() {
+                if !predecessors[successor].contains(&block) {
+                    predecessors[successor].push(block);
+                }
+            }
+        }
+        predecessors
+    }
+
+    // blocks reachable from the entry, each before its successors except
+    // along back edges. the first successor of a branch is walked last, so
+    // it ends up straight after the branch where it can.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        //(block, successors already pushed)
+        let mut stack = vec![(0, false)];
+        while let Some((block, done)) = stack.pop() {
+            if done {
+                order.push(block);
+                continue;
+            }
+            if visited[block] {
+                continue;
+            }
+            visited[block] = true;
+            stack.push((block, true));
+            for successor in self.blocks[block].terminator.successors() {
+                if !visited[successor] {
+                    stack.push((successor, false));
+                }
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    // immediate dominator of every reachable block, the entry is its own.
+    // the iterative algorithm of Cooper, Harvey and Kennedy.
+    pub fn dominators(&self) -> Vec<Option<BlockId>> {
+        let order = self.reverse_postorder();
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (index, &block) in order.iter().enumerate() {
+            position[block] = index;
+        }
+        let predecessors = self.predecessors();
+        let mut idom = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order[1..] {
+                let mut new = None;
+                for &predecessor in &predecessors[block] {
+                    if idom[predecessor].is_none() {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => predecessor,
+                        Some(mut other) => {
+                            let mut finger = predecessor;
+                            while finger != other {
+                                while position[finger] > position[other] {
+                                    finger = idom[finger].unwrap();
+                                }
+                                while position[other] > position[finger] {
+                                    other = idom[other].unwrap();
+                                }
+                            }
+                            finger
+                        }
+                    });
+                }
+                if idom[block] != new {
+                    idom[block] = new;
+                    changed = true;
+                }
+            }
+        }
+        idom
+    }
+
+    // children of each block in the dominator tree
+    pub fn dominator_tree(&self) -> Vec<Vec<BlockId>> {
+        let mut children = vec![Vec::new(); self.blocks.len()];
+        for (block, idom) in self.dominators().into_iter().enumerate() {
+            if let Some(idom) = idom.filter(|&idom| idom != block) {
+                children[idom].push(block);
+            }
+        }
+        children
+    }
+
+    // drop blocks nothing reaches and number the rest in reverse
+    // postorder, so most jumps are to the next block
+    pub fn order_blocks(&mut self) {
+        let order = self.reverse_postorder();
+        let renumber: HashMap<BlockId, BlockId> = order
+            .iter()
+            .enumerate()
+            .map(|(new, &old)| (old, new))
+            .collect();
+
+        let mut blocks: Vec<Option<Block>> = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(Some)
+            .collect();
+        for old in order {
+            let mut block = blocks[old].take().unwrap();
+            for target in block.terminator.successors_mut() {
+                *target = renumber[target];
+            }
+            for inst in &mut block.insts {
+                if let Op::Phi(incoming) = &mut inst.op {
+                    incoming.retain(|(predecessor, _)| renumber.contains_key(predecessor));
+                    for (predecessor, _) in incoming.iter_mut() {
+                        *predecessor = renumber[predecessor];
+                    }
+                    incoming.sort_by_key(|(predecessor, _)| *predecessor);
+                }
+            }
+            self.blocks.push(block);
+        }
+    }
+
+    // number values in the order they are defined, parameters first, so
+    // the text reads top to bottom once passes have moved things around
+    pub fn number_values(&mut self) {
+        let mut renumber = HashMap::new();
+        let defined = self.parameters.iter().copied().chain(
+            self.blocks
+                .iter()
+                .flat_map(|block| block.insts.iter().filter_map(|inst| inst.result)),
+        );
+        for value in defined {
+            renumber.insert(value, Value(renumber.len()));
+        }
+
+        let mut types = vec![Type::Float; renumber.len()];
+        for (old, new) in &renumber {
+            types[new.0] = self.types[old.0];
+        }
+        self.types = types;
+        self.names = std::mem::take(&mut self.names)
+            .into_iter()
+            .filter_map(|(old, name)| renumber.get(&old).map(|&new| (new, name)))
+            .collect();
+        for parameter in &mut self.parameters {
+            *parameter = renumber[parameter];
+        }
+        for block in &mut self.blocks {
+            for inst in &mut block.insts {
+                if let Some(result) = &mut inst.result {
+                    *result = renumber[result];
+                }
+                for operand in inst.op.operands_mut() {
+                    if let Operand::Value(value) = operand {
+                        *value = renumber[value];
+                    }
+                }
+            }
+            for operand in block.terminator.operands_mut() {
+                if let Operand::Value(value) = operand {
+                    *value = renumber[value];
+                }
+            }
+        }
+    }
+
+    // rewrite every use of a value in the map, following chains so that
+    // a -> b -> c ends up at c
+    pub fn replace_uses(&mut self, map: &HashMap<Value, Operand>) {
+        let resolve = |operand: &mut Operand| {
+            while let Operand::Value(value) = operand {
+                match map.get(value) {
+                    Some(replacement) => *operand = replacement.clone(),
+                    None => break,
+                }
+            }
+        };
+        for block in &mut self.blocks {
+            for inst in &mut block.insts {
+                inst.op.operands_mut().into_iter().for_each(resolve);
+            }
+            block
+                .terminator
+                .operands_mut()
+                .into_iter()
+                .for_each(resolve);
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Float => write!(f, "float"),
+            Type::Int => write!(f, "int"),
+            Type::String => write!(f, "string"),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Value(value) => write!(f, "{}", value),
+            //{:?} reads back as exactly the same f32
+            Operand::Float(value) => write!(f, "{:?}", value),
+            Operand::Int(value) => write!(f, "{}", value),
+            Operand::String(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div => "div",
+            BinOp::Eq => "eq",
+            BinOp::Ne => "ne",
+            BinOp::Lt => "lt",
+            BinOp::Le => "le",
+            BinOp::Gt => "gt",
+            BinOp::Ge => "ge",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// the operation alone, without its result. CSE compares these.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Copy(operand) => write!(f, "copy {}", operand),
+            Op::Neg(operand) => write!(f, "neg {}", operand),
+            Op::Binary(op, left, right) => write!(f, "{} {}, {}", op, left, right),
+            Op::IntToFloat(operand) => write!(f, "itof {}", operand),
+            Op::FloatToInt(operand) => write!(f, "ftoi {}", operand),
+            Op::Phi(incoming) => {
+                let incoming: Vec<String> = incoming
+                    .iter()
+                    .map(|(block, operand)| format!("[b{}: {}]", block, operand))
+                    .collect();
+                write!(f, "phi {}", incoming.join(", "))
+            }
+            Op::Load(name) => write!(f, "load @{}", name),
+            Op::Store(name, operand) => write!(f, "store @{}, {}", name, operand),
+            Op::Call(module, name, args) => {
+                let args: Vec<String> = args.iter().map(Operand::to_string).collect();
+                match module {
+                    Some(module) => write!(f, "call {}.{}({})", module, name, args.join(", ")),
+                    None => write!(f, "call {}({})", name, args.join(", ")),
+                }
+            }
+            Op::Eof => write!(f, "eof"),
+            Op::Input { prompt, location } => write!(f, "input {}, \"{}\"", prompt, location),
+            Op::PrintString(operand) => write!(f, "print.string {}", operand),
+            Op::PrintNumber(operand) => write!(f, "print.number {}", operand),
+            Op::PrintUsing(picture, operand) => {
+                write!(f, "print.using \"{}\", {}", picture, operand)
+            }
+            Op::PrintTab => write!(f, "print.tab"),
+            Op::PrintNewline => write!(f, "print.newline"),
+            Op::Import(name) => write!(f, "import {}", name),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jump b{}", target),
+            Terminator::Branch(condition, then, otherwise) => {
+                write!(f, "branch {}, b{}, b{}", condition, then, otherwise)
+            }
+            Terminator::Return(None) => write!(f, "return"),
+            Terminator::Return(Some(value)) => write!(f, "return {}", value),
+            Terminator::Exit(status) => write!(f, "exit {}", status),
+            Terminator::Stop(message) => write!(f, "stop \"{}\"", message),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parameters: Vec<String> = self
+            .parameters
+            .iter()
+            .map(|parameter| format!("{}: {}", parameter, self.types[parameter.0]))
+            .collect();
+        let export = match self.kind {
+            FunctionKind::Function { exported: true } => "export ",
+            _ => "",
+        };
+        write!(
+            f,
+            "{}function {}({})",
+            export,
+            self.name,
+            parameters.join(", ")
+        )?;
+        if let Some(ty) = self.returns {
+            write!(f, " -> {}", ty)?;
+        }
+        writeln!(f, " {{")?;
+
+        let predecessors = self.predecessors();
+        for (index, block) in self.blocks.iter().enumerate() {
+            let from: Vec<String> = predecessors[index]
+                .iter()
+                .map(|predecessor| format!("b{}", predecessor))
+                .collect();
+            if from.is_empty() {
+                writeln!(f, "b{}:", index)?;
+            } else {
+                writeln!(f, "b{}:  ; from {}", index, from.join(", "))?;
+            }
+            for inst in &block.insts {
+                match inst.result {
+                    Some(result) => {
+                        writeln!(f, "    {}: {} = {}", result, self.types[result.0], inst.op)?
+                    }
+                    None => writeln!(f, "    {}", inst.op)?,
+                }
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => writeln!(f, "module {}", name)?,
+            None => writeln!(f, "program")?,
+        }
+        for global in &self.globals {
+            writeln!(f, "global @{}: float", global)?;
+        }
+        for function in &self.functions {
+            writeln!(f)?;
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,900 @@
+use crate::backend::{self, joins, simple, write_if_changed, Backend, Generator, InputPolicy};
+use crate::ir::{BinOp, BlockId, Function, FunctionKind, Module, Operand};
+use crate::variables::Variables;
+use std::path::Path;
+
+// a standalone JavaScript program for node or a page. every FUNCTION is a
+// JS function, written through the Generator hooks so variables keep their
+// names and loops are while and do. JS has no goto, so a function that
+// needs one runs as a state machine: a loop round a switch on the block to
+// run next, where goto sets the block and continues the loop. floats stay
+// floats by rounding each result with Math.fround.
+
+// what PRINT and INPUT need at run time, shared with the wasm host
+pub const RUNTIME: &str = r#"
+// the digits of |x| exactly, and how many come before the decimal point
+function exactDigits(x) {
+  x = Math.abs(x);
+  const text = x >= 1e21 ? BigInt(x).toString() : x.toFixed(100);
+  const [whole, fraction = ""] = text.split(".");
+  return { digits: whole + fraction, point: whole.length };
+}
+
+// the first count digits, rounded half to even on the rest like the C
+// library does, and whether that carried into a new leading digit
+function roundDigits(digits, count) {
+  digits = digits.padEnd(count, "0");
+  const kept = digits.slice(0, count).split("").map(Number);
+  const rest = digits.slice(count);
+  const odd = kept.length > 0 && kept[kept.length - 1] % 2 === 1;
+  let carry = rest[0] > "5" || (rest[0] === "5" && (/[1-9]/.test(rest.slice(1)) || odd));
+  for (let i = kept.length - 1; carry && i >= 0; i--) {
+    kept[i] = (kept[i] + 1) % 10;
+    carry = kept[i] === 0;
+  }
+  if (carry) {
+    kept.unshift(1);
+  }
+  return { digits: kept.join(""), carried: carry };
+}
+
+// a float as an int, with nan 0 and numbers out of range the nearest int
+function toInt(n) {
+  return n !== n ? 0 : Math.max(-2147483648, Math.min(2147483647, Math.trunc(n)));
+}
+
+function sign(x) {
+  return x < 0 || Object.is(x, -0) ? "-" : "";
+}
+
+// nan and inf the way printf writes them, null for other numbers. nan goes
+// without its sign, which depends on how it was made
+function special(x) {
+  if (Number.isNaN(x)) {
+    return "nan";
+  }
+  if (!Number.isFinite(x)) {
+    return sign(x) + "inf";
+  }
+  return null;
+}
+
+// printf("%.*f")
+function formatFixed(x, precision) {
+  const { digits, point } = exactDigits(x);
+  const rounded = roundDigits(digits, point + precision).digits;
+  const whole = rounded.slice(0, rounded.length - precision).replace(/^0+(?=\d)/, "");
+  const fraction = rounded.slice(rounded.length - precision);
+  return sign(x) + whole + (precision > 0 ? "." + fraction : "");
+}
+
+// printf("%.*g")
+function formatGeneral(x, precision) {
+  const { digits, point } = exactDigits(x);
+  const first = digits.search(/[1-9]/);
+  if (first < 0) {
+    return sign(x) + "0";
+  }
+  let exponent = point - first - 1;
+  let { digits: significant, carried } = roundDigits(digits.slice(first), precision);
+  if (carried) {
+    exponent += 1;
+    significant = significant.slice(0, precision);
+  }
+  let text;
+  if (exponent < -4 || exponent >= precision) {
+    const fraction = significant.slice(1).replace(/0+$/, "");
+    const size = Math.abs(exponent);
+    text = significant[0] + (fraction ? "." + fraction : "") +
+      "e" + (exponent < 0 ? "-" : "+") + (size < 10 ? "0" : "") + size;
+  } else if (exponent >= 0) {
+    const fraction = significant.slice(exponent + 1).replace(/0+$/, "");
+    text = significant.slice(0, exponent + 1) + (fraction ? "." + fraction : "");
+  } else {
+    text = "0." + "0".repeat(-exponent - 1) + significant.replace(/0+$/, "");
+  }
+  return sign(x) + text;
+}
+
+// PRINT of a number: whole numbers without a fraction, anything else in
+// the fewest digits that read back as the same float
+function formatNumber(n) {
+  let text = special(n);
+  if (text !== null) {
+    return text;
+  }
+  // whole numbers the C backend's long can hold, from -2^63 up to 2^63,
+  // print without a fraction. BigInt writes all their digits.
+  if (n >= -(2 ** 63) && n < 2 ** 63 && n === Math.trunc(n)) {
+    return BigInt(n).toString();
+  }
+  for (let precision = 1; precision <= 9; precision++) {
+    text = formatGeneral(n, precision);
+    if (Math.fround(Number(text)) === n) {
+      break;
+    }
+  }
+  return text;
+}
+
+// PRINT USING, a ###.## picture prints like %6.2f
+function formatUsing(n, width, fraction) {
+  const text = special(n) ?? formatFixed(n, fraction);
+  return text.padStart(width);
+}
+
+// reads input the way scanf does, a line at a time from readLine
+function inputReader(readLine) {
+  let buffer = "";
+  let ended = false;
+
+  function more() {
+    if (ended) {
+      return false;
+    }
+    const line = readLine();
+    if (line === null) {
+      ended = true;
+      return false;
+    }
+    buffer += line;
+    return true;
+  }
+
+  return {
+    // scanf("%f"): a number, null if the text isn't one, undefined at the
+    // end of the input
+    number() {
+      for (;;) {
+        buffer = buffer.replace(/^\s+/, "");
+        if (buffer !== "") {
+          break;
+        }
+        if (!more()) {
+          return undefined;
+        }
+      }
+      //a number never runs over a newline, but the line may not all be here
+      while (!buffer.includes("\n") && more()) {}
+      const match = /^[+-]?((\d+\.?\d*|\.\d+)([eE][+-]?\d+)?|inf(inity)?|nan)/i.exec(buffer);
+      if (!match) {
+        return null;
+      }
+      buffer = buffer.slice(match[0].length);
+      const text = match[0].toLowerCase();
+      if (text.includes("inf")) {
+        return text.startsWith("-") ? -Infinity : Infinity;
+      }
+      return Math.fround(text.includes("nan") ? NaN : Number(text));
+    },
+    // scanf("%*s")
+    skipWord() {
+      buffer = buffer.replace(/^\s*\S*/, "");
+    },
+    // getchar() up to the end of the line
+    skipLine() {
+      for (;;) {
+        const newline = buffer.indexOf("\n");
+        if (newline >= 0) {
+          buffer = buffer.slice(newline + 1);
+          return;
+        }
+        buffer = "";
+        if (!more()) {
+          return;
+        }
+      }
+    },
+  };
+}
+
+class Exit {
+  constructor(status) {
+    this.status = status;
+  }
+}
+
+// lines written to the console as they are finished, for pages
+function consoleWriter(log) {
+  let line = "";
+  const write = (text) => {
+    const lines = (line + text).split("\n");
+    line = lines.pop();
+    lines.forEach((finished) => log(finished));
+  };
+  write.end = () => {
+    if (line !== "") {
+      log(line);
+    }
+    line = "";
+  };
+  return write;
+}
+
+// a line of input from the page's prompt hook, window.prompt unless the
+// page gives its own
+function promptReader(ask) {
+  return () => {
+    const line = ask();
+    return line === null || line === undefined ? null : line + "\n";
+  };
+}
+
+// the state PRINT and INPUT share, and what they do with it. host can give
+// write(text), error(text) and readLine(), anything missing goes to the
+// console and the page's prompt. output is buffered until input is read or
+// the program ends, like stdout.
+function runtime(host, inputPolicy) {
+  const write = host.write ?? consoleWriter((line) => console.log(line));
+  const error = host.error ?? ((text) => console.error(text.replace(/\n$/, "")));
+  const input = inputReader(host.readLine ?? promptReader(() => window.prompt("")));
+  let out = "";
+  let column = 0;
+  let eof = 0;
+
+  const print = (text) => {
+    out += text;
+    column += text.length;
+  };
+  const flush = () => {
+    if (out !== "") {
+      write(out);
+    }
+    out = "";
+  };
+
+  return {
+    print_string: print,
+    print_number: (n) => print(formatNumber(n)),
+    print_using: (n, width, fraction) => print(formatUsing(n, width, fraction)),
+    print_tab: () => {
+      do {
+        print(" ");
+      } while (column % 14 !== 0);
+    },
+    print_newline: () => {
+      out += "\n";
+      column = 0;
+    },
+    input: (prompt, location) => {
+      for (;;) {
+        print(prompt);
+        flush();
+        const value = input.number();
+        column = 0;
+        if (value === undefined) {
+          eof = 1;
+          return 0;
+        }
+        if (value !== null) {
+          return value;
+        }
+        if (inputPolicy === "zero") {
+          input.skipWord();
+          return 0;
+        }
+        if (inputPolicy === "error") {
+          error("Invalid input at " + location + "\n");
+          throw new Exit(1);
+        }
+        input.skipLine();
+        out += "?Redo from start\n";
+      }
+    },
+    eof: () => eof,
+    exit: (status) => {
+      throw new Exit(status);
+    },
+    stop: (message) => {
+      error(message + "\n");
+      throw new Exit(1);
+    },
+    end: () => {
+      flush();
+      write.end?.();
+    },
+  };
+}
+
+// run the program and give its exit status, once its output is all out
+function finish(tt, program) {
+  let status;
+  try {
+    status = program();
+  } catch (error) {
+    if (!(error instanceof Exit)) {
+      throw error;
+    }
+    status = error.status;
+  }
+  tt.end();
+  return status;
+}
+
+// stdin, stdout and stderr under node. input is read as it's needed, so
+// prompts show before the program waits for a line.
+function nodeHost() {
+  const fs = require("fs");
+  let pending = Buffer.alloc(0);
+  let ended = false;
+  const readLine = () => {
+    for (;;) {
+      const newline = pending.indexOf(10);
+      if (newline >= 0 || (ended && pending.length > 0)) {
+        const end = newline >= 0 ? newline + 1 : pending.length;
+        const line = pending.subarray(0, end).toString();
+        pending = pending.subarray(end);
+        return line;
+      }
+      if (ended) {
+        return null;
+      }
+      const chunk = Buffer.alloc(4096);
+      let read;
+      try {
+        read = fs.readSync(0, chunk, 0, chunk.length, null);
+      } catch (error) {
+        if (error.code === "EAGAIN") {
+          continue;
+        }
+        if (error.code !== "EOF") {
+          throw error;
+        }
+        read = 0;
+      }
+      if (read === 0) {
+        ended = true;
+      } else {
+        pending = Buffer.concat([pending, chunk.subarray(0, read)]);
+      }
+    }
+  };
+  return {
+    write: (text) => process.stdout.write(text),
+    error: (text) => process.stderr.write(text),
+    readLine,
+  };
+}
+"#;
+
+const PROGRAM_START: &str = r#"// a teeny tiny program. "node out.js" runs it with stdin and stdout. a page
+// can load it and call runTeenyTiny(host), where host has write(text) and
+// error(text) for output and readLine() which returns the next line of
+// input with its newline, or null at the end of it. without them output
+// goes to console.log and input comes from window.prompt.
+"use strict";
+
+// what INPUT does with text that isn't a number
+const INPUT_POLICY = "POLICY";
+"#;
+
+const PROGRAM_END: &str = r#"
+// runs the program, giving its exit status
+function runTeenyTiny(host = {}) {
+  const tt = runtime(host, INPUT_POLICY);
+  return finish(tt, () => program(tt));
+}
+
+if (typeof require !== "undefined" && require.main === module) {
+  process.exitCode = runTeenyTiny(nodeHost());
+}
+"#;
+
+pub struct JsEmitter {
+    //variables kept outside the functions, and the init guards
+    globals: String,
+    //finished function definitions
+    functions: String,
+    //function being emitted
+    code: String,
+    indent: usize,
+    //module whose IR is being added, None for the main program
+    module: Option<String>,
+    full_path: String,
+    pub input_policy: InputPolicy,
+}
+
+impl JsEmitter {
+    pub fn new(full_path: String) -> JsEmitter {
+        JsEmitter {
+            globals: String::new(),
+            functions: String::new(),
+            code: String::new(),
+            indent: 0,
+            module: None,
+            full_path,
+            input_policy: InputPolicy::Zero,
+        }
+    }
+
+    pub fn full_path(&self) -> &str {
+        &self.full_path
+    }
+
+    fn line(&mut self, code: String) {
+        self.code.push_str(&"  ".repeat(self.indent));
+        self.code.push_str(&code);
+        self.code.push('\n');
+    }
+
+    // variables of every module share the program's scope
+    fn global_name(&self, name: &str) -> String {
+        match &self.module {
+            Some(module) => format!("g_{}_{}", module, name),
+            None => format!("g_{}", name),
+        }
+    }
+
+    fn write_file(&mut self) {
+        let value = PROGRAM_START.replace(
+            "\"POLICY\"",
+            &format!("\"{}\"", policy_name(self.input_policy)),
+        ) + RUNTIME
+            + "\n// the program, printing and input go through tt\nfunction program(tt) {\n"
+            + &self.globals
+            + if self.globals.is_empty() { "" } else { "\n" }
+            + &self.functions
+            + "  return main();\n}\n"
+            + PROGRAM_END;
+        write_if_changed(Path::new(&self.full_path), &value);
+    }
+}
+
+impl Generator for JsEmitter {
+    fn reserved(&self) -> &'static [&'static str] {
+        RESERVED
+    }
+
+    fn has_goto(&self) -> bool {
+        false
+    }
+
+    // modules have to come before the modules that import them, and the
+    // main program last
+    fn start_program(&mut self, module: &Module, _variables: &[Variables]) {
+        self.module = module.name.clone();
+        for name in &module.globals {
+            let name = self.global_name(name);
+            self.globals.push_str(&format!("  let {} = 0;\n", name));
+        }
+    }
+
+    fn start_function(&mut self, function: &Function, variables: &Variables) {
+        let parameters: Vec<&str> = function
+            .parameters
+            .iter()
+            .map(|parameter| variables.name(*parameter).unwrap())
+            .collect();
+        self.indent = 1;
+        self.line(format!(
+            "function {}({}) {{",
+            js_function_name(&self.module, function),
+            parameters.join(", ")
+        ));
+        self.indent += 1;
+
+        let locals: Vec<&str> = variables
+            .locals()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        if !locals.is_empty() {
+            self.line(format!("let {};", locals.join(", ")));
+        }
+
+        if function.kind == FunctionKind::Init {
+            //the module's statements run once, when it is first imported
+            let guard = format!("{}__initialized", self.module.as_ref().unwrap());
+            self.globals
+                .push_str(&format!("  let {} = false;\n", guard));
+            self.start_if(guard.clone());
+            self.ret(None);
+            self.end_if();
+            self.line(format!("{} = true;", guard));
+        }
+    }
+
+    fn end_function(&mut self, _function: &Function, _variables: &Variables) {
+        self.indent -= 1;
+        self.line("}".into());
+        self.code.push('\n');
+        let code = std::mem::take(&mut self.code);
+        self.functions.push_str(&code);
+    }
+
+    fn constant(&mut self, constant: &Operand) -> String {
+        js_operand(constant)
+    }
+
+    fn binary(&mut self, op: BinOp, left: String, right: String) -> String {
+        let (left, right) = (js_bracket(left), js_bracket(right));
+        match op {
+            BinOp::Add => format!("Math.fround({} + {})", left, right),
+            BinOp::Sub => format!("Math.fround({} - {})", left, right),
+            BinOp::Mul => format!("Math.fround({} * {})", left, right),
+            BinOp::Div => format!("Math.fround({} / {})", left, right),
+            _ => format!("{} {} {} ? 1 : 0", left, js_comparison(op), right),
+        }
+    }
+
+    fn compare(&mut self, op: BinOp, left: String, right: String) -> String {
+        format!(
+            "{} {} {}",
+            js_bracket(left),
+            js_comparison(op),
+            js_bracket(right)
+        )
+    }
+
+    fn neg(&mut self, operand: String) -> String {
+        //keep "- -1" from turning into "--1"
+        if operand.starts_with('-') {
+            format!("-({})", operand)
+        } else {
+            format!("-{}", js_bracket(operand))
+        }
+    }
+
+    fn not(&mut self, condition: String) -> String {
+        //the opposite of a ! is what it has in brackets
+        match condition.strip_prefix('!') {
+            Some(inner) if simple(inner) => {
+                let inner = inner
+                    .strip_prefix('(')
+                    .and_then(|inner| inner.strip_suffix(')'));
+                inner.unwrap_or(&condition[1..]).into()
+            }
+            _ => format!("!{}", js_bracket(condition)),
+        }
+    }
+
+    fn and(&mut self, first: String, second: String) -> String {
+        //only || is looser than &&
+        let bracket = |operand: String| match joins(&operand, " || ") {
+            true => format!("({})", operand),
+            false => operand,
+        };
+        format!("{} && {}", bracket(first), bracket(second))
+    }
+
+    fn or(&mut self, first: String, second: String) -> String {
+        format!("{} || {}", first, second)
+    }
+
+    fn int_to_float(&mut self, operand: String) -> String {
+        format!("Math.fround({})", operand)
+    }
+
+    fn float_to_int(&mut self, operand: String) -> String {
+        format!("toInt({})", operand)
+    }
+
+    fn load(&mut self, name: &str) -> String {
+        self.global_name(name)
+    }
+
+    fn call(&mut self, module: &Option<String>, name: &str, args: Vec<String>) -> String {
+        let callee = match module {
+            Some(module) => format!("{}__{}", module, name),
+            None => format!("tt_fn_{}", name),
+        };
+        format!("{}({})", callee, args.join(", "))
+    }
+
+    fn eof(&mut self) -> String {
+        "tt.eof()".into()
+    }
+
+    fn assign(&mut self, variable: &str, value: String) {
+        self.line(format!("{} = {};", variable, value));
+    }
+
+    fn evaluate(&mut self, value: String) {
+        self.line(format!("{};", value));
+    }
+
+    fn store(&mut self, name: &str, value: String) {
+        let name = self.global_name(name);
+        self.line(format!("{} = {};", name, value));
+    }
+
+    fn input(&mut self, variable: &str, prompt: String, location: &str) {
+        self.line(format!(
+            "{} = tt.input({}, {});",
+            variable,
+            prompt,
+            js_string(location)
+        ));
+    }
+
+    fn print_string(&mut self, text: String) {
+        self.line(format!("tt.print_string({});", text));
+    }
+
+    fn print_number(&mut self, value: String) {
+        self.line(format!("tt.print_number({});", value));
+    }
+
+    fn print_using(&mut self, picture: &str, value: String) {
+        //"###.##" prints like "%6.2f"
+        let fraction = picture
+            .split_once('.')
+            .map_or(0, |(_, fraction)| fraction.len());
+        self.line(format!(
+            "tt.print_using({}, {}, {});",
+            value,
+            picture.len(),
+            fraction
+        ));
+    }
+
+    fn print_tab(&mut self) {
+        self.line("tt.print_tab();".into());
+    }
+
+    fn print_newline(&mut self) {
+        self.line("tt.print_newline();".into());
+    }
+
+    fn import(&mut self, module: &str) {
+        self.line(format!("{}__init();", module));
+    }
+
+    fn start_if(&mut self, condition: String) {
+        self.line(format!("if ({}) {{", condition));
+        self.indent += 1;
+    }
+
+    fn start_else_if(&mut self, condition: String) {
+        self.indent -= 1;
+        self.line(format!("}} else if ({}) {{", condition));
+        self.indent += 1;
+    }
+
+    fn start_else(&mut self) {
+        self.indent -= 1;
+        self.line("} else {".into());
+        self.indent += 1;
+    }
+
+    fn end_if(&mut self) {
+        self.indent -= 1;
+        self.line("}".into());
+    }
+
+    fn start_loop(&mut self) {
+        self.line("for (;;) {".into());
+        self.indent += 1;
+    }
+
+    fn start_while(&mut self, condition: String) {
+        self.line(format!("while ({}) {{", condition));
+        self.indent += 1;
+    }
+
+    fn end_loop(&mut self) {
+        self.indent -= 1;
+        self.line("}".into());
+    }
+
+    fn start_do(&mut self, _condition: String) {
+        self.line("do {".into());
+        self.indent += 1;
+    }
+
+    fn end_do(&mut self, condition: String) {
+        self.indent -= 1;
+        self.line(format!("}} while ({});", condition));
+    }
+
+    fn break_loop(&mut self) {
+        self.line("break;".into());
+    }
+
+    fn continue_loop(&mut self) {
+        self.line("continue;".into());
+    }
+
+    fn label(&mut self, _block: BlockId) {
+        unreachable!("JS has no labels to go to");
+    }
+
+    fn goto(&mut self, block: BlockId) {
+        self.line(format!("block = {};", block));
+        self.line("continue dispatch;".into());
+    }
+
+    fn ret(&mut self, value: Option<String>) {
+        match value {
+            Some(value) => self.line(format!("return {};", value)),
+            None => self.line("return;".into()),
+        }
+    }
+
+    fn exit(&mut self, status: String) {
+        self.line(format!("tt.exit({});", status));
+    }
+
+    fn stop(&mut self, message: &str) {
+        self.line(format!("tt.stop({});", js_string(message)));
+    }
+
+    // a switch on the block to run next in a loop, where goto sets the
+    // block and goes round again
+    fn start_dispatch(&mut self, entry: BlockId) {
+        self.line(format!("let block = {};", entry));
+        self.line("dispatch: for (;;) {".into());
+        self.line("  switch (block) {".into());
+        self.indent += 2;
+    }
+
+    fn start_case(&mut self, block: BlockId) {
+        self.line(format!("case {}:", block));
+        self.indent += 1;
+    }
+
+    fn end_case(&mut self) {
+        self.indent -= 1;
+    }
+
+    fn end_dispatch(&mut self) {
+        self.indent -= 2;
+        self.line("  }".into());
+        self.line("}".into());
+    }
+}
+
+// one script holds the program and everything it imports
+impl Backend for JsEmitter {
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String> {
+        for module in modules.iter().chain([program]) {
+            backend::generate(self, module);
+        }
+        self.write_file();
+        vec![self.full_path.clone()]
+    }
+
+    fn finish(&self, _files: &[String]) -> Option<String> {
+        Some(format!("Run with: node {}", self.full_path))
+    }
+}
+
+pub fn policy_name(policy: InputPolicy) -> &'static str {
+    match policy {
+        InputPolicy::Zero => "zero",
+        InputPolicy::Reprompt => "reprompt",
+        InputPolicy::Error => "error",
+    }
+}
+
+// the same names the C backend uses
+fn js_function_name(module: &Option<String>, function: &Function) -> String {
+    match (function.kind, module) {
+        (FunctionKind::Main, _) => "main".into(),
+        (_, Some(module)) => format!("{}__{}", module, function.name),
+        (_, None) => format!("tt_fn_{}", function.name),
+    }
+}
+
+// JS's reserved words, and the names the generated functions use that a
+// teeny tiny name could be
+const RESERVED: &[&str] = &[
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+    "arguments",
+    "eval",
+    "undefined",
+    "NaN",
+    "Infinity",
+    "Math",
+    "tt",
+    "block",
+    "toInt",
+    "main",
+];
+
+// an operand of an operator, which a comparison or a ?: inside it would
+// split
+fn js_bracket(operand: String) -> String {
+    match simple(&operand) {
+        true => operand,
+        false => format!("({})", operand),
+    }
+}
+
+fn js_comparison(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Eq => "===",
+        BinOp::Ne => "!==",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        _ => ">=",
+    }
+}
+
+fn js_operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Value(_) => unreachable!("a value is not a constant"),
+        Operand::Float(value) => js_float(*value),
+        Operand::Int(value) => value.to_string(),
+        Operand::String(text) => js_string(text),
+    }
+}
+
+// JS numbers are doubles, a float whose shortest digits mean something else
+// as a double goes through Math.fround
+fn js_float(value: f32) -> String {
+    if value.is_nan() {
+        return "NaN".into();
+    }
+    if value.is_infinite() {
+        return if value < 0.0 { "-Infinity" } else { "Infinity" }.into();
+    }
+    let digits = format!("{:?}", value);
+    if digits.parse::<f64>() == Ok(value as f64) {
+        digits
+    } else {
+        format!("Math.fround({})", digits)
+    }
+}
+
+fn js_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use strum::IntoEnumIterator;
@@ -7,16 +8,27 @@ pub struct Lexer {
     pub source: Vec<char>,
     pub current_char: Option<char>,
     pub current_pos: isize,
+    pub line: usize,
+    pub path: PathBuf,
+    //index of this source in the parser's file table
+    pub file: usize,
+    //"included from" lines added to lexing errors
+    pub include_note: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub text: String,
     pub kind: TokenType,
+    pub line: usize,
+    pub file: usize,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, EnumIter, EnumString, Clone)]
 pub enum TokenType {
+    //end of the source, never matched from text
+    #[strum(disabled)]
     EOF,
     NEWLINE,
     NUMBER,
@@ -25,14 +37,41 @@ pub enum TokenType {
     LABEL,
     GOTO,
     PRINT,
+    USING,
     INPUT,
+    //EOF builtin, set once INPUT runs out of stdin
+    #[strum(serialize = "EOF")]
+    INPUTEOF,
     LET,
+    CONST,
     IF,
     THEN,
     ENDIF,
     WHILE,
     REPEAT,
     ENDWHILE,
+    DO,
+    LOOP,
+    UNTIL,
+    SELECT,
+    CASE,
+    IS,
+    TO,
+    ELSE,
+    ENDSELECT,
+    BREAK,
+    CONTINUE,
+    END,
+    STOP,
+    EXIT,
+    INCLUDE,
+    MODULE,
+    IMPORT,
+    EXPORT,
+    FUNCTION,
+    ENDFUNCTION,
+    RETURN,
+    CALL,
     //operators
     EQ,
     PLUS,
@@ -45,16 +84,27 @@ pub enum TokenType {
     LTEQ,
     GT,
     GTEQ,
+    //separators
+    COMMA,
+    SEMICOLON,
+    DOT,
+    LPAREN,
+    RPAREN,
 }
 
 impl Token {
     fn new(text: String, kind: TokenType) -> Token {
-        Token { text, kind }
+        Token {
+            text,
+            kind,
+            line: 0,
+            file: 0,
+        }
     }
 }
 
 impl Lexer {
-    pub fn new(source: String) -> Lexer {
+    pub fn new(source: String, path: PathBuf) -> Lexer {
         //turn source string into Vec<char> for easier indexing
 
         let mut source: Vec<char> = source.chars().collect();
@@ -65,6 +115,10 @@ impl Lexer {
             source,
             current_char: None,
             current_pos: -1,
+            line: 1,
+            path,
+            file: 0,
+            include_note: String::new(),
         };
 
         lexer.next_char();
@@ -73,6 +127,9 @@ impl Lexer {
     }
 
     pub fn next_char(&mut self) {
+        if self.current_char == Some('\n') {
+            self.line += 1;
+        }
         self.current_pos += 1;
 
         if self.current_pos as usize >= self.source.len() {
@@ -89,13 +146,14 @@ impl Lexer {
             return '\0';
         }
 
-        return self.source[next_pos as usize];
+        self.source[next_pos as usize]
     }
 
     pub fn get_token(&mut self) -> Option<Token> {
         self.skip_whitespace();
         self.skip_comments();
 
+        let line = self.line;
         let mut token: Option<Token> = None;
 
         if let Some(current_char) = self.current_char {
@@ -105,6 +163,11 @@ impl Lexer {
                 '-' => token = Some(Token::new(current_char.into(), TokenType::MINUS)),
                 '*' => token = Some(Token::new(current_char.into(), TokenType::ASTERISK)),
                 '/' => token = Some(Token::new(current_char.into(), TokenType::SLASH)),
+                ',' => token = Some(Token::new(current_char.into(), TokenType::COMMA)),
+                ';' => token = Some(Token::new(current_char.into(), TokenType::SEMICOLON)),
+                '.' => token = Some(Token::new(current_char.into(), TokenType::DOT)),
+                '(' => token = Some(Token::new(current_char.into(), TokenType::LPAREN)),
+                ')' => token = Some(Token::new(current_char.into(), TokenType::RPAREN)),
                 '=' => {
                     if self.peek() == '=' {
                         let mut text = current_char.to_string();
@@ -183,10 +246,10 @@ impl Lexer {
                 _ => {
                     //handle number, identifier, other operators
                     //digit token
-                    if current_char.is_digit(10) {
+                    if current_char.is_ascii_digit() {
                         let start_pos = self.current_pos;
 
-                        while self.peek().is_digit(10) {
+                        while self.peek().is_ascii_digit() {
                             self.next_char();
                         }
 
@@ -194,11 +257,11 @@ impl Lexer {
                         if self.peek() == '.' {
                             self.next_char();
 
-                            if !self.peek().is_digit(10) {
+                            if !self.peek().is_ascii_digit() {
                                 self.abort_operation("Illegal character in number".into());
                             }
 
-                            while self.peek().is_digit(10) {
+                            while self.peek().is_ascii_digit() {
                                 self.next_char();
                             }
                         }
@@ -224,9 +287,9 @@ impl Lexer {
 
                         //matching of keyword
                         if let Some(keyword) = self.check_if_keyword(text.clone()) {
-                            token = Some(Token::new(text.into(), keyword));
+                            token = Some(Token::new(text, keyword));
                         } else {
-                            token = Some(Token::new(text.into(), TokenType::IDENT));
+                            token = Some(Token::new(text, TokenType::IDENT));
                         }
 
                         //check if token text exists in token types
@@ -239,11 +302,21 @@ impl Lexer {
 
         self.next_char();
 
-        return token;
+        if let Some(token) = token.as_mut() {
+            token.line = line;
+            token.file = self.file;
+        }
+        token
     }
 
     fn abort_operation(&self, message: String) {
-        panic!("Lexing error. {}", message);
+        panic!(
+            "Lexing error in {} on line {}. {}{}",
+            self.path.display(),
+            self.line,
+            message,
+            self.include_note
+        );
     }
 
     fn skip_whitespace(&mut self) {
@@ -276,7 +349,7 @@ impl Lexer {
                 None
             }
             //parsing error, return None
-            Err(_) => return None,
+            Err(_) => None,
         }
     }
 }
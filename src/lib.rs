@@ -0,0 +1,30 @@
+// the compiler as a library: the parser builds a Program, analysis passes
+// work on it and its control-flow graphs and optimizations rewrite it.
+// it is then lowered to SSA form IR, which has passes of its own, and the
+// Backend --emit picks by name turns that into output: C from the emitter,
+// and from asm, llvm, qbe, wasm, js, python and rust, x86-64 assembly, LLVM
+// IR, QBE IL, WebAssembly, JavaScript, Python and Rust
+pub mod analysis;
+pub mod asm;
+pub mod ast;
+pub mod backend;
+pub mod cfg;
+pub mod diag;
+pub mod emit;
+pub mod ir;
+pub mod js;
+pub mod lex;
+pub mod llvm;
+pub mod lower;
+pub mod module;
+pub mod opt;
+pub mod parse;
+pub mod passes;
+pub mod python;
+pub mod qbe;
+pub mod rust;
+pub mod source;
+pub mod structure;
+pub mod symbols;
+pub mod variables;
+pub mod wasm;
@@ -0,0 +1,611 @@
+use crate::backend::{self, using_format, write_if_changed, Backend, InputPolicy, ModuleFiles};
+use crate::ir::{BinOp, Function, FunctionKind, Module, Op, Operand, Terminator, Type};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// textual LLVM IR, for llc, lli or clang. the IR is already in SSA form, so
+// values and phis carry straight over. variables the FUNCTIONs share are
+// globals, printing and input go through a runtime written in LLVM IR that
+// calls libc. pointers are opaque, the only kind LLVM 17 and later read;
+// LLVM 14 reads them with -opaque-pointers. nothing refers to libc's stdout
+// or stderr, which are data symbols that don't link the same everywhere:
+// fflush(NULL) flushes output and errors are written to descriptor 2.
+
+// libc and the conversion EXIT uses, declared in every file
+const LIBC: &str = r#"declare i32 @printf(ptr, ...)
+declare i32 @snprintf(ptr, i64, ptr, ...)
+declare float @strtof(ptr, ptr)
+declare i32 @putchar(i32)
+declare i32 @scanf(ptr, ...)
+declare i32 @fflush(ptr)
+declare i32 @getchar()
+declare i32 @puts(ptr)
+declare i32 @dprintf(i32, ptr, ...)
+declare i64 @write(i32, ptr, i64)
+declare void @exit(i32) noreturn
+declare i32 @llvm.fptosi.sat.i32.f32(float)
+"#;
+
+// print and input helpers, in the main program's file only. modules
+// declare them and share tt_column and tt_eof with it.
+const RUNTIME: &str = r#"
+@tt_column = global i32 0
+@tt_eof = global i32 0
+
+@.format.string = private unnamed_addr constant [3 x i8] c"%s\00"
+@.format.long = private unnamed_addr constant [4 x i8] c"%ld\00"
+@.format.general = private unnamed_addr constant [5 x i8] c"%.*g\00"
+@.format.float = private unnamed_addr constant [3 x i8] c"%f\00"
+@.format.skip = private unnamed_addr constant [4 x i8] c"%*s\00"
+@.redo = private unnamed_addr constant [17 x i8] c"?Redo from start\00"
+@.invalid = private unnamed_addr constant [21 x i8] c"Invalid input at %s\0A\00"
+
+define void @tt_print_string(ptr %text) {
+  %printed = call i32 (ptr, ...) @printf(ptr @.format.string, ptr %text)
+  %column = load i32, ptr @tt_column
+  %moved = add i32 %column, %printed
+  store i32 %moved, ptr @tt_column
+  ret void
+}
+
+; nan without its sign, which depends on how it was made and which printf
+; would write as "-nan"
+define private float @tt_unsigned_nan(float %n) {
+  %nan = fcmp uno float %n, %n
+  %bits = bitcast float %n to i32
+  %cleared = and i32 %bits, 2147483647
+  %unsigned = bitcast i32 %cleared to float
+  %result = select i1 %nan, float %unsigned, float %n
+  ret float %result
+}
+
+define void @tt_print_number(float %signed) {
+entry:
+  %text = alloca [32 x i8]
+  %n = call float @tt_unsigned_nan(float %signed)
+  ; whole numbers an i64 can hold, from -2^63 up to 2^63, print without a
+  ; fraction
+  %above = fcmp oge float %n, 0xC3E0000000000000
+  %below = fcmp olt float %n, 0x43E0000000000000
+  %in_range = and i1 %above, %below
+  br i1 %in_range, label %check, label %shortest
+check:
+  %long = fptosi float %n to i64
+  %back = sitofp i64 %long to float
+  %is_whole = fcmp oeq float %n, %back
+  br i1 %is_whole, label %whole, label %shortest
+whole:
+  call i32 (ptr, i64, ptr, ...) @snprintf(ptr %text, i64 32, ptr @.format.long, i64 %long)
+  br label %done
+  ; shortest representation that reads back as the same float
+shortest:
+  %double = fpext float %n to double
+  br label %precision
+precision:
+  %digits = phi i32 [ 1, %shortest ], [ %more_digits, %next ]
+  call i32 (ptr, i64, ptr, ...) @snprintf(ptr %text, i64 32, ptr @.format.general, i32 %digits, double %double)
+  %read = call float @strtof(ptr %text, ptr null)
+  %same = fcmp oeq float %read, %n
+  br i1 %same, label %done, label %next
+next:
+  %more_digits = add i32 %digits, 1
+  %again = icmp sle i32 %more_digits, 9
+  br i1 %again, label %precision, label %done
+done:
+  call void @tt_print_string(ptr %text)
+  ret void
+}
+
+define void @tt_print_using(ptr %format, float %n) {
+  %unsigned = call float @tt_unsigned_nan(float %n)
+  %double = fpext float %unsigned to double
+  %printed = call i32 (ptr, ...) @printf(ptr %format, double %double)
+  %column = load i32, ptr @tt_column
+  %moved = add i32 %column, %printed
+  store i32 %moved, ptr @tt_column
+  ret void
+}
+
+define void @tt_print_tab() {
+entry:
+  br label %space
+space:
+  call i32 @putchar(i32 32)
+  %column = load i32, ptr @tt_column
+  %moved = add i32 %column, 1
+  store i32 %moved, ptr @tt_column
+  %zone = srem i32 %moved, 14
+  %more = icmp ne i32 %zone, 0
+  br i1 %more, label %space, label %done
+done:
+  ret void
+}
+
+define void @tt_print_newline() {
+  call i32 @putchar(i32 10)
+  store i32 0, ptr @tt_column
+  ret void
+}
+
+define void @tt_input(ptr %target, ptr %prompt, ptr %location) {
+entry:
+  br label %again
+again:
+  call void @tt_print_string(ptr %prompt)
+  call i32 @fflush(ptr null)
+  %read = call i32 (ptr, ...) @scanf(ptr @.format.float, ptr %target)
+  store i32 0, ptr @tt_column
+  %ok = icmp eq i32 %read, 1
+  br i1 %ok, label %done, label %failed
+failed:
+  store float 0.0, ptr %target
+  %eof = icmp eq i32 %read, -1
+  br i1 %eof, label %at_eof, label %invalid
+at_eof:
+  store i32 1, ptr @tt_eof
+  br label %done
+invalid:
+"#;
+
+// what the runtime does when INPUT reads something that isn't a number,
+// the rest of the invalid block
+fn input_policy_code(policy: InputPolicy) -> &'static str {
+    match policy {
+        InputPolicy::Zero => {
+            r#"  call i32 (ptr, ...) @scanf(ptr @.format.skip)
+  br label %done
+"#
+        }
+        InputPolicy::Reprompt => {
+            r#"  br label %discard
+discard:
+  %char = call i32 @getchar()
+  %newline = icmp eq i32 %char, 10
+  %end = icmp eq i32 %char, -1
+  %line_done = or i1 %newline, %end
+  br i1 %line_done, label %redo, label %discard
+redo:
+  call i32 @puts(ptr @.redo)
+  br label %again
+"#
+        }
+        InputPolicy::Error => {
+            r#"  call i32 (i32, ptr, ...) @dprintf(i32 2, ptr @.invalid, ptr %location)
+  call void @exit(i32 1)
+  unreachable
+"#
+        }
+    }
+}
+
+const RUNTIME_END: &str = r#"done:
+  ret void
+}
+"#;
+
+// what a module sees of the main program's runtime
+const RUNTIME_DECLARATIONS: &str = r#"
+@tt_column = external global i32
+@tt_eof = external global i32
+
+declare void @tt_print_string(ptr)
+declare void @tt_print_number(float)
+declare void @tt_print_using(ptr, float)
+declare void @tt_print_tab()
+declare void @tt_print_newline()
+declare void @tt_input(ptr, ptr, ptr)
+"#;
+
+pub struct LlvmEmitter {
+    //globals and string constants
+    globals: String,
+    //finished function definitions
+    functions: String,
+    //function being emitted
+    code: String,
+    full_path: String,
+    module: Option<String>,
+    //name of each string constant
+    strings: HashMap<String, String>,
+    //functions from other modules, by name, and how many arguments they take
+    external: BTreeMap<String, Option<usize>>,
+    //names for the values LLVM needs that the IR doesn't have
+    temporary_count: usize,
+    pub input_policy: InputPolicy,
+}
+
+impl LlvmEmitter {
+    pub fn new(full_path: String) -> LlvmEmitter {
+        LlvmEmitter {
+            globals: String::new(),
+            functions: String::new(),
+            code: String::new(),
+            full_path,
+            module: None,
+            strings: HashMap::new(),
+            external: BTreeMap::new(),
+            temporary_count: 0,
+            input_policy: InputPolicy::Zero,
+        }
+    }
+
+    pub fn full_path(&self) -> &str {
+        &self.full_path
+    }
+
+    fn line(&mut self, code: String) {
+        self.code.push_str("  ");
+        self.code.push_str(&code);
+        self.code.push('\n');
+    }
+
+    fn temporary(&mut self) -> String {
+        self.temporary_count += 1;
+        format!("%t{}", self.temporary_count)
+    }
+
+    // a string constant, which is a pointer to its first character
+    fn string(&mut self, text: &str) -> String {
+        if let Some(name) = self.strings.get(text) {
+            return name.clone();
+        }
+        let name = format!("@.string{}", self.strings.len());
+        self.globals.push_str(&format!(
+            "{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"\n",
+            name,
+            text.len() + 1,
+            llvm_escape(text)
+        ));
+        self.strings.insert(text.into(), name.clone());
+        name
+    }
+
+    fn operand(&mut self, operand: &Operand) -> String {
+        match operand {
+            Operand::Value(value) => format!("%v{}", value.0),
+            Operand::Float(value) => llvm_float(*value),
+            Operand::Int(value) => value.to_string(),
+            Operand::String(text) => self.string(text),
+        }
+    }
+
+    // an operand with its type in front, the way arguments are written
+    fn typed(&mut self, function: &Function, operand: &Operand) -> String {
+        let ty = llvm_type(function.operand_type(operand));
+        format!("{} {}", ty, self.operand(operand))
+    }
+
+    fn function(&mut self, module: &Option<String>, function: &Function) {
+        self.temporary_count = 0;
+        let parameters: Vec<String> = function
+            .parameters
+            .iter()
+            .map(|parameter| format!("float %v{}", parameter.0))
+            .collect();
+        let header = match function.kind {
+            FunctionKind::Main => "define i32 @main()".to_string(),
+            FunctionKind::Init => format!("define void @{}__init()", module.as_ref().unwrap()),
+            FunctionKind::Function { exported } => format!(
+                "define {}{} @{}({})",
+                if exported { "" } else { "internal " },
+                function.returns.map_or("void", llvm_type),
+                llvm_function_name(module, &function.name),
+                parameters.join(", ")
+            ),
+        };
+
+        if function.kind == FunctionKind::Init {
+            //the module's statements run once, when it is first imported
+            self.globals
+                .push_str("@.initialized = internal global i1 false\n");
+            self.code.push_str("init:\n");
+            self.line("%initialized = load i1, ptr @.initialized".into());
+            self.line("br i1 %initialized, label %already, label %b0".into());
+            self.code.push_str("already:\n");
+            self.line("ret void".into());
+        }
+
+        for (index, block) in function.blocks.iter().enumerate() {
+            self.code.push_str(&format!("b{}:\n", index));
+            if index == 0 && function.kind == FunctionKind::Init {
+                self.line("store i1 true, ptr @.initialized".into());
+            }
+            //INPUT reads into memory, a slot per INPUT at the top of the
+            //function where LLVM turns them back into registers
+            if index == 0 {
+                for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+                    if let (Some(result), Op::Input { .. }) = (inst.result, &inst.op) {
+                        self.line(format!("%input{} = alloca float", result.0));
+                    }
+                }
+            }
+            for inst in &block.insts {
+                self.instruction(function, inst.result.map(|result| result.0), &inst.op);
+            }
+            self.terminator(function, &block.terminator);
+        }
+
+        let code = std::mem::take(&mut self.code);
+        self.functions
+            .push_str(&format!("\n{} {{\n{}}}\n", header, code));
+    }
+
+    fn instruction(&mut self, function: &Function, result: Option<usize>, op: &Op) {
+        let value = match op {
+            //LLVM has no copy, a cast to the same type is one
+            Op::Copy(operand) => {
+                let ty = llvm_type(function.operand_type(operand));
+                format!("bitcast {} to {}", self.typed(function, operand), ty)
+            }
+            Op::Neg(operand) => format!("fneg {}", self.typed(function, operand)),
+            Op::Binary(op, left, right) => {
+                let left = self.typed(function, left);
+                let right = self.operand(right);
+                let instruction = match op {
+                    BinOp::Add => "fadd",
+                    BinOp::Sub => "fsub",
+                    BinOp::Mul => "fmul",
+                    BinOp::Div => "fdiv",
+                    _ => {
+                        let compared = self.temporary();
+                        self.line(format!(
+                            "{} = fcmp {} {}, {}",
+                            compared,
+                            llvm_predicate(*op),
+                            left,
+                            right
+                        ));
+                        self.line(format!(
+                            "%v{} = zext i1 {} to i32",
+                            result.unwrap(),
+                            compared
+                        ));
+                        return;
+                    }
+                };
+                format!("{} {}, {}", instruction, left, right)
+            }
+            Op::IntToFloat(operand) => {
+                format!("sitofp {} to float", self.typed(function, operand))
+            }
+            //fptosi is poison for nan and numbers out of range
+            Op::FloatToInt(operand) => format!(
+                "call i32 @llvm.fptosi.sat.i32.f32({})",
+                self.typed(function, operand)
+            ),
+            Op::Phi(incoming) => {
+                let ty = llvm_type(function.types[result.unwrap()]);
+                let incoming: Vec<String> = incoming
+                    .iter()
+                    .map(|(block, operand)| format!("[ {}, %b{} ]", self.operand(operand), block))
+                    .collect();
+                format!("phi {} {}", ty, incoming.join(", "))
+            }
+            Op::Load(name) => format!("load float, ptr {}", global_name(name)),
+            Op::Store(name, operand) => {
+                let operand = self.typed(function, operand);
+                self.line(format!("store {}, ptr {}", operand, global_name(name)));
+                return;
+            }
+            Op::Call(module, name, args) => {
+                let callee = llvm_function_name(module, name);
+                self.external.insert(callee.clone(), Some(args.len()));
+                let args: Vec<String> = args.iter().map(|arg| self.typed(function, arg)).collect();
+                format!("call float @{}({})", callee, args.join(", "))
+            }
+            Op::Eof => "load i32, ptr @tt_eof".into(),
+            Op::Input { prompt, location } => {
+                let result = result.unwrap();
+                let prompt = self.operand(prompt);
+                let location = self.string(location);
+                self.line(format!(
+                    "call void @tt_input(ptr %input{}, ptr {}, ptr {})",
+                    result, prompt, location
+                ));
+                format!("load float, ptr %input{}", result)
+            }
+            Op::PrintString(text) => {
+                let text = self.operand(text);
+                self.line(format!("call void @tt_print_string(ptr {})", text));
+                return;
+            }
+            Op::PrintNumber(value) => {
+                let value = self.typed(function, value);
+                self.line(format!("call void @tt_print_number({})", value));
+                return;
+            }
+            Op::PrintUsing(picture, value) => {
+                let format = self.string(&using_format(picture));
+                let value = self.typed(function, value);
+                self.line(format!(
+                    "call void @tt_print_using(ptr {}, {})",
+                    format, value
+                ));
+                return;
+            }
+            Op::PrintTab => {
+                self.line("call void @tt_print_tab()".into());
+                return;
+            }
+            Op::PrintNewline => {
+                self.line("call void @tt_print_newline()".into());
+                return;
+            }
+            Op::Import(name) => {
+                self.external.insert(format!("{}__init", name), None);
+                self.line(format!("call void @{}__init()", name));
+                return;
+            }
+        };
+        self.line(format!("%v{} = {}", result.unwrap(), value));
+    }
+
+    fn terminator(&mut self, function: &Function, terminator: &Terminator) {
+        match terminator {
+            Terminator::Jump(target) => self.line(format!("br label %b{}", target)),
+            Terminator::Branch(condition, then, otherwise) => {
+                let condition = self.operand(condition);
+                let taken = self.temporary();
+                self.line(format!("{} = icmp ne i32 {}, 0", taken, condition));
+                self.line(format!(
+                    "br i1 {}, label %b{}, label %b{}",
+                    taken, then, otherwise
+                ));
+            }
+            Terminator::Return(None) => self.line("ret void".into()),
+            Terminator::Return(Some(value)) => {
+                let value = self.typed(function, value);
+                self.line(format!("ret {}", value));
+            }
+            Terminator::Exit(status) => {
+                let status = self.typed(function, status);
+                self.line(format!("call void @exit({})", status));
+                self.line("unreachable".into());
+            }
+            Terminator::Stop(message) => {
+                let message = format!("{}\n", message);
+                let text = self.string(&message);
+                self.line(format!(
+                    "call i64 @write(i32 2, ptr {}, i64 {})",
+                    text,
+                    message.len()
+                ));
+                self.line("call void @exit(i32 1)".into());
+                self.line("unreachable".into());
+            }
+        }
+    }
+}
+
+impl ModuleFiles for LlvmEmitter {
+    // emitter for a module's own .ll file, next to this one's output
+    fn for_module(&self, name: &str) -> LlvmEmitter {
+        let full_path = Path::new(&self.full_path).with_file_name(format!("{}.ll", name));
+        let mut emitter = LlvmEmitter::new(full_path.display().to_string());
+        emitter.module = Some(name.into());
+        emitter.input_policy = self.input_policy;
+        emitter
+    }
+
+    fn program(&mut self, module: &Module) {
+        for name in &module.globals {
+            self.globals.push_str(&format!(
+                "{} = internal global float 0.0\n",
+                global_name(name)
+            ));
+        }
+        for function in &module.functions {
+            self.function(&module.name, function);
+        }
+        //calls into modules this file doesn't define
+        for function in &module.functions {
+            self.external
+                .remove(&llvm_function_name(&module.name, &function.name));
+        }
+    }
+
+    fn write_file(&mut self) -> String {
+        let mut value = String::from(LIBC);
+        match self.module {
+            Some(_) => value.push_str(RUNTIME_DECLARATIONS),
+            None => {
+                value.push_str(RUNTIME);
+                value.push_str(input_policy_code(self.input_policy));
+                value.push_str(RUNTIME_END);
+            }
+        }
+        if !self.external.is_empty() {
+            value.push('\n');
+        }
+        for (name, args) in &self.external {
+            match args {
+                Some(count) => value.push_str(&format!(
+                    "declare float @{}({})\n",
+                    name,
+                    vec!["float"; *count].join(", ")
+                )),
+                None => value.push_str(&format!("declare void @{}()\n", name)),
+            }
+        }
+        if !self.globals.is_empty() {
+            value.push('\n');
+            value.push_str(&self.globals);
+        }
+        value.push_str(&self.functions);
+        write_if_changed(Path::new(&self.full_path), &value);
+        self.full_path.clone()
+    }
+}
+
+impl Backend for LlvmEmitter {
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String> {
+        backend::emit_files(self, modules, program)
+    }
+
+    //llc makes position independent objects, which cc links as PIE the
+    //way it does by default. the IR uses opaque ptr, which LLVM 14 only
+    //reads with -opaque-pointers
+    fn finish(&self, files: &[String]) -> Option<String> {
+        let mut steps = Vec::new();
+        let mut objects = Vec::new();
+        for file in files {
+            let object = file.replace(".ll", ".o");
+            steps.push(format!(
+                "llc -opaque-pointers -relocation-model=pic -filetype=obj -o {} {}",
+                object, file
+            ));
+            objects.push(object);
+        }
+        steps.push(format!("cc {}", objects.join(" ")));
+        Some(format!("Build with: {}", steps.join(" && ")))
+    }
+}
+
+fn llvm_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "float",
+        Type::Int => "i32",
+        Type::String => "ptr",
+    }
+}
+
+// the symbol of a FUNCTION, the same name the C backend gives it
+fn llvm_function_name(module: &Option<String>, name: &str) -> String {
+    match module {
+        Some(module) => format!("{}__{}", module, name),
+        None => format!("tt_fn_{}", name),
+    }
+}
+
+// variables can be called anything, even exit or main, the dot keeps
+// them apart from functions
+fn global_name(name: &str) -> String {
+    format!("@var.{}", name)
+}
+
+// the ordered comparison, except != which like C is true for NaN
+fn llvm_predicate(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Eq => "oeq",
+        BinOp::Ne => "une",
+        BinOp::Lt => "olt",
+        BinOp::Le => "ole",
+        BinOp::Gt => "ogt",
+        _ => "oge",
+    }
+}
+
+// LLVM writes float constants as the hex of the same value as a double,
+// which covers NaN and infinity too
+fn llvm_float(value: f32) -> String {
+    format!("0x{:016X}", (value as f64).to_bits())
+}
+
+fn llvm_escape(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'"' && byte != b'\\' => (byte as char).to_string(),
+            _ => format!("\\{:02X}", byte),
+        })
+        .collect()
+}
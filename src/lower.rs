@@ -0,0 +1,636 @@
+use crate::analysis;
+use crate::ast::{self, CaseItem, Expr, LoopCondition, PrintItem, Stmt, StmtKind};
+use crate::ir::{
+    BinOp, Block, BlockId, Function, FunctionKind, Inst, Module, Op, Operand, Terminator, Type,
+    Value,
+};
+use crate::lex::TokenType;
+use std::collections::{HashMap, HashSet};
+
+// IR for a parsed program. every variable starts out as a load and store
+// by name, then the ones no FUNCTION shares become SSA values.
+pub fn program(program: &ast::Program) -> Module {
+    let shared = analysis::shared_globals(program);
+    let mut functions = Vec::new();
+
+    for function in &program.functions {
+        let memory = shared
+            .iter()
+            .copied()
+            .filter(|name| !analysis::is_local(function, name))
+            .collect();
+        let kind = FunctionKind::Function {
+            exported: function.exported,
+        };
+        let mut builder = Builder::new(&function.name, kind, Some(Type::Float), memory);
+        for parameter in &function.parameters {
+            let value = builder.function.new_value(Type::Float);
+            builder.function.parameters.push(value);
+            builder.function.names.insert(value, parameter.clone());
+            builder.push(Op::Store(parameter.clone(), Operand::Value(value)));
+        }
+        builder.block(&function.body);
+        //falling off the end returns 0
+        functions.push(builder.finish(Some(Operand::Float(0.0))));
+    }
+
+    let mut builder = match program.module {
+        Some(_) => Builder::new("init", FunctionKind::Init, None, shared.clone()),
+        None => Builder::new("main", FunctionKind::Main, Some(Type::Int), shared.clone()),
+    };
+    builder.block(&program.body);
+    let end = match program.module {
+        Some(_) => None,
+        None => Some(Operand::Int(0)),
+    };
+    functions.push(builder.finish(end));
+
+    Module {
+        name: program.module.clone(),
+        globals: program
+            .globals
+            .iter()
+            .filter(|name| shared.contains(name.as_str()))
+            .cloned()
+            .collect(),
+        functions,
+    }
+}
+
+struct Builder<'a> {
+    function: Function,
+    //instructions and terminator of each block, the terminator is None
+    //until the block is finished
+    blocks: Vec<(Vec<Inst>, Option<Terminator>)>,
+    current: BlockId,
+    labels: HashMap<&'a str, BlockId>,
+    //where CONTINUE and BREAK go in each enclosing loop
+    loops: Vec<(BlockId, BlockId)>,
+    //variables that stay loads and stores
+    memory: HashSet<&'a str>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(
+        name: &str,
+        kind: FunctionKind,
+        returns: Option<Type>,
+        memory: HashSet<&'a str>,
+    ) -> Builder<'a> {
+        Builder {
+            function: Function {
+                name: name.into(),
+                kind,
+                parameters: Vec::new(),
+                returns,
+                blocks: Vec::new(),
+                types: Vec::new(),
+                names: HashMap::new(),
+            },
+            blocks: vec![(Vec::new(), None)],
+            current: 0,
+            labels: HashMap::new(),
+            loops: Vec::new(),
+            memory,
+        }
+    }
+
+    fn finish(mut self, end: Option<Operand>) -> Function {
+        self.end_block(Terminator::Return(end));
+        self.function.blocks = self
+            .blocks
+            .into_iter()
+            .map(|(insts, terminator)| Block {
+                insts,
+                terminator: terminator.unwrap_or(Terminator::Return(None)),
+            })
+            .collect();
+        self.function.order_blocks();
+        promote_variables(&mut self.function, &self.memory);
+        self.function.number_values();
+        self.function
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push((Vec::new(), None));
+        self.blocks.len() - 1
+    }
+
+    fn push(&mut self, op: Op) {
+        self.blocks[self.current].0.push(Inst { result: None, op });
+    }
+
+    fn define(&mut self, ty: Type, op: Op) -> Operand {
+        let result = self.function.new_value(ty);
+        self.blocks[self.current].0.push(Inst {
+            result: Some(result),
+            op,
+        });
+        Operand::Value(result)
+    }
+
+    fn end_block(&mut self, terminator: Terminator) {
+        self.blocks[self.current].1 = Some(terminator);
+    }
+
+    // end the block with a jump somewhere else. what follows goes in a new
+    // block that only a label can make reachable.
+    fn jump_away(&mut self, terminator: Terminator) {
+        self.end_block(terminator);
+        self.current = self.new_block();
+    }
+
+    fn label(&mut self, name: &'a str) -> BlockId {
+        if let Some(&block) = self.labels.get(name) {
+            return block;
+        }
+        let block = self.new_block();
+        self.labels.insert(name, block);
+        block
+    }
+
+    fn block(&mut self, body: &'a [Stmt]) {
+        for stmt in body {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) {
+        match &stmt.kind {
+            StmtKind::Print {
+                using,
+                items,
+                newline,
+            } => {
+                for item in items {
+                    match item {
+                        PrintItem::Text(text) => {
+                            self.push(Op::PrintString(Operand::String(text.clone())))
+                        }
+                        PrintItem::Value(expr) => {
+                            let value = self.float(expr);
+                            match using {
+                                Some(picture) => self.push(Op::PrintUsing(picture.clone(), value)),
+                                None => self.push(Op::PrintNumber(value)),
+                            }
+                        }
+                        PrintItem::Tab => self.push(Op::PrintTab),
+                    }
+                }
+                if *newline {
+                    self.push(Op::PrintNewline);
+                }
+            }
+            StmtKind::If { condition, body } => {
+                let condition = self.condition(condition);
+                let then = self.new_block();
+                let after = self.new_block();
+                self.end_block(Terminator::Branch(condition, then, after));
+                self.current = then;
+                self.block(body);
+                self.end_block(Terminator::Jump(after));
+                self.current = after;
+            }
+            StmtKind::While { condition, body } => {
+                let test = self.new_block();
+                let start = self.new_block();
+                let after = self.new_block();
+                self.end_block(Terminator::Jump(test));
+                self.current = test;
+                let condition = self.condition(condition);
+                self.end_block(Terminator::Branch(condition, start, after));
+
+                self.current = start;
+                self.loops.push((test, after));
+                self.block(body);
+                self.loops.pop();
+                self.end_block(Terminator::Jump(test));
+                self.current = after;
+            }
+            StmtKind::Do {
+                body, condition, ..
+            } => {
+                let start = self.new_block();
+                let test = self.new_block();
+                let after = self.new_block();
+                self.end_block(Terminator::Jump(start));
+
+                self.current = start;
+                self.loops.push((test, after));
+                self.block(body);
+                self.loops.pop();
+                self.end_block(Terminator::Jump(test));
+
+                self.current = test;
+                let terminator = match condition {
+                    LoopCondition::Forever => Terminator::Jump(start),
+                    LoopCondition::Until(condition) => {
+                        Terminator::Branch(self.condition(condition), after, start)
+                    }
+                    LoopCondition::While(condition) => {
+                        Terminator::Branch(self.condition(condition), start, after)
+                    }
+                };
+                self.end_block(terminator);
+                self.current = after;
+            }
+            StmtKind::Select {
+                subject,
+                cases,
+                default,
+            } => {
+                //the subject is worked out once, each CASE item tests it
+                //in turn until one matches
+                let subject = self.float(subject);
+                let after = self.new_block();
+                for case in cases {
+                    let body = self.new_block();
+                    for item in &case.items {
+                        let next = self.new_block();
+                        self.case_item(&subject, item, body, next);
+                        self.current = next;
+                    }
+                    let next = self.current;
+                    self.current = body;
+                    self.block(&case.body);
+                    self.end_block(Terminator::Jump(after));
+                    self.current = next;
+                }
+                if let Some(body) = default {
+                    self.block(body);
+                }
+                self.end_block(Terminator::Jump(after));
+                self.current = after;
+            }
+            StmtKind::Break => {
+                let (_, after) = *self.loops.last().unwrap();
+                self.jump_away(Terminator::Jump(after));
+            }
+            StmtKind::Continue => {
+                let (test, _) = *self.loops.last().unwrap();
+                self.jump_away(Terminator::Jump(test));
+            }
+            StmtKind::Label(name) => {
+                let block = self.label(name);
+                self.end_block(Terminator::Jump(block));
+                self.current = block;
+            }
+            StmtKind::Goto(name) => {
+                let block = self.label(name);
+                self.jump_away(Terminator::Jump(block));
+            }
+            StmtKind::Let(name, value) => {
+                let value = self.float(value);
+                self.push(Op::Store(name.clone(), value));
+            }
+            StmtKind::Input {
+                prompt,
+                targets,
+                location,
+            } => {
+                for (index, target) in targets.iter().enumerate() {
+                    //the prompt is only shown before the first value
+                    let prompt = if index == 0 { prompt.as_str() } else { "" };
+                    let value = self.define(
+                        Type::Float,
+                        Op::Input {
+                            prompt: Operand::String(prompt.into()),
+                            location: location.clone(),
+                        },
+                    );
+                    self.push(Op::Store(target.clone(), value));
+                }
+            }
+            StmtKind::Call(call) => {
+                self.expression(call);
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.float(value),
+                    None => Operand::Float(0.0),
+                };
+                self.jump_away(Terminator::Return(Some(value)));
+            }
+            StmtKind::Import(name) => self.push(Op::Import(name.clone())),
+            StmtKind::End => self.jump_away(Terminator::Exit(Operand::Int(0))),
+            StmtKind::Stop { location, message } => {
+                let mut text = format!("STOP at {}", location);
+                if let Some(message) = message {
+                    text = format!("{}: {}", text, message);
+                }
+                self.jump_away(Terminator::Stop(text));
+            }
+            StmtKind::Exit(value) => {
+                let value = self.float(value);
+                let status = self.define(Type::Int, Op::FloatToInt(value));
+                self.jump_away(Terminator::Exit(status));
+            }
+        }
+    }
+
+    // branch to body when the item matches the subject, to next otherwise
+    fn case_item(&mut self, subject: &Operand, item: &'a CaseItem, body: BlockId, next: BlockId) {
+        let test = match item {
+            CaseItem::Is(op, value) => {
+                let value = self.float(value);
+                self.define(Type::Int, Op::Binary(bin_op(op), subject.clone(), value))
+            }
+            CaseItem::Range(low, high) => {
+                let low = self.float(low);
+                let above = self.define(Type::Int, Op::Binary(BinOp::Ge, subject.clone(), low));
+                let upper = self.new_block();
+                self.end_block(Terminator::Branch(above, upper, next));
+                self.current = upper;
+                let high = self.float(high);
+                self.define(Type::Int, Op::Binary(BinOp::Le, subject.clone(), high))
+            }
+            CaseItem::Value(value) => {
+                let value = self.float(value);
+                self.define(Type::Int, Op::Binary(BinOp::Eq, subject.clone(), value))
+            }
+        };
+        self.end_block(Terminator::Branch(test, body, next));
+    }
+
+    // an expression as an int that is 0 for false
+    fn condition(&mut self, expr: &'a Expr) -> Operand {
+        let value = self.expression(expr);
+        match self.function.operand_type(&value) {
+            Type::Float => {
+                self.define(Type::Int, Op::Binary(BinOp::Ne, value, Operand::Float(0.0)))
+            }
+            _ => value,
+        }
+    }
+
+    // an expression as a float, comparisons and EOF give 1 or 0
+    fn float(&mut self, expr: &'a Expr) -> Operand {
+        let value = self.expression(expr);
+        match self.function.operand_type(&value) {
+            Type::Int => self.define(Type::Float, Op::IntToFloat(value)),
+            _ => value,
+        }
+    }
+
+    fn expression(&mut self, expr: &'a Expr) -> Operand {
+        match expr {
+            Expr::Number(text) => Operand::Float(text.parse().unwrap()),
+            Expr::Variable(name) => self.define(Type::Float, Op::Load(name.clone())),
+            Expr::InputEof => self.define(Type::Int, Op::Eof),
+            Expr::Call(module, name, args) => {
+                let args = args.iter().map(|arg| self.float(arg)).collect();
+                self.define(Type::Float, Op::Call(module.clone(), name.clone(), args))
+            }
+            Expr::Unary(TokenType::MINUS, operand) => {
+                let operand = self.float(operand);
+                self.define(Type::Float, Op::Neg(operand))
+            }
+            Expr::Unary(_, operand) => self.float(operand),
+            Expr::Binary(left, op, right) => {
+                let left = self.float(left);
+                let right = self.float(right);
+                let op = bin_op(op);
+                let ty = match op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => Type::Float,
+                    _ => Type::Int,
+                };
+                self.define(ty, Op::Binary(op, left, right))
+            }
+        }
+    }
+}
+
+fn bin_op(op: &TokenType) -> BinOp {
+    match op {
+        TokenType::PLUS => BinOp::Add,
+        TokenType::MINUS => BinOp::Sub,
+        TokenType::ASTERISK => BinOp::Mul,
+        TokenType::SLASH => BinOp::Div,
+        TokenType::EQEQ => BinOp::Eq,
+        TokenType::NOTEQ => BinOp::Ne,
+        TokenType::LT => BinOp::Lt,
+        TokenType::LTEQ => BinOp::Le,
+        TokenType::GT => BinOp::Gt,
+        TokenType::GTEQ => BinOp::Ge,
+        _ => unreachable!("{:?} is not an operator", op),
+    }
+}
+
+// turn the variables not kept in memory into SSA values: a phi wherever
+// different assignments can meet (the dominance frontiers of the blocks
+// assigning it), every load replaced by the value that reaches it and
+// every store by a copy. nothing reaching a load means it reads 0, like a
+// C static.
+fn promote_variables(function: &mut Function, memory: &HashSet<&str>) {
+    let idom = function.dominators();
+    let predecessors = function.predecessors();
+    let mut frontiers = vec![HashSet::new(); function.blocks.len()];
+    for (block, predecessors) in predecessors.iter().enumerate() {
+        if predecessors.len() < 2 {
+            continue;
+        }
+        for &predecessor in predecessors {
+            let mut runner = predecessor;
+            while Some(runner) != idom[block] {
+                frontiers[runner].insert(block);
+                runner = idom[runner].unwrap();
+            }
+        }
+    }
+
+    //blocks assigning each variable, in the order variables first appear
+    let mut variables: Vec<String> = Vec::new();
+    let mut assigned: HashMap<String, Vec<BlockId>> = HashMap::new();
+    for (block, data) in function.blocks.iter().enumerate() {
+        for inst in &data.insts {
+            if let Op::Store(name, _) = &inst.op {
+                if memory.contains(name.as_str()) {
+                    continue;
+                }
+                let blocks = assigned.entry(name.clone()).or_insert_with(|| {
+                    variables.push(name.clone());
+                    Vec::new()
+                });
+                if !blocks.contains(&block) {
+                    blocks.push(block);
+                }
+            }
+        }
+    }
+
+    let mut phis: HashMap<Value, String> = HashMap::new();
+    let mut placed: Vec<(BlockId, Value)> = Vec::new();
+    for name in variables {
+        let mut has_phi = HashSet::new();
+        let mut work = assigned[&name].clone();
+        while let Some(block) = work.pop() {
+            let mut frontier: Vec<BlockId> = frontiers[block].iter().copied().collect();
+            frontier.sort();
+            for join in frontier {
+                if !has_phi.insert(join) {
+                    continue;
+                }
+                let value = function.new_value(Type::Float);
+                placed.push((join, value));
+                phis.insert(value, name.clone());
+                function.names.insert(value, name.clone());
+                if !assigned[&name].contains(&join) {
+                    work.push(join);
+                }
+            }
+        }
+    }
+    for (block, value) in placed {
+        function.blocks[block].insts.insert(
+            0,
+            Inst {
+                result: Some(value),
+                op: Op::Phi(Vec::new()),
+            },
+        );
+    }
+
+    let mut renamer = Renamer {
+        memory,
+        phis,
+        children: function.dominator_tree(),
+        current: HashMap::new(),
+        replaced: HashMap::new(),
+    };
+    renamer.rename(function, 0);
+    remove_dead_phis(function);
+}
+
+struct Renamer<'a> {
+    memory: &'a HashSet<&'a str>,
+    //the variable each inserted phi is for
+    phis: HashMap<Value, String>,
+    children: Vec<Vec<BlockId>>,
+    //value of each variable at this point, innermost last
+    current: HashMap<String, Vec<Operand>>,
+    //loads and what they read
+    replaced: HashMap<Value, Operand>,
+}
+
+impl Renamer<'_> {
+    fn value_of(&self, name: &str) -> Operand {
+        self.current
+            .get(name)
+            .and_then(|values| values.last())
+            .cloned()
+            .unwrap_or(Operand::Float(0.0))
+    }
+
+    fn resolve(&self, operand: &mut Operand) {
+        if let Operand::Value(value) = operand {
+            if let Some(replacement) = self.replaced.get(value) {
+                *operand = replacement.clone();
+            }
+        }
+    }
+
+    // walks the dominator tree, so every load sees the assignment that
+    // dominates it
+    fn rename(&mut self, function: &mut Function, block: BlockId) {
+        let mut defined = Vec::new();
+        let insts = std::mem::take(&mut function.blocks[block].insts);
+        for mut inst in insts {
+            if let Some(name) = inst.result.and_then(|result| self.phis.get(&result)) {
+                let name = name.clone();
+                self.current
+                    .entry(name.clone())
+                    .or_default()
+                    .push(Operand::Value(inst.result.unwrap()));
+                defined.push(name);
+                function.blocks[block].insts.push(inst);
+                continue;
+            }
+            for operand in inst.op.operands_mut() {
+                self.resolve(operand);
+            }
+            match &inst.op {
+                Op::Load(name) if !self.memory.contains(name.as_str()) => {
+                    let value = self.value_of(name);
+                    self.replaced.insert(inst.result.unwrap(), value);
+                    continue;
+                }
+                Op::Store(name, value) if !self.memory.contains(name.as_str()) => {
+                    let copy = function.new_value(Type::Float);
+                    let name = name.clone();
+                    function.names.insert(copy, name.clone());
+                    inst = Inst {
+                        result: Some(copy),
+                        op: Op::Copy(value.clone()),
+                    };
+                    self.current
+                        .entry(name.clone())
+                        .or_default()
+                        .push(Operand::Value(copy));
+                    defined.push(name);
+                }
+                _ => {}
+            }
+            function.blocks[block].insts.push(inst);
+        }
+        for operand in function.blocks[block].terminator.operands_mut() {
+            self.resolve(operand);
+        }
+
+        let mut successors = function.blocks[block].terminator.successors();
+        successors.dedup();
+        for successor in successors {
+            for inst in &mut function.blocks[successor].insts {
+                let Some(name) = inst.result.and_then(|result| self.phis.get(&result)) else {
+                    continue;
+                };
+                let value = self.value_of(name);
+                if let Op::Phi(incoming) = &mut inst.op {
+                    incoming.push((block, value));
+                }
+            }
+        }
+
+        for child in self.children[block].clone() {
+            self.rename(function, child);
+        }
+        for name in defined {
+            self.current.get_mut(&name).unwrap().pop();
+        }
+    }
+}
+
+// phis nothing but themselves use, left where a variable is assigned
+// but not read after the join
+fn remove_dead_phis(function: &mut Function) {
+    loop {
+        let mut used = vec![false; function.types.len()];
+        for block in &function.blocks {
+            for inst in &block.insts {
+                for operand in inst.op.operands() {
+                    if let Operand::Value(value) = operand {
+                        if Some(*value) != inst.result {
+                            used[value.0] = true;
+                        }
+                    }
+                }
+            }
+            for operand in block.terminator.operands() {
+                if let Operand::Value(value) = operand {
+                    used[value.0] = true;
+                }
+            }
+        }
+
+        let mut changed = false;
+        for block in &mut function.blocks {
+            block.insts.retain(|inst| {
+                let dead = matches!(inst.op, Op::Phi(_)) && !used[inst.result.unwrap().0];
+                changed |= dead;
+                !dead
+            });
+        }
+        if !changed {
+            break;
+        }
+    }
+}
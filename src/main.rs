@@ -1,32 +1,131 @@
-use emit::Emitter;
-use lex::Lexer;
-use parse::Parser;
+use std::path::PathBuf;
 use std::{env, fs, io::Read};
+use teeny_tiny_rust::backend::InputPolicy;
+use teeny_tiny_rust::backend::{self, Constructor};
+use teeny_tiny_rust::diag::Diagnostics;
+use teeny_tiny_rust::lex::Lexer;
+use teeny_tiny_rust::parse::Parser;
+use teeny_tiny_rust::passes::PassManager;
+use teeny_tiny_rust::{analysis, cfg, lower, opt};
 
-mod emit;
-mod lex;
-mod parse;
+// what the compiler writes out
+enum Output {
+    //the control-flow graph, as Graphviz DOT on stdout
+    CfgDot,
+    //a backend's output, from the IR
+    Backend(Constructor),
+}
 
 fn main() {
-    println!("Teeny Tiny Compiler - Rust edition");
-
     let mut source = String::new();
+    let mut file_path = None;
+    let mut input_policy = InputPolicy::Zero;
+    let mut include_paths = Vec::new();
+    let mut output = Output::Backend(backend::by_name("c").unwrap());
+    let mut diagnostics = Diagnostics::new();
+    let mut optimize = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-I" {
+            //-I dir adds a directory to search for INCLUDE files
+            let directory = args.next().expect("Error: -I needs a directory");
+            include_paths.push(PathBuf::from(directory));
+        } else if let Some(directory) = arg.strip_prefix("-I") {
+            include_paths.push(PathBuf::from(directory));
+        } else if let Some(name) = arg.strip_prefix("--input-policy=") {
+            //what INPUT does with text that isn't a number
+            input_policy = InputPolicy::from_name(name).unwrap_or_else(|| {
+                panic!("Error: Unknown input policy {name}, expected zero, reprompt or error")
+            });
+        } else if arg == "-O" {
+            optimize = true;
+        } else if let Some(name) = arg.strip_prefix("--emit=") {
+            output = match name {
+                "cfg-dot" => Output::CfgDot,
+                _ => Output::Backend(backend::by_name(name).unwrap_or_else(|| {
+                    panic!("Error: Unknown output {name}, expected c, cfg-dot, ir, asm, llvm, wasm, js, python, rust or qbe")
+                })),
+            };
+        } else if diagnostics.flag(&arg) {
+            //-Wname and -Wno-name switch a warning on and off, -Werror
+            //makes them fail the compile
+        } else if arg.starts_with('-') {
+            panic!("Error: Unknown option {arg}");
+        } else if file_path.replace(arg).is_some() {
+            panic!("Error: Compiler needs exactly one source file");
+        }
+    }
 
-    if env::args().len() != 2 {
+    let Some(file_path) = file_path else {
         panic!("Error: Compiler needs source file as argument");
+    };
+
+    let backend = match output {
+        Output::CfgDot => None,
+        Output::Backend(constructor) => Some(constructor(input_policy)),
+    };
+    //DOT and IR go to stdout, so nothing else may
+    if backend
+        .as_ref()
+        .is_some_and(|backend| !backend.writes_stdout())
+    {
+        println!("Teeny Tiny Compiler - Rust edition");
     }
 
-    if let Some(file_path) = env::args().nth(1) {
-        //open file provided in args
-        let mut file = fs::File::open(file_path).unwrap();
-        file.read_to_string(&mut source).unwrap();
+    //open file provided in args
+    let mut file = fs::File::open(&file_path).unwrap();
+    file.read_to_string(&mut source).unwrap();
+
+    let lexer = Lexer::new(source, PathBuf::from(file_path));
+    let mut parser = Parser::new(lexer);
+    parser.include_paths = include_paths;
+    let mut program = parser.program(); //start parser
+
+    //imported modules come first, each before the modules that import it
+    let mut modules = std::mem::take(&mut parser.modules.programs);
+    for module in modules.iter().chain([&program]) {
+        analysis::check_definite_assignment(module);
+        analysis::check_control_flow(module, &mut diagnostics);
+        analysis::check_variables(module, &mut diagnostics);
     }
+    diagnostics.finish();
 
-    let lexer = Lexer::new(source);
-    let emitter = Emitter::new("out.c".to_string());
-    let mut parser = Parser::new(lexer, emitter);
+    let Some(mut backend) = backend else {
+        for module in modules.iter().chain([&program]) {
+            print!("{}", cfg::program_dot(module));
+        }
+        return;
+    };
 
-    parser.program(); //start parser
-    parser.emitter.write_file(); // write output to file
-    println!("Parsing completed")
+    for module in modules.iter_mut().chain([&mut program]) {
+        if optimize {
+            opt::fold_constants(module);
+            opt::simplify_control_flow(module);
+        }
+        analysis::drop_unread_variables(module);
+    }
+
+    let mut ir: Vec<_> = modules
+        .iter()
+        .chain([&program])
+        .map(lower::program)
+        .collect();
+    if optimize {
+        let passes = PassManager::optimizing();
+        for module in &mut ir {
+            passes.run(module);
+        }
+    }
+    let program = ir.pop().unwrap();
+    let modules = ir;
+
+    let files = backend.emit(&modules, &program);
+    if backend.writes_stdout() {
+        return;
+    }
+    println!("Parsing completed");
+    if let Some(next) = backend.finish(&files) {
+        println!("{}", next);
+    }
 }
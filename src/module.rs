@@ -0,0 +1,18 @@
+use crate::ast::Program;
+use std::collections::HashMap;
+
+// what a compiled MODULE offers the files that import it
+pub struct ModuleInterface {
+    //exported FUNCTIONs and how many arguments they take
+    pub functions: HashMap<String, usize>,
+}
+
+// every module compiled so far, shared by the parsers of one compile
+#[derive(Default)]
+pub struct ModuleRegistry {
+    pub compiled: HashMap<String, ModuleInterface>,
+    //modules being compiled right now, outermost import first
+    pub in_progress: Vec<String>,
+    //parsed modules, each after the modules it imports
+    pub programs: Vec<Program>,
+}
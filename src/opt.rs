@@ -0,0 +1,509 @@
+use crate::ast::{CaseItem, Expr, LoopCondition, PrintItem, Program, Stmt, StmtKind};
+use crate::cfg::{Cfg, Edge, NodeKind, ENTRY};
+use crate::lex::TokenType;
+use crate::symbols::Span;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// fold constant subexpressions and drop operations that can't change a
+// float. folding is done in f32 like the C, and anything giving NaN is
+// left for the C to work out, so the program prints exactly what it would
+// have unoptimized.
+pub fn fold_constants(program: &mut Program) {
+    fold_body(&mut program.body);
+    for function in &mut program.functions {
+        fold_body(&mut function.body);
+    }
+}
+
+fn fold_body(body: &mut Vec<Stmt>) {
+    for mut stmt in std::mem::take(body) {
+        match &mut stmt.kind {
+            StmtKind::If {
+                condition,
+                body: inner,
+            } => {
+                fold_in_place(condition);
+                fold_body(inner);
+                match condition.constant_value() {
+                    //NaN counts as true in C as well
+                    Some(value) if value != 0.0 => {
+                        body.append(inner);
+                        continue;
+                    }
+                    Some(_) if !contains_label(inner) => continue,
+                    _ => {}
+                }
+            }
+            StmtKind::While {
+                condition,
+                body: inner,
+            } => {
+                fold_in_place(condition);
+                fold_body(inner);
+                if condition.constant_value() == Some(0.0) && !contains_label(inner) {
+                    continue;
+                }
+            }
+            StmtKind::Do {
+                body: inner,
+                condition,
+                ..
+            } => {
+                fold_body(inner);
+                if let LoopCondition::Until(condition) | LoopCondition::While(condition) = condition
+                {
+                    fold_in_place(condition);
+                }
+            }
+            StmtKind::Select {
+                subject,
+                cases,
+                default,
+            } => {
+                fold_in_place(subject);
+                for case in cases {
+                    for item in &mut case.items {
+                        match item {
+                            CaseItem::Is(_, value) | CaseItem::Value(value) => fold_in_place(value),
+                            CaseItem::Range(low, high) => {
+                                fold_in_place(low);
+                                fold_in_place(high);
+                            }
+                        }
+                    }
+                    fold_body(&mut case.body);
+                }
+                if let Some(default) = default {
+                    fold_body(default);
+                }
+            }
+            StmtKind::Print { items, .. } => {
+                for item in items {
+                    if let PrintItem::Value(value) = item {
+                        fold_in_place(value);
+                    }
+                }
+            }
+            StmtKind::Let(_, value)
+            | StmtKind::Call(value)
+            | StmtKind::Return(Some(value))
+            | StmtKind::Exit(value) => fold_in_place(value),
+            _ => {}
+        }
+        body.push(stmt);
+    }
+}
+
+// a label inside keeps a block alive whatever its condition, GOTO can reach it
+pub fn contains_label(body: &[Stmt]) -> bool {
+    body.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Label(_) => true,
+        StmtKind::If { body, .. } | StmtKind::While { body, .. } | StmtKind::Do { body, .. } => {
+            contains_label(body)
+        }
+        StmtKind::Select { cases, default, .. } => {
+            cases.iter().any(|case| contains_label(&case.body))
+                || default.as_deref().is_some_and(contains_label)
+        }
+        _ => false,
+    })
+}
+
+fn fold_in_place(expr: &mut Expr) {
+    let folded = fold(std::mem::replace(expr, Expr::InputEof));
+    *expr = folded;
+}
+
+pub fn fold(expr: Expr) -> Expr {
+    match expr {
+        Expr::Call(module, name, args) => {
+            Expr::Call(module, name, args.into_iter().map(fold).collect())
+        }
+        Expr::Unary(op, operand) => match (op, fold(*operand)) {
+            //unary + does nothing to a float
+            (TokenType::PLUS, operand) => operand,
+            (TokenType::MINUS, Expr::Unary(TokenType::MINUS, inner)) => *inner,
+            (op, operand) => {
+                let expr = Expr::Unary(op, Box::new(operand));
+                match expr.constant_value() {
+                    Some(value) if !value.is_nan() => Expr::number(value),
+                    _ => expr,
+                }
+            }
+        },
+        Expr::Binary(left, op, right) => {
+            let expr = Expr::Binary(Box::new(fold(*left)), op, Box::new(fold(*right)));
+            match expr.constant_value() {
+                Some(value) if !value.is_nan() => Expr::number(value),
+                _ => simplify(expr),
+            }
+        }
+        Expr::Number(_) | Expr::Variable(_) | Expr::InputEof => expr,
+    }
+}
+
+// identities that hold for every float, NaN, infinities and -0 included.
+// x+0 and x*0 are not among them: -0+0 is +0 and NaN*0 is NaN.
+fn simplify(expr: Expr) -> Expr {
+    let Expr::Binary(left, op, right) = expr else {
+        return expr;
+    };
+    let is = |expr: &Expr, wanted: f32| {
+        expr.constant_value().is_some_and(|value| {
+            value == wanted && value.is_sign_negative() == wanted.is_sign_negative()
+        })
+    };
+
+    match op {
+        TokenType::ASTERISK if is(&right, 1.0) => *left,
+        TokenType::ASTERISK if is(&left, 1.0) => *right,
+        TokenType::SLASH if is(&right, 1.0) => *left,
+        TokenType::MINUS if is(&right, 0.0) => *left,
+        TokenType::PLUS if is(&right, -0.0) => *left,
+        TokenType::PLUS if is(&left, -0.0) => *right,
+        _ => Expr::Binary(left, op, right),
+    }
+}
+
+// remove statements nothing reaches, send GOTOs straight to where the
+// labels they name jump on to, and drop GOTOs to the very next statement
+// and labels nothing jumps to, so straight-line code ends up in one block
+pub fn simplify_control_flow(program: &mut Program) {
+    simplify_body(&mut program.body, Span { file: 0, line: 1 });
+    for function in &mut program.functions {
+        simplify_body(&mut function.body, function.span);
+    }
+}
+
+fn simplify_body(body: &mut Vec<Stmt>, span: Span) {
+    //each change can open the way for another, go until nothing changes
+    loop {
+        let (keep, targets) = {
+            let cfg = Cfg::build(body, span);
+            let live = live_statements(&cfg);
+            let mut keep = Vec::new();
+            mark_kept(body, &live, &mut keep);
+            (keep, jump_targets(&cfg))
+        };
+
+        let mut changed = sweep(body, &keep, &mut 0);
+        for_each_stmt(body, &mut |stmt| {
+            if let StmtKind::Goto(label) = &mut stmt.kind {
+                if let Some(target) = targets.get(label) {
+                    *label = target.clone();
+                    changed = true;
+                }
+            }
+        });
+        changed |= remove_jumps_to_next(body);
+
+        let mut jumped_to = HashSet::new();
+        for_each_stmt(body, &mut |stmt| {
+            if let StmtKind::Goto(label) = &stmt.kind {
+                jumped_to.insert(label.clone());
+            }
+        });
+        changed |= remove_labels(body, &jumped_to);
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+// statements with a node some path from the start reaches. branches on a
+// constant condition only go the one way.
+fn live_statements(cfg: &Cfg) -> HashSet<*const Stmt> {
+    let mut reached = HashSet::from([ENTRY]);
+    let mut queue = VecDeque::from([ENTRY]);
+    while let Some(node) = queue.pop_front() {
+        let taken = match cfg.nodes[node].kind {
+            NodeKind::Condition(_, condition) => condition.constant_value().map(|value| {
+                if value != 0.0 {
+                    Edge::True
+                } else {
+                    Edge::False
+                }
+            }),
+            _ => None,
+        };
+        for &(successor, edge) in &cfg.nodes[node].successors {
+            let dead = matches!(edge, Edge::True | Edge::False)
+                && taken.is_some_and(|taken| taken != edge);
+            if !dead && reached.insert(successor) {
+                queue.push_back(successor);
+            }
+        }
+    }
+
+    reached
+        .into_iter()
+        .filter_map(|node| match cfg.nodes[node].kind {
+            NodeKind::Statement(stmt)
+            | NodeKind::Condition(stmt, _)
+            | NodeKind::Join(stmt)
+            | NodeKind::Select(stmt, _) => Some(stmt as *const Stmt),
+            _ => None,
+        })
+        .collect()
+}
+
+// whether to keep each statement, in the order sweep walks them. compound
+// statements stay while anything inside them is live, GOTO can jump in.
+fn mark_kept(body: &[Stmt], live: &HashSet<*const Stmt>, keep: &mut Vec<bool>) -> bool {
+    let mut any = false;
+    for stmt in body {
+        let index = keep.len();
+        keep.push(false);
+        let inside = child_bodies(stmt)
+            .into_iter()
+            .fold(false, |inside, body| mark_kept(body, live, keep) | inside);
+        keep[index] = live.contains(&(stmt as *const Stmt)) || inside;
+        any |= keep[index];
+    }
+    any
+}
+
+fn sweep(body: &mut Vec<Stmt>, keep: &[bool], next: &mut usize) -> bool {
+    let mut changed = false;
+    for mut stmt in std::mem::take(body) {
+        let kept = keep[*next];
+        *next += 1;
+        for inner in child_bodies_mut(&mut stmt) {
+            changed |= sweep(inner, keep, next);
+        }
+        if kept {
+            body.push(stmt);
+        } else {
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn child_bodies(stmt: &Stmt) -> Vec<&Vec<Stmt>> {
+    match &stmt.kind {
+        StmtKind::If { body, .. } | StmtKind::While { body, .. } | StmtKind::Do { body, .. } => {
+            vec![body]
+        }
+        StmtKind::Select { cases, default, .. } => cases
+            .iter()
+            .map(|case| &case.body)
+            .chain(default.as_ref())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn child_bodies_mut(stmt: &mut Stmt) -> Vec<&mut Vec<Stmt>> {
+    match &mut stmt.kind {
+        StmtKind::If { body, .. } | StmtKind::While { body, .. } | StmtKind::Do { body, .. } => {
+            vec![body]
+        }
+        StmtKind::Select { cases, default, .. } => cases
+            .iter_mut()
+            .map(|case| &mut case.body)
+            .chain(default.as_mut())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn for_each_stmt(body: &mut [Stmt], f: &mut dyn FnMut(&mut Stmt)) {
+    for stmt in body {
+        f(stmt);
+        for inner in child_bodies_mut(stmt) {
+            for_each_stmt(inner, f);
+        }
+    }
+}
+
+// labels that only lead on to a GOTO, and the label that GOTO chain ends at
+fn jump_targets(cfg: &Cfg) -> HashMap<String, String> {
+    let labels: HashMap<&str, usize> = cfg
+        .nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(index, node)| match node.kind {
+            NodeKind::Statement(Stmt {
+                kind: StmtKind::Label(label),
+                ..
+            }) => Some((label.as_str(), index)),
+            _ => None,
+        })
+        .collect();
+
+    let mut targets = HashMap::new();
+    for (&label, &start) in &labels {
+        let mut target = label;
+        let mut node = start;
+        let mut seen = HashSet::new();
+        loop {
+            if !seen.insert(node) {
+                //a loop of GOTOs, leave it be
+                target = label;
+                break;
+            }
+            match cfg.nodes[node].kind {
+                NodeKind::Statement(Stmt {
+                    kind: StmtKind::Label(_),
+                    ..
+                }) => match cfg.nodes[node].successors[..] {
+                    [(next, Edge::Next)] => node = next,
+                    _ => break,
+                },
+                NodeKind::Statement(Stmt {
+                    kind: StmtKind::Goto(next),
+                    ..
+                }) => {
+                    target = next;
+                    node = labels[next.as_str()];
+                }
+                _ => break,
+            }
+        }
+        if target != label {
+            targets.insert(label.to_string(), target.to_string());
+        }
+    }
+    targets
+}
+
+// a GOTO to one of the labels right after it does nothing
+fn remove_jumps_to_next(body: &mut Vec<Stmt>) -> bool {
+    let mut changed = false;
+    let mut index = 0;
+    while index < body.len() {
+        if let StmtKind::Goto(target) = &body[index].kind {
+            let next_labels = body[index + 1..].iter().map_while(|stmt| match &stmt.kind {
+                StmtKind::Label(label) => Some(label),
+                _ => None,
+            });
+            if next_labels.clone().any(|label| label == target) {
+                body.remove(index);
+                changed = true;
+                continue;
+            }
+        }
+        for inner in child_bodies_mut(&mut body[index]) {
+            changed |= remove_jumps_to_next(inner);
+        }
+        index += 1;
+    }
+    changed
+}
+
+fn remove_labels(body: &mut Vec<Stmt>, jumped_to: &HashSet<String>) -> bool {
+    let before = body.len();
+    body.retain(|stmt| !matches!(&stmt.kind, StmtKind::Label(label) if !jumped_to.contains(label)));
+    let mut changed = body.len() != before;
+    for stmt in body {
+        for inner in child_bodies_mut(stmt) {
+            changed |= remove_labels(inner, jumped_to);
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parse::Parser;
+    use std::path::PathBuf;
+
+    fn parse(source: &str) -> Program {
+        let lexer = Lexer::new(source.to_string(), PathBuf::from("test.teeny"));
+        Parser::new(lexer).program()
+    }
+
+    // an expression after folding, with y a variable
+    fn folded(expression: &str) -> String {
+        let mut program = parse(&format!("INPUT y\nLET x = {}\n", expression));
+        fold_constants(&mut program);
+        match &program.body[1].kind {
+            StmtKind::Let(_, value) => value.to_string(),
+            kind => panic!("{:?}", kind),
+        }
+    }
+
+    #[test]
+    fn constants_fold_in_f32() {
+        assert_eq!(folded("2 * 3 + 1"), "7.0");
+        assert_eq!(folded("16777216 + 1"), "16777216.0");
+        assert_eq!(folded("-y * 1"), "-y");
+        assert_eq!(folded("1 / 0"), "inf");
+        //NaN's sign is the C's to choose
+        assert_eq!(folded("0 / 0"), "0 / 0");
+    }
+
+    #[test]
+    fn identities_that_hold_for_every_float() {
+        assert_eq!(folded("y * 1"), "y");
+        assert_eq!(folded("1 * y / 1"), "y");
+        assert_eq!(folded("y - 0"), "y");
+        assert_eq!(folded("y + -0"), "y");
+        //-0 + 0 is 0, NaN * 0 is NaN
+        assert_eq!(folded("y + 0"), "y + 0");
+        assert_eq!(folded("y * 0"), "y * 0");
+    }
+
+    // the statements of a body, one line each and nested ones indented
+    fn outline(body: &[Stmt]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for stmt in body {
+            lines.push(match &stmt.kind {
+                StmtKind::Label(label) => format!("LABEL {}", label),
+                StmtKind::Goto(label) => format!("GOTO {}", label),
+                StmtKind::Let(name, value) => format!("LET {} = {}", name, value),
+                StmtKind::If { condition, .. } => format!("IF {}", condition),
+                StmtKind::While { condition, .. } => format!("WHILE {}", condition),
+                kind => format!("{:?}", kind)
+                    .split([' ', '{', '('])
+                    .next()
+                    .unwrap()
+                    .into(),
+            });
+            for body in child_bodies(stmt) {
+                lines.extend(outline(body).into_iter().map(|line| format!("  {}", line)));
+            }
+        }
+        lines
+    }
+
+    fn simplified(source: &str) -> Vec<String> {
+        let mut program = parse(source);
+        fold_constants(&mut program);
+        simplify_control_flow(&mut program);
+        outline(&program.body)
+    }
+
+    #[test]
+    fn branches_on_constants() {
+        let source = "IF 1 > 2 THEN\nLET a = 1\nENDIF\nIF 2 > 1 THEN\nLET a = 2\nENDIF\n\
+                      WHILE 0 > 1 REPEAT\nLET a = 3\nENDWHILE\nIF 0 == 1 THEN\nLABEL in\nLET a = 4\nENDIF\n\
+                      PRINT a\nGOTO in\n";
+        assert_eq!(
+            simplified(source),
+            [
+                "LET a = 2",
+                "IF 0.0",
+                "  LABEL in",
+                "  LET a = 4",
+                "Print",
+                "GOTO in"
+            ]
+        );
+    }
+
+    #[test]
+    fn goto_chains_and_code_nothing_reaches() {
+        let source = "LABEL top\nPRINT 1\nGOTO middle\nPRINT 0\nLABEL middle\nGOTO top\n";
+        assert_eq!(simplified(source), ["LABEL top", "Print", "GOTO top"]);
+
+        //straight-line code once the jumps are gone
+        let source = "GOTO one\nLABEL two\nPRINT 2\nGOTO three\nLABEL one\nGOTO two\n\
+                      PRINT 1\nLABEL three\nLABEL unused\nPRINT 3\n";
+        assert_eq!(simplified(source), ["Print", "Print"]);
+    }
+}
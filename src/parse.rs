@@ -1,43 +1,107 @@
-use crate::emit::Emitter;
+use crate::ast::{
+    Case, CaseItem, Expr, Function, LoopCondition, PrintItem, Program, Stmt, StmtKind,
+};
 use crate::lex::{Lexer, Token, TokenType};
+use crate::module::{ModuleInterface, ModuleRegistry};
+use crate::source::{self, SourceFile};
+use crate::symbols::{Namespace, Span, Symbol, SymbolKind, SymbolTable};
 use std::collections::HashSet;
-use std::fmt::format;
+use std::fs;
+use std::path::PathBuf;
 
 pub struct Parser {
-    lexer: Lexer,
-    pub emitter: Emitter,
+    //lexers of the files being read, the innermost INCLUDE on top
+    lexers: Vec<Lexer>,
+    files: Vec<SourceFile>,
+    //canonical paths of every file read so far, for include-once
+    included: HashSet<PathBuf>,
+    //directories searched for INCLUDE files after the including file's own
+    pub include_paths: Vec<PathBuf>,
     current_token: Option<Token>,
     peek_token: Option<Token>,
-    symbols: HashSet<String>,
-    labels_declared: HashSet<String>,
-    labels_gotoed: HashSet<String>,
+    symbols: SymbolTable,
+    //GOTOs of the current scope, checked against its labels at the end
+    gotos: Vec<(String, Span)>,
+    //variables read before any assignment to them has been seen, resolved
+    //at the end of their scope
+    unresolved: Vec<(String, Span)>,
+    globals: Vec<String>,
+    //locals of the FUNCTION being parsed
+    locals: Vec<String>,
+    functions: Vec<Function>,
+    loop_depth: usize,
+    //name of the MODULE being compiled, None for the main program
+    module_name: Option<String>,
+    pub modules: ModuleRegistry,
+}
+
+// values matched by a constant CASE item, used to spot overlapping cases
+struct CaseRange {
+    low: f32,
+    low_open: bool,
+    high: f32,
+    high_open: bool,
+}
+
+impl CaseRange {
+    fn overlaps(&self, other: &CaseRange) -> bool {
+        //intersect the two ranges and see if anything is left
+        let (low, low_open) = if self.low == other.low {
+            (self.low, self.low_open || other.low_open)
+        } else if self.low > other.low {
+            (self.low, self.low_open)
+        } else {
+            (other.low, other.low_open)
+        };
+        let (high, high_open) = if self.high == other.high {
+            (self.high, self.high_open || other.high_open)
+        } else if self.high < other.high {
+            (self.high, self.high_open)
+        } else {
+            (other.high, other.high_open)
+        };
+
+        low < high || (low == high && !low_open && !high_open)
+    }
 }
 
 impl Parser {
-    pub fn new(lexer: Lexer, emitter: Emitter) -> Parser {
+    pub fn new(lexer: Lexer) -> Parser {
+        //a module's lexer comes with the note saying where it is imported
+        let files = vec![SourceFile {
+            path: lexer.path.clone(),
+            included_from: None,
+            import_note: lexer.include_note.clone(),
+        }];
+        let included = lexer.path.canonicalize().into_iter().collect();
+
         let mut parser = Parser {
-            lexer,
-            emitter,
+            lexers: vec![lexer],
+            files,
+            included,
+            include_paths: Vec::new(),
             current_token: None,
             peek_token: None,
-            symbols: HashSet::new(),
-            labels_declared: HashSet::new(),
-            labels_gotoed: HashSet::new(),
+            symbols: SymbolTable::new(),
+            gotos: Vec::new(),
+            unresolved: Vec::new(),
+            globals: Vec::new(),
+            locals: Vec::new(),
+            functions: Vec::new(),
+            loop_depth: 0,
+            module_name: None,
+            modules: ModuleRegistry::default(),
         };
 
         //initialize current and peek token
         parser.next_token();
         parser.next_token();
 
-        return parser;
+        parser
     }
 
     fn check_token(&mut self, kind: TokenType) -> bool {
-        return kind == self.current_token.clone().unwrap().kind;
-    }
-
-    fn check_peek(&mut self, kind: TokenType) -> bool {
-        return kind == self.peek_token.clone().unwrap().kind;
+        kind == self.current_token.clone().unwrap().kind
     }
 
     fn match_token(&mut self, kind: TokenType) {
@@ -54,179 +118,811 @@ impl Parser {
 
     fn next_token(&mut self) {
         self.current_token = self.peek_token.clone();
-        self.peek_token = self.lexer.get_token();
+        self.peek_token = self.lexers.last_mut().unwrap().get_token();
+
+        //the end of an included file carries on in the file that included it
+        while self.check_peek(TokenType::EOF) && self.lexers.len() > 1 {
+            self.lexers.pop();
+            self.peek_token = self.lexers.last_mut().unwrap().get_token();
+        }
     }
 
-    fn abort_operation(&self, message: String) {
-        panic!("Error. {message}")
+    fn check_peek(&self, kind: TokenType) -> bool {
+        kind == self.peek_token.as_ref().unwrap().kind
     }
 
-    // program::={statement}
-    pub fn program(&mut self) {
-        self.emitter.header_line("#include <stdio.h>".into());
-        self.emitter.header_line("int main(void){".into());
+    fn abort_operation(&self, message: String) -> ! {
+        self.abort_at(self.span(), message)
+    }
+
+    fn abort_at(&self, span: Span, message: String) -> ! {
+        source::abort_at(&self.files, span, message)
+    }
 
+    // abort, pointing at where the symbol involved was declared
+    fn abort_with_note(&self, message: String, symbol: &Symbol) -> ! {
+        self.abort_with_note_at(self.span(), message, symbol)
+    }
+
+    fn abort_with_note_at(&self, span: Span, message: String, symbol: &Symbol) -> ! {
+        self.abort_at(
+            span,
+            format!(
+                "{}\n  note: {} was declared in {} on line {}",
+                message,
+                symbol.name,
+                self.files[symbol.span.file].path.display(),
+                symbol.span.line
+            ),
+        )
+    }
+
+    fn span(&self) -> Span {
+        let token = self.current_token.as_ref().unwrap();
+        Span {
+            file: token.file,
+            line: token.line,
+        }
+    }
+
+    // add a symbol to the current scope, it's an error if the name is taken
+    fn declare(&mut self, name: String, kind: SymbolKind) {
+        self.declare_at(name, kind, self.span());
+    }
+
+    fn declare_at(&mut self, name: String, kind: SymbolKind, span: Span) {
+        let symbol = Symbol::new(name, kind, span);
+        if let Err(existing) = self.symbols.declare(symbol) {
+            let existing = existing.clone();
+            let what = match existing.kind {
+                SymbolKind::Variable => "Variable",
+                SymbolKind::Constant(_) => "Constant",
+                SymbolKind::Label => "Label",
+                SymbolKind::Function { .. } => "Function",
+                SymbolKind::Module => "Module",
+            };
+            self.abort_with_note(
+                format!("{} already exists {}", what, existing.name),
+                &existing,
+            )
+        }
+    }
+
+    // where a token is, in words for runtime messages in the generated C
+    fn runtime_location(&self, token: &Token) -> String {
+        let span = Span {
+            file: token.file,
+            line: token.line,
+        };
+        source::describe(&self.files, span)
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+    }
+
+    // look next to the current file first, then along the include paths
+    fn find_file(&self, name: &str) -> Option<PathBuf> {
+        let token = self.current_token.as_ref().unwrap();
+        let including = &self.files[token.file].path;
+
+        let directory = including.parent().map(PathBuf::from).unwrap_or_default();
+        std::iter::once(directory)
+            .chain(self.include_paths.iter().cloned())
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file())
+    }
+
+    fn include(&mut self, name: String) {
+        let token = self.current_token.clone().unwrap();
+        let Some(path) = self.find_file(&name) else {
+            self.abort_operation(format!("Cannot find include file {}", name))
+        };
+        let canonical = path.canonicalize().unwrap();
+
+        if self
+            .lexers
+            .iter()
+            .any(|lexer| lexer.path.canonicalize().ok().as_ref() == Some(&canonical))
+        {
+            let mut cycle: Vec<String> = self
+                .lexers
+                .iter()
+                .map(|lexer| lexer.path.display().to_string())
+                .collect();
+            cycle.push(path.display().to_string());
+            self.abort_operation(format!("Include cycle: {}", cycle.join(" -> ")))
+        }
+
+        //every file is only included once
+        if !self.included.insert(canonical) {
+            return;
+        }
+
+        let source = fs::read_to_string(&path).unwrap_or_else(|error| {
+            self.abort_operation(format!("Cannot read {}: {}", path.display(), error))
+        });
+
+        self.files.push(SourceFile {
+            path: path.clone(),
+            included_from: Some((token.file, token.line)),
+            import_note: String::new(),
+        });
+        let mut lexer = Lexer::new(source, path);
+        lexer.file = self.files.len() - 1;
+        lexer.include_note = source::include_chain(&self.files, lexer.file);
+        self.lexers.push(lexer);
+    }
+
+    fn check_not_constant(&self) {
+        let name = &self.current_token.as_ref().unwrap().text;
+        if let Some(symbol) = self.symbols.lookup(Namespace::Value, name) {
+            if let SymbolKind::Constant(_) = symbol.kind {
+                self.abort_with_note(format!("Cannot assign to constant {}", name), symbol)
+            }
+        }
+    }
+
+    // program ::= [MODULE ident nl] {statement | function | import}
+    pub fn program(&mut self) -> Program {
         while self.check_token(TokenType::NEWLINE) {
             self.next_token();
         }
 
+        if let Some(name) = self.module_name.clone() {
+            //an imported file has to say which module it is
+            if !self.check_token(TokenType::MODULE) {
+                self.abort_operation(format!("Expected MODULE {} at the top of the file", name))
+            }
+            self.next_token();
+            if self.current_token.as_ref().unwrap().text != name {
+                self.abort_operation(format!(
+                    "Expected MODULE {}, got MODULE {}",
+                    name,
+                    self.current_token.as_ref().unwrap().text
+                ))
+            }
+            self.match_token(TokenType::IDENT);
+            self.nl();
+        }
+
+        let mut body = Vec::new();
         while !self.check_token(TokenType::EOF) {
-            self.statement();
+            if self.check_token(TokenType::FUNCTION) || self.check_token(TokenType::EXPORT) {
+                self.function();
+            } else if self.check_token(TokenType::IMPORT) {
+                body.push(self.import());
+            } else {
+                body.extend(self.statement());
+            }
         }
 
-        //return on program end
-        self.emitter.emit_line("return 0;".into());
-        self.emitter.emit_line("}".into());
+        self.check_gotos();
+        let reads = std::mem::take(&mut self.unresolved);
+        if let Some((name, span)) = self.resolve_reads(reads).into_iter().next() {
+            self.abort_at(
+                span,
+                format!("Referencing variable that is never assigned: {}", name),
+            )
+        }
 
-        for label in &self.labels_gotoed {
-            if !self.labels_declared.contains(label) {
-                self.abort_operation(format!("Attempting to GOTO to undeclared label: {}", label))
-            }
+        Program {
+            module: self.module_name.clone(),
+            files: self.files.clone(),
+            globals: std::mem::take(&mut self.globals),
+            functions: std::mem::take(&mut self.functions),
+            body,
         }
     }
 
-    fn statement(&mut self) {
-        //check first token
+    // reads of names not assigned yet in the scope being left, handing
+    // back the ones the scope doesn't declare. a FUNCTION passes those on
+    // to the top level, where anything still unknown was never assigned.
+    fn resolve_reads(&self, reads: Vec<(String, Span)>) -> Vec<(String, Span)> {
+        reads
+            .into_iter()
+            .filter(
+                |(name, span)| match self.symbols.lookup_local(Namespace::Value, name) {
+                    Some(
+                        symbol @ Symbol {
+                            kind: SymbolKind::Constant(_),
+                            ..
+                        },
+                    ) => self.abort_with_note_at(
+                        *span,
+                        format!("Constant {} used before it is defined", name),
+                        symbol,
+                    ),
+                    Some(_) => false,
+                    None => true,
+                },
+            )
+            .collect()
+    }
+
+    fn check_gotos(&self) {
+        //labels are only visible in the scope they are declared in
+        for (label, span) in &self.gotos {
+            if self.symbols.lookup_local(Namespace::Label, label).is_none() {
+                self.abort_at(
+                    *span,
+                    format!("Attempting to GOTO to undeclared label: {}", label),
+                )
+            }
+        }
+    }
 
-        if self.check_token(TokenType::PRINT) {
-            // PRINT (expression | string)
+    fn function(&mut self) {
+        // function ::= [EXPORT] FUNCTION ident ( [ident {, ident}] ) nl {statement} ENDFUNCTION nl
+        let exported = self.check_token(TokenType::EXPORT);
+        if exported {
+            if self.module_name.is_none() {
+                self.abort_operation("EXPORT is only allowed in a MODULE".into())
+            }
             self.next_token();
+        }
+        self.match_token(TokenType::FUNCTION);
 
-            if self.check_token(TokenType::STRING) {
-                // string, print string
-                self.emitter.emit_line(format!(
-                    "printf(\"{}\\n\");",
-                    self.current_token.as_ref().unwrap().text,
-                ));
+        let name = self.current_token.clone().unwrap().text;
+        let name_span = self.span();
+        self.match_token(TokenType::IDENT);
 
-                self.next_token();
-            } else {
-                self.emitter.emit(format!("printf(\"%.2f\\n\", (float)("));
-                self.expression();
-                self.emitter.emit_line("));".into());
+        self.match_token(TokenType::LPAREN);
+        let mut parameters: Vec<(String, Span)> = Vec::new();
+        while !self.check_token(TokenType::RPAREN) {
+            if !parameters.is_empty() {
+                self.match_token(TokenType::COMMA);
             }
+            parameters.push((self.current_token.clone().unwrap().text, self.span()));
+            self.match_token(TokenType::IDENT);
+        }
+        self.match_token(TokenType::RPAREN);
+
+        //declared before the body so the function can call itself
+        self.declare_at(
+            name.clone(),
+            SymbolKind::Function {
+                arity: parameters.len(),
+                exported,
+            },
+            name_span,
+        );
+        let ty = self.symbols.lookup(Namespace::Function, &name).unwrap().ty;
+
+        //parameters are the first locals of the function's scope
+        self.symbols.push_scope();
+        for (parameter, span) in &parameters {
+            self.declare_at(parameter.clone(), SymbolKind::Variable, *span);
+        }
+
+        //labels belong to the function they are in
+        let gotos = std::mem::take(&mut self.gotos);
+        let outer_reads = std::mem::take(&mut self.unresolved);
+        self.nl();
+
+        let body = self.block(&[TokenType::ENDFUNCTION]);
+        self.match_token(TokenType::ENDFUNCTION);
+        self.check_gotos();
+
+        let reads = std::mem::replace(&mut self.unresolved, outer_reads);
+        let reads = self.resolve_reads(reads);
+        self.unresolved.extend(reads);
+
+        self.symbols.pop_scope();
+        self.gotos = gotos;
+        self.functions.push(Function {
+            name,
+            span: name_span,
+            exported,
+            ty,
+            parameters: parameters.into_iter().map(|(name, _)| name).collect(),
+            locals: std::mem::take(&mut self.locals),
+            body,
+        });
+        self.nl();
+    }
+
+    fn import(&mut self) -> Stmt {
+        // import ::= IMPORT ident nl
+        let span = self.span();
+        self.match_token(TokenType::IMPORT);
+        let name = self.current_token.clone().unwrap().text;
+        if self.check_token(TokenType::IDENT) {
+            self.compile_module(&name);
+
+            if self.symbols.lookup(Namespace::Module, &name).is_none() {
+                self.declare(name.clone(), SymbolKind::Module);
+            }
+        }
+        self.match_token(TokenType::IDENT);
+        self.nl();
+
+        Stmt {
+            kind: StmtKind::Import(name),
+            span,
+        }
+    }
+
+    // parse an imported module, once per compile
+    fn compile_module(&mut self, name: &str) {
+        if self.modules.compiled.contains_key(name) {
+            return;
+        }
+        if self.modules.in_progress.iter().any(|module| module == name) {
+            let mut cycle = self.modules.in_progress.clone();
+            cycle.push(name.into());
+            self.abort_operation(format!("Import cycle: {}", cycle.join(" -> ")))
+        }
+
+        let Some(path) = self.find_file(&format!("{}.teeny", name)) else {
+            self.abort_operation(format!("Cannot find module {}", name))
+        };
+        let source = fs::read_to_string(&path).unwrap_or_else(|error| {
+            self.abort_operation(format!("Cannot read {}: {}", path.display(), error))
+        });
+
+        //errors in the module lead back through this IMPORT
+        let span = self.span();
+        let mut lexer = Lexer::new(source, path);
+        lexer.include_note = format!(
+            "\n  imported from {} on line {}{}",
+            self.files[span.file].path.display(),
+            span.line,
+            source::include_chain(&self.files, span.file)
+        );
+
+        let mut parser = Parser::new(lexer);
+        parser.include_paths = self.include_paths.clone();
+        parser.module_name = Some(name.into());
+        parser.modules = std::mem::take(&mut self.modules);
+        parser.modules.in_progress.push(name.into());
+
+        let program = parser.program();
+
+        let functions = parser
+            .symbols
+            .global_scope()
+            .symbols()
+            .filter_map(|symbol| match symbol.kind {
+                SymbolKind::Function {
+                    arity,
+                    exported: true,
+                } => Some((symbol.name.clone(), arity)),
+                _ => None,
+            })
+            .collect();
+
+        self.modules = std::mem::take(&mut parser.modules);
+        self.modules.in_progress.pop();
+        self.modules.programs.push(program);
+        self.modules
+            .compiled
+            .insert(name.into(), ModuleInterface { functions });
+    }
+
+    // declare a LET or INPUT target the first time it is assigned
+    fn declare_variable(&mut self, name: &str) {
+        if self.symbols.lookup(Namespace::Value, name).is_some() {
+            return;
+        }
+
+        //new names inside a FUNCTION are its locals, elsewhere they are globals
+        self.declare(name.into(), SymbolKind::Variable);
+        if self.symbols.is_global() {
+            self.globals.push(name.into());
+        } else {
+            self.locals.push(name.into());
+        }
+    }
+
+    // statements up to one of the given tokens, which is left unmatched
+    fn block(&mut self, ends: &[TokenType]) -> Vec<Stmt> {
+        let mut body = Vec::new();
+        while !ends.iter().any(|end| self.check_token(end.clone())) {
+            body.extend(self.statement());
+        }
+        body
+    }
+
+    // a statement, or None for one that leaves nothing to run
+    fn statement(&mut self) -> Option<Stmt> {
+        //check first token
+        let span = self.span();
+
+        let kind = if self.check_token(TokenType::PRINT) {
+            // PRINT [USING string ;] [item {(; | ,) item}] [; | ,]
+            self.next_token();
+            self.print_items()
         } else if self.check_token(TokenType::IF) {
             // IF comparison THEN statement ENDIF
             self.next_token();
-            self.emitter.emit("if(".into());
-            self.comparison();
+            let condition = self.comparison();
 
             self.match_token(TokenType::THEN);
             self.nl();
-            self.emitter.emit_line(") {".into());
 
-            while !self.check_token(TokenType::ENDIF) {
-                self.statement();
-            }
+            let body = self.block(&[TokenType::ENDIF]);
             self.match_token(TokenType::ENDIF);
-            self.emitter.emit_line("}".into());
+            StmtKind::If { condition, body }
         } else if self.check_token(TokenType::WHILE) {
             // WHILE comparison REPEAT statement ENDWHILE
             self.next_token();
-            self.emitter.emit("while(".into());
-            self.comparison();
+            let condition = self.comparison();
 
             self.match_token(TokenType::REPEAT);
             self.nl();
-            self.emitter.emit_line("){".into());
 
-            while !self.check_token(TokenType::ENDWHILE) {
-                self.statement();
-            }
+            self.loop_depth += 1;
+            let body = self.block(&[TokenType::ENDWHILE]);
+            self.loop_depth -= 1;
             self.match_token(TokenType::ENDWHILE);
-            self.emitter.emit_line("}".into());
-        } else if self.check_token(TokenType::LABEL) {
-            //LABEL ident
+            StmtKind::While { condition, body }
+        } else if self.check_token(TokenType::DO) {
+            // DO nl {statement} LOOP [(UNTIL | WHILE) comparison]
+            self.next_token();
+            self.nl();
+
+            self.loop_depth += 1;
+            let body = self.block(&[TokenType::LOOP]);
+            self.loop_depth -= 1;
+            let end = self.span();
+            self.match_token(TokenType::LOOP);
+
+            let condition = if self.check_token(TokenType::UNTIL) {
+                self.next_token();
+                LoopCondition::Until(self.comparison())
+            } else if self.check_token(TokenType::WHILE) {
+                self.next_token();
+                LoopCondition::While(self.comparison())
+            } else {
+                //bare LOOP runs until BREAK, GOTO or END
+                LoopCondition::Forever
+            };
+            StmtKind::Do {
+                body,
+                condition,
+                end,
+            }
+        } else if self.check_token(TokenType::SELECT) {
+            // SELECT CASE expression nl {CASE case_item {, case_item} nl {statement}}
+            // [CASE ELSE nl {statement}] ENDSELECT
             self.next_token();
+            self.match_token(TokenType::CASE);
+            let subject = self.expression();
+            self.nl();
+
+            //constant values matched by earlier cases, with their line
+            let mut matched: Vec<(CaseRange, usize)> = Vec::new();
+            let mut cases = Vec::new();
+            let mut default = None;
+
+            while !self.check_token(TokenType::ENDSELECT) {
+                let case_span = self.span();
+                self.match_token(TokenType::CASE);
+                if default.is_some() {
+                    self.abort_operation("CASE after CASE ELSE".into())
+                }
 
-            //if insert returns false, it means that the value
-            // already exists in the set. abort operation if so.
-            // clone because insert consumes the value
-            if !self
-                .labels_declared
-                .insert(self.current_token.clone().unwrap().text)
-            {
+                if self.check_token(TokenType::ELSE) {
+                    self.next_token();
+                    self.nl();
+                    default = Some(self.block(&[TokenType::CASE, TokenType::ENDSELECT]));
+                } else {
+                    let mut items = Vec::new();
+                    loop {
+                        items.push(self.case_item(&mut matched));
+                        if !self.check_token(TokenType::COMMA) {
+                            break;
+                        }
+                        self.next_token();
+                    }
+                    self.nl();
+
+                    let body = self.block(&[TokenType::CASE, TokenType::ENDSELECT]);
+                    cases.push(Case {
+                        items,
+                        body,
+                        span: case_span,
+                    });
+                }
+            }
+            self.match_token(TokenType::ENDSELECT);
+            StmtKind::Select {
+                subject,
+                cases,
+                default,
+            }
+        } else if self.check_token(TokenType::BREAK) || self.check_token(TokenType::CONTINUE) {
+            // BREAK | CONTINUE, only inside a loop
+            if self.loop_depth == 0 {
                 self.abort_operation(format!(
-                    "Label already exists {}",
-                    self.current_token.as_ref().unwrap().text
+                    "{:?} outside of a loop",
+                    self.current_token.as_ref().unwrap().kind
                 ))
             }
 
-            self.emitter
-                .emit_line(format!("{}:", self.current_token.as_ref().unwrap().text));
+            let kind = if self.check_token(TokenType::BREAK) {
+                StmtKind::Break
+            } else {
+                StmtKind::Continue
+            };
+            self.next_token();
+            kind
+        } else if self.check_token(TokenType::LABEL) {
+            //LABEL ident
+            self.next_token();
+
+            let name = self.current_token.clone().unwrap().text;
+            if self.check_token(TokenType::IDENT) {
+                self.declare(name.clone(), SymbolKind::Label);
+            }
             self.match_token(TokenType::IDENT);
+            StmtKind::Label(name)
         } else if self.check_token(TokenType::GOTO) {
             //GOTO ident
             self.next_token();
-            self.labels_gotoed
-                .insert(self.current_token.clone().unwrap().text);
-            self.emitter.emit_line(format!(
-                "goto {};",
-                self.current_token.as_ref().unwrap().text
-            ));
+            let name = self.current_token.clone().unwrap().text;
+            self.gotos.push((name.clone(), self.span()));
             self.match_token(TokenType::IDENT);
+            StmtKind::Goto(name)
         } else if self.check_token(TokenType::LET) {
             // LET ident = expression
             self.next_token();
+            self.check_not_constant();
 
-            if !self
-                .symbols
-                .contains(&self.current_token.as_ref().unwrap().text)
-            {
-                self.symbols
-                    .insert(self.current_token.clone().unwrap().text);
-                self.emitter.header_line(format!(
-                    "float {};",
-                    self.current_token.as_ref().unwrap().text
-                ));
+            let name = self.current_token.clone().unwrap().text;
+            if self.check_token(TokenType::IDENT) {
+                self.declare_variable(&name);
             }
-            self.emitter
-                .emit(format!("{} = ", self.current_token.as_ref().unwrap().text));
             self.match_token(TokenType::IDENT);
             self.match_token(TokenType::EQ);
 
-            self.expression();
-            self.emitter.emit_line(";".into());
+            StmtKind::Let(name, self.expression())
+        } else if self.check_token(TokenType::CONST) {
+            // CONST ident = expression, worked out now and never emitted
+            self.next_token();
+            let name = self.current_token.clone().unwrap().text;
+            let span = self.span();
+            self.match_token(TokenType::IDENT);
+            self.match_token(TokenType::EQ);
+
+            let expr = self.expression();
+            match expr.constant_value() {
+                Some(value) => self.declare_at(name, SymbolKind::Constant(value), span),
+                None => self.abort_operation(format!(
+                    "Value of constant {} must be a constant expression",
+                    name
+                )),
+            }
+            self.nl();
+            return None;
         } else if self.check_token(TokenType::INPUT) {
+            // INPUT [string (, | ;)] ident {, ident}
+            let location = self.runtime_location(self.current_token.as_ref().unwrap());
             self.next_token();
 
-            //if variable doesn't exist declare in symbols set
-            if !self
-                .symbols
-                .contains(&self.current_token.as_ref().unwrap().text)
-            {
-                self.symbols
-                    .insert(self.current_token.clone().unwrap().text);
-                self.emitter.header_line(format!(
-                    "float {};",
-                    self.current_token.as_ref().unwrap().text
-                ));
+            let mut prompt = String::new();
+            if self.check_token(TokenType::STRING) {
+                prompt = self.current_token.clone().unwrap().text;
+                self.next_token();
+                if self.check_token(TokenType::SEMICOLON) {
+                    self.next_token();
+                } else {
+                    self.match_token(TokenType::COMMA);
+                }
             }
 
-            //emit scanf but also validate input.
-            //if invalid set the variable to 0 and clear input
-            self.emitter.emit_line(format!(
-                "if(0 == scanf(\"%f\", &{})) {}",
-                self.current_token.as_ref().unwrap().text,
-                "{"
-            ));
-            self.emitter.emit_line(format!(
-                "{} = 0;",
-                self.current_token.as_ref().unwrap().text
-            ));
-            self.emitter.emit("scanf(\"%".into());
-            self.emitter.emit_line("*s\");".into());
-            self.emitter.emit_line("}".into());
-            self.match_token(TokenType::IDENT);
+            let mut targets = Vec::new();
+            loop {
+                self.check_not_constant();
+                let name = self.current_token.clone().unwrap().text;
+                self.match_token(TokenType::IDENT);
+
+                self.declare_variable(&name);
+                targets.push(name);
+
+                if !self.check_token(TokenType::COMMA) {
+                    break;
+                }
+                self.next_token();
+            }
+            StmtKind::Input {
+                prompt,
+                targets,
+                location,
+            }
+        } else if self.check_token(TokenType::CALL) {
+            // CALL call, for a function whose result isn't needed
+            self.next_token();
+            if !self.check_token(TokenType::IDENT) {
+                self.match_token(TokenType::IDENT);
+            }
+            StmtKind::Call(self.call())
+        } else if self.check_token(TokenType::RETURN) {
+            // RETURN [expression]
+            if self.symbols.is_global() {
+                self.abort_operation("RETURN outside of a FUNCTION".into())
+            }
+            self.next_token();
+
+            if self.check_token(TokenType::NEWLINE) {
+                StmtKind::Return(None)
+            } else {
+                StmtKind::Return(Some(self.expression()))
+            }
+        } else if self.check_token(TokenType::FUNCTION)
+            || self.check_token(TokenType::EXPORT)
+            || self.check_token(TokenType::IMPORT)
+            || self.check_token(TokenType::MODULE)
+        {
+            self.abort_operation(format!(
+                "{:?} is only allowed at the top level of a file",
+                self.current_token.as_ref().unwrap().kind
+            ))
+        } else if self.check_token(TokenType::INCLUDE) {
+            // INCLUDE string, the file's statements follow this line
+            self.next_token();
+            let name = self.current_token.clone().unwrap().text;
+            if self.check_token(TokenType::STRING) {
+                self.include(name);
+            }
+            self.match_token(TokenType::STRING);
+            self.nl();
+            return None;
+        } else if self.check_token(TokenType::END) {
+            // END
+            self.next_token();
+            StmtKind::End
+        } else if self.check_token(TokenType::STOP) {
+            // STOP [string]
+            let location = self.runtime_location(self.current_token.as_ref().unwrap());
+            self.next_token();
+
+            let mut message = None;
+            if self.check_token(TokenType::STRING) {
+                message = Some(self.current_token.clone().unwrap().text);
+                self.next_token();
+            }
+            StmtKind::Stop { location, message }
+        } else if self.check_token(TokenType::EXIT) {
+            // EXIT expression
+            self.next_token();
+            StmtKind::Exit(self.expression())
         } else {
             self.abort_operation(format!(
                 "Invalid statement at {} {:?}",
                 self.current_token.as_ref().unwrap().text,
                 self.current_token.as_ref().unwrap().kind
             ))
-        }
+        };
         self.nl();
+
+        Some(Stmt { kind, span })
+    }
+    fn case_item(&mut self, matched: &mut Vec<(CaseRange, usize)>) -> CaseItem {
+        // case_item ::= IS comparison_operator expression | expression [TO expression]
+        let line = self.current_token.as_ref().unwrap().line;
+
+        let (item, range) = if self.check_token(TokenType::IS) {
+            self.next_token();
+            let mut op = self.current_token.clone().unwrap().kind;
+            if op == TokenType::EQ {
+                //CASE IS = 3 reads better than CASE IS == 3
+                op = TokenType::EQEQ;
+            } else if !self.is_comparison_operator() {
+                self.abort_operation(format!(
+                    "Expected comparison operator after IS, got {}",
+                    self.current_token.as_ref().unwrap().text
+                ))
+            }
+            self.next_token();
+
+            let value = self.expression();
+            let range = value.constant_value().and_then(|value| match op {
+                TokenType::EQEQ => Some((value, false, value, false)),
+                TokenType::LT => Some((f32::NEG_INFINITY, false, value, true)),
+                TokenType::LTEQ => Some((f32::NEG_INFINITY, false, value, false)),
+                TokenType::GT => Some((value, true, f32::INFINITY, false)),
+                TokenType::GTEQ => Some((value, false, f32::INFINITY, false)),
+                //IS != matches nearly everything, don't bother
+                _ => None,
+            });
+
+            (CaseItem::Is(op, value), range)
+        } else {
+            let low = self.expression();
+            if self.check_token(TokenType::TO) {
+                self.next_token();
+                let high = self.expression();
+                let range = match (low.constant_value(), high.constant_value()) {
+                    (Some(low), Some(high)) if low <= high => Some((low, false, high, false)),
+                    _ => None,
+                };
+
+                (CaseItem::Range(low, high), range)
+            } else {
+                let range = low
+                    .constant_value()
+                    .map(|value| (value, false, value, false));
+
+                (CaseItem::Value(low), range)
+            }
+        };
+
+        if let Some((low, low_open, high, high_open)) = range {
+            let range = CaseRange {
+                low,
+                low_open,
+                high,
+                high_open,
+            };
+            if let Some((_, earlier)) = matched.iter().find(|(other, _)| other.overlaps(&range)) {
+                self.abort_operation(format!("CASE overlaps the CASE on line {}", earlier))
+            }
+            matched.push((range, line));
+        }
+
+        item
     }
+
+    fn print_items(&mut self) -> StmtKind {
+        let mut using = None;
+        if self.check_token(TokenType::USING) {
+            self.next_token();
+            using = Some(self.using_picture());
+            self.match_token(TokenType::STRING);
+            self.match_token(TokenType::SEMICOLON);
+        }
+
+        // a trailing separator keeps the cursor on the same line
+        let mut newline = true;
+        let mut items = Vec::new();
+
+        while !self.check_token(TokenType::NEWLINE) {
+            if self.check_token(TokenType::STRING) {
+                items.push(PrintItem::Text(self.current_token.clone().unwrap().text));
+                self.next_token();
+            } else {
+                items.push(PrintItem::Value(self.expression()));
+            }
+
+            newline = true;
+            if self.check_token(TokenType::SEMICOLON) {
+                // ; prints the next item right after this one
+                self.next_token();
+                newline = false;
+            } else if self.check_token(TokenType::COMMA) {
+                // , moves to the next print zone
+                items.push(PrintItem::Tab);
+                self.next_token();
+                newline = false;
+            } else {
+                break;
+            }
+        }
+
+        StmtKind::Print {
+            using,
+            items,
+            newline,
+        }
+    }
+
+    fn using_picture(&mut self) -> String {
+        // a BASIC picture like "###.##", digits before and after the point
+        let picture = self.current_token.clone().unwrap().text;
+        let (whole, fraction) = match picture.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (picture.as_str(), None),
+        };
+
+        if whole.is_empty() && fraction.is_none()
+            || whole.chars().any(|c| c != '#')
+            || fraction.is_some_and(|f| f.chars().any(|c| c != '#'))
+        {
+            self.abort_operation(format!("Invalid USING format: {}", picture))
+        }
+        picture
+    }
+
     fn nl(&mut self) {
         // require at least 1 newline
         self.match_token(TokenType::NEWLINE);
@@ -235,57 +931,72 @@ impl Parser {
         }
     }
 
-    fn expression(&mut self) {
-        // expression ::= term OPERATOR term
+    fn expression(&mut self) -> Expr {
+        // expression ::= term {(+ | -) term}
 
-        self.term();
+        let mut expr = self.term();
 
         // can have 0 or more OPERATOR and expressions
         while self.check_token(TokenType::PLUS) || self.check_token(TokenType::MINUS) {
-            self.emitter.emit(self.current_token.clone().unwrap().text);
+            let op = self.current_token.clone().unwrap().kind;
             self.next_token();
-            self.term();
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.term()));
         }
+        expr
     }
 
-    fn term(&mut self) {
-        self.unary();
+    fn term(&mut self) -> Expr {
+        let mut expr = self.unary();
         // can have 0 or more expressions
 
         while self.check_token(TokenType::ASTERISK) || self.check_token(TokenType::SLASH) {
-            self.emitter.emit(self.current_token.clone().unwrap().text);
+            let op = self.current_token.clone().unwrap().kind;
             self.next_token();
-            self.unary()
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.unary()));
         }
+        expr
     }
 
-    fn unary(&mut self) {
+    fn unary(&mut self) -> Expr {
         // unary::= + - primary
         //optional unary
         if self.check_token(TokenType::PLUS) || self.check_token(TokenType::MINUS) {
-            self.emitter.emit(self.current_token.clone().unwrap().text);
+            let op = self.current_token.clone().unwrap().kind;
             self.next_token();
+            return Expr::Unary(op, Box::new(self.primary()));
         }
-        self.primary();
+        self.primary()
     }
 
-    fn primary(&mut self) {
-        //primary ::= number |ident
-        if self.check_token(TokenType::NUMBER) {
-            self.emitter.emit(self.current_token.clone().unwrap().text);
+    fn primary(&mut self) -> Expr {
+        //primary ::= number | ident | EOF
+        if self.check_token(TokenType::INPUTEOF) {
+            //1 once an INPUT has hit the end of stdin
+            self.next_token();
+            Expr::InputEof
+        } else if self.check_token(TokenType::NUMBER) {
+            let text = self.current_token.clone().unwrap().text;
             self.next_token();
+            Expr::Number(text)
+        } else if self.check_token(TokenType::IDENT)
+            && (self.check_peek(TokenType::LPAREN) || self.check_peek(TokenType::DOT))
+        {
+            self.call()
         } else if self.check_token(TokenType::IDENT) {
-            if !self
-                .symbols
-                .contains(&self.current_token.as_ref().unwrap().text)
-            {
-                self.abort_operation(format!(
-                    "Referencing variable before assignment: {} ",
-                    self.current_token.clone().unwrap().text
-                ))
+            let name = self.current_token.clone().unwrap().text;
+            let symbol = self.symbols.lookup(Namespace::Value, &name);
+            if let Some(SymbolKind::Constant(value)) = symbol.map(|symbol| &symbol.kind) {
+                //constants are inlined as literals
+                let value = Expr::number(*value);
+                self.next_token();
+                return value;
+            }
+            if symbol.is_none() {
+                //may still be assigned further down, flow analysis decides
+                self.unresolved.push((name.clone(), self.span()));
             }
-            self.emitter.emit(self.current_token.clone().unwrap().text);
             self.next_token();
+            Expr::Variable(name)
         } else {
             self.abort_operation(format!(
                 "Unexpected token at {}",
@@ -294,16 +1005,72 @@ impl Parser {
         }
     }
 
-    fn comparison(&mut self) {
-        //comparison ::= expression
+    fn call(&mut self) -> Expr {
+        // call ::= [ident .] ident ( [expression {, expression}] )
+        let mut name = self.current_token.clone().unwrap().text;
+        self.match_token(TokenType::IDENT);
 
-        self.expression();
-
-        if self.is_comparison_operator() {
-            self.emitter.emit(self.current_token.clone().unwrap().text);
+        let mut module = self.module_name.clone();
+        //the local function called, for pointing at its declaration
+        let mut declared = None;
+        let arity = if self.check_token(TokenType::DOT) {
             self.next_token();
-            self.expression();
+            if self.symbols.lookup(Namespace::Module, &name).is_none() {
+                self.abort_operation(format!("Module {} is not imported", name))
+            }
+            let function = self.current_token.clone().unwrap().text;
+            self.match_token(TokenType::IDENT);
+
+            let Some(&arity) = self.modules.compiled[&name].functions.get(&function) else {
+                self.abort_operation(format!(
+                    "Module {} does not export a function {}",
+                    name, function
+                ))
+            };
+            module = Some(name);
+            name = function;
+            arity
         } else {
+            match self.symbols.lookup(Namespace::Function, &name) {
+                Some(
+                    symbol @ Symbol {
+                        kind: SymbolKind::Function { arity, .. },
+                        ..
+                    },
+                ) => {
+                    declared = Some(symbol.clone());
+                    *arity
+                }
+                _ => self.abort_operation(format!("Calling undeclared function {}", name)),
+            }
+        };
+
+        self.match_token(TokenType::LPAREN);
+        let mut args = Vec::new();
+        while !self.check_token(TokenType::RPAREN) {
+            if !args.is_empty() {
+                self.match_token(TokenType::COMMA);
+            }
+            args.push(self.expression());
+        }
+        self.match_token(TokenType::RPAREN);
+
+        if args.len() != arity {
+            let message = format!("{} takes {} arguments, got {}", name, arity, args.len());
+            match declared {
+                Some(symbol) => self.abort_with_note(message, &symbol),
+                None => self.abort_operation(message),
+            }
+        }
+        Expr::Call(module, name, args)
+    }
+
+    fn comparison(&mut self) -> Expr {
+        //comparison ::= expression (== | != | < | <= | > | >=) expression {...}
+
+        let mut expr = self.expression();
+
+        if !self.is_comparison_operator() {
             self.abort_operation(format!(
                 "Expected comparison operator at: {}",
                 self.current_token.as_ref().unwrap().text
@@ -311,18 +1078,229 @@ impl Parser {
         }
 
         while self.is_comparison_operator() {
-            self.emitter.emit(self.current_token.clone().unwrap().text);
+            let op = self.current_token.clone().unwrap().kind;
             self.next_token();
-            self.expression();
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.expression()));
         }
+        expr
     }
 
     fn is_comparison_operator(&mut self) -> bool {
-        return self.check_token(TokenType::GT)
+        self.check_token(TokenType::GT)
             || self.check_token(TokenType::GTEQ)
             || self.check_token(TokenType::LT)
             || self.check_token(TokenType::LTEQ)
             || self.check_token(TokenType::EQEQ)
-            || self.check_token(TokenType::NOTEQ);
+            || self.check_token(TokenType::NOTEQ)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::panic;
+
+    // a directory of source files for one test, named by the test
+    fn files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("teeny-parse-{}-{}", std::process::id(), test));
+        for (name, source) in files {
+            let path = directory.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        directory
+    }
+
+    fn parse(path: PathBuf) -> Parser {
+        let source = fs::read_to_string(&path).unwrap();
+        let mut parser = Parser::new(Lexer::new(source, path));
+        parser.program();
+        parser
+    }
+
+    fn program(path: PathBuf, include_paths: &[PathBuf]) -> Program {
+        let source = fs::read_to_string(&path).unwrap();
+        let mut parser = Parser::new(Lexer::new(source, path));
+        parser.include_paths = include_paths.to_vec();
+        parser.program()
+    }
+
+    // the message a compile stops with
+    fn error(path: PathBuf) -> String {
+        let result = panic::catch_unwind(|| parse(path));
+        let payload = result.err().expect("the compile should fail");
+        payload.downcast_ref::<String>().unwrap().clone()
+    }
+
+    #[test]
+    fn case_that_matches_a_value_an_earlier_case_does() {
+        let directory = files(
+            "case-overlap",
+            &[(
+                "main.teeny",
+                "INPUT x\nSELECT CASE x\nCASE 1, 7\nPRINT 1\nCASE IS < 0\nPRINT 2\n\
+                 CASE 3 TO 7\nPRINT 3\nENDSELECT\n",
+            )],
+        );
+        let message = error(directory.join("main.teeny"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(
+            message.contains("line 7. CASE overlaps the CASE on line 3"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn cases_that_only_meet_at_an_open_end() {
+        let directory = files(
+            "case-touch",
+            &[(
+                "main.teeny",
+                "INPUT x\nSELECT CASE x\nCASE IS < 3\nPRINT 1\nCASE 3 TO 5\nPRINT 2\n\
+                 CASE IS > 5\nPRINT 3\nCASE ELSE\nPRINT 4\nENDSELECT\n",
+            )],
+        );
+        let program = program(directory.join("main.teeny"), &[]);
+        fs::remove_dir_all(&directory).unwrap();
+
+        let StmtKind::Select { cases, default, .. } = &program.body[1].kind else {
+            panic!("{:?}", program.body[1]);
+        };
+        assert_eq!(cases.len(), 3);
+        assert!(default.is_some());
+    }
+
+    #[test]
+    fn include_found_along_the_include_paths_and_read_once() {
+        let directory = files(
+            "include-paths",
+            &[
+                (
+                    "main.teeny",
+                    "INCLUDE \"lib.teeny\"\nINCLUDE \"lib.teeny\"\nPRINT x\n",
+                ),
+                ("sys/lib.teeny", "LET x = 2\n"),
+            ],
+        );
+        let program = program(directory.join("main.teeny"), &[directory.join("sys")]);
+        let message = error(directory.join("main.teeny"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(program.body.len(), 2);
+        assert_eq!(program.files.len(), 2);
+        assert!(
+            message.contains("line 1. Cannot find include file lib.teeny"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn files_that_include_each_other() {
+        let directory = files(
+            "include-cycle",
+            &[
+                ("main.teeny", "INCLUDE \"a.teeny\"\n"),
+                ("a.teeny", "INCLUDE \"b.teeny\"\n"),
+                ("b.teeny", "INCLUDE \"a.teeny\"\n"),
+            ],
+        );
+        let message = error(directory.join("main.teeny"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(message.contains("Include cycle: "), "{message}");
+        assert!(
+            message.contains(&format!(
+                "{} -> {}",
+                directory.join("b.teeny").display(),
+                directory.join("a.teeny").display()
+            )),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn import_offers_only_what_the_module_exports() {
+        let module = "MODULE geometry\nEXPORT FUNCTION area(w, h)\nRETURN w * h\nENDFUNCTION\n\
+                      FUNCTION twice(x)\nRETURN x * 2\nENDFUNCTION\n";
+        let directory = files(
+            "import-exports",
+            &[
+                ("main.teeny", "IMPORT geometry\nPRINT geometry.area(3, 4)\n"),
+                ("lib/geometry.teeny", module),
+            ],
+        );
+        let source = fs::read_to_string(directory.join("main.teeny")).unwrap();
+        let mut parser = Parser::new(Lexer::new(source, directory.join("main.teeny")));
+        parser.include_paths = vec![directory.join("lib")];
+        parser.program();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let geometry = &parser.modules.compiled["geometry"];
+        assert_eq!(geometry.functions, HashMap::from([("area".to_string(), 2)]));
+        assert_eq!(parser.modules.programs.len(), 1);
+    }
+
+    #[test]
+    fn module_calling_a_function_its_module_does_not_export() {
+        let directory = files(
+            "import-hidden",
+            &[
+                ("main.teeny", "IMPORT geometry\nPRINT geometry.twice(3)\n"),
+                (
+                    "geometry.teeny",
+                    "MODULE geometry\nFUNCTION twice(x)\nRETURN x * 2\nENDFUNCTION\n",
+                ),
+            ],
+        );
+        let message = error(directory.join("main.teeny"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(
+            message.contains("line 2. Module geometry does not export a function twice"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn modules_that_import_each_other() {
+        let directory = files(
+            "import-cycle",
+            &[
+                ("main.teeny", "IMPORT a\n"),
+                ("a.teeny", "MODULE a\nIMPORT b\n"),
+                ("b.teeny", "MODULE b\nIMPORT a\n"),
+            ],
+        );
+        let message = error(directory.join("main.teeny"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(message.contains("Import cycle: a -> b -> a"), "{message}");
+    }
+
+    #[test]
+    fn include_cycle_in_a_module_leads_back_to_the_main_file() {
+        let directory = files(
+            "module-cycle",
+            &[
+                ("main.teeny", "IMPORT a\nPRINT 1\n"),
+                ("a.teeny", "MODULE a\nINCLUDE \"u.teeny\"\n"),
+                ("u.teeny", "INCLUDE \"a.teeny\"\n"),
+            ],
+        );
+        let message = error(directory.join("main.teeny"));
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(message.contains("Include cycle: "), "{message}");
+        assert!(
+            message.ends_with(&format!(
+                "\n  included from {} on line 2\n  imported from {} on line 1",
+                directory.join("a.teeny").display(),
+                directory.join("main.teeny").display()
+            )),
+            "{message}"
+        );
     }
 }
@@ -0,0 +1,603 @@
+use crate::ir::{BinOp, Block, BlockId, Function, Inst, Module, Op, Operand, Terminator, Value};
+use std::collections::{HashMap, HashSet};
+
+// an optimization over one function, true if it changed anything
+pub struct Pass {
+    pub name: &'static str,
+    pub run: fn(&mut Function) -> bool,
+}
+
+// runs passes over every function of a module in order, and the whole
+// list again while any pass still finds something to do
+pub struct PassManager {
+    passes: Vec<Pass>,
+}
+
+//one pass can open up work for another, but not forever
+const MAX_ROUNDS: usize = 8;
+
+impl Default for PassManager {
+    fn default() -> PassManager {
+        PassManager::new()
+    }
+}
+
+impl PassManager {
+    pub fn new() -> PassManager {
+        PassManager { passes: Vec::new() }
+    }
+
+    // the passes -O runs
+    pub fn optimizing() -> PassManager {
+        let mut manager = PassManager::new();
+        manager.add("copy-propagation", propagate_copies);
+        manager.add("cse", eliminate_common_subexpressions);
+        manager.add("licm", hoist_loop_invariants);
+        manager.add("dse", eliminate_dead_stores);
+        manager
+    }
+
+    pub fn add(&mut self, name: &'static str, run: fn(&mut Function) -> bool) {
+        self.passes.push(Pass { name, run });
+    }
+
+    pub fn run(&self, module: &mut Module) {
+        for function in &mut module.functions {
+            for _ in 0..MAX_ROUNDS {
+                let mut changed = false;
+                for pass in &self.passes {
+                    changed |= (pass.run)(function);
+                }
+                if !changed {
+                    break;
+                }
+            }
+            //new preheaders go at the end, put them back in line
+            function.order_blocks();
+            function.number_values();
+        }
+    }
+}
+
+// uses of a copy read what it copies instead, and so do uses of a phi
+// whose operands are all the same (or the phi itself)
+pub fn propagate_copies(function: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let mut copies = HashMap::new();
+        for block in &function.blocks {
+            for inst in &block.insts {
+                let Some(result) = inst.result else {
+                    continue;
+                };
+                match &inst.op {
+                    Op::Copy(operand) => {
+                        copies.insert(result, operand.clone());
+                    }
+                    Op::Phi(incoming) => {
+                        let mut sources = incoming
+                            .iter()
+                            .map(|(_, operand)| operand)
+                            .filter(|&operand| *operand != Operand::Value(result));
+                        let Some(first) = sources.next() else {
+                            continue;
+                        };
+                        if sources.all(|operand| operand == first) {
+                            copies.insert(result, first.clone());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if copies.is_empty() {
+            return changed;
+        }
+
+        //a value taking over from a copy takes its variable's name, if it
+        //has none of its own
+        let mut renamed: Vec<(Value, Value)> = copies
+            .iter()
+            .filter_map(|(result, operand)| match operand {
+                Operand::Value(value) => Some((*value, *result)),
+                _ => None,
+            })
+            .collect();
+        renamed.sort();
+        for (value, copy) in renamed {
+            if let Some(name) = function.names.get(&copy).cloned() {
+                function.names.entry(value).or_insert(name);
+            }
+        }
+
+        function.replace_uses(&copies);
+        for block in &mut function.blocks {
+            block.insts.retain(|inst| {
+                !inst
+                    .result
+                    .is_some_and(|result| copies.contains_key(&result))
+            });
+        }
+        changed = true;
+    }
+}
+
+// a pure instruction that repeats one in a dominating block (or earlier in
+// the same block) reuses its value instead
+pub fn eliminate_common_subexpressions(function: &mut Function) -> bool {
+    let children = function.dominator_tree();
+    let mut available: HashMap<String, Value> = HashMap::new();
+    let mut replaced = HashMap::new();
+
+    //(block, whether its children are done), the keys each block added
+    //leave scope once its subtree is done
+    let mut stack = vec![(0, false)];
+    let mut added: Vec<Vec<String>> = vec![Vec::new(); function.blocks.len()];
+    while let Some((block, done)) = stack.pop() {
+        if done {
+            for key in &added[block] {
+                available.remove(key);
+            }
+            continue;
+        }
+        for inst in &function.blocks[block].insts {
+            let Some(result) = inst.result else {
+                continue;
+            };
+            if !inst.op.is_pure() {
+                continue;
+            }
+            let key = format!("{} {}", function.types[result.0], canonical(&inst.op));
+            match available.get(&key) {
+                Some(&earlier) => {
+                    replaced.insert(result, Operand::Value(earlier));
+                }
+                None => {
+                    available.insert(key.clone(), result);
+                    added[block].push(key);
+                }
+            }
+        }
+        stack.push((block, true));
+        for &child in children[block].iter().rev() {
+            stack.push((child, false));
+        }
+    }
+
+    if replaced.is_empty() {
+        return false;
+    }
+    function.replace_uses(&replaced);
+    for block in &mut function.blocks {
+        block.insts.retain(|inst| {
+            !inst
+                .result
+                .is_some_and(|result| replaced.contains_key(&result))
+        });
+    }
+    true
+}
+
+// an operation written so that a + b and b + a compare equal
+fn canonical(op: &Op) -> String {
+    match op {
+        Op::Binary(op @ (BinOp::Add | BinOp::Mul | BinOp::Eq | BinOp::Ne), left, right) => {
+            let (left, right) = (left.to_string(), right.to_string());
+            if left <= right {
+                format!("{} {}, {}", op, left, right)
+            } else {
+                format!("{} {}, {}", op, right, left)
+            }
+        }
+        _ => op.to_string(),
+    }
+}
+
+// pure instructions inside a loop whose operands all come from outside it
+// work out the same value every time round, so they move to a block that
+// runs once just before the loop is entered
+pub fn hoist_loop_invariants(function: &mut Function) -> bool {
+    let idom = function.dominators();
+    let predecessors = function.predecessors();
+
+    //each loop header and the blocks of its loop, from its back edges
+    let mut loops: Vec<(BlockId, HashSet<BlockId>)> = Vec::new();
+    for (block, data) in function.blocks.iter().enumerate() {
+        for header in data.terminator.successors() {
+            if !dominates(&idom, header, block) {
+                continue;
+            }
+            let mut body = HashSet::from([header]);
+            let mut work = vec![block];
+            while let Some(member) = work.pop() {
+                if body.insert(member) {
+                    work.extend(&predecessors[member]);
+                }
+            }
+            match loops.iter_mut().find(|(other, _)| *other == header) {
+                Some((_, blocks)) => blocks.extend(body),
+                None => loops.push((header, body)),
+            }
+        }
+    }
+    //innermost loops first, so invariants can climb out one loop at a time
+    loops.sort_by_key(|(_, body)| body.len());
+
+    let mut changed = false;
+    for index in 0..loops.len() {
+        let (header, body) = loops[index].clone();
+        //the entry block has to stay block 0, with nothing before it
+        if header == 0 {
+            continue;
+        }
+        let mut defined_inside = HashSet::new();
+        for &block in &body {
+            defined_inside.extend(
+                function.blocks[block]
+                    .insts
+                    .iter()
+                    .filter_map(|inst| inst.result),
+            );
+        }
+
+        let order: Vec<BlockId> = function
+            .reverse_postorder()
+            .into_iter()
+            .filter(|block| body.contains(block))
+            .collect();
+
+        let mut hoisted = Vec::new();
+        for block in order {
+            let insts = std::mem::take(&mut function.blocks[block].insts);
+            for inst in insts {
+                let invariant = inst.op.is_pure()
+                    && inst.op.operands().iter().all(|operand| match operand {
+                        Operand::Value(value) => !defined_inside.contains(value),
+                        _ => true,
+                    });
+                if invariant {
+                    defined_inside.remove(&inst.result.unwrap());
+                    hoisted.push(inst);
+                } else {
+                    function.blocks[block].insts.push(inst);
+                }
+            }
+        }
+        if hoisted.is_empty() {
+            continue;
+        }
+        let preheader = preheader(function, header, &body);
+        function.blocks[preheader].insts.extend(hoisted);
+        //a new preheader belongs to the loops around this one
+        for (_, outer) in &mut loops[index + 1..] {
+            if outer.contains(&header) {
+                outer.insert(preheader);
+            }
+        }
+        changed = true;
+    }
+    changed
+}
+
+fn dominates(idom: &[Option<BlockId>], dominator: BlockId, mut block: BlockId) -> bool {
+    loop {
+        if block == dominator {
+            return true;
+        }
+        match idom[block] {
+            Some(parent) if parent != block => block = parent,
+            _ => return false,
+        }
+    }
+}
+
+// the block every entry into the loop passes through just before the
+// header, made if there isn't one already
+fn preheader(function: &mut Function, header: BlockId, body: &HashSet<BlockId>) -> BlockId {
+    let outside: Vec<BlockId> = function.predecessors()[header]
+        .iter()
+        .copied()
+        .filter(|block| !body.contains(block))
+        .collect();
+    if let [single] = outside[..] {
+        if function.blocks[single].terminator.successors() == [header] {
+            return single;
+        }
+    }
+
+    let preheader = function.blocks.len();
+    function.blocks.push(Block {
+        insts: Vec::new(),
+        terminator: Terminator::Jump(header),
+    });
+    for &block in &outside {
+        for target in function.blocks[block].terminator.successors_mut() {
+            if *target == header {
+                *target = preheader;
+            }
+        }
+    }
+
+    //phis in the header take one value from the preheader, which picks
+    //between the ones from outside
+    let mut phis = Vec::new();
+    for inst in &mut function.blocks[header].insts {
+        let Op::Phi(incoming) = &mut inst.op else {
+            continue;
+        };
+        let from_outside: Vec<(BlockId, Operand)> = incoming
+            .iter()
+            .filter(|(block, _)| outside.contains(block))
+            .cloned()
+            .collect();
+        incoming.retain(|(block, _)| !outside.contains(block));
+        phis.push(from_outside);
+    }
+    let mut header_phis = Vec::new();
+    for from_outside in phis {
+        let value = function.new_value(function.operand_type(&from_outside[0].1));
+        function.blocks[preheader].insts.push(Inst {
+            result: Some(value),
+            op: Op::Phi(from_outside),
+        });
+        header_phis.push(value);
+    }
+    let mut header_phis = header_phis.into_iter();
+    for inst in &mut function.blocks[header].insts {
+        if let Op::Phi(incoming) = &mut inst.op {
+            incoming.push((preheader, Operand::Value(header_phis.next().unwrap())));
+        }
+    }
+    preheader
+}
+
+// stores to a global that another store overwrites before anything could
+// read it, and values nothing uses
+pub fn eliminate_dead_stores(function: &mut Function) -> bool {
+    let mut changed = false;
+    for block in &mut function.blocks {
+        //globals stored again further down the block, walking backwards
+        let mut overwritten = HashSet::new();
+        let mut keep = vec![true; block.insts.len()];
+        for (index, inst) in block.insts.iter().enumerate().rev() {
+            match &inst.op {
+                Op::Store(name, _) => keep[index] = overwritten.insert(name.clone()),
+                Op::Load(name) => {
+                    overwritten.remove(name);
+                }
+                //a FUNCTION may read any global
+                Op::Call(..) | Op::Import(_) => overwritten.clear(),
+                _ => {}
+            }
+        }
+        let mut keep = keep.into_iter();
+        let before = block.insts.len();
+        block.insts.retain(|_| keep.next().unwrap());
+        changed |= block.insts.len() != before;
+    }
+
+    //values are live if something with an effect uses them, or a live
+    //value does. phis feeding only each other round a loop are not.
+    let removable = |op: &Op| op.is_pure() || matches!(op, Op::Phi(_));
+    let mut definitions = HashMap::new();
+    let mut work = Vec::new();
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if let Some(result) = inst.result {
+                definitions.insert(result, &inst.op);
+            }
+            if !removable(&inst.op) {
+                work.extend(inst.op.operands());
+            }
+        }
+        work.extend(block.terminator.operands());
+    }
+    let mut live = HashSet::new();
+    while let Some(operand) = work.pop() {
+        if let Operand::Value(value) = operand {
+            if live.insert(*value) {
+                if let Some(op) = definitions.get(value) {
+                    work.extend(op.operands());
+                }
+            }
+        }
+    }
+
+    for block in &mut function.blocks {
+        let before = block.insts.len();
+        block.insts.retain(|inst| {
+            !removable(&inst.op) || inst.result.is_some_and(|result| live.contains(&result))
+        });
+        changed |= block.insts.len() != before;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parse::Parser;
+    use crate::{ir, lower, opt};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // the IR for a program after -O's folding of its syntax
+    fn lower(source: &str) -> Module {
+        let lexer = Lexer::new(source.to_string(), PathBuf::from("test.teeny"));
+        let mut program = Parser::new(lexer).program();
+        opt::fold_constants(&mut program);
+        lower::program(&program)
+    }
+
+    fn phis(function: &Function) -> Vec<&Op> {
+        ops(function, |op| matches!(op, Op::Phi(_)))
+    }
+
+    fn ops(function: &Function, wanted: fn(&Op) -> bool) -> Vec<&Op> {
+        function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .map(|inst| &inst.op)
+            .filter(|op| wanted(op))
+            .collect()
+    }
+
+    // run a pass until it finds nothing more to do
+    fn settle(function: &mut Function, pass: fn(&mut Function) -> bool) {
+        let mut rounds = 0;
+        while pass(function) {
+            rounds += 1;
+            assert!(rounds < 10, "{}", function);
+        }
+    }
+
+    #[test]
+    fn phi_of_zero_and_negative_zero_stays() {
+        let mut module =
+            lower("INPUT c\nLET x = 0\nIF c > 0 THEN\nLET x = -0\nENDIF\nPRINT 1 / x\n");
+        let main = &mut module.functions[0];
+        assert_eq!(main.kind, ir::FunctionKind::Main);
+        assert!(main.to_string().contains("-0.0"));
+        let before = phis(main).len();
+        propagate_copies(main);
+        assert_eq!(phis(main).len(), before);
+        assert!(!main.to_string().contains("div 1.0, 0.0"));
+    }
+
+    #[test]
+    fn copies_go_and_their_names_stay() {
+        let mut module = lower("INPUT a\nLET b = a\nLET c = b\nPRINT c\n");
+        let main = &mut module.functions[0];
+        assert_eq!(ops(main, |op| matches!(op, Op::Copy(_))).len(), 3);
+        assert!(propagate_copies(main));
+        assert!(!propagate_copies(main));
+
+        assert!(ops(main, |op| matches!(op, Op::Copy(_))).is_empty());
+        let input = main.blocks[0].insts[0].result.unwrap();
+        assert_eq!(main.names[&input], "a");
+        assert_eq!(
+            ops(main, |op| matches!(op, Op::PrintNumber(_))),
+            [&Op::PrintNumber(Operand::Value(input))]
+        );
+    }
+
+    #[test]
+    fn repeated_expressions_are_worked_out_once_where_they_dominate() {
+        let mut module =
+            lower("INPUT a\nPRINT a * 2 + 1\nPRINT 2 * a + 1\nIF a > 0 THEN\nPRINT a * 3\nENDIF\nPRINT a * 3\n");
+        let main = &mut module.functions[0];
+        settle(main, propagate_copies);
+        settle(main, eliminate_common_subexpressions);
+
+        let binary = |op: &Op| matches!(op, Op::Binary(BinOp::Mul | BinOp::Add, ..));
+        //a * 2 + 1 once, and a * 3 on each side of the IF
+        assert_eq!(ops(main, binary).len(), 4, "{}", main);
+    }
+
+    #[test]
+    fn invariants_leave_the_loop() {
+        let mut module = lower(
+            "INPUT a\nLET i = 0\nWHILE i < 10 REPEAT\nPRINT a * 3\nLET i = i + 1\nENDWHILE\n",
+        );
+        let main = &mut module.functions[0];
+        settle(main, propagate_copies);
+        assert!(hoist_loop_invariants(main));
+
+        let printing = main
+            .blocks
+            .iter()
+            .position(|block| {
+                block
+                    .insts
+                    .iter()
+                    .any(|inst| matches!(inst.op, Op::PrintNumber(_)))
+            })
+            .unwrap();
+        let multiplying = main
+            .blocks
+            .iter()
+            .position(|block| {
+                block
+                    .insts
+                    .iter()
+                    .any(|inst| matches!(inst.op, Op::Binary(BinOp::Mul, ..)))
+            })
+            .unwrap();
+        assert_ne!(printing, multiplying, "{}", main);
+        assert!(dominates(&main.dominators(), multiplying, printing));
+    }
+
+    #[test]
+    fn stores_overwritten_and_values_nothing_reads() {
+        let mut module = lower(
+            "LET g = 0\nFUNCTION f()\nLET g = 1\nLET g = 2\nENDFUNCTION\nCALL f()\nPRINT g\n\
+             LET i = 0\nLET j = 0\nWHILE i < 3 REPEAT\nLET j = j + 1\nLET i = i + 1\nENDWHILE\n",
+        );
+        let f = module
+            .functions
+            .iter_mut()
+            .find(|function| function.name == "f")
+            .unwrap();
+        assert!(eliminate_dead_stores(f));
+        assert_eq!(
+            ops(f, |op| matches!(op, Op::Store(..))),
+            [&Op::Store("g".into(), Operand::Float(2.0))]
+        );
+
+        let main = module
+            .functions
+            .iter_mut()
+            .find(|function| function.kind == ir::FunctionKind::Main)
+            .unwrap();
+        settle(main, propagate_copies);
+        assert_eq!(phis(main).len(), 2);
+        settle(main, eliminate_dead_stores);
+        //j only feeds itself round the loop
+        assert_eq!(phis(main).len(), 1, "{}", main);
+        assert_eq!(
+            ops(main, |op| matches!(op, Op::Binary(BinOp::Add, ..))).len(),
+            1
+        );
+    }
+
+    static ROUNDS: AtomicUsize = AtomicUsize::new(0);
+
+    fn never_done(_: &mut Function) -> bool {
+        ROUNDS.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    #[test]
+    fn pass_manager_stops_and_tidies_up() {
+        let mut manager = PassManager::new();
+        manager.add("never-done", never_done);
+        let mut module = lower("PRINT 1\n");
+        manager.run(&mut module);
+        assert_eq!(ROUNDS.load(Ordering::Relaxed), MAX_ROUNDS);
+
+        let mut module = lower(
+            "INPUT a\nLET i = 0\nWHILE i < 10 REPEAT\nPRINT a * 3\nLET i = i + 1\nENDWHILE\n\
+             PRINT a * 3\n",
+        );
+        PassManager::optimizing().run(&mut module);
+        let main = &module.functions[0];
+        //blocks in order again after the preheader went on the end, and
+        //values numbered from 0 the way they are defined
+        let blocks: Vec<BlockId> = (0..main.blocks.len()).collect();
+        assert_eq!(main.reverse_postorder(), blocks, "{}", main);
+        let values: Vec<Value> = main
+            .blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .filter_map(|inst| inst.result)
+            .collect();
+        assert_eq!(values, (0..main.types.len()).map(Value).collect::<Vec<_>>());
+        assert!(ops(main, |op| matches!(op, Op::Copy(_))).is_empty());
+        assert_eq!(
+            ops(main, |op| matches!(op, Op::Binary(BinOp::Mul, ..))).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,648 @@
+use crate::backend::{self, joins, simple, write_if_changed, Backend, Generator, InputPolicy};
+use crate::ir::{BinOp, BlockId, Function, FunctionKind, Module, Op, Operand};
+use crate::js::policy_name;
+use crate::variables::Variables;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+// a Python 3 script, one def per FUNCTION, written through the Generator
+// hooks so variables keep their names and loops are while loops. Python
+// has no goto, so a function that needs one runs as a state machine: a
+// loop round an if for each block a goto leads to. numbers are Python
+// floats rounded to 32 bits after each operation, so the script prints the
+// same as the C build.
+
+const PROGRAM_START: &str = r#"#!/usr/bin/env python3
+# a teeny tiny program. numbers are 32 bit floats like in C, so every
+# result goes through f32().
+import math
+import re
+import struct
+import sys
+
+# what INPUT does with text that isn't a number
+INPUT_POLICY = "POLICY"
+
+# the output column, for the print zones of ",", and what EOF reads
+column = 0
+eof = 0
+
+
+def f32(x):
+    # x rounded to the nearest 32 bit float
+    try:
+        return struct.unpack("f", struct.pack("f", x))[0]
+    except OverflowError:
+        return math.copysign(math.inf, x)
+
+
+def divide(a, b):
+    # dividing by zero gives infinity or nan, like C, instead of raising
+    if b == 0:
+        if a == 0 or a != a:
+            return math.nan
+        return math.copysign(math.inf, a) * math.copysign(1.0, b)
+    return f32(a / b)
+
+
+def trunc(x):
+    # x as an int, with nan 0 and numbers out of range the nearest int
+    if x != x:
+        return 0
+    return int(max(-2147483648.0, min(2147483647.0, x)))
+
+
+def special(n):
+    # nan and inf the way printf writes them, None for other numbers. nan
+    # goes without its sign, which depends on how it was made
+    if n != n:
+        return "nan"
+    if math.isinf(n):
+        return "-inf" if n < 0 else "inf"
+    return None
+
+
+def print_string(text):
+    global column
+    sys.stdout.write(text)
+    column += len(text)
+
+
+def print_number(n):
+    # whole numbers the C backend's long can hold, from -2^63 up to 2^63,
+    # print without a fraction, anything else in the fewest digits that
+    # read back as the same float
+    text = special(n)
+    if text is None and -(2**63) <= n < 2**63 and n == int(n):
+        text = "%d" % n
+    elif text is None:
+        for precision in range(1, 10):
+            text = "%.*g" % (precision, n)
+            if f32(float(text)) == n:
+                break
+    print_string(text)
+
+
+def print_using(n, width, fraction):
+    # a ###.## picture prints like %6.2f
+    text = special(n) or "%.*f" % (fraction, n)
+    print_string(text.rjust(width))
+
+
+def print_tab():
+    while True:
+        print_string(" ")
+        if column % 14 == 0:
+            break
+
+
+def print_newline():
+    global column
+    sys.stdout.write("\n")
+    column = 0
+
+
+# input read in but not used yet. INPUT takes numbers from it one at a time
+# the way scanf does, so a line can hold more than one
+pending = ""
+NUMBER = re.compile(r"[+-]?((\d+\.?\d*|\.\d+)([eE][+-]?\d+)?|inf(inity)?|nan)", re.IGNORECASE)
+
+
+def input_number(prompt, location):
+    global column, eof, pending
+    while True:
+        print_string(prompt)
+        sys.stdout.flush()
+        while not pending.strip():
+            try:
+                pending = input() + "\n"
+            except EOFError:
+                pending = ""
+                column = 0
+                eof = 1
+                return 0.0
+        pending = pending.lstrip()
+        column = 0
+        match = NUMBER.match(pending)
+        if match:
+            pending = pending[match.end():]
+            return f32(float(match.group()))
+        if INPUT_POLICY == "zero":
+            # skip the word that isn't a number
+            pending = re.sub(r"^\S*", "", pending)
+            return 0.0
+        if INPUT_POLICY == "error":
+            sys.stderr.write("Invalid input at %s\n" % location)
+            sys.exit(1)
+        # throw the rest of the line away and ask again
+        pending = pending[pending.find("\n") + 1:]
+        sys.stdout.write("?Redo from start\n")
+
+
+def stop(message):
+    sys.stderr.write(message + "\n")
+    sys.exit(1)
+"#;
+
+const PROGRAM_END: &str = r#"
+
+if __name__ == "__main__":
+    sys.exit(main())
+"#;
+
+pub struct PythonEmitter {
+    //variables kept outside the functions, and the init guards
+    globals: String,
+    //finished function definitions
+    functions: String,
+    //function being emitted
+    code: String,
+    indent: usize,
+    //true until the block just opened has a line, which Python needs
+    empty: bool,
+    //the loops the code is in, innermost last, with the condition of a
+    //do loop for continue to test
+    loops: Vec<Option<String>>,
+    //module whose IR is being added, None for the main program
+    module: Option<String>,
+    full_path: String,
+    pub input_policy: InputPolicy,
+}
+
+impl PythonEmitter {
+    pub fn new(full_path: String) -> PythonEmitter {
+        PythonEmitter {
+            globals: String::new(),
+            functions: String::new(),
+            code: String::new(),
+            indent: 0,
+            empty: false,
+            loops: Vec::new(),
+            module: None,
+            full_path,
+            input_policy: InputPolicy::Zero,
+        }
+    }
+
+    pub fn full_path(&self) -> &str {
+        &self.full_path
+    }
+
+    fn line(&mut self, code: String) {
+        self.code.push_str(&"    ".repeat(self.indent));
+        self.code.push_str(&code);
+        self.code.push('\n');
+        self.empty = false;
+    }
+
+    // a line ending in ':' and the block under it
+    fn open(&mut self, code: String) {
+        self.line(code);
+        self.indent += 1;
+        self.empty = true;
+    }
+
+    fn close(&mut self) {
+        if self.empty {
+            self.line("pass".into());
+        }
+        self.indent -= 1;
+    }
+
+    // variables of every module share the script's globals
+    fn global_name(&self, name: &str) -> String {
+        match &self.module {
+            Some(module) => format!("g_{}_{}", module, name),
+            None => format!("g_{}", name),
+        }
+    }
+
+    fn write_file(&mut self) {
+        let mut value = PROGRAM_START.replace(
+            "\"POLICY\"",
+            &format!("\"{}\"", policy_name(self.input_policy)),
+        );
+        if !self.globals.is_empty() {
+            value.push_str("\n\n");
+            value.push_str(&self.globals);
+        }
+        value.push_str(&self.functions);
+        value.push_str(PROGRAM_END);
+        write_if_changed(Path::new(&self.full_path), &value);
+    }
+}
+
+impl Generator for PythonEmitter {
+    fn reserved(&self) -> &'static [&'static str] {
+        RESERVED
+    }
+
+    fn has_goto(&self) -> bool {
+        false
+    }
+
+    // modules have to come before the modules that import them, and the
+    // main program last
+    fn start_program(&mut self, module: &Module, _variables: &[Variables]) {
+        self.module = module.name.clone();
+        for name in &module.globals {
+            let name = self.global_name(name);
+            self.globals.push_str(&format!("{} = 0.0\n", name));
+        }
+    }
+
+    fn start_function(&mut self, function: &Function, variables: &Variables) {
+        let parameters: Vec<&str> = function
+            .parameters
+            .iter()
+            .map(|parameter| variables.name(*parameter).unwrap())
+            .collect();
+        self.code.push_str("\n\n");
+        self.open(format!(
+            "def {}({}):",
+            python_function_name(&self.module, function),
+            parameters.join(", ")
+        ));
+
+        //globals the function assigns have to be declared
+        let mut assigned: BTreeSet<String> = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .filter_map(|inst| match &inst.op {
+                Op::Store(name, _) => Some(self.global_name(name)),
+                _ => None,
+            })
+            .collect();
+        let guard = self
+            .module
+            .as_ref()
+            .map(|module| format!("{}__initialized", module));
+        if function.kind == FunctionKind::Init {
+            assigned.extend(guard.clone());
+        }
+        if !assigned.is_empty() {
+            let names: Vec<String> = assigned.into_iter().collect();
+            self.line(format!("global {}", names.join(", ")));
+        }
+
+        if function.kind == FunctionKind::Init {
+            //the module's statements run once, when it is first imported
+            let guard = guard.unwrap();
+            self.globals.push_str(&format!("{} = False\n", guard));
+            self.open(format!("if {}:", guard));
+            self.ret(None);
+            self.close();
+            self.line(format!("{} = True", guard));
+        }
+    }
+
+    fn end_function(&mut self, _function: &Function, _variables: &Variables) {
+        self.close();
+        let code = std::mem::take(&mut self.code);
+        self.functions.push_str(&code);
+    }
+
+    fn constant(&mut self, constant: &Operand) -> String {
+        python_operand(constant)
+    }
+
+    fn binary(&mut self, op: BinOp, left: String, right: String) -> String {
+        match op {
+            BinOp::Add => format!("f32({} + {})", left, right),
+            BinOp::Sub => format!("f32({} - {})", left, right),
+            BinOp::Mul => format!("f32({} * {})", left, right),
+            BinOp::Div => format!("divide({}, {})", left, right),
+            //a bool, which Python counts as 1 or 0
+            _ => format!(
+                "{} {} {}",
+                python_bracket(left),
+                python_comparison(op),
+                python_bracket(right)
+            ),
+        }
+    }
+
+    fn neg(&mut self, operand: String) -> String {
+        if operand.starts_with('-') {
+            format!("-({})", operand)
+        } else {
+            format!("-{}", python_bracket(operand))
+        }
+    }
+
+    fn not(&mut self, condition: String) -> String {
+        //the opposite of a not is what it has in brackets
+        match condition.strip_prefix("not ") {
+            Some(inner) if simple(inner) => {
+                let inner = inner
+                    .strip_prefix('(')
+                    .and_then(|inner| inner.strip_suffix(')'));
+                return inner.unwrap_or(&condition[4..]).into();
+            }
+            _ => {}
+        }
+        format!("not {}", python_bracket(condition))
+    }
+
+    fn and(&mut self, first: String, second: String) -> String {
+        //only or is looser than and
+        let bracket = |operand: String| match joins(&operand, " or ") {
+            true => format!("({})", operand),
+            false => operand,
+        };
+        format!("{} and {}", bracket(first), bracket(second))
+    }
+
+    fn or(&mut self, first: String, second: String) -> String {
+        format!("{} or {}", first, second)
+    }
+
+    fn int_to_float(&mut self, operand: String) -> String {
+        format!("f32({})", operand)
+    }
+
+    fn float_to_int(&mut self, operand: String) -> String {
+        format!("trunc({})", operand)
+    }
+
+    fn load(&mut self, name: &str) -> String {
+        self.global_name(name)
+    }
+
+    fn call(&mut self, module: &Option<String>, name: &str, args: Vec<String>) -> String {
+        let callee = match module {
+            Some(module) => format!("{}__{}", module, name),
+            None => format!("tt_fn_{}", name),
+        };
+        format!("{}({})", callee, args.join(", "))
+    }
+
+    fn eof(&mut self) -> String {
+        "eof".into()
+    }
+
+    fn assign(&mut self, variable: &str, value: String) {
+        self.line(format!("{} = {}", variable, value));
+    }
+
+    fn evaluate(&mut self, value: String) {
+        self.line(value);
+    }
+
+    fn store(&mut self, name: &str, value: String) {
+        let name = self.global_name(name);
+        self.line(format!("{} = {}", name, value));
+    }
+
+    fn input(&mut self, variable: &str, prompt: String, location: &str) {
+        self.line(format!(
+            "{} = input_number({}, {})",
+            variable,
+            prompt,
+            python_string(location)
+        ));
+    }
+
+    fn print_string(&mut self, text: String) {
+        self.line(format!("print_string({})", text));
+    }
+
+    fn print_number(&mut self, value: String) {
+        self.line(format!("print_number({})", value));
+    }
+
+    fn print_using(&mut self, picture: &str, value: String) {
+        let fraction = picture
+            .split_once('.')
+            .map_or(0, |(_, fraction)| fraction.len());
+        self.line(format!(
+            "print_using({}, {}, {})",
+            value,
+            picture.len(),
+            fraction
+        ));
+    }
+
+    fn print_tab(&mut self) {
+        self.line("print_tab()".into());
+    }
+
+    fn print_newline(&mut self) {
+        self.line("print_newline()".into());
+    }
+
+    fn import(&mut self, module: &str) {
+        self.line(format!("{}__init()", module));
+    }
+
+    fn start_if(&mut self, condition: String) {
+        self.open(format!("if {}:", condition));
+    }
+
+    fn start_else_if(&mut self, condition: String) {
+        self.close();
+        self.open(format!("elif {}:", condition));
+    }
+
+    fn start_else(&mut self) {
+        self.close();
+        self.open("else:".into());
+    }
+
+    fn end_if(&mut self) {
+        self.close();
+    }
+
+    fn start_loop(&mut self) {
+        self.loops.push(None);
+        self.open("while True:".into());
+    }
+
+    fn start_while(&mut self, condition: String) {
+        self.loops.push(None);
+        self.open(format!("while {}:", condition));
+    }
+
+    fn end_loop(&mut self) {
+        self.loops.pop();
+        self.close();
+    }
+
+    //Python has no do loop, so the test is an if at the bottom
+    fn start_do(&mut self, condition: String) {
+        self.loops.push(Some(condition));
+        self.open("while True:".into());
+    }
+
+    fn end_do(&mut self, condition: String) {
+        self.loops.pop();
+        let condition = self.not(condition);
+        self.open(format!("if {}:", condition));
+        self.break_loop();
+        self.close();
+        self.close();
+    }
+
+    fn break_loop(&mut self) {
+        self.line("break".into());
+    }
+
+    fn continue_loop(&mut self) {
+        //going round a do loop again means testing its condition first
+        if let Some(Some(condition)) = self.loops.last() {
+            let condition = condition.clone();
+            self.open(format!("if {}:", condition));
+            self.line("continue".into());
+            self.close();
+            self.break_loop();
+        } else {
+            self.line("continue".into());
+        }
+    }
+
+    fn label(&mut self, _block: BlockId) {
+        unreachable!("Python has no labels");
+    }
+
+    fn goto(&mut self, block: BlockId) {
+        self.line(format!("block = {}", block));
+        self.line("continue".into());
+    }
+
+    fn ret(&mut self, value: Option<String>) {
+        match value {
+            Some(value) => self.line(format!("return {}", value)),
+            None => self.line("return".into()),
+        }
+    }
+
+    fn exit(&mut self, status: String) {
+        self.line(format!("sys.exit({})", status));
+    }
+
+    fn stop(&mut self, message: &str) {
+        self.line(format!("stop({})", python_string(message)));
+    }
+
+    // an if for each case in a loop, where goto sets the case and goes
+    // round again
+    fn start_dispatch(&mut self, entry: BlockId) {
+        self.line(format!("block = {}", entry));
+        self.open("while True:".into());
+    }
+
+    fn start_case(&mut self, block: BlockId) {
+        self.open(format!("if block == {}:", block));
+    }
+
+    fn end_case(&mut self) {
+        self.close();
+    }
+
+    fn end_dispatch(&mut self) {
+        self.close();
+    }
+}
+
+// like js, one script holds the program and its modules
+impl Backend for PythonEmitter {
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String> {
+        for module in modules.iter().chain([program]) {
+            backend::generate(self, module);
+        }
+        self.write_file();
+        vec![self.full_path.clone()]
+    }
+
+    fn finish(&self, _files: &[String]) -> Option<String> {
+        Some(format!("Run with: python3 {}", self.full_path))
+    }
+}
+
+// Python's keywords, and the names the script's runtime uses that a
+// teeny tiny name could be. teeny tiny names have no underscores, so the
+// runtime's other names and the g_ and tt_fn_ prefixes are safe.
+const RESERVED: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield", "int", "float", "input", "math", "re", "struct", "sys", "f32", "divide",
+    "trunc", "special", "pending", "column", "eof", "main", "block", "NUMBER",
+];
+
+// an operand of a comparison or of not, which binds looser than the
+// arithmetic the runtime's functions do
+fn python_bracket(operand: String) -> String {
+    match simple(&operand) {
+        true => operand,
+        false => format!("({})", operand),
+    }
+}
+
+// the same names the C backend uses
+fn python_function_name(module: &Option<String>, function: &Function) -> String {
+    match (function.kind, module) {
+        (FunctionKind::Main, _) => "main".into(),
+        (_, Some(module)) => format!("{}__{}", module, function.name),
+        (_, None) => format!("tt_fn_{}", function.name),
+    }
+}
+
+fn python_comparison(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        _ => ">=",
+    }
+}
+
+fn python_operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Value(_) => unreachable!("a value is not a constant"),
+        Operand::Float(value) => python_float(*value),
+        Operand::Int(value) => value.to_string(),
+        Operand::String(text) => python_string(text),
+    }
+}
+
+// Python floats are doubles, a float whose shortest digits mean something
+// else as a double goes through f32()
+fn python_float(value: f32) -> String {
+    if value.is_nan() {
+        return "math.nan".into();
+    }
+    if value.is_infinite() {
+        return if value < 0.0 { "-math.inf" } else { "math.inf" }.into();
+    }
+    let digits = format!("{:?}", value);
+    let digits = if value < 0.0 {
+        format!("({})", digits)
+    } else {
+        digits
+    };
+    if digits.trim_matches(['(', ')']).parse::<f64>() == Ok(value as f64) {
+        digits
+    } else {
+        format!("f32({})", digits.trim_matches(['(', ')']))
+    }
+}
+
+fn python_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,575 @@
+use crate::backend::{self, using_format, write_if_changed, Backend, InputPolicy, ModuleFiles};
+use crate::ir::{BinOp, BlockId, Function, FunctionKind, Module, Op, Operand, Terminator, Type};
+use std::collections::HashMap;
+use std::path::Path;
+
+// QBE intermediate language, for qbe to turn into assembly. like LLVM, QBE
+// takes SSA form, so values become temporaries, phis carry straight over
+// and every IR block is a QBE block. variables the FUNCTIONs share are data,
+// printing and input go through a runtime written in QBE IL that calls
+// libc. symbols from other files need no declaring.
+
+// print and input helpers, in the main program's file only. modules call
+// them and share tt_column and tt_eof with it.
+const RUNTIME: &str = r#"export data $tt_column = { w 0 }
+export data $tt_eof = { w 0 }
+
+data $.format.string = { b "%s", b 0 }
+data $.format.long = { b "%ld", b 0 }
+data $.format.general = { b "%.*g", b 0 }
+data $.format.float = { b "%f", b 0 }
+data $.format.skip = { b "%*s", b 0 }
+data $.redo = { b "?Redo from start", b 0 }
+data $.invalid = { b "Invalid input at %s", b 10, b 0 }
+
+# a float as an int: nan is 0 and numbers out of range the nearest int,
+# where stosi alone is undefined
+export function w $tt_int(s %n) {
+@start
+	%nan =w cuos %n, %n
+	jnz %nan, @zero, @low
+@zero
+	ret 0
+@low
+	%below =w cles %n, s_-2147483648
+	jnz %below, @min, @high
+@min
+	ret -2147483648
+@high
+	%above =w cges %n, s_2147483648
+	jnz %above, @max, @convert
+@max
+	ret 2147483647
+@convert
+	%int =w stosi %n
+	ret %int
+}
+
+export function $tt_print_string(l %text) {
+@start
+	%printed =w call $printf(l $.format.string, ..., l %text)
+	%column =w loadw $tt_column
+	%moved =w add %column, %printed
+	storew %moved, $tt_column
+	ret
+}
+
+# nan without its sign, which depends on how it was made and which printf
+# would write as "-nan"
+function s $tt_unsigned_nan(s %n) {
+@start
+	%nan =w cuos %n, %n
+	jnz %nan, @clear, @keep
+@clear
+	%bits =w cast %n
+	%cleared =w and %bits, 2147483647
+	%unsigned =s cast %cleared
+	ret %unsigned
+@keep
+	ret %n
+}
+
+export function $tt_print_number(s %signed) {
+@start
+	%text =l alloc8 32
+	%n =s call $tt_unsigned_nan(s %signed)
+	# whole numbers a long can hold, from -2^63 up to 2^63, print without a
+	# fraction
+	%above =w cges %n, s_-9223372036854775808
+	%below =w clts %n, s_9223372036854775808
+	%in_range =w and %above, %below
+	jnz %in_range, @check, @shortest
+@check
+	%long =l stosi %n
+	%back =s sltof %long
+	%is_whole =w ceqs %n, %back
+	jnz %is_whole, @whole, @shortest
+@whole
+	call $snprintf(l %text, l 32, l $.format.long, ..., l %long)
+	jmp @done
+	# shortest representation that reads back as the same float
+@shortest
+	%double =d exts %n
+@precision
+	%digits =w phi @shortest 1, @next %more_digits
+	call $snprintf(l %text, l 32, l $.format.general, ..., w %digits, d %double)
+	%read =s call $strtof(l %text, l 0)
+	%same =w ceqs %read, %n
+	jnz %same, @done, @next
+@next
+	%more_digits =w add %digits, 1
+	%again =w cslew %more_digits, 9
+	jnz %again, @precision, @done
+@done
+	call $tt_print_string(l %text)
+	ret
+}
+
+export function $tt_print_using(l %format, s %n) {
+@start
+	%unsigned =s call $tt_unsigned_nan(s %n)
+	%double =d exts %unsigned
+	%printed =w call $printf(l %format, ..., d %double)
+	%column =w loadw $tt_column
+	%moved =w add %column, %printed
+	storew %moved, $tt_column
+	ret
+}
+
+export function $tt_print_tab() {
+@space
+	call $putchar(w 32)
+	%column =w loadw $tt_column
+	%moved =w add %column, 1
+	storew %moved, $tt_column
+	%zone =w rem %moved, 14
+	jnz %zone, @space, @done
+@done
+	ret
+}
+
+export function $tt_print_newline() {
+@start
+	call $putchar(w 10)
+	storew 0, $tt_column
+	ret
+}
+
+export function s $tt_input(l %prompt, l %location) {
+@start
+	%target =l alloc4 4
+@again
+	call $tt_print_string(l %prompt)
+	%stdout =l loadl $stdout
+	call $fflush(l %stdout)
+	%read =w call $scanf(l $.format.float, ..., l %target)
+	storew 0, $tt_column
+	%ok =w ceqw %read, 1
+	jnz %ok, @done, @failed
+@failed
+	stores s_0, %target
+	%eof =w ceqw %read, -1
+	jnz %eof, @at_eof, @invalid
+@at_eof
+	storew 1, $tt_eof
+	jmp @done
+@invalid
+"#;
+
+// what the runtime does when INPUT reads something that isn't a number,
+// the rest of the invalid block
+fn input_policy_code(policy: InputPolicy) -> &'static str {
+    match policy {
+        InputPolicy::Zero => {
+            r#"	call $scanf(l $.format.skip, ...)
+	jmp @done
+"#
+        }
+        InputPolicy::Reprompt => {
+            r#"@discard
+	%char =w call $getchar()
+	%newline =w ceqw %char, 10
+	%end =w ceqw %char, -1
+	%line_done =w or %newline, %end
+	jnz %line_done, @redo, @discard
+@redo
+	call $puts(l $.redo)
+	jmp @again
+"#
+        }
+        InputPolicy::Error => {
+            r#"	%stderr =l loadl $stderr
+	call $fprintf(l %stderr, l $.invalid, ..., l %location)
+	call $exit(w 1)
+	hlt
+"#
+        }
+    }
+}
+
+const RUNTIME_END: &str = r#"@done
+	%n =s loads %target
+	ret %n
+}
+"#;
+
+pub struct QbeEmitter {
+    //variables and string constants
+    data: String,
+    //finished function definitions
+    functions: String,
+    //function being emitted
+    code: String,
+    full_path: String,
+    module: Option<String>,
+    //name of each string constant
+    strings: HashMap<String, String>,
+    //names for the values QBE needs that the IR doesn't have
+    temporary_count: usize,
+    pub input_policy: InputPolicy,
+}
+
+impl QbeEmitter {
+    pub fn new(full_path: String) -> QbeEmitter {
+        QbeEmitter {
+            data: String::new(),
+            functions: String::new(),
+            code: String::new(),
+            full_path,
+            module: None,
+            strings: HashMap::new(),
+            temporary_count: 0,
+            input_policy: InputPolicy::Zero,
+        }
+    }
+
+    pub fn full_path(&self) -> &str {
+        &self.full_path
+    }
+
+    fn line(&mut self, code: String) {
+        self.code.push('\t');
+        self.code.push_str(&code);
+        self.code.push('\n');
+    }
+
+    fn temporary(&mut self) -> String {
+        self.temporary_count += 1;
+        format!("%t{}", self.temporary_count)
+    }
+
+    // the data holding a string, with a NUL on the end
+    fn string(&mut self, text: &str) -> String {
+        if let Some(name) = self.strings.get(text) {
+            return name.clone();
+        }
+        let name = format!("$.string{}", self.strings.len());
+        self.data
+            .push_str(&format!("data {} = {{ {}b 0 }}\n", name, qbe_bytes(text)));
+        self.strings.insert(text.into(), name.clone());
+        name
+    }
+
+    fn operand(&mut self, operand: &Operand) -> String {
+        match operand {
+            Operand::Value(value) => format!("%v{}", value.0),
+            //QBE has no way to write NaN or infinity, they come from their
+            //bits
+            Operand::Float(value) if !value.is_finite() => {
+                let temporary = self.temporary();
+                self.line(format!("{} =s cast {}", temporary, value.to_bits() as i32));
+                temporary
+            }
+            Operand::Float(value) => qbe_float(*value),
+            Operand::Int(value) => value.to_string(),
+            Operand::String(text) => self.string(text),
+        }
+    }
+
+    // an operand with its type in front, the way arguments are written
+    fn typed(&mut self, function: &Function, operand: &Operand) -> String {
+        let ty = qbe_type(function.operand_type(operand));
+        format!("{} {}", ty, self.operand(operand))
+    }
+
+    fn function(&mut self, module: &Option<String>, function: &Function) {
+        self.temporary_count = 0;
+        let parameters: Vec<String> = function
+            .parameters
+            .iter()
+            .map(|parameter| format!("s %v{}", parameter.0))
+            .collect();
+        let header = match function.kind {
+            FunctionKind::Main => "export function w $main()".to_string(),
+            FunctionKind::Init => {
+                format!("export function ${}__init()", module.as_ref().unwrap())
+            }
+            FunctionKind::Function { exported } => format!(
+                "{}function {}${}({})",
+                if exported { "export " } else { "" },
+                function
+                    .returns
+                    .map_or(String::new(), |ty| format!("{} ", qbe_type(ty))),
+                qbe_function_name(module, &function.name),
+                parameters.join(", ")
+            ),
+        };
+
+        if function.kind == FunctionKind::Init {
+            //the module's statements run once, when it is first imported
+            self.data.push_str("data $.initialized = { w 0 }\n");
+            self.code.push_str("@init\n");
+            self.line("%initialized =w loadw $.initialized".into());
+            self.line("jnz %initialized, @already, @b0".into());
+            self.code.push_str("@already\n");
+            self.line("ret".into());
+        }
+
+        for (index, block) in function.blocks.iter().enumerate() {
+            self.code.push_str(&format!("@b{}\n", index));
+            if index == 0 && function.kind == FunctionKind::Init {
+                self.line("storew 1, $.initialized".into());
+            }
+            for inst in &block.insts {
+                self.instruction(function, inst.result.map(|result| result.0), &inst.op);
+            }
+            self.phi_constants(function, index);
+            self.terminator(function, &block.terminator);
+        }
+
+        let code = std::mem::take(&mut self.code);
+        self.functions
+            .push_str(&format!("\n{} {{\n{}}}\n", header, code));
+    }
+
+    // a phi can't make a temporary for a NaN or infinity coming in, so the
+    // block it comes from does, as %v<phi>.<block>
+    fn phi_constants(&mut self, function: &Function, index: BlockId) {
+        for successor in function.blocks[index].terminator.successors() {
+            for inst in &function.blocks[successor].insts {
+                let Op::Phi(incoming) = &inst.op else {
+                    continue;
+                };
+                for (from, operand) in incoming {
+                    if let (true, Operand::Float(value)) = (*from == index, operand) {
+                        if !value.is_finite() {
+                            self.line(format!(
+                                "%v{}.{} =s cast {}",
+                                inst.result.unwrap().0,
+                                index,
+                                value.to_bits() as i32
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn instruction(&mut self, function: &Function, result: Option<usize>, op: &Op) {
+        let value = match op {
+            Op::Copy(operand) => format!("copy {}", self.operand(operand)),
+            Op::Neg(operand) => format!("neg {}", self.operand(operand)),
+            Op::Binary(op, left, right) => {
+                let left = self.operand(left);
+                let right = self.operand(right);
+                format!("{} {}, {}", qbe_instruction(*op), left, right)
+            }
+            Op::IntToFloat(operand) => format!("swtof {}", self.operand(operand)),
+            Op::FloatToInt(operand) => format!("call $tt_int(s {})", self.operand(operand)),
+            Op::Phi(incoming) => {
+                let result = result.unwrap();
+                let incoming: Vec<String> = incoming
+                    .iter()
+                    .map(|(block, operand)| match operand {
+                        Operand::Float(value) if !value.is_finite() => {
+                            format!("@b{} %v{}.{}", block, result, block)
+                        }
+                        _ => format!("@b{} {}", block, self.operand(operand)),
+                    })
+                    .collect();
+                format!("phi {}", incoming.join(", "))
+            }
+            Op::Load(name) => format!("loads {}", global_name(name)),
+            Op::Store(name, operand) => {
+                let operand = self.operand(operand);
+                self.line(format!("stores {}, {}", operand, global_name(name)));
+                return;
+            }
+            Op::Call(module, name, args) => {
+                let callee = qbe_function_name(module, name);
+                let args: Vec<String> = args.iter().map(|arg| self.typed(function, arg)).collect();
+                format!("call ${}({})", callee, args.join(", "))
+            }
+            Op::Eof => "loadw $tt_eof".into(),
+            Op::Input { prompt, location } => {
+                let prompt = self.operand(prompt);
+                let location = self.string(location);
+                format!("call $tt_input(l {}, l {})", prompt, location)
+            }
+            Op::PrintString(text) => {
+                let text = self.operand(text);
+                self.line(format!("call $tt_print_string(l {})", text));
+                return;
+            }
+            Op::PrintNumber(value) => {
+                let value = self.typed(function, value);
+                self.line(format!("call $tt_print_number({})", value));
+                return;
+            }
+            Op::PrintUsing(picture, value) => {
+                let format = self.string(&using_format(picture));
+                let value = self.typed(function, value);
+                self.line(format!("call $tt_print_using(l {}, {})", format, value));
+                return;
+            }
+            Op::PrintTab => {
+                self.line("call $tt_print_tab()".into());
+                return;
+            }
+            Op::PrintNewline => {
+                self.line("call $tt_print_newline()".into());
+                return;
+            }
+            Op::Import(name) => {
+                self.line(format!("call ${}__init()", name));
+                return;
+            }
+        };
+        let result = result.unwrap();
+        let ty = qbe_type(function.types[result]);
+        self.line(format!("%v{} ={} {}", result, ty, value));
+    }
+
+    fn terminator(&mut self, function: &Function, terminator: &Terminator) {
+        match terminator {
+            Terminator::Jump(target) => self.line(format!("jmp @b{}", target)),
+            Terminator::Branch(condition, then, otherwise) => {
+                let condition = self.operand(condition);
+                self.line(format!("jnz {}, @b{}, @b{}", condition, then, otherwise));
+            }
+            Terminator::Return(None) => self.line("ret".into()),
+            Terminator::Return(Some(value)) => {
+                let value = self.operand(value);
+                self.line(format!("ret {}", value));
+            }
+            Terminator::Exit(status) => {
+                let status = self.typed(function, status);
+                self.line(format!("call $exit({})", status));
+                self.line("hlt".into());
+            }
+            Terminator::Stop(message) => {
+                let message = self.string(&format!("{}\n", message));
+                let stderr = self.temporary();
+                self.line(format!("{} =l loadl $stderr", stderr));
+                self.line(format!("call $fputs(l {}, l {})", message, stderr));
+                self.line("call $exit(w 1)".into());
+                self.line("hlt".into());
+            }
+        }
+    }
+}
+
+impl ModuleFiles for QbeEmitter {
+    // emitter for a module's own .ssa file, next to this one's output
+    fn for_module(&self, name: &str) -> QbeEmitter {
+        let full_path = Path::new(&self.full_path).with_file_name(format!("{}.ssa", name));
+        let mut emitter = QbeEmitter::new(full_path.display().to_string());
+        emitter.module = Some(name.into());
+        emitter.input_policy = self.input_policy;
+        emitter
+    }
+
+    fn program(&mut self, module: &Module) {
+        for name in &module.globals {
+            self.data
+                .push_str(&format!("data {} = {{ s 0 }}\n", global_name(name)));
+        }
+        for function in &module.functions {
+            self.function(&module.name, function);
+        }
+    }
+
+    fn write_file(&mut self) -> String {
+        let mut value = String::new();
+        if self.module.is_none() {
+            value.push_str(RUNTIME);
+            value.push_str(input_policy_code(self.input_policy));
+            value.push_str(RUNTIME_END);
+        }
+        if !self.data.is_empty() {
+            if !value.is_empty() {
+                value.push('\n');
+            }
+            value.push_str(&self.data);
+        }
+        value.push_str(&self.functions);
+        write_if_changed(Path::new(&self.full_path), &value);
+        self.full_path.clone()
+    }
+}
+
+impl Backend for QbeEmitter {
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String> {
+        backend::emit_files(self, modules, program)
+    }
+
+    //qbe takes one file at a time. its assembly goes in .qbe.s, so it
+    //doesn't overwrite what --emit=asm writes
+    fn finish(&self, files: &[String]) -> Option<String> {
+        let mut steps = Vec::new();
+        let mut assembly = Vec::new();
+        for file in files {
+            let file_assembly = file.replace(".ssa", ".qbe.s");
+            steps.push(format!("qbe -o {} {}", file_assembly, file));
+            assembly.push(file_assembly);
+        }
+        steps.push(format!("cc {}", assembly.join(" ")));
+        Some(format!("Build with: {}", steps.join(" && ")))
+    }
+}
+
+// w for comparison results, s for floats and l for pointers
+fn qbe_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "s",
+        Type::Int => "w",
+        Type::String => "l",
+    }
+}
+
+// the symbol of a FUNCTION, the same name the C backend gives it
+fn qbe_function_name(module: &Option<String>, name: &str) -> String {
+    match module {
+        Some(module) => format!("{}__{}", module, name),
+        None => format!("tt_fn_{}", name),
+    }
+}
+
+// the dot keeps variables apart from functions and libc
+fn global_name(name: &str) -> String {
+    format!("$var.{}", name)
+}
+
+// the comparisons are ordered, except != which like C is true for NaN
+fn qbe_instruction(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        BinOp::Div => "div",
+        BinOp::Eq => "ceqs",
+        BinOp::Ne => "cnes",
+        BinOp::Lt => "clts",
+        BinOp::Le => "cles",
+        BinOp::Gt => "cgts",
+        _ => "cges",
+    }
+}
+
+// the shortest digits that read back as the same float, never with an
+// exponent
+fn qbe_float(value: f32) -> String {
+    format!("s_{}", value)
+}
+
+// printable characters go in quotes, anything else as a byte of its own
+fn qbe_bytes(text: &str) -> String {
+    let mut out = String::new();
+    let mut quoted = String::new();
+    for byte in text.bytes() {
+        if (b' '..=b'~').contains(&byte) && byte != b'"' && byte != b'\\' {
+            quoted.push(byte as char);
+            continue;
+        }
+        if !quoted.is_empty() {
+            out.push_str(&format!("b \"{}\", ", quoted));
+            quoted.clear();
+        }
+        out.push_str(&format!("b {}, ", byte));
+    }
+    if !quoted.is_empty() {
+        out.push_str(&format!("b \"{}\", ", quoted));
+    }
+    out
+}
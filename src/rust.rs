@@ -0,0 +1,855 @@
+use crate::backend::{self, write_if_changed, Backend, Generator, InputPolicy};
+use crate::ir::{BinOp, BlockId, Function, FunctionKind, Module, Operand, Type};
+use crate::js::policy_name;
+use crate::variables::Variables;
+use std::path::Path;
+
+// a single main.rs for plain rustc. every FUNCTION is a Rust function
+// taking the runtime state, written through the Generator hooks so
+// variables keep their names and loops are while and loop. Rust has no
+// goto, so a function that needs one runs as a state machine: a loop round
+// a match on the block to run next. numbers are f32, like float in the C
+// build.
+
+const PROGRAM_START: &str = r#"// a teeny tiny program. build it with "rustc -O main.rs".
+#![allow(
+    dead_code,
+    non_snake_case,
+    unreachable_code,
+    unused_assignments,
+    unused_mut,
+    unused_parens,
+    unused_variables
+)]
+
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+// what INPUT does with text that isn't a number
+const INPUT_POLICY: &str = "POLICY";
+
+// what PRINT and INPUT share, and the program's variables
+struct Tt {
+    vars: Vars,
+    //stdout, written out when input is read or the program ends
+    out: BufWriter<io::Stdout>,
+    //the output column, for the print zones of ","
+    column: usize,
+    eof: i32,
+    //input read in but not used yet. INPUT takes numbers from it one at a
+    //time the way scanf does, so a line can hold more than one
+    pending: String,
+}
+
+// nan and inf the way printf writes them, None for other numbers. nan goes
+// without its sign, which depends on how it was made
+fn special(n: f32) -> Option<String> {
+    if n.is_nan() {
+        Some("nan".into())
+    } else if n.is_infinite() {
+        let sign = if n < 0.0 { "-" } else { "" };
+        Some(format!("{}inf", sign))
+    } else {
+        None
+    }
+}
+
+// printf("%.*g")
+fn format_general(x: f64, precision: usize) -> String {
+    let scientific = format!("{:.*e}", precision - 1, x);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_fraction(mantissa), sign, exponent.abs())
+    } else {
+        let fraction = (precision as i32 - 1 - exponent) as usize;
+        trim_fraction(&format!("{:.*}", fraction, x))
+    }
+}
+
+// %g drops trailing zeros, and the point if nothing is left after it
+fn trim_fraction(text: &str) -> String {
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+// how much of the start of text is a number, the way scanf("%f") reads one
+fn number_length(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let digits = |from: usize| {
+        bytes[from.min(bytes.len())..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    let mut length = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        length += 1;
+    }
+    let word = text[length..].to_ascii_lowercase();
+    for name in ["infinity", "inf", "nan"] {
+        if word.starts_with(name) {
+            return length + name.len();
+        }
+    }
+    let whole = digits(length);
+    length += whole;
+    let mut fraction = 0;
+    if bytes.get(length) == Some(&b'.') {
+        fraction = digits(length + 1);
+        if whole + fraction > 0 {
+            length += 1 + fraction;
+        }
+    }
+    if whole + fraction == 0 {
+        return 0;
+    }
+    if matches!(bytes.get(length), Some(b'e' | b'E')) {
+        let mut exponent = length + 1;
+        if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+            exponent += 1;
+        }
+        let count = digits(exponent);
+        if count > 0 {
+            length = exponent + count;
+        }
+    }
+    length
+}
+
+// the number scanf("%f") would read from text
+fn parse_number(text: &str) -> f32 {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let n: f32 = unsigned.parse().unwrap_or(0.0);
+    if negative {
+        -n
+    } else {
+        n
+    }
+}
+
+impl Tt {
+    fn new() -> Tt {
+        Tt {
+            vars: Vars::default(),
+            out: BufWriter::new(io::stdout()),
+            column: 0,
+            eof: 0,
+            pending: String::new(),
+        }
+    }
+
+    fn print_string(&mut self, text: &str) {
+        let _ = self.out.write_all(text.as_bytes());
+        self.column += text.len();
+    }
+
+    // whole numbers an i64 can hold print without a fraction, anything else
+    // in the fewest digits that read back as the same float
+    fn print_number(&mut self, n: f32) {
+        let text = match special(n) {
+            Some(text) => text,
+            None if n >= i64::MIN as f32 && n < -(i64::MIN as f32) && n == n.trunc() => {
+                format!("{}", n as i64)
+            }
+            None => {
+                let mut text = String::new();
+                for precision in 1..=9 {
+                    text = format_general(n as f64, precision);
+                    if text.parse::<f32>() == Ok(n) {
+                        break;
+                    }
+                }
+                text
+            }
+        };
+        self.print_string(&text);
+    }
+
+    // a ###.## picture prints like %6.2f
+    fn print_using(&mut self, n: f32, width: usize, fraction: usize) {
+        let text = special(n).unwrap_or_else(|| format!("{:.*}", fraction, n as f64));
+        self.print_string(&format!("{:>1$}", text, width));
+    }
+
+    fn print_tab(&mut self) {
+        loop {
+            self.print_string(" ");
+            if self.column % 14 == 0 {
+                break;
+            }
+        }
+    }
+
+    fn print_newline(&mut self) {
+        let _ = self.out.write_all(b"\n");
+        self.column = 0;
+    }
+
+    // the next number in the input, Some(None) if the text there isn't one
+    // and None at the end of the input
+    fn scan_number(&mut self) -> Option<Option<f32>> {
+        while self.pending.trim_start().is_empty() {
+            self.pending.clear();
+            match io::stdin().read_line(&mut self.pending) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+        }
+        self.pending = self.pending.trim_start().to_string();
+        let length = number_length(&self.pending);
+        if length == 0 {
+            return Some(None);
+        }
+        let n = parse_number(&self.pending[..length]);
+        self.pending.drain(..length);
+        Some(Some(n))
+    }
+
+    fn input(&mut self, prompt: &str, location: &str) -> f32 {
+        loop {
+            self.print_string(prompt);
+            let _ = self.out.flush();
+            let number = self.scan_number();
+            self.column = 0;
+            match number {
+                None => {
+                    self.eof = 1;
+                    return 0.0;
+                }
+                Some(Some(n)) => return n,
+                Some(None) => {}
+            }
+            match INPUT_POLICY {
+                "zero" => {
+                    //skip the word that isn't a number
+                    let word = self
+                        .pending
+                        .find(char::is_whitespace)
+                        .unwrap_or(self.pending.len());
+                    self.pending.drain(..word);
+                    return 0.0;
+                }
+                "error" => {
+                    eprintln!("Invalid input at {}", location);
+                    self.exit(1);
+                }
+                _ => {
+                    //throw the rest of the line away and ask again
+                    let line = self
+                        .pending
+                        .find('\n')
+                        .map_or(self.pending.len(), |end| end + 1);
+                    self.pending.drain(..line);
+                    let _ = self.out.write_all(b"?Redo from start\n");
+                }
+            }
+        }
+    }
+
+    fn exit(&mut self, status: i32) -> ! {
+        let _ = self.out.flush();
+        process::exit(status)
+    }
+
+    fn stop(&mut self, message: &str) -> ! {
+        eprintln!("{}", message);
+        self.exit(1)
+    }
+}
+
+fn main() {
+    let mut tt = Tt::new();
+    let status = program(&mut tt);
+    tt.exit(status)
+}
+"#;
+
+pub struct RustEmitter {
+    //fields of Vars, the variables kept outside the functions and the init
+    //guards
+    vars: String,
+    //finished function definitions
+    functions: String,
+    //function being emitted
+    code: String,
+    indent: usize,
+    //true in the main program, whose EXIT gives main() the status
+    main: bool,
+    //true once the line being written calls a FUNCTION, which borrows tt
+    called: bool,
+    //the loops the code is in, innermost last, with the condition of a
+    //do loop for continue to test
+    loops: Vec<Option<String>>,
+    //module whose IR is being added, None for the main program
+    module: Option<String>,
+    full_path: String,
+    pub input_policy: InputPolicy,
+}
+
+impl RustEmitter {
+    pub fn new(full_path: String) -> RustEmitter {
+        RustEmitter {
+            vars: String::new(),
+            functions: String::new(),
+            code: String::new(),
+            indent: 0,
+            main: false,
+            called: false,
+            loops: Vec::new(),
+            module: None,
+            full_path,
+            input_policy: InputPolicy::Zero,
+        }
+    }
+
+    pub fn full_path(&self) -> &str {
+        &self.full_path
+    }
+
+    fn line(&mut self, code: String) {
+        self.code.push_str(&"    ".repeat(self.indent));
+        self.code.push_str(&code);
+        self.code.push('\n');
+        self.called = false;
+    }
+
+    // an argument for a method of tt, which can't borrow tt for a call
+    // while it holds it
+    fn argument(&mut self, value: String) -> String {
+        if !self.called {
+            return value;
+        }
+        self.line(format!("let value = {};", value));
+        "value".into()
+    }
+
+    // variables of every module share Vars
+    fn global_name(&self, name: &str) -> String {
+        match &self.module {
+            Some(module) => format!("g_{}_{}", module, name),
+            None => format!("g_{}", name),
+        }
+    }
+
+    fn write_file(&mut self) {
+        let mut value = PROGRAM_START.replace(
+            "\"POLICY\"",
+            &format!("\"{}\"", policy_name(self.input_policy)),
+        );
+        value.push_str("\n// variables FUNCTIONs share, and which modules have run\n");
+        value.push_str("#[derive(Default)]\n");
+        if self.vars.is_empty() {
+            value.push_str("struct Vars {}\n");
+        } else {
+            value.push_str(&format!("struct Vars {{\n{}}}\n", self.vars));
+        }
+        value.push_str(&self.functions);
+        write_if_changed(Path::new(&self.full_path), &value);
+    }
+}
+
+impl Generator for RustEmitter {
+    fn reserved(&self) -> &'static [&'static str] {
+        RESERVED
+    }
+
+    fn has_goto(&self) -> bool {
+        false
+    }
+
+    // modules have to come before the modules that import them, and the
+    // main program last
+    fn start_program(&mut self, module: &Module, _variables: &[Variables]) {
+        self.module = module.name.clone();
+        for name in &module.globals {
+            let name = self.global_name(name);
+            self.vars.push_str(&format!("    {}: f32,\n", name));
+        }
+    }
+
+    fn start_function(&mut self, function: &Function, variables: &Variables) {
+        let mut parameters = Vec::new();
+        for parameter in &function.parameters {
+            let ty = rust_type(function.types[parameter.0]);
+            let name = variables.name(*parameter).unwrap();
+            parameters.push(format!("mut {}: {}", name, ty));
+        }
+        //last, so the arguments before it can call FUNCTIONs
+        parameters.push("tt: &mut Tt".into());
+        let returns = match function.returns {
+            Some(ty) => format!(" -> {}", rust_type(ty)),
+            None => String::new(),
+        };
+        self.code.push('\n');
+        self.line(format!(
+            "fn {}({}){} {{",
+            rust_function_name(&self.module, function),
+            parameters.join(", "),
+            returns
+        ));
+        self.indent += 1;
+        self.main = function.kind == FunctionKind::Main;
+
+        for (name, ty) in variables.locals() {
+            self.line(format!(
+                "let mut {}: {} = {};",
+                name,
+                rust_type(ty),
+                rust_default(ty)
+            ));
+        }
+
+        if function.kind == FunctionKind::Init {
+            //the module's statements run once, when it is first imported
+            let guard = format!("{}__initialized", self.module.as_ref().unwrap());
+            self.vars.push_str(&format!("    {}: bool,\n", guard));
+            self.start_if(format!("tt.vars.{}", guard));
+            self.ret(None);
+            self.end_if();
+            self.line(format!("tt.vars.{} = true;", guard));
+        }
+    }
+
+    fn end_function(&mut self, _function: &Function, _variables: &Variables) {
+        self.indent -= 1;
+        self.line("}".into());
+        let code = std::mem::take(&mut self.code);
+        self.functions.push_str(&code);
+    }
+
+    fn constant(&mut self, constant: &Operand) -> String {
+        rust_operand(constant)
+    }
+
+    fn binary(&mut self, op: BinOp, left: String, right: String) -> String {
+        match op {
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => {
+                let precedence = rust_precedence(op);
+                let left = rust_bracket(left, precedence);
+                let right = rust_bracket(right, precedence + 1);
+                format!("{} {} {}", left, rust_operator(op), right)
+            }
+            _ => format!("({}) as i32", self.compare(op, left, right)),
+        }
+    }
+
+    fn compare(&mut self, op: BinOp, left: String, right: String) -> String {
+        //comparisons don't chain, so one inside another needs brackets,
+        //and a < straight after a cast's type would start generic arguments
+        let left = match expression_precedence(&left) {
+            CAST => format!("({})", left),
+            _ => rust_bracket(left, COMPARISON + 1),
+        };
+        let right = rust_bracket(right, COMPARISON + 1);
+        format!("{} {} {}", left, rust_operator(op), right)
+    }
+
+    fn truth(&mut self, value: String) -> String {
+        format!("{} != 0", rust_bracket(value, COMPARISON + 1))
+    }
+
+    fn neg(&mut self, operand: String) -> String {
+        if operand.starts_with('-') {
+            format!("-({})", operand)
+        } else {
+            format!("-{}", rust_bracket(operand, UNARY))
+        }
+    }
+
+    fn not(&mut self, condition: String) -> String {
+        //the opposite of a ! is what it has in brackets
+        match condition.strip_prefix('!') {
+            Some(inner) if expression_precedence(inner) == PRIMARY => {
+                let inner = inner
+                    .strip_prefix('(')
+                    .and_then(|inner| inner.strip_suffix(')'));
+                inner.unwrap_or(&condition[1..]).into()
+            }
+            _ => format!("!{}", rust_bracket(condition, UNARY)),
+        }
+    }
+
+    fn and(&mut self, first: String, second: String) -> String {
+        format!(
+            "{} && {}",
+            rust_bracket(first, AND),
+            rust_bracket(second, AND)
+        )
+    }
+
+    fn or(&mut self, first: String, second: String) -> String {
+        format!("{} || {}", first, second)
+    }
+
+    fn int_to_float(&mut self, operand: String) -> String {
+        format!("{} as f32", rust_bracket(operand, CAST))
+    }
+
+    //saturates, nan to 0 and numbers out of range to the nearest int
+    fn float_to_int(&mut self, operand: String) -> String {
+        format!("{} as i32", rust_bracket(operand, CAST))
+    }
+
+    fn load(&mut self, name: &str) -> String {
+        format!("tt.vars.{}", self.global_name(name))
+    }
+
+    fn call(&mut self, module: &Option<String>, name: &str, args: Vec<String>) -> String {
+        let callee = match module {
+            Some(module) => format!("{}__{}", module, name),
+            None => format!("tt_fn_{}", name),
+        };
+        self.called = true;
+        let args: Vec<String> = args.into_iter().chain(["tt".to_string()]).collect();
+        format!("{}({})", callee, args.join(", "))
+    }
+
+    fn eof(&mut self) -> String {
+        "tt.eof".into()
+    }
+
+    fn assign(&mut self, variable: &str, value: String) {
+        self.line(format!("{} = {};", variable, value));
+    }
+
+    fn evaluate(&mut self, value: String) {
+        self.line(format!("{};", value));
+    }
+
+    fn store(&mut self, name: &str, value: String) {
+        let name = self.global_name(name);
+        self.line(format!("tt.vars.{} = {};", name, value));
+    }
+
+    fn input(&mut self, variable: &str, prompt: String, location: &str) {
+        self.line(format!(
+            "{} = tt.input({}, {:?});",
+            variable, prompt, location
+        ));
+    }
+
+    fn print_string(&mut self, text: String) {
+        let text = self.argument(text);
+        self.line(format!("tt.print_string({});", text));
+    }
+
+    fn print_number(&mut self, value: String) {
+        let value = self.argument(value);
+        self.line(format!("tt.print_number({});", value));
+    }
+
+    fn print_using(&mut self, picture: &str, value: String) {
+        let fraction = picture
+            .split_once('.')
+            .map_or(0, |(_, fraction)| fraction.len());
+        let value = self.argument(value);
+        self.line(format!(
+            "tt.print_using({}, {}, {});",
+            value,
+            picture.len(),
+            fraction
+        ));
+    }
+
+    fn print_tab(&mut self) {
+        self.line("tt.print_tab();".into());
+    }
+
+    fn print_newline(&mut self) {
+        self.line("tt.print_newline();".into());
+    }
+
+    fn import(&mut self, module: &str) {
+        self.line(format!("{}__init(tt);", module));
+    }
+
+    fn start_if(&mut self, condition: String) {
+        self.line(format!("if {} {{", condition));
+        self.indent += 1;
+    }
+
+    fn start_else_if(&mut self, condition: String) {
+        self.indent -= 1;
+        self.line(format!("}} else if {} {{", condition));
+        self.indent += 1;
+    }
+
+    fn start_else(&mut self) {
+        self.indent -= 1;
+        self.line("} else {".into());
+        self.indent += 1;
+    }
+
+    fn end_if(&mut self) {
+        self.indent -= 1;
+        self.line("}".into());
+    }
+
+    fn start_loop(&mut self) {
+        self.loops.push(None);
+        self.line("loop {".into());
+        self.indent += 1;
+    }
+
+    fn start_while(&mut self, condition: String) {
+        self.loops.push(None);
+        self.line(format!("while {} {{", condition));
+        self.indent += 1;
+    }
+
+    fn end_loop(&mut self) {
+        self.loops.pop();
+        self.indent -= 1;
+        self.line("}".into());
+    }
+
+    //Rust has no do loop, so the test is an if at the bottom
+    fn start_do(&mut self, condition: String) {
+        self.start_loop();
+        *self.loops.last_mut().unwrap() = Some(condition);
+    }
+
+    fn end_do(&mut self, condition: String) {
+        let condition = self.not(condition);
+        self.start_if(condition);
+        self.break_loop();
+        self.end_if();
+        self.end_loop();
+    }
+
+    fn break_loop(&mut self) {
+        self.line("break;".into());
+    }
+
+    fn continue_loop(&mut self) {
+        //going round a do loop again means testing its condition first
+        if let Some(Some(condition)) = self.loops.last() {
+            let condition = condition.clone();
+            self.start_if(condition);
+            self.line("continue;".into());
+            self.end_if();
+            self.break_loop();
+        } else {
+            self.line("continue;".into());
+        }
+    }
+
+    fn label(&mut self, _block: BlockId) {
+        unreachable!("Rust has no labels");
+    }
+
+    fn goto(&mut self, block: BlockId) {
+        self.line(format!("block = {};", block));
+        self.line("continue;".into());
+    }
+
+    fn ret(&mut self, value: Option<String>) {
+        match value {
+            Some(value) => self.line(format!("return {};", value)),
+            None => self.line("return;".into()),
+        }
+    }
+
+    fn exit(&mut self, status: String) {
+        //main's status goes back to main(), anywhere else exits
+        if self.main {
+            self.ret(Some(status));
+        } else {
+            let status = self.argument(status);
+            self.line(format!("tt.exit({});", status));
+        }
+    }
+
+    fn stop(&mut self, message: &str) {
+        self.line(format!("tt.stop({:?});", message));
+    }
+
+    // a match on the block to run next in a loop, where goto sets the
+    // block and goes round again
+    fn start_dispatch(&mut self, entry: BlockId) {
+        self.line(format!("let mut block = {};", entry));
+        self.line("loop {".into());
+        self.line("    match block {".into());
+        self.indent += 2;
+    }
+
+    fn start_case(&mut self, block: BlockId) {
+        self.line(format!("{} => {{", block));
+        self.indent += 1;
+    }
+
+    fn end_case(&mut self) {
+        self.indent -= 1;
+        self.line("}".into());
+    }
+
+    fn end_dispatch(&mut self) {
+        self.line("_ => unreachable!(),".into());
+        self.indent -= 2;
+        self.line("    }".into());
+        self.line("}".into());
+    }
+}
+
+// one crate root holds the program and its modules
+impl Backend for RustEmitter {
+    fn emit(&mut self, modules: &[Module], program: &Module) -> Vec<String> {
+        for module in modules.iter().chain([program]) {
+            backend::generate(self, module);
+        }
+        self.write_file();
+        vec![self.full_path.clone()]
+    }
+
+    fn finish(&self, _files: &[String]) -> Option<String> {
+        Some(format!("Build with: rustc -O {}", self.full_path))
+    }
+}
+
+// Rust's keywords, and the names the generated functions use that a
+// teeny tiny name could be
+const RESERVED: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "union", "unsafe", "unsized", "use", "virtual", "where", "while", "yield", "tt", "block",
+    "value",
+];
+
+const OR: u8 = 1;
+const AND: u8 = 2;
+const COMPARISON: u8 = 3;
+const CAST: u8 = 6;
+//negation and !
+const UNARY: u8 = 7;
+//names, constants, calls and anything in brackets
+const PRIMARY: u8 = 8;
+
+fn rust_precedence(op: BinOp) -> u8 {
+    match op {
+        BinOp::Mul | BinOp::Div => 5,
+        BinOp::Add | BinOp::Sub => 4,
+        _ => COMPARISON,
+    }
+}
+
+// the precedence of the loosest operator outside brackets and quotes in an
+// expression the hooks wrote, where binary operators have a space each side
+fn expression_precedence(expression: &str) -> u8 {
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut lowest = PRIMARY;
+    for (index, byte) in expression.bytes().enumerate() {
+        match byte {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            _ if quoted => {}
+            b'(' => depth += 1,
+            b')' => depth -= 1,
+            b' ' if depth == 0 => {
+                let precedence = match expression[index + 1..].split(' ').next().unwrap() {
+                    "||" => OR,
+                    "&&" => AND,
+                    "<" | "<=" | ">" | ">=" | "==" | "!=" => COMPARISON,
+                    "+" | "-" => 4,
+                    "*" | "/" => 5,
+                    "as" => CAST,
+                    _ => continue,
+                };
+                lowest = lowest.min(precedence);
+            }
+            _ => {}
+        }
+    }
+    if lowest == PRIMARY && (expression.starts_with('-') || expression.starts_with('!')) {
+        return UNARY;
+    }
+    lowest
+}
+
+// an operand that needs at least the given precedence, in brackets if it
+// binds looser
+fn rust_bracket(operand: String, precedence: u8) -> String {
+    if expression_precedence(&operand) < precedence {
+        format!("({})", operand)
+    } else {
+        operand
+    }
+}
+
+// the same names the C backend uses, except the main program, which main()
+// calls
+fn rust_function_name(module: &Option<String>, function: &Function) -> String {
+    match (function.kind, module) {
+        (FunctionKind::Main, _) => "program".into(),
+        (_, Some(module)) => format!("{}__{}", module, function.name),
+        (_, None) => format!("tt_fn_{}", function.name),
+    }
+}
+
+fn rust_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "f32",
+        Type::Int => "i32",
+        Type::String => "&'static str",
+    }
+}
+
+fn rust_default(ty: Type) -> &'static str {
+    match ty {
+        Type::Float => "0.0",
+        Type::Int => "0",
+        Type::String => "\"\"",
+    }
+}
+
+fn rust_operator(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        _ => ">=",
+    }
+}
+
+fn rust_operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Value(_) => unreachable!("a value is not a constant"),
+        Operand::Float(value) => rust_float(*value),
+        //negative ints in parentheses, "as" binds tighter than "-"
+        Operand::Int(value) if *value < 0 => format!("({})", value),
+        Operand::Int(value) => value.to_string(),
+        Operand::String(text) => format!("{:?}", text),
+    }
+}
+
+fn rust_float(value: f32) -> String {
+    if value.is_nan() {
+        "f32::NAN".into()
+    } else if value.is_infinite() && value < 0.0 {
+        "f32::NEG_INFINITY".into()
+    } else if value.is_infinite() {
+        "f32::INFINITY".into()
+    } else if value.is_sign_negative() {
+        format!("({:?}f32)", value)
+    } else {
+        format!("{:?}f32", value)
+    }
+}
@@ -0,0 +1,47 @@
+use crate::symbols::Span;
+use std::path::PathBuf;
+
+// a source file taking part in the compile, and where it was included from
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub included_from: Option<(usize, usize)>,
+    //for the main file of a module, "imported from" lines leading back to
+    //the file the compile started with
+    pub import_note: String,
+}
+
+pub fn abort_at(files: &[SourceFile], span: Span, message: String) -> ! {
+    panic!(
+        "Error in {} on line {}. {message}{}",
+        files[span.file].path.display(),
+        span.line,
+        include_chain(files, span.file)
+    )
+}
+
+// "included from" lines leading back to the main file, and on to the file
+// the compile started with when this is a module
+pub fn include_chain(files: &[SourceFile], file: usize) -> String {
+    let mut chain = String::new();
+    let mut current = &files[file];
+    while let Some((file, line)) = current.included_from {
+        current = &files[file];
+        chain.push_str(&format!(
+            "\n  included from {} on line {}",
+            current.path.display(),
+            line
+        ));
+    }
+    chain.push_str(&current.import_note);
+    chain
+}
+
+// a span in words, naming the file only when it isn't the main one
+pub fn describe(files: &[SourceFile], span: Span) -> String {
+    if span.file == 0 {
+        format!("line {}", span.line)
+    } else {
+        format!("line {} of {}", span.line, files[span.file].path.display())
+    }
+}
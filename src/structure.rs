@@ -0,0 +1,944 @@
+use crate::ir::{BlockId, Function, Terminator};
+use crate::variables::Variables;
+use std::collections::{HashMap, HashSet};
+
+// the if, while and do statements behind a function's CFG, for backends
+// that print source code. blocks are laid out down the dominator tree,
+// with the ones more than one way leads into after the code that leads
+// there and what follows a loop after the loop, so jumps mostly fall
+// through or become break and continue. a branch to a block that only
+// tests something else and branches to one of the same places becomes a
+// && or ||, the way SELECT CASE tests its cases. the rest are gotos, and
+// a CFG with a GOTO into the middle of a loop comes out as nothing but
+// ifs and gotos.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    //the instructions of a block
+    Block(BlockId),
+    //the copies for the phis of a block, on the way in from another
+    Copies(BlockId, BlockId),
+    //the branch a block ends with
+    If(Condition, Vec<Statement>, Vec<Statement>),
+    Loop(Loop, Vec<Statement>),
+    Break,
+    Continue,
+    Goto(BlockId),
+    Label(BlockId),
+    //the return, END, EXIT or STOP a block ends with
+    End(BlockId),
+    //a function as a state machine, for languages without goto: a case
+    //for the entry and each block a goto leads to, where goto picks the
+    //case to run next
+    Dispatch(Vec<(BlockId, Vec<Statement>)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    //the condition of the branch a block ends with, or its opposite
+    Test { block: BlockId, negated: bool },
+    //the second only tested if the first holds
+    And(Box<Condition>, Box<Condition>),
+    //the second only tested if the first doesn't hold
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    // the block whose branch is tested first
+    pub fn block(&self) -> BlockId {
+        match self {
+            Condition::Test { block, .. } => *block,
+            Condition::And(first, _) | Condition::Or(first, _) => first.block(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Loop {
+    Forever,
+    //tested before each time round
+    While(Condition),
+    //tested after each time round
+    DoWhile(Condition),
+}
+
+pub fn function(function: &Function, variables: &Variables) -> Vec<Statement> {
+    let (merged, conditions) = short_circuit(function, variables);
+    let mut statements = match Structurer::new(&merged, variables, conditions) {
+        Some(structurer) => structurer.tree(0, None, &mut Vec::new()),
+        None => linear(function, variables),
+    };
+    let mut targets = HashSet::new();
+    gotos(&statements, &mut targets);
+    remove_labels(&mut statements, &targets);
+    statements
+}
+
+// function() for languages without goto, where a function that still has
+// gotos runs as a state machine instead
+pub fn function_without_goto(function: &Function, variables: &Variables) -> Vec<Statement> {
+    let statements = self::function(function, variables);
+    let mut targets = HashSet::new();
+    gotos(&statements, &mut targets);
+    if targets.is_empty() {
+        return statements;
+    }
+    vec![dispatch(function, variables)]
+}
+
+// the blocks in order, cut into a case at each label a goto leads to. a
+// case that runs on into the next one ends with a goto to it
+fn dispatch(function: &Function, variables: &Variables) -> Statement {
+    let statements = linear(function, variables);
+    let mut targets = HashSet::new();
+    gotos(&statements, &mut targets);
+    let mut cases: Vec<(BlockId, Vec<Statement>)> = vec![(0, Vec::new())];
+    for statement in statements {
+        match statement {
+            Statement::Label(block) if targets.contains(&block) => {
+                if cases.len() == 1 && cases[0].1.is_empty() && !targets.contains(&cases[0].0) {
+                    //the entry runs straight into it
+                    cases[0].0 = block;
+                    continue;
+                }
+                let (_, body) = cases.last_mut().unwrap();
+                if !leaves(body) {
+                    body.push(Statement::Goto(block));
+                }
+                cases.push((block, Vec::new()));
+            }
+            Statement::Label(_) => {}
+            statement => cases.last_mut().unwrap().1.push(statement),
+        }
+    }
+    Statement::Dispatch(cases)
+}
+
+struct Structurer<'a> {
+    function: &'a Function,
+    variables: &'a Variables,
+    //children in the dominator tree laid out after a block, and after the
+    //loop a block heads, each in reverse postorder
+    children: Vec<Vec<BlockId>>,
+    exits: Vec<Vec<BlockId>>,
+    //predecessors that don't jump back round a loop
+    forward: Vec<usize>,
+    //where the back edges into each loop header come from
+    latches: Vec<Vec<BlockId>>,
+    //what each block's branch tests
+    conditions: Vec<Condition>,
+}
+
+// a loop the code being laid out is in
+struct Enclosing {
+    header: BlockId,
+    //where break goes, the code after the loop
+    follow: Option<BlockId>,
+}
+
+impl<'a> Structurer<'a> {
+    // None when a jump goes back into a loop other than through its
+    // header, which none of the statements can say
+    fn new(
+        function: &'a Function,
+        variables: &'a Variables,
+        conditions: Vec<Condition>,
+    ) -> Option<Structurer<'a>> {
+        let count = function.blocks.len();
+        let idom = function.dominators();
+        let order = function.reverse_postorder();
+        let mut position = vec![usize::MAX; count];
+        for (index, &block) in order.iter().enumerate() {
+            position[block] = index;
+        }
+
+        let mut forward = vec![0; count];
+        let mut latches = vec![Vec::new(); count];
+        let mut loops: HashMap<BlockId, HashSet<BlockId>> = HashMap::new();
+        let predecessors = function.predecessors();
+        for &block in &order {
+            let mut successors = function.blocks[block].terminator.successors();
+            successors.dedup();
+            for successor in successors {
+                if position[successor] > position[block] {
+                    forward[successor] += 1;
+                    continue;
+                }
+                if !dominates(&idom, successor, block) {
+                    return None;
+                }
+                latches[successor].push(block);
+                //the blocks that get back to the latch without passing
+                //through the header
+                let body = loops
+                    .entry(successor)
+                    .or_insert_with(|| HashSet::from([successor]));
+                let mut work = vec![block];
+                while let Some(member) = work.pop() {
+                    if body.insert(member) {
+                        work.extend(&predecessors[member]);
+                    }
+                }
+            }
+        }
+
+        //a loop takes in the blocks that leave it only for the code after
+        //it or to end the program, the way BREAK and END inside it do,
+        //outer loops first so they hold whatever the ones in them do
+        let mut follows = vec![None; count];
+        let mut headers: Vec<BlockId> = loops.keys().copied().collect();
+        headers.sort_by_key(|header| (usize::MAX - loops[header].len(), position[*header]));
+        for (index, &header) in headers.iter().enumerate() {
+            let outer: Vec<BlockId> = headers[..index]
+                .iter()
+                .copied()
+                .filter(|outer| loops[outer].contains(&header))
+                .collect();
+            let body = &loops[&header];
+            let follow = follow(function, header, body, &latches[header], &position);
+            let mut body = body.clone();
+            loop {
+                let taken: Vec<BlockId> = order
+                    .iter()
+                    .copied()
+                    .filter(|&block| {
+                        !body.contains(&block)
+                            && Some(block) != follow
+                            && dominates(&idom, header, block)
+                            && outer.iter().all(|outer| loops[outer].contains(&block))
+                            && function.blocks[block].terminator.successors().iter().all(
+                                |successor| body.contains(successor) || Some(*successor) == follow,
+                            )
+                    })
+                    .collect();
+                if taken.is_empty() {
+                    break;
+                }
+                body.extend(taken);
+            }
+            follows[header] = follow;
+            loops.insert(header, body);
+        }
+
+        let mut children = vec![Vec::new(); count];
+        let mut exits = vec![Vec::new(); count];
+        for &block in &order[1..] {
+            let parent = idom[block].unwrap();
+            //a block outside loops its dominator is in goes after the
+            //outermost of them
+            let outside = loops
+                .iter()
+                .filter(|(_, body)| body.contains(&parent) && !body.contains(&block))
+                .max_by_key(|(_, body)| body.len());
+            match outside {
+                Some((&header, _)) => exits[header].push(block),
+                None => children[parent].push(block),
+            }
+        }
+        //what follows a loop comes straight after it
+        for (header, follow) in follows.iter().enumerate() {
+            if let Some(place) = exits[header].iter().position(|&exit| Some(exit) == *follow) {
+                let follow = exits[header].remove(place);
+                exits[header].insert(0, follow);
+            }
+        }
+
+        Some(Structurer {
+            function,
+            variables,
+            children,
+            exits,
+            forward,
+            latches,
+            conditions,
+        })
+    }
+
+    fn is_header(&self, block: BlockId) -> bool {
+        !self.latches[block].is_empty()
+    }
+
+    // a block and the ones laid out under it, falling through to next
+    fn tree(
+        &self,
+        block: BlockId,
+        next: Option<BlockId>,
+        loops: &mut Vec<Enclosing>,
+    ) -> Vec<Statement> {
+        if !self.is_header(block) {
+            let mut statements = vec![Statement::Label(block)];
+            statements.extend(self.node(block, next, loops));
+            return statements;
+        }
+
+        let exits = &self.exits[block];
+        let follow = exits.first().copied().or(next);
+        loops.push(Enclosing {
+            header: block,
+            follow,
+        });
+        let body = self.node(block, Some(block), loops);
+        loops.pop();
+
+        let mut statements = vec![Statement::Label(block), self.loop_statement(block, body)];
+        for (index, &exit) in exits.iter().enumerate() {
+            let next = exits.get(index + 1).copied().or(next);
+            statements.extend(self.tree(exit, next, loops));
+        }
+        statements
+    }
+
+    // a block's code and the branches out of it, then the children more
+    // than one way leads into
+    fn node(
+        &self,
+        block: BlockId,
+        next: Option<BlockId>,
+        loops: &mut Vec<Enclosing>,
+    ) -> Vec<Statement> {
+        let merges: Vec<BlockId> = self.children[block]
+            .iter()
+            .copied()
+            .filter(|&child| self.forward[child] > 1)
+            .collect();
+        let after = merges.first().copied().or(next);
+
+        let mut statements = Vec::new();
+        if !self.variables.is_quiet(self.function, block) {
+            statements.push(Statement::Block(block));
+        }
+        match &self.function.blocks[block].terminator {
+            Terminator::Jump(target) => statements.extend(self.arm(block, *target, after, loops)),
+            Terminator::Branch(_, then, otherwise) if then == otherwise => {
+                statements.push(Statement::If(
+                    Condition::Test {
+                        block,
+                        negated: false,
+                    },
+                    Vec::new(),
+                    Vec::new(),
+                ));
+                statements.extend(self.arm(block, *then, after, loops));
+            }
+            Terminator::Branch(_, then, otherwise) => {
+                let then = self.arm(block, *then, after, loops);
+                let otherwise = self.arm(block, *otherwise, after, loops);
+                statements.extend(branch(self.conditions[block].clone(), then, otherwise));
+            }
+            Terminator::Return(_) | Terminator::Exit(_) | Terminator::Stop(_) => {
+                statements.push(Statement::End(block))
+            }
+        }
+
+        for (index, &merge) in merges.iter().enumerate() {
+            let next = merges.get(index + 1).copied().or(next);
+            statements.extend(self.tree(merge, next, loops));
+        }
+        statements
+    }
+
+    // the way from a block to one of its successors: the copies for its
+    // phis, then the successor itself if nothing else leads there, or a
+    // jump to it
+    fn arm(
+        &self,
+        block: BlockId,
+        target: BlockId,
+        next: Option<BlockId>,
+        loops: &mut Vec<Enclosing>,
+    ) -> Vec<Statement> {
+        let mut statements = Vec::new();
+        if !self
+            .variables
+            .copies(self.function, block, target)
+            .is_empty()
+        {
+            statements.push(Statement::Copies(block, target));
+        }
+        if self.children[block].contains(&target) && self.forward[target] == 1 {
+            statements.extend(self.tree(target, next, loops));
+        } else {
+            statements.extend(jump(target, next, loops));
+        }
+        statements
+    }
+
+    // a loop's body as a while or do loop where the test is all there is
+    // at its start or end
+    fn loop_statement(&self, header: BlockId, mut body: Vec<Statement>) -> Statement {
+        //while: if (!condition) break; at the start
+        if let Some(Statement::If(condition, then, otherwise)) = body.first() {
+            if condition.block() == header && then == &[Statement::Break] && otherwise.is_empty() {
+                let condition = opposite(condition.clone());
+                body.remove(0);
+                return Statement::Loop(Loop::While(condition), body);
+            }
+        }
+
+        //do: if (!condition) break; at the end of the block that goes
+        //back round, which nothing else does
+        if let [latch] = self.latches[header][..] {
+            if let Some(Statement::If(condition, then, otherwise)) = body.last() {
+                if condition.block() == latch
+                    && then == &[Statement::Break]
+                    && otherwise.is_empty()
+                    && !continues(&body)
+                {
+                    let condition = opposite(condition.clone());
+                    body.pop();
+                    //with nothing but the test in the block that goes
+                    //back round, continue is the way there
+                    if self.variables.is_quiet(self.function, latch)
+                        && body.last() == Some(&Statement::Label(latch))
+                    {
+                        body.pop();
+                        retarget(&mut body, latch);
+                        if contains_goto(&body, latch) {
+                            body.push(Statement::Label(latch));
+                        }
+                    }
+                    return Statement::Loop(Loop::DoWhile(condition), body);
+                }
+            }
+        }
+        Statement::Loop(Loop::Forever, body)
+    }
+}
+
+// a function with each branch to a block that does nothing but branch to
+// one of the same places folded into it, as an && or ||, the way SELECT
+// CASE tests a list of cases. the blocks folded in are left unreachable.
+fn short_circuit(function: &Function, variables: &Variables) -> (Function, Vec<Condition>) {
+    let mut merged = function.clone();
+    let mut conditions: Vec<Condition> = (0..function.blocks.len())
+        .map(|block| Condition::Test {
+            block,
+            negated: false,
+        })
+        .collect();
+    let mut predecessors = function.predecessors();
+    let no_copies = |from, to| variables.copies(function, from, to).is_empty();
+    for block in function.reverse_postorder() {
+        while let Terminator::Branch(operand, then, otherwise) =
+            merged.blocks[block].terminator.clone()
+        {
+            let foldable = |next: BlockId| {
+                next != block
+                    && predecessors[next] == [block]
+                    && variables.is_quiet(function, next)
+                    && no_copies(block, next)
+            };
+            let fold = match merged.blocks[otherwise].terminator {
+                Terminator::Branch(_, next_then, next_otherwise) if foldable(otherwise) => {
+                    if next_then == then {
+                        Some((false, otherwise, false, then, next_otherwise))
+                    } else if next_otherwise == then {
+                        Some((false, otherwise, true, then, next_then))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+            let fold = fold.or(match merged.blocks[then].terminator {
+                Terminator::Branch(_, next_then, next_otherwise) if foldable(then) => {
+                    if next_otherwise == otherwise {
+                        Some((true, then, false, next_then, otherwise))
+                    } else if next_then == otherwise {
+                        Some((true, then, true, next_otherwise, otherwise))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            });
+            let Some((and, next, negated, new_then, new_otherwise)) = fold else {
+                break;
+            };
+            let successors = merged.blocks[next].terminator.successors();
+            if new_then == new_otherwise
+                || !successors
+                    .iter()
+                    .all(|&successor| no_copies(next, successor))
+                || !no_copies(block, if and { otherwise } else { then })
+            {
+                break;
+            }
+
+            let first = Box::new(conditions[block].clone());
+            let second = match negated {
+                false => conditions[next].clone(),
+                true => opposite(conditions[next].clone()),
+            };
+            let second = Box::new(second);
+            conditions[block] = if and {
+                Condition::And(first, second)
+            } else {
+                Condition::Or(first, second)
+            };
+            merged.blocks[block].terminator = Terminator::Branch(operand, new_then, new_otherwise);
+            merged.blocks[next].terminator = Terminator::Return(None);
+            for successor in successors {
+                predecessors[successor].retain(|&predecessor| predecessor != next);
+                if !predecessors[successor].contains(&block) {
+                    predecessors[successor].push(block);
+                }
+            }
+        }
+    }
+    (merged, conditions)
+}
+
+// where a loop goes when it's done: the way out of its test at the top
+// or the bottom if it has one, otherwise where the most ways out lead
+fn follow(
+    function: &Function,
+    header: BlockId,
+    body: &HashSet<BlockId>,
+    latches: &[BlockId],
+    position: &[usize],
+) -> Option<BlockId> {
+    let outside = |block: BlockId| {
+        let mut successors: Vec<BlockId> = function.blocks[block]
+            .terminator
+            .successors()
+            .into_iter()
+            .filter(|successor| !body.contains(successor))
+            .collect();
+        successors.sort_by_key(|&successor| position[successor]);
+        successors
+    };
+    let tested = std::iter::once(header)
+        .chain(latches.iter().copied())
+        .flat_map(outside)
+        .next();
+    if tested.is_some() {
+        return tested;
+    }
+
+    let mut ways: Vec<BlockId> = body.iter().copied().flat_map(outside).collect();
+    ways.sort_by_key(|&way| position[way]);
+    ways.dedup();
+    let mut candidates = ways.clone();
+    candidates.extend(ways.iter().copied().flat_map(outside));
+    candidates.into_iter().max_by_key(|&candidate| {
+        let score = ways
+            .iter()
+            .filter(|&&way| {
+                way == candidate
+                    || function.blocks[way]
+                        .terminator
+                        .successors()
+                        .contains(&candidate)
+            })
+            .count();
+        (score, position[candidate])
+    })
+}
+
+// a jump that falls through, breaks out of the loop it is in, goes round
+// it again or has to be a goto
+fn jump(target: BlockId, next: Option<BlockId>, loops: &[Enclosing]) -> Vec<Statement> {
+    if Some(target) == next {
+        return Vec::new();
+    }
+    if let Some(innermost) = loops.last() {
+        if target == innermost.header {
+            return vec![Statement::Continue];
+        }
+        if Some(target) == innermost.follow {
+            return vec![Statement::Break];
+        }
+    }
+    vec![Statement::Goto(target)]
+}
+
+// an if for a branch with a way to go each side. when one side leaves
+// and the other doesn't, the other goes after the if instead of in an else
+fn branch(condition: Condition, then: Vec<Statement>, otherwise: Vec<Statement>) -> Vec<Statement> {
+    if is_empty(&otherwise) {
+        return vec![Statement::If(condition, then, otherwise)];
+    }
+    if is_empty(&then) {
+        return vec![Statement::If(opposite(condition), otherwise, then)];
+    }
+    if leaves(&otherwise) && !leaves(&then) {
+        let mut statements = vec![Statement::If(opposite(condition), otherwise, Vec::new())];
+        statements.extend(then);
+        return statements;
+    }
+    if leaves(&then) {
+        let mut statements = vec![Statement::If(condition, then, Vec::new())];
+        statements.extend(otherwise);
+        return statements;
+    }
+    vec![Statement::If(condition, then, otherwise)]
+}
+
+// true for statements that never get to their end
+fn leaves(statements: &[Statement]) -> bool {
+    match statements.last() {
+        Some(Statement::Break | Statement::Continue | Statement::Goto(_) | Statement::End(_)) => {
+            true
+        }
+        Some(Statement::If(_, then, otherwise)) => leaves(then) && leaves(otherwise),
+        _ => false,
+    }
+}
+
+// true for statements that do nothing, labels aside
+fn is_empty(statements: &[Statement]) -> bool {
+    statements
+        .iter()
+        .all(|statement| matches!(statement, Statement::Label(_)))
+}
+
+// true if the loop these statements are the body of goes round again
+// from anywhere inside, loops nested in it aside
+fn continues(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| match statement {
+        Statement::Continue => true,
+        Statement::If(_, then, otherwise) => continues(then) || continues(otherwise),
+        _ => false,
+    })
+}
+
+// gotos to a block in the body of a loop, loops nested in it aside,
+// become continue
+fn retarget(statements: &mut [Statement], target: BlockId) {
+    for statement in statements {
+        match statement {
+            Statement::Goto(block) if *block == target => *statement = Statement::Continue,
+            Statement::If(_, then, otherwise) => {
+                retarget(then, target);
+                retarget(otherwise, target);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn opposite(condition: Condition) -> Condition {
+    match condition {
+        Condition::Test { block, negated } => Condition::Test {
+            block,
+            negated: !negated,
+        },
+        Condition::And(first, second) => {
+            Condition::Or(Box::new(opposite(*first)), Box::new(opposite(*second)))
+        }
+        Condition::Or(first, second) => {
+            Condition::And(Box::new(opposite(*first)), Box::new(opposite(*second)))
+        }
+    }
+}
+
+fn dominates(idom: &[Option<BlockId>], dominator: BlockId, mut block: BlockId) -> bool {
+    loop {
+        if block == dominator {
+            return true;
+        }
+        match idom[block] {
+            Some(parent) if parent != block => block = parent,
+            _ => return false,
+        }
+    }
+}
+
+// the blocks in order, with ifs and gotos for the jumps
+fn linear(function: &Function, variables: &Variables) -> Vec<Statement> {
+    let mut statements = Vec::new();
+    for (block, data) in function.blocks.iter().enumerate() {
+        statements.push(Statement::Label(block));
+        if !variables.is_quiet(function, block) {
+            statements.push(Statement::Block(block));
+        }
+        let next = Some(block + 1);
+        let arm = |target: BlockId| {
+            let mut statements = Vec::new();
+            if !variables.copies(function, block, target).is_empty() {
+                statements.push(Statement::Copies(block, target));
+                statements.extend(jump(target, next, &[]));
+                return statements;
+            }
+            //straight past blocks that do nothing but jump on
+            let mut target = target;
+            for _ in 0..function.blocks.len() {
+                match function.blocks[target].terminator {
+                    Terminator::Jump(on)
+                        if variables.is_quiet(function, target)
+                            && variables.copies(function, target, on).is_empty() =>
+                    {
+                        target = on
+                    }
+                    _ => break,
+                }
+            }
+            statements.extend(jump(target, next, &[]));
+            statements
+        };
+        match &data.terminator {
+            Terminator::Jump(target) => statements.extend(arm(*target)),
+            Terminator::Branch(_, then, otherwise) => {
+                let condition = Condition::Test {
+                    block,
+                    negated: false,
+                };
+                statements.extend(branch(condition, arm(*then), arm(*otherwise)))
+            }
+            Terminator::Return(_) | Terminator::Exit(_) | Terminator::Stop(_) => {
+                statements.push(Statement::End(block))
+            }
+        }
+    }
+
+    //code after a jump that no goto leads to never runs, and a goto to
+    //the code straight after it does nothing
+    loop {
+        let count = statements.len();
+        let mut targets = HashSet::new();
+        gotos(&statements, &mut targets);
+        let mut reachable = true;
+        let mut kept = Vec::new();
+        for statement in statements {
+            if let Statement::Label(block) = statement {
+                reachable |= targets.contains(&block);
+            }
+            if reachable {
+                reachable = !leaves(std::slice::from_ref(&statement));
+                kept.push(statement);
+            }
+        }
+        for index in (0..kept.len()).rev() {
+            if let Statement::Goto(target) = kept[index] {
+                let mut after = kept[index + 1..]
+                    .iter()
+                    .take_while(|statement| matches!(statement, Statement::Label(_)));
+                if after.any(|statement| *statement == Statement::Label(target)) {
+                    kept.remove(index);
+                }
+            }
+        }
+        statements = kept;
+        if statements.len() == count {
+            return statements;
+        }
+    }
+}
+
+fn gotos(statements: &[Statement], targets: &mut HashSet<BlockId>) {
+    for statement in statements {
+        match statement {
+            Statement::Goto(target) => {
+                targets.insert(*target);
+            }
+            Statement::If(_, then, otherwise) => {
+                gotos(then, targets);
+                gotos(otherwise, targets);
+            }
+            Statement::Loop(_, body) => gotos(body, targets),
+            _ => {}
+        }
+    }
+}
+
+fn contains_goto(statements: &[Statement], target: BlockId) -> bool {
+    let mut targets = HashSet::new();
+    gotos(statements, &mut targets);
+    targets.contains(&target)
+}
+
+// labels only where a goto goes
+fn remove_labels(statements: &mut Vec<Statement>, targets: &HashSet<BlockId>) {
+    statements.retain(|statement| match statement {
+        Statement::Label(block) => targets.contains(block),
+        _ => true,
+    });
+    for statement in statements {
+        match statement {
+            Statement::If(_, then, otherwise) => {
+                remove_labels(then, targets);
+                remove_labels(otherwise, targets);
+            }
+            Statement::Loop(_, body) => remove_labels(body, targets),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::lower;
+    use crate::parse::Parser;
+    use std::path::PathBuf;
+
+    fn statements(source: &str) -> Vec<Statement> {
+        structured(source, super::function)
+    }
+
+    fn structured(
+        source: &str,
+        structure: fn(&Function, &Variables) -> Vec<Statement>,
+    ) -> Vec<Statement> {
+        let lexer = Lexer::new(source.to_string(), PathBuf::from("test.teeny"));
+        let program = Parser::new(lexer).program();
+        let function = lower::program(&program).functions.pop().unwrap();
+        let variables = Variables::new(&function, &[]);
+        structure(&function, &variables)
+    }
+
+    // every statement, the ones inside others too
+    fn flatten(statements: &[Statement]) -> Vec<&Statement> {
+        let mut all = Vec::new();
+        for statement in statements {
+            all.push(statement);
+            match statement {
+                Statement::If(_, then, otherwise) => {
+                    all.extend(flatten(then));
+                    all.extend(flatten(otherwise));
+                }
+                Statement::Loop(_, body) => all.extend(flatten(body)),
+                _ => {}
+            }
+        }
+        all
+    }
+
+    fn conditions(statements: &[Statement]) -> Vec<&Condition> {
+        flatten(statements)
+            .into_iter()
+            .filter_map(|statement| match statement {
+                Statement::If(condition, ..) => Some(condition),
+                Statement::Loop(Loop::While(condition) | Loop::DoWhile(condition), _) => {
+                    Some(condition)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn break_and_continue_come_back() {
+        let statements = statements(
+            "INPUT n\nWHILE n > 0 REPEAT\nLET n = n - 1\nIF n > 2 THEN\n\
+             IF n == 3 THEN\nCONTINUE\nENDIF\nPRINT 0\nENDIF\n\
+             IF n == 5 THEN\nBREAK\nENDIF\nPRINT n\nENDWHILE\n\
+             DO\nLET n = n + 1\nIF n == 2 THEN\nCONTINUE\nENDIF\nPRINT n\nLOOP UNTIL n > 4\n",
+        );
+        let all = flatten(&statements);
+        assert!(all.contains(&&Statement::Break), "{:#?}", statements);
+        assert!(all.contains(&&Statement::Continue), "{:#?}", statements);
+        assert!(
+            all.iter()
+                .any(|statement| matches!(statement, Statement::Loop(Loop::While(_), _))),
+            "{:#?}",
+            statements
+        );
+        assert!(
+            all.iter()
+                .any(|statement| matches!(statement, Statement::Loop(Loop::DoWhile(_), _))),
+            "{:#?}",
+            statements
+        );
+        assert!(
+            !all.iter()
+                .any(|statement| matches!(statement, Statement::Goto(_) | Statement::Label(_))),
+            "{:#?}",
+            statements
+        );
+    }
+
+    #[test]
+    fn case_tests_merge_into_and_and_or() {
+        let statements = statements(
+            "INPUT n\nSELECT CASE n\nCASE 1, 2\nPRINT 1\nCASE 5 TO 20\nPRINT 2\n\
+             CASE ELSE\nPRINT 3\nENDSELECT\n",
+        );
+        let conditions = conditions(&statements);
+        assert!(
+            conditions
+                .iter()
+                .any(|condition| matches!(condition, Condition::Or(..))),
+            "{:#?}",
+            statements
+        );
+        assert!(
+            conditions
+                .iter()
+                .any(|condition| matches!(condition, Condition::And(..))),
+            "{:#?}",
+            statements
+        );
+        assert!(
+            !flatten(&statements)
+                .iter()
+                .any(|statement| matches!(statement, Statement::Goto(_))),
+            "{:#?}",
+            statements
+        );
+    }
+
+    #[test]
+    fn goto_into_a_loop_falls_back_to_gotos() {
+        let statements = statements(
+            "INPUT n\nIF n > 5 THEN\nGOTO inside\nENDIF\nWHILE n > 0 REPEAT\nPRINT n\nLABEL inside\n\
+             LET n = n - 1\nENDWHILE\nPRINT n\n",
+        );
+        let all = flatten(&statements);
+        assert!(
+            all.iter()
+                .any(|statement| matches!(statement, Statement::Goto(_))),
+            "{:#?}",
+            statements
+        );
+        assert!(
+            !all.iter()
+                .any(|statement| matches!(statement, Statement::Loop(..))),
+            "{:#?}",
+            statements
+        );
+        //every goto has somewhere to go
+        for statement in &all {
+            if let Statement::Goto(target) = statement {
+                assert!(
+                    all.contains(&&Statement::Label(*target)),
+                    "{:#?}",
+                    statements
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn without_goto_a_goto_into_a_loop_is_a_state_machine() {
+        let source = "INPUT n\nIF n > 5 THEN\nGOTO inside\nENDIF\nWHILE n > 0 REPEAT\nPRINT n\n\
+                      LABEL inside\nLET n = n - 1\nENDWHILE\nPRINT n\n";
+        let machine = structured(source, function_without_goto);
+        let [Statement::Dispatch(cases)] = &machine[..] else {
+            panic!("{:#?}", machine);
+        };
+        let blocks: Vec<BlockId> = cases.iter().map(|(block, _)| *block).collect();
+        assert_eq!(blocks[0], 0, "{:#?}", machine);
+        for (_, body) in cases {
+            //each case says where to go next, to another case
+            assert!(leaves(body), "{:#?}", machine);
+            for statement in flatten(body) {
+                assert!(!matches!(
+                    statement,
+                    Statement::Label(_) | Statement::Loop(..)
+                ));
+                if let Statement::Goto(target) = statement {
+                    assert!(blocks.contains(target), "{:#?}", machine);
+                }
+            }
+        }
+
+        //a program that needs no goto keeps its loops
+        let source = "INPUT n\nWHILE n > 0 REPEAT\nLET n = n - 1\nENDWHILE\nPRINT n\n";
+        assert_eq!(
+            structured(source, function_without_goto),
+            statements(source)
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+// where something is in the source, file is an index into the parser's file table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub file: usize,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Constant(f32),
+    Label,
+    Function { arity: usize, exported: bool },
+    //an IMPORTed module
+    Module,
+}
+
+// names live in separate namespaces, so a LABEL and a LET can share a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    //variables and constants
+    Value,
+    Label,
+    Function,
+    Module,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Float,
+    //labels and modules have no value
+    Void,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub ty: Type,
+    pub span: Span,
+}
+
+impl Symbol {
+    pub fn new(name: String, kind: SymbolKind, span: Span) -> Symbol {
+        let ty = match kind {
+            SymbolKind::Label | SymbolKind::Module => Type::Void,
+            _ => Type::Float,
+        };
+        Symbol {
+            name,
+            kind,
+            ty,
+            span,
+        }
+    }
+
+    pub fn namespace(&self) -> Namespace {
+        match self.kind {
+            SymbolKind::Variable | SymbolKind::Constant(_) => Namespace::Value,
+            SymbolKind::Label => Namespace::Label,
+            SymbolKind::Function { .. } => Namespace::Function,
+            SymbolKind::Module => Namespace::Module,
+        }
+    }
+}
+
+// symbols declared in one scope, the file itself or a FUNCTION body
+#[derive(Default)]
+pub struct Scope {
+    symbols: HashMap<(Namespace, String), Symbol>,
+    //order of declaration, so walking a scope is repeatable
+    order: Vec<(Namespace, String)>,
+}
+
+impl Scope {
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.order.iter().map(|key| &self.symbols[key])
+    }
+}
+
+pub struct SymbolTable {
+    //innermost scope last
+    scopes: Vec<Scope>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            scopes: vec![Scope::default()],
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    pub fn pop_scope(&mut self) -> Scope {
+        if self.scopes.len() == 1 {
+            panic!("Cannot leave the global scope");
+        }
+        self.scopes.pop().unwrap()
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.scopes.len() == 1
+    }
+
+    // add a symbol to the innermost scope, or hand back the one already
+    // using that name there
+    pub fn declare(&mut self, symbol: Symbol) -> Result<(), &Symbol> {
+        let key = (symbol.namespace(), symbol.name.clone());
+        let scope = self.scopes.last_mut().unwrap();
+
+        if scope.symbols.contains_key(&key) {
+            return Err(&scope.symbols[&key]);
+        }
+        scope.order.push(key.clone());
+        scope.symbols.insert(key, symbol);
+        Ok(())
+    }
+
+    // look a name up from the innermost scope outwards
+    pub fn lookup(&self, namespace: Namespace, name: &str) -> Option<&Symbol> {
+        let key = (namespace, name.to_string());
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.symbols.get(&key))
+    }
+
+    // look a name up in the innermost scope only
+    pub fn lookup_local(&self, namespace: Namespace, name: &str) -> Option<&Symbol> {
+        let key = (namespace, name.to_string());
+        self.scopes.last().unwrap().symbols.get(&key)
+    }
+
+    pub fn global_scope(&self) -> &Scope {
+        &self.scopes[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(name: &str, line: usize) -> Symbol {
+        Symbol::new(name.into(), SymbolKind::Variable, Span { file: 0, line })
+    }
+
+    #[test]
+    fn a_function_scope_shadows_the_globals() {
+        let mut table = SymbolTable::new();
+        table.declare(variable("x", 1)).unwrap();
+        table.declare(variable("y", 2)).unwrap();
+
+        table.push_scope();
+        assert!(!table.is_global());
+        table.declare(variable("x", 4)).unwrap();
+        assert_eq!(table.lookup(Namespace::Value, "x").unwrap().span.line, 4);
+        //globals are still seen, just not as locals
+        assert_eq!(table.lookup(Namespace::Value, "y").unwrap().span.line, 2);
+        assert!(table.lookup_local(Namespace::Value, "y").is_none());
+
+        let scope = table.pop_scope();
+        let locals: Vec<&str> = scope.symbols().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(locals, ["x"]);
+        assert!(table.is_global());
+        assert_eq!(table.lookup(Namespace::Value, "x").unwrap().span.line, 1);
+    }
+
+    #[test]
+    fn declaring_a_name_twice_gives_back_the_first() {
+        let mut table = SymbolTable::new();
+        table.declare(variable("x", 1)).unwrap();
+        let constant = Symbol::new(
+            "x".into(),
+            SymbolKind::Constant(2.0),
+            Span { file: 0, line: 3 },
+        );
+
+        let earlier = table.declare(constant).unwrap_err();
+        assert_eq!(earlier.kind, SymbolKind::Variable);
+        assert_eq!(earlier.span.line, 1);
+    }
+
+    #[test]
+    fn namespaces_keep_labels_apart_from_variables() {
+        let mut table = SymbolTable::new();
+        table.declare(variable("x", 1)).unwrap();
+        let label = Symbol::new("x".into(), SymbolKind::Label, Span { file: 0, line: 2 });
+        table.declare(label).unwrap();
+
+        assert_eq!(table.lookup(Namespace::Label, "x").unwrap().ty, Type::Void);
+        assert_eq!(table.lookup(Namespace::Value, "x").unwrap().ty, Type::Float);
+        assert!(table.lookup(Namespace::Function, "x").is_none());
+        let names: Vec<Namespace> = table
+            .global_scope()
+            .symbols()
+            .map(Symbol::namespace)
+            .collect();
+        assert_eq!(names, [Namespace::Value, Namespace::Label]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot leave the global scope")]
+    fn the_global_scope_cannot_be_left() {
+        SymbolTable::new().pop_scope();
+    }
+}
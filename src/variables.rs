@@ -0,0 +1,855 @@
+use crate::ir::{BlockId, Function, Inst, Op, Operand, Type, Value};
+use std::collections::{HashMap, HashSet};
+
+// SSA values turned back into variables, for backends that print source
+// code. a phi shares a variable with the values it picks between wherever
+// their lifetimes don't overlap, and so do the values of one source
+// variable, so most phis need no copies and most variables keep the name
+// they have in the program. a value that is only used once, before anything
+// else happens, needs no variable at all: it is written into the
+// expression that uses it.
+pub struct Variables {
+    //variable of each value, None for values written inline or unused
+    variable: Vec<Option<usize>>,
+    inline: Vec<bool>,
+    names: Vec<String>,
+    types: Vec<Type>,
+    //variables holding a parameter, which the function's signature declares
+    parameters: HashSet<usize>,
+    //a variable of each type to keep a value in while copies for phis
+    //that swap round go in
+    spares: HashMap<Type, usize>,
+}
+
+// what a copy for a phi reads
+#[derive(Debug, Clone, Copy)]
+pub enum Source<'a> {
+    Operand(&'a Operand),
+    Variable(&'a str),
+}
+
+impl Variables {
+    // reserved are names the output can't use, which get a '_' on the end
+    pub fn new(function: &Function, reserved: &[&str]) -> Variables {
+        let uses = Uses::new(function);
+        let inline = inline_values(function, &uses);
+
+        //values that need somewhere to live: parameters, and everything
+        //that isn't written inline, except what nothing reads and that
+        //has no effect (or just leaves its value behind, like a call)
+        let mut needed = vec![false; function.types.len()];
+        for parameter in &function.parameters {
+            needed[parameter.0] = true;
+        }
+        for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+            let Some(result) = inst.result else {
+                continue;
+            };
+            needed[result.0] = !inline[result.0]
+                && (uses.count[result.0] > 0 || matches!(inst.op, Op::Input { .. }));
+        }
+
+        let interference = Interference::new(function, &inline, &needed);
+        let mut classes = Classes::new(function, &needed);
+
+        //phis first, so their copies go, then copies from one value to
+        //another, then the values of the same variable
+        for block in &function.blocks {
+            for inst in &block.insts {
+                let (Some(result), Op::Phi(incoming)) = (inst.result, &inst.op) else {
+                    continue;
+                };
+                for (_, operand) in incoming {
+                    if let Operand::Value(value) = operand {
+                        classes.join(result, *value, &interference);
+                    }
+                }
+            }
+        }
+        for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+            if let (Some(result), Op::Copy(Operand::Value(value))) = (inst.result, &inst.op) {
+                if needed[value.0] && needed[result.0] {
+                    classes.join(result, *value, &interference);
+                }
+            }
+        }
+        let roots = classes.roots();
+        for (index, &first) in roots.iter().enumerate() {
+            for &second in &roots[index + 1..] {
+                if classes.name[first].is_some() && classes.name[first] == classes.name[second] {
+                    classes.join(Value(first), Value(second), &interference);
+                }
+            }
+        }
+
+        //number the variables in the order their first value is defined,
+        //so parameters come first and keep their names
+        let mut variable = vec![None; function.types.len()];
+        let mut names = Vec::new();
+        let mut types = Vec::new();
+        let mut taken: HashSet<String> = HashSet::new();
+        let mut temporaries = 0;
+        for root in classes.roots() {
+            let name = match &classes.name[root] {
+                Some(name) => {
+                    let base = if reserved.contains(&name.as_str()) {
+                        format!("{}_", name)
+                    } else {
+                        name.clone()
+                    };
+                    let mut name = base.clone();
+                    let mut version = 1;
+                    while taken.contains(&name) {
+                        version += 1;
+                        name = format!("{}_{}", base, version);
+                    }
+                    name
+                }
+                None => loop {
+                    temporaries += 1;
+                    let name = format!("t_{}", temporaries);
+                    if !taken.contains(&name) {
+                        break name;
+                    }
+                },
+            };
+            taken.insert(name.clone());
+            for &member in &classes.members[root] {
+                variable[member] = Some(names.len());
+            }
+            names.push(name);
+            types.push(function.types[root]);
+        }
+
+        let mut spares = HashMap::new();
+        let predecessors = function.predecessors();
+        for (to, predecessors) in predecessors.iter().enumerate() {
+            for &from in predecessors {
+                let copies = sequence(&variable, &types, phi_copies(&variable, function, from, to));
+                for (slot, _) in copies {
+                    let Slot::Spare(spare) = slot else {
+                        continue;
+                    };
+                    spares.entry(spare).or_insert_with(|| {
+                        let name = loop {
+                            temporaries += 1;
+                            let name = format!("t_{}", temporaries);
+                            if !taken.contains(&name) {
+                                break name;
+                            }
+                        };
+                        names.push(name);
+                        types.push(spare);
+                        names.len() - 1
+                    });
+                }
+            }
+        }
+
+        let parameters = function
+            .parameters
+            .iter()
+            .filter_map(|parameter| variable[parameter.0])
+            .collect();
+        Variables {
+            variable,
+            inline,
+            names,
+            types,
+            parameters,
+            spares,
+        }
+    }
+
+    // the variable holding a value, None if it is written inline or unused
+    pub fn name(&self, value: Value) -> Option<&str> {
+        self.variable[value.0].map(|variable| self.names[variable].as_str())
+    }
+
+    pub fn is_inline(&self, value: Value) -> bool {
+        self.inline[value.0]
+    }
+
+    // the variables a function declares, the parameters aside
+    pub fn locals(&self) -> Vec<(&str, Type)> {
+        (0..self.names.len())
+            .filter(|variable| !self.parameters.contains(variable))
+            .map(|variable| (self.names[variable].as_str(), self.types[variable]))
+            .collect()
+    }
+
+    // the assignments for the phis of a block, on the way in from another,
+    // in an order where none sets a variable one after it reads
+    pub fn copies<'a>(
+        &'a self,
+        function: &'a Function,
+        from: BlockId,
+        to: BlockId,
+    ) -> Vec<(&'a str, Source<'a>)> {
+        let copies = phi_copies(&self.variable, function, from, to);
+        sequence(&self.variable, &self.types, copies)
+            .into_iter()
+            .map(|(slot, read)| {
+                let source = match read {
+                    Read::Operand(operand) => Source::Operand(operand),
+                    Read::Slot(slot) => Source::Variable(self.slot_name(slot)),
+                };
+                (self.slot_name(slot), source)
+            })
+            .collect()
+    }
+
+    fn slot_name(&self, slot: Slot) -> &str {
+        match slot {
+            Slot::Variable(variable) => &self.names[variable],
+            Slot::Spare(spare) => &self.names[self.spares[&spare]],
+        }
+    }
+
+    // true if a block's instructions leave nothing to write out
+    pub fn is_quiet(&self, function: &Function, block: BlockId) -> bool {
+        function.blocks[block]
+            .insts
+            .iter()
+            .all(|inst| self.is_silent(inst))
+    }
+
+    // true for an instruction that has nothing to write where it is: a phi,
+    // a value written inline or one that nothing uses and that has no
+    // effect, or a copy from the variable it is going into
+    pub fn is_silent(&self, inst: &Inst) -> bool {
+        let Some(result) = inst.result else {
+            return false;
+        };
+        if self.inline[result.0] {
+            return true;
+        }
+        match &inst.op {
+            Op::Phi(_) => true,
+            Op::Copy(Operand::Value(value)) if self.name(result).is_some() => {
+                self.variable[value.0] == self.variable[result.0]
+            }
+            Op::Call(..) | Op::Input { .. } => false,
+            _ => self.name(result).is_none(),
+        }
+    }
+}
+
+// where a copy for a phi goes, and what it reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Variable(usize),
+    Spare(Type),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Read<'a> {
+    Operand(&'a Operand),
+    Slot(Slot),
+}
+
+// the copies for the phis of a block, on the way in from another, all to
+// be done at once
+fn phi_copies<'a>(
+    variable: &[Option<usize>],
+    function: &'a Function,
+    from: BlockId,
+    to: BlockId,
+) -> Vec<(Slot, Read<'a>)> {
+    let mut copies = Vec::new();
+    for inst in &function.blocks[to].insts {
+        let (Some(result), Op::Phi(incoming)) = (inst.result, &inst.op) else {
+            continue;
+        };
+        let Some(target) = variable[result.0] else {
+            continue;
+        };
+        let Some((_, operand)) = incoming.iter().find(|(block, _)| *block == from) else {
+            continue;
+        };
+        if let Operand::Value(value) = operand {
+            if variable[value.0] == Some(target) {
+                continue;
+            }
+        }
+        copies.push((Slot::Variable(target), Read::Operand(operand)));
+    }
+    copies
+}
+
+// copies that are all to be done at once, one at a time: each goes once
+// nothing left reads the variable it sets, and when they go round in a
+// circle, like a swap, the first one's variable is kept in a spare first
+fn sequence<'a>(
+    variable: &[Option<usize>],
+    types: &[Type],
+    mut pending: Vec<(Slot, Read<'a>)>,
+) -> Vec<(Slot, Read<'a>)> {
+    let reads = |read: &Read, slot: Slot| match read {
+        Read::Operand(Operand::Value(value)) => variable[value.0].map(Slot::Variable) == Some(slot),
+        Read::Operand(_) => false,
+        Read::Slot(other) => *other == slot,
+    };
+    let mut sequence = Vec::new();
+    while !pending.is_empty() {
+        let free = pending
+            .iter()
+            .position(|(slot, _)| !pending.iter().any(|(_, read)| reads(read, *slot)));
+        if let Some(index) = free {
+            sequence.push(pending.remove(index));
+            continue;
+        }
+        let (kept, _) = pending[0];
+        let Slot::Variable(variable) = kept else {
+            unreachable!("a spare is only read");
+        };
+        let spare = Slot::Spare(types[variable]);
+        sequence.push((spare, Read::Slot(kept)));
+        for (_, read) in &mut pending {
+            if reads(read, kept) {
+                *read = Read::Slot(spare);
+            }
+        }
+    }
+    sequence
+}
+
+// how often each value is used, and where: the block and the index of the
+// instruction, the length of the block for its terminator, or None when a
+// phi uses it
+struct Uses {
+    count: Vec<usize>,
+    site: Vec<Option<(BlockId, usize)>>,
+}
+
+impl Uses {
+    fn new(function: &Function) -> Uses {
+        let mut uses = Uses {
+            count: vec![0; function.types.len()],
+            site: vec![None; function.types.len()],
+        };
+        for (index, block) in function.blocks.iter().enumerate() {
+            for (position, inst) in block.insts.iter().enumerate() {
+                let site = match inst.op {
+                    Op::Phi(_) => None,
+                    _ => Some((index, position)),
+                };
+                for operand in inst.op.operands() {
+                    uses.add(operand, site);
+                }
+            }
+            for operand in block.terminator.operands() {
+                uses.add(operand, Some((index, block.insts.len())));
+            }
+        }
+        uses
+    }
+
+    fn add(&mut self, operand: &Operand, site: Option<(BlockId, usize)>) {
+        if let Operand::Value(value) = operand {
+            self.count[value.0] += 1;
+            self.site[value.0] = site;
+        }
+    }
+}
+
+// values used once, later in the block that works them out, by something
+// that isn't a phi. whatever comes between has to be written inline too,
+// so nothing can change what the value reads before it is used. C leaves
+// the order of the operands of an expression open, so a call may not sit
+// next to anything else that has or sees an effect.
+fn inline_values(function: &Function, uses: &Uses) -> Vec<bool> {
+    let mut inline = vec![false; function.types.len()];
+    let mut site = HashMap::new();
+    for (index, block) in function.blocks.iter().enumerate() {
+        for (position, inst) in block.insts.iter().enumerate() {
+            let Some(result) = inst.result else {
+                continue;
+            };
+            let expression =
+                inst.op.is_pure() || matches!(inst.op, Op::Load(_) | Op::Eof | Op::Call(..));
+            if !expression || function.names.contains_key(&result) || uses.count[result.0] != 1 {
+                continue;
+            }
+            if let Some((used_in, used_at)) = uses.site[result.0] {
+                if used_in == index && used_at > position {
+                    inline[result.0] = true;
+                    site.insert(result, (index, position, used_at));
+                }
+            }
+        }
+    }
+
+    //what an expression reads that something else could change, and
+    //whether it changes anything itself
+    let effects = |op: &Op| match op {
+        Op::Call(..) => (true, true),
+        Op::Load(_) | Op::Eof => (true, false),
+        _ => (false, false),
+    };
+
+    loop {
+        let mut changed = false;
+        let mut values: Vec<Value> = site.keys().copied().collect();
+        values.sort();
+        for value in values {
+            if !inline[value.0] {
+                continue;
+            }
+            let (block, position, used_at) = site[&value];
+            let insts = &function.blocks[block].insts;
+            let (reads, changes) = effects(&insts[position].op);
+            let fits = insts[position + 1..used_at].iter().all(|inst| {
+                let Some(result) = inst.result else {
+                    return false;
+                };
+                let unused = uses.count[result.0] == 0
+                    && (inst.op.is_pure() || matches!(inst.op, Op::Load(_) | Op::Eof));
+                if !inline[result.0] && !unused {
+                    return false;
+                }
+                let (other_reads, other_changes) = effects(&inst.op);
+                !(changes && other_reads || reads && other_changes)
+            });
+            if !fits {
+                inline[value.0] = false;
+                changed = true;
+            }
+        }
+
+        //an expression whose operands each have effects of their own,
+        //with a call among them, works out all but the last one first
+        for block in &function.blocks {
+            let consumers = block
+                .insts
+                .iter()
+                .map(|inst| inst.op.operands())
+                .chain([block.terminator.operands()]);
+            for operands in consumers {
+                let mut effectful: Vec<(Value, bool)> = Vec::new();
+                for operand in operands {
+                    let Operand::Value(value) = operand else {
+                        continue;
+                    };
+                    if !inline[value.0] {
+                        continue;
+                    }
+                    let (reads, changes) = tree_effects(function, &inline, *value);
+                    if reads {
+                        effectful.push((*value, changes));
+                    }
+                }
+                if effectful.len() > 1 && effectful.iter().any(|(_, changes)| *changes) {
+                    effectful.sort();
+                    for (value, _) in &effectful[..effectful.len() - 1] {
+                        inline[value.0] = false;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            return inline;
+        }
+    }
+}
+
+// whether an inline value, and what is written inline into it, read
+// anything that can change, and whether they change anything
+fn tree_effects(function: &Function, inline: &[bool], value: Value) -> (bool, bool) {
+    let Some(inst) = definition(function, value) else {
+        return (false, false);
+    };
+    let (mut reads, mut changes) = match inst.op {
+        Op::Call(..) => (true, true),
+        Op::Load(_) | Op::Eof => (true, false),
+        _ => (false, false),
+    };
+    for operand in inst.op.operands() {
+        if let Operand::Value(operand) = operand {
+            if inline[operand.0] {
+                let (operand_reads, operand_changes) = tree_effects(function, inline, *operand);
+                reads |= operand_reads;
+                changes |= operand_changes;
+            }
+        }
+    }
+    (reads, changes)
+}
+
+fn definition(function: &Function, value: Value) -> Option<&Inst> {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .find(|inst| inst.result == Some(value))
+}
+
+// which values are alive at the same time, so can't share a variable.
+// the copies for a phi go on the edge into its block, after the branch
+// has worked out its condition, and one written inline is used where the
+// expression it is written into is.
+struct Interference {
+    edges: HashSet<(usize, usize)>,
+}
+
+impl Interference {
+    fn new(function: &Function, inline: &[bool], needed: &[bool]) -> Interference {
+        let reads = |operands: Vec<&Operand>| -> HashSet<usize> {
+            let mut reads = HashSet::new();
+            let mut work = operands;
+            while let Some(operand) = work.pop() {
+                let Operand::Value(value) = operand else {
+                    continue;
+                };
+                if inline[value.0] {
+                    work.extend(definition(function, *value).unwrap().op.operands());
+                } else {
+                    reads.insert(value.0);
+                }
+            }
+            reads
+        };
+        let defined = |inst: &Inst| inst.result.filter(|result| needed[result.0]);
+
+        //values a successor reads from its predecessor, through its phis
+        let phi_reads = |from: BlockId, to: BlockId| -> HashSet<usize> {
+            let mut reads = HashSet::new();
+            for inst in &function.blocks[to].insts {
+                if let Op::Phi(incoming) = &inst.op {
+                    for (block, operand) in incoming {
+                        if let (true, Operand::Value(value)) = (*block == from, operand) {
+                            reads.insert(value.0);
+                        }
+                    }
+                }
+            }
+            reads
+        };
+        let phis = |block: BlockId| -> Vec<usize> {
+            function.blocks[block]
+                .insts
+                .iter()
+                .filter(|inst| matches!(inst.op, Op::Phi(_)))
+                .filter_map(defined)
+                .map(|value| value.0)
+                .collect()
+        };
+        let successors = |block: BlockId| -> Vec<BlockId> {
+            let mut successors = function.blocks[block].terminator.successors();
+            successors.sort();
+            successors.dedup();
+            successors
+        };
+
+        //live on the way out of each block, before its terminator
+        let live_out = |block: BlockId, live_in: &[HashSet<usize>]| -> HashSet<usize> {
+            let mut live = reads(function.blocks[block].terminator.operands());
+            for successor in successors(block) {
+                live.extend(&live_in[successor]);
+                live.extend(phi_reads(block, successor));
+            }
+            live
+        };
+
+        let mut live_in = vec![HashSet::new(); function.blocks.len()];
+        let mut order = function.reverse_postorder();
+        order.reverse();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order {
+                let mut live = live_out(block, &live_in);
+                for inst in function.blocks[block].insts.iter().rev() {
+                    if let Op::Phi(_) = inst.op {
+                        if let Some(result) = inst.result {
+                            live.remove(&result.0);
+                        }
+                        continue;
+                    }
+                    if let Some(result) = inst.result {
+                        if inline[result.0] {
+                            continue;
+                        }
+                        live.remove(&result.0);
+                    }
+                    live.extend(reads(inst.op.operands()));
+                }
+                if live != live_in[block] {
+                    live_in[block] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut interference = Interference {
+            edges: HashSet::new(),
+        };
+        for &block in &order {
+            //a phi's copy on an edge, with everything alive across it
+            for successor in successors(block) {
+                let mut across = live_in[successor].clone();
+                across.extend(phi_reads(block, successor));
+                across.extend(phis(successor));
+                for inst in &function.blocks[successor].insts {
+                    let (Some(result), Op::Phi(incoming)) = (defined(inst), &inst.op) else {
+                        continue;
+                    };
+                    let source = incoming.iter().find_map(|(from, operand)| match operand {
+                        Operand::Value(value) if *from == block => Some(value.0),
+                        _ => None,
+                    });
+                    for &other in &across {
+                        if Some(other) != source {
+                            interference.add(result.0, other);
+                        }
+                    }
+                }
+            }
+
+            let mut live = live_out(block, &live_in);
+            for inst in function.blocks[block].insts.iter().rev() {
+                if let Op::Phi(_) = inst.op {
+                    continue;
+                }
+                if let Some(result) = inst.result {
+                    if inline[result.0] {
+                        continue;
+                    }
+                    if needed[result.0] {
+                        //a copy's value is the same as what it copies
+                        let source = match &inst.op {
+                            Op::Copy(Operand::Value(value)) => Some(value.0),
+                            _ => None,
+                        };
+                        for &other in &live {
+                            if Some(other) != source {
+                                interference.add(result.0, other);
+                            }
+                        }
+                    }
+                    live.remove(&result.0);
+                }
+                live.extend(reads(inst.op.operands()));
+            }
+
+            //phis are set together as the block starts
+            let phis = phis(block);
+            for &phi in &phis {
+                for &other in live.iter().chain(&phis) {
+                    interference.add(phi, other);
+                }
+            }
+            if block == 0 {
+                for parameter in &function.parameters {
+                    for &other in live.iter().chain(function.parameters.iter().map(|p| &p.0)) {
+                        interference.add(parameter.0, other);
+                    }
+                }
+            }
+        }
+        interference
+    }
+
+    fn add(&mut self, first: usize, second: usize) {
+        if first != second {
+            self.edges.insert((first.min(second), first.max(second)));
+        }
+    }
+
+    fn contains(&self, first: usize, second: usize) -> bool {
+        self.edges.contains(&(first.min(second), first.max(second)))
+    }
+}
+
+// values sharing a variable, joined as long as none of them interfere and
+// they don't hold two different source variables
+struct Classes {
+    parent: Vec<usize>,
+    members: Vec<Vec<usize>>,
+    name: Vec<Option<String>>,
+    types: Vec<Type>,
+}
+
+impl Classes {
+    fn new(function: &Function, needed: &[bool]) -> Classes {
+        let count = function.types.len();
+        Classes {
+            parent: (0..count).collect(),
+            members: (0..count)
+                .map(|value| {
+                    if needed[value] {
+                        vec![value]
+                    } else {
+                        Vec::new()
+                    }
+                })
+                .collect(),
+            name: (0..count)
+                .map(|value| function.names.get(&Value(value)).cloned())
+                .collect(),
+            types: function.types.clone(),
+        }
+    }
+
+    fn find(&self, mut value: usize) -> usize {
+        while self.parent[value] != value {
+            value = self.parent[value];
+        }
+        value
+    }
+
+    // the first value of each class, in order
+    fn roots(&self) -> Vec<usize> {
+        (0..self.parent.len())
+            .filter(|&value| self.parent[value] == value && !self.members[value].is_empty())
+            .collect()
+    }
+
+    fn join(&mut self, first: Value, second: Value, interference: &Interference) {
+        let (first, second) = (self.find(first.0), self.find(second.0));
+        if first == second || self.members[first].is_empty() || self.members[second].is_empty() {
+            return;
+        }
+        if self.types[first] != self.types[second] {
+            return;
+        }
+        if let (Some(first), Some(second)) = (&self.name[first], &self.name[second]) {
+            if first != second {
+                return;
+            }
+        }
+        let clash = self.members[first].iter().any(|&a| {
+            self.members[second]
+                .iter()
+                .any(|&b| interference.contains(a, b))
+        });
+        if clash {
+            return;
+        }
+
+        //the class is named for its first value
+        let (root, other) = (first.min(second), first.max(second));
+        self.parent[other] = root;
+        let members = std::mem::take(&mut self.members[other]);
+        self.members[root].extend(members);
+        if self.name[root].is_none() {
+            self.name[root] = self.name[other].clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::lower;
+    use crate::parse::Parser;
+    use crate::passes::PassManager;
+    use crate::structure::{self, Statement};
+    use std::path::PathBuf;
+
+    fn main_function(source: &str, optimize: bool) -> Function {
+        let lexer = Lexer::new(source.to_string(), PathBuf::from("test.teeny"));
+        let program = Parser::new(lexer).program();
+        let mut module = lower::program(&program);
+        if optimize {
+            PassManager::optimizing().run(&mut module);
+        }
+        module.functions.pop().unwrap()
+    }
+
+    // every copy for a phi the function's statements need
+    fn all_copies(function: &Function, variables: &Variables) -> Vec<(String, String)> {
+        fn walk(statements: &[Statement], edges: &mut Vec<(BlockId, BlockId)>) {
+            for statement in statements {
+                match statement {
+                    Statement::Copies(from, to) => edges.push((*from, *to)),
+                    Statement::If(_, then, otherwise) => {
+                        walk(then, edges);
+                        walk(otherwise, edges);
+                    }
+                    Statement::Loop(_, body) => walk(body, edges),
+                    _ => {}
+                }
+            }
+        }
+        let mut edges = Vec::new();
+        walk(&structure::function(function, variables), &mut edges);
+        edges
+            .into_iter()
+            .flat_map(|(from, to)| variables.copies(function, from, to))
+            .map(|(variable, source)| {
+                let source = match source {
+                    Source::Operand(Operand::Value(value)) => {
+                        variables.name(*value).unwrap_or("inline").to_string()
+                    }
+                    Source::Operand(operand) => operand.to_string(),
+                    Source::Variable(name) => name.to_string(),
+                };
+                (variable.to_string(), source)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn values_of_a_variable_keep_its_name() {
+        let function = main_function(
+            "INPUT x\nLET x = x * 2\nPRINT x\nLET x = x + 1\nPRINT x\nLET int = x\nPRINT int\n",
+            false,
+        );
+        let variables = Variables::new(&function, &["int"]);
+        let mut locals = variables.locals();
+        locals.sort_by_key(|(name, _)| *name);
+        assert_eq!(locals, [("int_", Type::Float), ("x", Type::Float)]);
+    }
+
+    // copy propagation leaves a and b as phis that pick each other
+    #[test]
+    fn a_swap_goes_through_a_spare() {
+        let function = main_function(
+            "LET a = 1\nLET b = 2\nINPUT n\nWHILE n > 0 REPEAT\n\
+             LET t = a\nLET a = b\nLET b = t\nLET n = n - 1\nPRINT a\nENDWHILE\nPRINT b\n",
+            true,
+        );
+        let variables = Variables::new(&function, &[]);
+        let copies = all_copies(&function, &variables);
+        //one of a and b is kept in the spare, set from the other, then set
+        //from the spare
+        let spare = copies
+            .iter()
+            .find(|(variable, _)| variable.starts_with("t_"))
+            .map(|(variable, kept)| (variable.clone(), kept.clone()))
+            .unwrap_or_else(|| panic!("no spare in {:?}", copies));
+        let (spare, kept) = spare;
+        let other = if kept == "a" { "b" } else { "a" };
+        let index = |copy: (&str, &str)| {
+            copies
+                .iter()
+                .position(|(variable, source)| (variable.as_str(), source.as_str()) == copy)
+                .unwrap_or_else(|| panic!("no {:?} in {:?}", copy, copies))
+        };
+        let first = index((&spare, &kept));
+        let second = index((&kept, other));
+        let third = index((other, &spare));
+        assert!(first < second && second < third, "{:?}", copies);
+    }
+
+    #[test]
+    fn copies_that_dont_swap_need_no_spare() {
+        let function = main_function(
+            "LET a = 1\nLET b = 2\nINPUT n\nWHILE n > 0 REPEAT\n\
+             LET a = b\nLET b = b + 1\nLET n = n - 1\nENDWHILE\nPRINT a\nPRINT b\n",
+            true,
+        );
+        let variables = Variables::new(&function, &[]);
+        assert!(
+            variables
+                .locals()
+                .iter()
+                .all(|(name, _)| !name.starts_with("t_")),
+            "{:?}",
+            variables.locals()
+        );
+    }
+}
@@ -0,0 +1,1120 @@
+use crate::backend::{write_if_changed, Backend, InputPolicy};
+use crate::ir::{self, BinOp, BlockId, FunctionKind, Op, Operand, Terminator, Type};
+use crate::js::{self, policy_name};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// WebAssembly, as a .wat text module and the same module encoded as a
+// binary .wasm, for a browser page or node. the program and every module it
+// imports go into one wasm module. printing and input are host functions
+// the .wasm.js file next to it provides.
+//
+// wasm only has structured control flow, so each function with more than
+// one block runs as a dispatch loop: a block index in a local picks the
+// block to run through a br_table, and jumping to a block sets the index
+// and goes round the loop again. jumps to the next block just fall
+// through.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValType {
+    I32,
+    F32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+// the instructions the backend uses, branch targets are label depths
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Block(String),
+    Loop(String),
+    End,
+    Br(u32),
+    BrIf(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Unreachable,
+    Call(u32),
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Const(i32),
+    F32Const(f32),
+    I32Eqz,
+    F32Eq,
+    F32Ne,
+    F32Lt,
+    F32Gt,
+    F32Le,
+    F32Ge,
+    F32Neg,
+    F32Add,
+    F32Sub,
+    F32Mul,
+    F32Div,
+    F32ConvertI32S,
+    //saturates where C would be undefined, instead of trapping
+    I32TruncSatF32S,
+}
+
+pub struct Import {
+    pub name: &'static str,
+    pub ty: FuncType,
+}
+
+pub struct Func {
+    pub name: String,
+    pub ty: FuncType,
+    //names of the parameters, then the locals and their types
+    pub params: Vec<String>,
+    pub locals: Vec<(String, ValType)>,
+    pub body: Vec<Instr>,
+    pub export: Option<String>,
+}
+
+pub struct Global {
+    pub name: String,
+    pub ty: ValType,
+}
+
+// a whole wasm module. imports come first in the function index space, and
+// all imports are functions from "env"
+#[derive(Default)]
+pub struct WasmModule {
+    pub imports: Vec<Import>,
+    pub funcs: Vec<Func>,
+    //mutable, starting at zero
+    pub globals: Vec<Global>,
+    //memory starts with the strings, at offset 0
+    pub data: Vec<u8>,
+}
+
+// what the host provides, the .wasm.js file implements these
+const HOST_FUNCTIONS: &[(&str, &[ValType], &[ValType])] = &[
+    ("print_string", &[ValType::I32], &[]),
+    ("print_number", &[ValType::F32], &[]),
+    (
+        "print_using",
+        &[ValType::F32, ValType::I32, ValType::I32],
+        &[],
+    ),
+    ("print_tab", &[], &[]),
+    ("print_newline", &[], &[]),
+    ("input", &[ValType::I32, ValType::I32], &[ValType::F32]),
+    ("eof", &[], &[ValType::I32]),
+    ("exit", &[ValType::I32], &[]),
+    ("stop", &[ValType::I32], &[]),
+];
+
+pub struct WasmEmitter {
+    wasm: WasmModule,
+    full_path: String,
+    //where each string is in memory
+    strings: HashMap<String, i32>,
+    functions: HashMap<String, u32>,
+    globals: HashMap<String, u32>,
+    pub input_policy: InputPolicy,
+}
+
+impl WasmEmitter {
+    pub fn new(full_path: String) -> WasmEmitter {
+        let mut wasm = WasmModule::default();
+        for (name, params, results) in HOST_FUNCTIONS {
+            wasm.imports.push(Import {
+                name,
+                ty: FuncType {
+                    params: params.to_vec(),
+                    results: results.to_vec(),
+                },
+            });
+        }
+        WasmEmitter {
+            wasm,
+            full_path,
+            strings: HashMap::new(),
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            input_policy: InputPolicy::Zero,
+        }
+    }
+
+    pub fn full_path(&self) -> &str {
+        &self.full_path
+    }
+
+    fn import(&self, name: &str) -> u32 {
+        HOST_FUNCTIONS
+            .iter()
+            .position(|(host, _, _)| *host == name)
+            .unwrap() as u32
+    }
+
+    fn string(&mut self, text: &str) -> i32 {
+        if let Some(&offset) = self.strings.get(text) {
+            return offset;
+        }
+        let offset = self.wasm.data.len() as i32;
+        self.wasm.data.extend(text.as_bytes());
+        self.wasm.data.push(0);
+        self.strings.insert(text.into(), offset);
+        offset
+    }
+
+    fn global(&mut self, name: String, ty: ValType) -> u32 {
+        let index = self.wasm.globals.len() as u32;
+        self.wasm.globals.push(Global {
+            name: name.clone(),
+            ty,
+        });
+        self.globals.insert(name, index);
+        index
+    }
+
+    // add a module's IR. modules have to come before the modules that
+    // import them, and the main program last.
+    fn program(&mut self, module: &ir::Module) {
+        for name in &module.globals {
+            self.global(global_name(&module.name, name), ValType::F32);
+        }
+        //every function gets its index first, so calls can go either way
+        let first = self.wasm.imports.len() + self.wasm.funcs.len();
+        for (index, function) in module.functions.iter().enumerate() {
+            self.functions.insert(
+                wasm_function_name(&module.name, function),
+                (first + index) as u32,
+            );
+        }
+        for function in &module.functions {
+            let func = self.function(&module.name, function);
+            self.wasm.funcs.push(func);
+        }
+    }
+
+    fn function(&mut self, module: &Option<String>, function: &ir::Function) -> Func {
+        let mut builder = FunctionBuilder {
+            emitter: self,
+            function,
+            module,
+            locals: HashMap::new(),
+            phis: HashMap::new(),
+            block_local: 0,
+            body: Vec::new(),
+        };
+        let mut params = Vec::new();
+        for parameter in &function.parameters {
+            builder.locals.insert(parameter.0, params.len() as u32);
+            params.push(format!("v{}", parameter.0));
+        }
+
+        //a local for every value, one for each phi that the jumps into its
+        //block set, and the block index of the dispatch loop
+        let mut locals = Vec::new();
+        let mut next = params.len() as u32;
+        let mut local = |name: String, ty: ValType| {
+            locals.push((name, ty));
+            next += 1;
+            next - 1
+        };
+        for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+            let Some(result) = inst.result else {
+                continue;
+            };
+            let ty = val_type(function.types[result.0]);
+            builder
+                .locals
+                .insert(result.0, local(format!("v{}", result.0), ty));
+            if let Op::Phi(_) = inst.op {
+                builder
+                    .phis
+                    .insert(result.0, local(format!("phi{}", result.0), ty));
+            }
+        }
+        if function.blocks.len() > 1 {
+            builder.block_local = local("block".into(), ValType::I32);
+        }
+
+        if function.kind == FunctionKind::Init {
+            //the module's statements run once, when it is first imported
+            let guard = builder.emitter.global(
+                format!("{}.initialized", module.as_ref().unwrap()),
+                ValType::I32,
+            );
+            builder.body.extend([
+                Instr::Block("start".into()),
+                Instr::GlobalGet(guard),
+                Instr::I32Eqz,
+                Instr::BrIf(0),
+                Instr::Return,
+                Instr::End,
+                Instr::I32Const(1),
+                Instr::GlobalSet(guard),
+            ]);
+        }
+        builder.blocks();
+
+        let ty = FuncType {
+            params: vec![ValType::F32; params.len()],
+            results: function.returns.map(val_type).into_iter().collect(),
+        };
+        let body = builder.body;
+        Func {
+            name: wasm_function_name(module, function),
+            ty,
+            params,
+            locals,
+            body,
+            export: (function.kind == FunctionKind::Main).then(|| "main".into()),
+        }
+    }
+
+    // the text, the binary and the host that runs it under node
+    fn write_files(&mut self) -> Vec<String> {
+        let path = Path::new(&self.full_path);
+        write_if_changed(path, &self.wasm.to_wat());
+
+        //the binary only changes when the text does
+        let binary = path.with_extension("wasm");
+        let bytes = self.wasm.to_binary();
+        if fs::read(&binary).map_or(true, |existing| existing != bytes) {
+            fs::write(&binary, bytes).unwrap();
+        }
+
+        let file = binary.file_name().unwrap().to_string_lossy();
+        let host = HOST_START
+            .replace(
+                "\"POLICY\"",
+                &format!("\"{}\"", policy_name(self.input_policy)),
+            )
+            .replace("WASM_FILE", &file)
+            + js::RUNTIME
+            + &HOST_END.replace("WASM_FILE", &file);
+        let host_path = path.with_extension("wasm.js");
+        write_if_changed(&host_path, &host);
+
+        [path, &binary, &host_path]
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+}
+
+// one wasm module holds the program and everything it imports
+impl Backend for WasmEmitter {
+    fn emit(&mut self, modules: &[ir::Module], program: &ir::Module) -> Vec<String> {
+        for module in modules.iter().chain([program]) {
+            self.program(module);
+        }
+        self.write_files()
+    }
+
+    fn finish(&self, files: &[String]) -> Option<String> {
+        Some(format!("Run with: node {}", files.last().unwrap()))
+    }
+}
+
+// the body of one function, as it's emitted
+struct FunctionBuilder<'a> {
+    emitter: &'a mut WasmEmitter,
+    function: &'a ir::Function,
+    module: &'a Option<String>,
+    //local of each value, and of each phi's shadow
+    locals: HashMap<usize, u32>,
+    phis: HashMap<usize, u32>,
+    block_local: u32,
+    body: Vec<Instr>,
+}
+
+impl FunctionBuilder<'_> {
+    fn blocks(&mut self) {
+        let count = self.function.blocks.len();
+        if count == 1 {
+            self.block(0);
+            return;
+        }
+
+        //one wasm block per IR block, the innermost is block 0. the
+        //br_table goes to the end of a block, which is where its code is.
+        self.body.push(Instr::Loop("dispatch".into()));
+        for index in (0..count).rev() {
+            self.body.push(Instr::Block(format!("b{}", index)));
+        }
+        self.body.push(Instr::LocalGet(self.block_local));
+        self.body
+            .push(Instr::BrTable((0..count as u32).collect(), 0));
+        for index in 0..count {
+            self.body.push(Instr::End);
+            self.block(index);
+        }
+        self.body.push(Instr::End);
+        self.body.push(Instr::Unreachable);
+    }
+
+    fn block(&mut self, index: BlockId) {
+        let block = &self.function.blocks[index];
+        for inst in &block.insts {
+            self.instruction(inst.result.map(|result| result.0), &inst.op);
+        }
+        for successor in block.terminator.successors() {
+            for inst in &self.function.blocks[successor].insts {
+                let Op::Phi(incoming) = &inst.op else {
+                    continue;
+                };
+                let (_, value) = incoming.iter().find(|(from, _)| *from == index).unwrap();
+                self.operand(value);
+                self.body
+                    .push(Instr::LocalSet(self.phis[&inst.result.unwrap().0]));
+            }
+        }
+        self.terminator(index, &block.terminator);
+    }
+
+    fn operand(&mut self, operand: &Operand) {
+        let instr = match operand {
+            Operand::Value(value) => Instr::LocalGet(self.locals[&value.0]),
+            Operand::Float(value) => Instr::F32Const(*value),
+            Operand::Int(value) => Instr::I32Const(*value),
+            Operand::String(text) => Instr::I32Const(self.emitter.string(text)),
+        };
+        self.body.push(instr);
+    }
+
+    fn call_host(&mut self, name: &str) {
+        self.body.push(Instr::Call(self.emitter.import(name)));
+    }
+
+    fn instruction(&mut self, result: Option<usize>, op: &Op) {
+        match op {
+            Op::Copy(operand) => self.operand(operand),
+            Op::Neg(operand) => {
+                self.operand(operand);
+                self.body.push(Instr::F32Neg);
+            }
+            Op::Binary(op, left, right) => {
+                self.operand(left);
+                self.operand(right);
+                self.body.push(match op {
+                    BinOp::Add => Instr::F32Add,
+                    BinOp::Sub => Instr::F32Sub,
+                    BinOp::Mul => Instr::F32Mul,
+                    BinOp::Div => Instr::F32Div,
+                    BinOp::Eq => Instr::F32Eq,
+                    BinOp::Ne => Instr::F32Ne,
+                    BinOp::Lt => Instr::F32Lt,
+                    BinOp::Le => Instr::F32Le,
+                    BinOp::Gt => Instr::F32Gt,
+                    BinOp::Ge => Instr::F32Ge,
+                });
+            }
+            Op::IntToFloat(operand) => {
+                self.operand(operand);
+                self.body.push(Instr::F32ConvertI32S);
+            }
+            Op::FloatToInt(operand) => {
+                self.operand(operand);
+                self.body.push(Instr::I32TruncSatF32S);
+            }
+            //the jumps into the block have set it
+            Op::Phi(_) => {
+                let phi = self.phis[&result.unwrap()];
+                self.body.push(Instr::LocalGet(phi));
+            }
+            Op::Load(name) => {
+                let global = self.emitter.globals[&global_name(self.module, name)];
+                self.body.push(Instr::GlobalGet(global));
+            }
+            Op::Store(name, operand) => {
+                self.operand(operand);
+                let global = self.emitter.globals[&global_name(self.module, name)];
+                self.body.push(Instr::GlobalSet(global));
+            }
+            Op::Call(module, name, args) => {
+                for arg in args {
+                    self.operand(arg);
+                }
+                let callee = match module {
+                    Some(module) => format!("{}__{}", module, name),
+                    None => format!("tt_fn_{}", name),
+                };
+                self.body.push(Instr::Call(self.emitter.functions[&callee]));
+            }
+            Op::Eof => self.call_host("eof"),
+            Op::Input { prompt, location } => {
+                self.operand(prompt);
+                let location = self.emitter.string(location);
+                self.body.push(Instr::I32Const(location));
+                self.call_host("input");
+            }
+            Op::PrintString(text) => {
+                self.operand(text);
+                self.call_host("print_string");
+            }
+            Op::PrintNumber(value) => {
+                self.operand(value);
+                self.call_host("print_number");
+            }
+            Op::PrintUsing(picture, value) => {
+                //"###.##" prints like "%6.2f"
+                let fraction = picture
+                    .split_once('.')
+                    .map_or(0, |(_, fraction)| fraction.len());
+                self.operand(value);
+                self.body.push(Instr::I32Const(picture.len() as i32));
+                self.body.push(Instr::I32Const(fraction as i32));
+                self.call_host("print_using");
+            }
+            Op::PrintTab => self.call_host("print_tab"),
+            Op::PrintNewline => self.call_host("print_newline"),
+            Op::Import(name) => {
+                let init = self.emitter.functions[&format!("{}__init", name)];
+                self.body.push(Instr::Call(init));
+            }
+        }
+        if let Some(result) = result {
+            self.body.push(Instr::LocalSet(self.locals[&result]));
+        }
+    }
+
+    // set the block index and go round the dispatch loop, which from the
+    // code of a block is outside the blocks of the ones after it
+    fn jump(&mut self, from: BlockId, target: BlockId) {
+        self.body.push(Instr::I32Const(target as i32));
+        self.body.push(Instr::LocalSet(self.block_local));
+        let depth = (self.function.blocks.len() - 1 - from) as u32;
+        self.body.push(Instr::Br(depth));
+    }
+
+    fn terminator(&mut self, block: BlockId, terminator: &Terminator) {
+        let next = block + 1;
+        let depth = (self.function.blocks.len().saturating_sub(1 + block)) as u32;
+        match terminator {
+            Terminator::Jump(target) if *target == next => {}
+            Terminator::Jump(target) => self.jump(block, *target),
+            Terminator::Branch(condition, then, otherwise) => {
+                if *then == next || *otherwise == next {
+                    //set the index for the jump, and branch round the loop
+                    //only when taking it
+                    let (target, on_true) = if *then == next {
+                        (*otherwise, false)
+                    } else {
+                        (*then, true)
+                    };
+                    self.body.push(Instr::I32Const(target as i32));
+                    self.body.push(Instr::LocalSet(self.block_local));
+                    self.operand(condition);
+                    if !on_true {
+                        self.body.push(Instr::I32Eqz);
+                    }
+                    self.body.push(Instr::BrIf(depth));
+                } else {
+                    self.body.push(Instr::I32Const(*then as i32));
+                    self.body.push(Instr::I32Const(*otherwise as i32));
+                    self.operand(condition);
+                    self.body.push(Instr::Select);
+                    self.body.push(Instr::LocalSet(self.block_local));
+                    self.body.push(Instr::Br(depth));
+                }
+            }
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    self.operand(value);
+                }
+                self.body.push(Instr::Return);
+            }
+            Terminator::Exit(status) => {
+                self.operand(status);
+                self.call_host("exit");
+                self.body.push(Instr::Unreachable);
+            }
+            Terminator::Stop(message) => {
+                let message = self.emitter.string(message);
+                self.body.push(Instr::I32Const(message));
+                self.call_host("stop");
+                self.body.push(Instr::Unreachable);
+            }
+        }
+    }
+}
+
+fn val_type(ty: Type) -> ValType {
+    match ty {
+        Type::Int => ValType::I32,
+        Type::Float => ValType::F32,
+        //an offset into memory
+        Type::String => ValType::I32,
+    }
+}
+
+// the same names the C backend uses, so they read the same in the .wat
+fn wasm_function_name(module: &Option<String>, function: &ir::Function) -> String {
+    match (function.kind, module) {
+        (FunctionKind::Main, _) => "main".into(),
+        (_, Some(module)) => format!("{}__{}", module, function.name),
+        (_, None) => format!("tt_fn_{}", function.name),
+    }
+}
+
+// globals of all the modules share one namespace
+fn global_name(module: &Option<String>, name: &str) -> String {
+    match module {
+        Some(module) => format!("{}.{}", module, name),
+        None => name.into(),
+    }
+}
+
+fn wat_type(ty: ValType) -> &'static str {
+    match ty {
+        ValType::I32 => "i32",
+        ValType::F32 => "f32",
+    }
+}
+
+fn wat_float(value: f32) -> String {
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    if value.is_nan() {
+        format!("{}nan:{:#x}", sign, value.to_bits() & 0x7f_ffff)
+    } else if value.is_infinite() {
+        format!("{}inf", sign)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+// bytes go in a wat string as they are, other than quotes, backslashes and
+// anything unprintable
+fn wat_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b' '..=b'~' if byte != b'"' && byte != b'\\' => (byte as char).to_string(),
+            _ => format!("\\{:02x}", byte),
+        })
+        .collect()
+}
+
+impl WasmModule {
+    fn memory_pages(&self) -> u32 {
+        (self.data.len() as u32).div_ceil(65536).max(1)
+    }
+
+    pub fn to_wat(&self) -> String {
+        let mut out = String::from("(module\n");
+        for import in &self.imports {
+            out.push_str(&format!(
+                "  (import \"env\" \"{}\" (func ${}{}))\n",
+                import.name,
+                import.name,
+                wat_signature(&import.ty, None)
+            ));
+        }
+        out.push_str(&format!(
+            "  (memory (export \"memory\") {})\n",
+            self.memory_pages()
+        ));
+        for global in &self.globals {
+            out.push_str(&format!(
+                "  (global ${} (mut {}) ({}.const 0))\n",
+                global.name,
+                wat_type(global.ty),
+                wat_type(global.ty)
+            ));
+        }
+        if !self.data.is_empty() {
+            out.push_str(&format!(
+                "  (data (i32.const 0) \"{}\")\n",
+                wat_string(&self.data)
+            ));
+        }
+        for func in &self.funcs {
+            out.push_str(&format!("\n  (func ${}", func.name));
+            if let Some(export) = &func.export {
+                out.push_str(&format!(" (export \"{}\")", export));
+            }
+            out.push_str(&wat_signature(&func.ty, Some(&func.params)));
+            out.push('\n');
+            for (name, ty) in &func.locals {
+                out.push_str(&format!("    (local ${} {})\n", name, wat_type(*ty)));
+            }
+            self.wat_body(func, &mut out);
+            out.push_str("  )\n");
+        }
+        out.push_str(")\n");
+        out
+    }
+
+    fn wat_body(&self, func: &Func, out: &mut String) {
+        let local_name = |index: u32| {
+            let index = index as usize;
+            match index.checked_sub(func.params.len()) {
+                None => &func.params[index],
+                Some(local) => &func.locals[local].0,
+            }
+        };
+        let function_name = |index: u32| {
+            let index = index as usize;
+            match index.checked_sub(self.imports.len()) {
+                None => self.imports[index].name,
+                Some(func) => &self.funcs[func].name,
+            }
+        };
+        //names of the enclosing blocks and loops, innermost last
+        let mut labels: Vec<&str> = Vec::new();
+        let label = |labels: &Vec<&str>, depth: u32| {
+            format!("${}", labels[labels.len() - 1 - depth as usize])
+        };
+
+        for instr in &func.body {
+            if *instr == Instr::End {
+                labels.pop();
+            }
+            out.push_str(&"  ".repeat(labels.len() + 2));
+            let text = match instr {
+                Instr::Block(name) | Instr::Loop(name) => {
+                    let keyword = if let Instr::Block(_) = instr {
+                        "block"
+                    } else {
+                        "loop"
+                    };
+                    labels.push(name);
+                    format!("{} ${}", keyword, name)
+                }
+                Instr::End => "end".into(),
+                Instr::Br(depth) => format!("br {}", label(&labels, *depth)),
+                Instr::BrIf(depth) => format!("br_if {}", label(&labels, *depth)),
+                Instr::BrTable(targets, default) => {
+                    let targets: Vec<String> =
+                        targets.iter().map(|depth| label(&labels, *depth)).collect();
+                    format!(
+                        "br_table {} {}",
+                        targets.join(" "),
+                        label(&labels, *default)
+                    )
+                }
+                Instr::Return => "return".into(),
+                Instr::Unreachable => "unreachable".into(),
+                Instr::Call(index) => format!("call ${}", function_name(*index)),
+                Instr::Select => "select".into(),
+                Instr::LocalGet(index) => format!("local.get ${}", local_name(*index)),
+                Instr::LocalSet(index) => format!("local.set ${}", local_name(*index)),
+                Instr::GlobalGet(index) => {
+                    format!("global.get ${}", self.globals[*index as usize].name)
+                }
+                Instr::GlobalSet(index) => {
+                    format!("global.set ${}", self.globals[*index as usize].name)
+                }
+                Instr::I32Const(value) => format!("i32.const {}", value),
+                Instr::F32Const(value) => format!("f32.const {}", wat_float(*value)),
+                Instr::I32Eqz => "i32.eqz".into(),
+                Instr::F32Eq => "f32.eq".into(),
+                Instr::F32Ne => "f32.ne".into(),
+                Instr::F32Lt => "f32.lt".into(),
+                Instr::F32Gt => "f32.gt".into(),
+                Instr::F32Le => "f32.le".into(),
+                Instr::F32Ge => "f32.ge".into(),
+                Instr::F32Neg => "f32.neg".into(),
+                Instr::F32Add => "f32.add".into(),
+                Instr::F32Sub => "f32.sub".into(),
+                Instr::F32Mul => "f32.mul".into(),
+                Instr::F32Div => "f32.div".into(),
+                Instr::F32ConvertI32S => "f32.convert_i32_s".into(),
+                Instr::I32TruncSatF32S => "i32.trunc_sat_f32_s".into(),
+            };
+            out.push_str(&text);
+            out.push('\n');
+        }
+    }
+
+    // the binary format, section by section
+    pub fn to_binary(&self) -> Vec<u8> {
+        //each distinct signature once
+        let mut types: Vec<&FuncType> = Vec::new();
+        for ty in self
+            .imports
+            .iter()
+            .map(|import| &import.ty)
+            .chain(self.funcs.iter().map(|func| &func.ty))
+        {
+            if !types.contains(&ty) {
+                types.push(ty);
+            }
+        }
+        let type_of = |ty: &FuncType| types.iter().position(|other| *other == ty).unwrap() as u32;
+
+        let mut out = b"\0asm".to_vec();
+        out.extend(1u32.to_le_bytes());
+
+        let mut section = Vec::new();
+        leb_u32(&mut section, types.len() as u32);
+        for ty in &types {
+            section.push(0x60);
+            leb_u32(&mut section, ty.params.len() as u32);
+            section.extend(ty.params.iter().map(|ty| val_type_byte(*ty)));
+            leb_u32(&mut section, ty.results.len() as u32);
+            section.extend(ty.results.iter().map(|ty| val_type_byte(*ty)));
+        }
+        push_section(&mut out, 1, &section);
+
+        let mut section = Vec::new();
+        leb_u32(&mut section, self.imports.len() as u32);
+        for import in &self.imports {
+            push_name(&mut section, "env");
+            push_name(&mut section, import.name);
+            section.push(0x00);
+            leb_u32(&mut section, type_of(&import.ty));
+        }
+        push_section(&mut out, 2, &section);
+
+        let mut section = Vec::new();
+        leb_u32(&mut section, self.funcs.len() as u32);
+        for func in &self.funcs {
+            leb_u32(&mut section, type_of(&func.ty));
+        }
+        push_section(&mut out, 3, &section);
+
+        let mut section = Vec::new();
+        leb_u32(&mut section, 1);
+        section.push(0x00);
+        leb_u32(&mut section, self.memory_pages());
+        push_section(&mut out, 5, &section);
+
+        if !self.globals.is_empty() {
+            let mut section = Vec::new();
+            leb_u32(&mut section, self.globals.len() as u32);
+            for global in &self.globals {
+                section.push(val_type_byte(global.ty));
+                section.push(0x01);
+                match global.ty {
+                    ValType::I32 => encode(&mut section, &Instr::I32Const(0)),
+                    ValType::F32 => encode(&mut section, &Instr::F32Const(0.0)),
+                }
+                section.push(0x0b);
+            }
+            push_section(&mut out, 6, &section);
+        }
+
+        let exports: Vec<(usize, &String)> = self
+            .funcs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, func)| func.export.as_ref().map(|name| (index, name)))
+            .collect();
+        let mut section = Vec::new();
+        leb_u32(&mut section, exports.len() as u32 + 1);
+        push_name(&mut section, "memory");
+        section.push(0x02);
+        leb_u32(&mut section, 0);
+        for (index, name) in exports {
+            push_name(&mut section, name);
+            section.push(0x00);
+            leb_u32(&mut section, (self.imports.len() + index) as u32);
+        }
+        push_section(&mut out, 7, &section);
+
+        let mut section = Vec::new();
+        leb_u32(&mut section, self.funcs.len() as u32);
+        for func in &self.funcs {
+            let mut code = Vec::new();
+            //locals are declared as runs of the same type
+            let mut runs: Vec<(u32, ValType)> = Vec::new();
+            for (_, ty) in &func.locals {
+                match runs.last_mut() {
+                    Some((count, last)) if last == ty => *count += 1,
+                    _ => runs.push((1, *ty)),
+                }
+            }
+            leb_u32(&mut code, runs.len() as u32);
+            for (count, ty) in runs {
+                leb_u32(&mut code, count);
+                code.push(val_type_byte(ty));
+            }
+            for instr in &func.body {
+                encode(&mut code, instr);
+            }
+            code.push(0x0b);
+            leb_u32(&mut section, code.len() as u32);
+            section.extend(code);
+        }
+        push_section(&mut out, 10, &section);
+
+        if !self.data.is_empty() {
+            let mut section = Vec::new();
+            leb_u32(&mut section, 1);
+            section.push(0x00);
+            encode(&mut section, &Instr::I32Const(0));
+            section.push(0x0b);
+            leb_u32(&mut section, self.data.len() as u32);
+            section.extend(&self.data);
+            push_section(&mut out, 11, &section);
+        }
+        out
+    }
+}
+
+fn wat_signature(ty: &FuncType, names: Option<&[String]>) -> String {
+    let mut out = String::new();
+    for (index, param) in ty.params.iter().enumerate() {
+        match names {
+            Some(names) => {
+                out.push_str(&format!(" (param ${} {})", names[index], wat_type(*param)))
+            }
+            None => out.push_str(&format!(" (param {})", wat_type(*param))),
+        }
+    }
+    for result in &ty.results {
+        out.push_str(&format!(" (result {})", wat_type(*result)));
+    }
+    out
+}
+
+fn val_type_byte(ty: ValType) -> u8 {
+    match ty {
+        ValType::I32 => 0x7f,
+        ValType::F32 => 0x7d,
+    }
+}
+
+fn leb_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn leb_i32(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        //done once the rest is all sign, and the sign bit of this byte says so
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn push_name(out: &mut Vec<u8>, name: &str) {
+    leb_u32(out, name.len() as u32);
+    out.extend(name.as_bytes());
+}
+
+fn push_section(out: &mut Vec<u8>, id: u8, section: &[u8]) {
+    out.push(id);
+    leb_u32(out, section.len() as u32);
+    out.extend(section);
+}
+
+fn encode(out: &mut Vec<u8>, instr: &Instr) {
+    match instr {
+        //blocks and loops leave nothing on the stack
+        Instr::Block(_) => out.extend([0x02, 0x40]),
+        Instr::Loop(_) => out.extend([0x03, 0x40]),
+        Instr::End => out.push(0x0b),
+        Instr::Br(depth) => {
+            out.push(0x0c);
+            leb_u32(out, *depth);
+        }
+        Instr::BrIf(depth) => {
+            out.push(0x0d);
+            leb_u32(out, *depth);
+        }
+        Instr::BrTable(targets, default) => {
+            out.push(0x0e);
+            leb_u32(out, targets.len() as u32);
+            for target in targets {
+                leb_u32(out, *target);
+            }
+            leb_u32(out, *default);
+        }
+        Instr::Return => out.push(0x0f),
+        Instr::Unreachable => out.push(0x00),
+        Instr::Call(index) => {
+            out.push(0x10);
+            leb_u32(out, *index);
+        }
+        Instr::Select => out.push(0x1b),
+        Instr::LocalGet(index) => {
+            out.push(0x20);
+            leb_u32(out, *index);
+        }
+        Instr::LocalSet(index) => {
+            out.push(0x21);
+            leb_u32(out, *index);
+        }
+        Instr::GlobalGet(index) => {
+            out.push(0x23);
+            leb_u32(out, *index);
+        }
+        Instr::GlobalSet(index) => {
+            out.push(0x24);
+            leb_u32(out, *index);
+        }
+        Instr::I32Const(value) => {
+            out.push(0x41);
+            leb_i32(out, *value);
+        }
+        Instr::F32Const(value) => {
+            out.push(0x43);
+            out.extend(value.to_bits().to_le_bytes());
+        }
+        Instr::I32Eqz => out.push(0x45),
+        Instr::F32Eq => out.push(0x5b),
+        Instr::F32Ne => out.push(0x5c),
+        Instr::F32Lt => out.push(0x5d),
+        Instr::F32Gt => out.push(0x5e),
+        Instr::F32Le => out.push(0x5f),
+        Instr::F32Ge => out.push(0x60),
+        Instr::F32Neg => out.push(0x8c),
+        Instr::F32Add => out.push(0x92),
+        Instr::F32Sub => out.push(0x93),
+        Instr::F32Mul => out.push(0x94),
+        Instr::F32Div => out.push(0x95),
+        Instr::F32ConvertI32S => out.push(0xb2),
+        Instr::I32TruncSatF32S => out.extend([0xfc, 0x00]),
+    }
+}
+
+// the host for a .wasm file, around the JS runtime, with the input policy and
+// WASM_FILE filled in
+const HOST_START: &str = r#"// runs a teeny tiny program compiled to WebAssembly. "node WASM_FILE.js"
+// runs WASM_FILE with stdin and stdout. a page can load this file and call
+// runTeenyTiny(bytes, host) itself, where host has write(text) and
+// error(text) for output, and readLine() which returns the next line of
+// input with its newline, or null at the end of the input.
+"use strict";
+
+// what INPUT does with text that isn't a number
+const INPUT_POLICY = "POLICY";
+"#;
+
+const HOST_END: &str = r#"
+// instantiate and run the program, resolving to its exit status
+async function runTeenyTiny(bytes, host) {
+  let memory;
+  const tt = runtime(host, INPUT_POLICY);
+  const decoder = new TextDecoder();
+
+  //strings are NUL terminated, in memory
+  const text = (offset) => {
+    const bytes = new Uint8Array(memory.buffer);
+    let end = offset;
+    while (bytes[end] !== 0) {
+      end++;
+    }
+    return decoder.decode(bytes.subarray(offset, end));
+  };
+  const env = {
+    ...tt,
+    print_string: (offset) => tt.print_string(text(offset)),
+    input: (prompt, location) => tt.input(text(prompt), text(location)),
+    stop: (message) => tt.stop(text(message)),
+  };
+
+  const { instance } = await WebAssembly.instantiate(bytes, { env });
+  memory = instance.exports.memory;
+  return finish(tt, () => instance.exports.main());
+}
+
+if (typeof require !== "undefined" && require.main === module) {
+  const path = require("path").join(__dirname, "WASM_FILE");
+  runTeenyTiny(require("fs").readFileSync(path), nodeHost()).then((status) => {
+    process.exitCode = status;
+  });
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(instr: Instr) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode(&mut out, &instr);
+        out
+    }
+
+    #[test]
+    fn leb128() {
+        let mut out = Vec::new();
+        leb_u32(&mut out, 624485);
+        assert_eq!(out, [0xe5, 0x8e, 0x26]);
+        for (value, bytes) in [
+            (0, &[0x00][..]),
+            (-1, &[0x7f]),
+            (63, &[0x3f]),
+            (64, &[0xc0, 0x00]),
+            (-64, &[0x40]),
+            (-65, &[0xbf, 0x7f]),
+            (-123456, &[0xc0, 0xbb, 0x78]),
+        ] {
+            let mut out = Vec::new();
+            leb_i32(&mut out, value);
+            assert_eq!(out, bytes, "{}", value);
+        }
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(
+            encoded(Instr::F32Const(1.0)),
+            [0x43, 0x00, 0x00, 0x80, 0x3f]
+        );
+        assert_eq!(encoded(Instr::I32Const(-1)), [0x41, 0x7f]);
+        assert_eq!(
+            encoded(Instr::BrTable(vec![0, 1], 2)),
+            [0x0e, 0x02, 0x00, 0x01, 0x02]
+        );
+        assert_eq!(encoded(Instr::I32TruncSatF32S), [0xfc, 0x00]);
+        assert_eq!(encoded(Instr::Block("b1".into())), [0x02, 0x40]);
+    }
+
+    #[test]
+    fn a_whole_module() {
+        let number = FuncType {
+            params: vec![ValType::F32],
+            results: vec![],
+        };
+        let wasm = WasmModule {
+            imports: vec![Import {
+                name: "print_number",
+                ty: number,
+            }],
+            funcs: vec![Func {
+                name: "main".into(),
+                ty: FuncType {
+                    params: vec![],
+                    results: vec![],
+                },
+                params: vec![],
+                locals: vec![],
+                body: vec![Instr::F32Const(1.0), Instr::Call(0)],
+                export: Some("main".into()),
+            }],
+            ..Default::default()
+        };
+        let mut expected = b"\0asm\x01\0\0\0".to_vec();
+        //the two signatures, print_number's first
+        expected.extend([0x01, 0x08, 0x02, 0x60, 0x01, 0x7d, 0x00, 0x60, 0x00, 0x00]);
+        expected.extend([0x02, 0x14, 0x01, 0x03]);
+        expected.extend(b"env\x0cprint_number\x00\x00");
+        expected.extend([0x03, 0x02, 0x01, 0x01]);
+        //one page of memory, even with no strings
+        expected.extend([0x05, 0x03, 0x01, 0x00, 0x01]);
+        expected.extend([0x07, 0x11, 0x02]);
+        expected.extend(b"\x06memory\x02\x00\x04main\x00\x01");
+        expected.extend([0x0a, 0x0b, 0x01, 0x09, 0x00]);
+        expected.extend([0x43, 0x00, 0x00, 0x80, 0x3f, 0x10, 0x00, 0x0b]);
+        assert_eq!(wasm.to_binary(), expected);
+    }
+}
@@ -0,0 +1,361 @@
+// compiles programs to C with and without -O, builds them with cc and
+// checks both print the same thing, and what that is. the other backends
+// have to print and exit the same as the C, where their tools are installed
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const LOOPS_AND_CASES: &str = "LET a = 1\nLET b = 0\nLET n = 0\nWHILE n < 10 REPEAT\n\
+    LET t = a + b\nLET a = b\nLET b = t\nLET n = n + 1\n\
+    IF n == 3 THEN\nCONTINUE\nENDIF\n\
+    SELECT CASE b * 2\nCASE 2, 4\nPRINT \"small \"; b\n\
+    CASE 5 TO 20\nPRINT \"middle \"; b\nCASE IS > 60\nPRINT \"big \"; b\nBREAK\n\
+    CASE ELSE\nPRINT \"other \"; b\nENDSELECT\nENDWHILE\n\
+    LET k = 0\nDO\nLET k = k + a * 2\nLOOP UNTIL k > 50\nPRINT k; \" \"; n\n";
+
+const FUNCTIONS_GLOBALS_AND_INPUT: &str = "LET calls = 0\nFUNCTION fib(n)\nLET calls = calls + 1\n\
+    IF n < 2 THEN\nRETURN n\nENDIF\nRETURN fib(n - 1) + fib(n - 2)\nENDFUNCTION\n\
+    FUNCTION bump()\nLET calls = calls * 2\nRETURN calls\nENDFUNCTION\n\
+    PRINT fib(10); \" \"; calls\nPRINT calls + bump(); \" \"; calls\n\
+    LET x = 0\nINPUT \"? \", x\nLABEL again\nIF EOF == 1 THEN\nGOTO done\nENDIF\n\
+    PRINT x * x\nINPUT \"? \", x\nGOTO again\nLABEL done\nPRINT \"bye\"\n";
+
+// a GOTO into a loop, which no structured loop can say
+const GOTO_INTO_A_LOOP: &str = "LET n = 0\nINPUT n\nIF n > 2 THEN\nGOTO inside\nENDIF\n\
+    WHILE n < 6 REPEAT\nPRINT \"top \"; n\nLABEL inside\nPRINT \"in \"; n\nLET n = n + 1\n\
+    ENDWHILE\nPRINT \"done\"\n";
+
+const CONTINUE_USING_AND_EXIT: &str = "FUNCTION sq(x)\nRETURN x * x\nENDFUNCTION\n\
+    LET i = 0\nLET s = 0\nDO\nLET i = i + 1\nIF i == 3 THEN\nCONTINUE\nENDIF\n\
+    LET j = 0\nWHILE j < i REPEAT\nLET j = j + 1\nIF j == 2 THEN\nCONTINUE\nENDIF\n\
+    LET s = s + sq(j)\nENDWHILE\nPRINT i, s\nLOOP UNTIL i >= 5\n\
+    PRINT USING \"###.##\"; 0 - s\nEXIT s / 7\n";
+
+const SPECIAL_NUMBERS: &str = "LET x = 0\nINPUT x\nLET y = x + 1\nPRINT x / x\nPRINT 0 - x / x\n\
+    PRINT y / x\nPRINT 0 - y / x\nPRINT 0 - x\nPRINT 16777216 * y\nPRINT 0.1 * y\n\
+    PRINT USING \"###.##\"; 0 - x / x\nEXIT 0 - y / x\n";
+
+// the programs every backend has to run the way the C does, and their input
+const PROGRAMS: &[(&str, &str)] = &[
+    (LOOPS_AND_CASES, ""),
+    (FUNCTIONS_GLOBALS_AND_INPUT, "3\n4\n"),
+    (GOTO_INTO_A_LOOP, "1\n"),
+    (GOTO_INTO_A_LOOP, "4\n"),
+    (CONTINUE_USING_AND_EXIT, ""),
+    (SPECIAL_NUMBERS, "0\n"),
+];
+
+// a directory of its own for each test, the compiler writes out.c into it
+fn scratch(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("teeny-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+// what the program prints for input, built with the given flags
+fn run(directory: &Path, flags: &[&str], input: &str) -> String {
+    String::from_utf8(output(directory, flags, input).stdout).unwrap()
+}
+
+// the program's output and exit status
+fn output(directory: &Path, flags: &[&str], input: &str) -> Output {
+    compile(directory, flags);
+    build(directory, "cc", &["-o", "out", "out.c", "-lm"]);
+    execute(directory, "./out", &[], input)
+}
+
+// what the compiler printed, after checking it succeeded
+fn compile(directory: &Path, flags: &[&str]) -> String {
+    let compiled = Command::new(env!("CARGO_BIN_EXE_teeny_tiny_rust"))
+        .args(flags)
+        .arg("test.teeny")
+        .current_dir(directory)
+        .output()
+        .unwrap();
+    assert!(
+        compiled.status.success(),
+        "{}",
+        String::from_utf8_lossy(&compiled.stderr)
+    );
+    String::from_utf8(compiled.stdout).unwrap()
+}
+
+fn build(directory: &Path, tool: &str, args: &[&str]) {
+    let built = Command::new(tool)
+        .args(args)
+        .current_dir(directory)
+        .status()
+        .unwrap();
+    assert!(built.success(), "{} {:?} failed", tool, args);
+}
+
+fn execute(directory: &Path, program: &str, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(directory)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// whether a tool a backend needs is installed. any argument will do, it
+// only has to start
+fn installed(tool: &str) -> bool {
+    Command::new(tool).arg("--version").output().is_ok()
+}
+
+// the tool a backend's output needs, besides cc
+fn tool(backend: &str) -> &'static str {
+    match backend {
+        "asm" => "as",
+        "llvm" => "llc",
+        "wasm" | "js" => "node",
+        "python" => "python3",
+        "rust" => "rustc",
+        "qbe" => "qbe",
+        _ => panic!("no backend {}", backend),
+    }
+}
+
+// the program's output and exit status through --emit=backend
+fn backend_output(directory: &Path, backend: &str, flags: &[&str], input: &str) -> Output {
+    let emit = format!("--emit={}", backend);
+    let printed = compile(directory, &[flags, &[emit.as_str()]].concat());
+    let (program, args) = match backend {
+        //the compiler builds it
+        "asm" => {
+            assert!(printed.contains("Built out"), "{}", printed);
+            ("./out", &[][..])
+        }
+        "llvm" => {
+            build(
+                directory,
+                "llc",
+                &[
+                    "-opaque-pointers",
+                    "-relocation-model=pic",
+                    "-filetype=obj",
+                    "-o",
+                    "out.o",
+                    "out.ll",
+                ],
+            );
+            build(directory, "cc", &["-o", "out", "out.o"]);
+            ("./out", &[][..])
+        }
+        "wasm" => ("node", &["out.wasm.js"][..]),
+        "js" => ("node", &["out.js"][..]),
+        "python" => ("python3", &["out.py"][..]),
+        "rust" => {
+            build(directory, "rustc", &["-O", "-o", "out", "main.rs"]);
+            ("./out", &[][..])
+        }
+        "qbe" => {
+            build(directory, "qbe", &["-o", "out.qbe.s", "out.ssa"]);
+            build(directory, "cc", &["-o", "out", "out.qbe.s"]);
+            ("./out", &[][..])
+        }
+        _ => unreachable!(),
+    };
+    execute(directory, program, args, input)
+}
+
+// checks each program prints the same and exits the same through the
+// backend as through C, with and without -O
+fn same_as_c(backend: &str) {
+    if !installed(tool(backend)) {
+        eprintln!("skipping {}, {} is not installed", backend, tool(backend));
+        return;
+    }
+    let directory = scratch(backend);
+    for (source, input) in PROGRAMS {
+        fs::write(directory.join("test.teeny"), source).unwrap();
+        for flags in [&[][..], &["-O"]] {
+            let c = output(&directory, flags, input);
+            let other = backend_output(&directory, backend, flags, input);
+            let c_text = String::from_utf8_lossy(&c.stdout);
+            let other_text = String::from_utf8_lossy(&other.stdout);
+            assert_eq!(
+                c_text, other_text,
+                "{} {:?} printed differently",
+                backend, flags
+            );
+            assert_eq!(
+                c.status.code(),
+                other.status.code(),
+                "{} {:?} exited differently",
+                backend,
+                flags
+            );
+        }
+    }
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+// the output with -O, after checking it matches the output without
+fn same_optimized(name: &str, source: &str, input: &str) -> String {
+    let directory = scratch(name);
+    fs::write(directory.join("test.teeny"), source).unwrap();
+    let plain = run(&directory, &[], input);
+    let optimized = run(&directory, &["-O"], input);
+    fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(plain, optimized);
+    optimized
+}
+
+#[test]
+fn negative_zero_through_a_phi() {
+    let source = "INPUT c\nLET x = 0\nIF c > 0 THEN\nLET x = -0\nENDIF\nPRINT 1 / x\n";
+    assert_eq!(same_optimized("negative-zero", source, "5\n"), "-inf\n");
+}
+
+#[test]
+fn nan_prints_without_a_sign() {
+    let source = "LET x = 0\nINPUT x\nPRINT x / x\nPRINT 0 - x / x\n\
+                  PRINT USING \"###.##\"; 0 - x / x\nPRINT 0 - 0 / 0\n";
+    assert_eq!(
+        same_optimized("nan", source, "0\n"),
+        "nan\nnan\n   nan\nnan\n"
+    );
+}
+
+#[test]
+fn exit_saturates_to_an_int() {
+    let directory = scratch("exit");
+    for (value, status) in [
+        ("0 / 0", 0),
+        ("1 / 0", 255),
+        ("0 - 1 / 0", 0),
+        ("3000000000", 255),
+        ("300.7", 44),
+        ("0 - 2.5", 254),
+    ] {
+        // read from input, so -O can't work it out
+        let source = format!("LET x = 0\nINPUT x\nEXIT {} + x\n", value);
+        fs::write(directory.join("test.teeny"), source).unwrap();
+        for flags in [&[][..], &["-O"]] {
+            let output = output(&directory, flags, "0\n");
+            assert_eq!(output.status.code(), Some(status), "EXIT {}", value);
+        }
+    }
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn whole_numbers_print_in_full_up_to_the_range_of_a_long() {
+    let source = "PRINT 16777216\nPRINT 1000000000\nPRINT 123456789012\n\
+                  PRINT 0 - 4294967296 * 2147483648\nPRINT 4294967296 * 2147483648\n\
+                  PRINT 0.5\n";
+    assert_eq!(
+        same_optimized("whole-numbers", source, ""),
+        "16777216\n1000000000\n123456790528\n-9223372036854775808\n9.223372e+18\n0.5\n"
+    );
+}
+
+#[test]
+fn c_keeps_the_shape_and_the_names_of_the_program() {
+    let source = "LET a = 1\nLET b = 2\nLET n = 0\nWHILE n < 3 REPEAT\n\
+                  LET t = a\nLET a = b\nLET b = t\n\
+                  SELECT CASE n\nCASE 0, 2\nPRINT a\nCASE ELSE\nPRINT b\nENDSELECT\n\
+                  LET n = n + 1\nIF n == 5 THEN\nBREAK\nENDIF\nENDWHILE\n\
+                  DO\nLET n = n - 1\nIF n == 1 THEN\nCONTINUE\nENDIF\nPRINT n\n\
+                  LOOP UNTIL n <= 0\n";
+    let directory = scratch("shape");
+    fs::write(directory.join("test.teeny"), source).unwrap();
+    for flags in [&[][..], &["-O"]] {
+        assert_eq!(run(&directory, flags, ""), "2\n2\n2\n2\n0\n");
+        let c = fs::read_to_string(directory.join("out.c")).unwrap();
+        for expected in [
+            "while (n < 3.0f) {",
+            "n == 0.0f || n == 2.0f",
+            "} else {",
+            "do {",
+        ] {
+            assert!(c.contains(expected), "no {} in\n{}", expected, c);
+        }
+        assert!(!c.contains("goto"), "a goto in\n{}", c);
+    }
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn loops_and_cases() {
+    assert_eq!(
+        same_optimized("loops-and-cases", LOOPS_AND_CASES, ""),
+        "small 1\nsmall 1\nmiddle 3\nmiddle 5\nmiddle 8\nother 13\nother 21\nbig 34\n84 9\n"
+    );
+}
+
+#[test]
+fn functions_globals_and_input() {
+    assert_eq!(
+        same_optimized(
+            "functions-globals-input",
+            FUNCTIONS_GLOBALS_AND_INPUT,
+            "3\n4\n"
+        ),
+        "55 177\n531 354\n? 9\n? 16\n? bye\n"
+    );
+}
+
+#[test]
+fn asm_runs_like_c() {
+    same_as_c("asm");
+}
+
+#[test]
+fn llvm_runs_like_c() {
+    same_as_c("llvm");
+}
+
+#[test]
+fn wasm_runs_like_c() {
+    same_as_c("wasm");
+}
+
+#[test]
+fn js_runs_like_c() {
+    same_as_c("js");
+}
+
+#[test]
+fn python_runs_like_c() {
+    same_as_c("python");
+}
+
+#[test]
+fn rust_runs_like_c() {
+    same_as_c("rust");
+}
+
+#[test]
+fn qbe_runs_like_c() {
+    same_as_c("qbe");
+}
+
+#[test]
+fn unread_variables_are_not_declared_but_their_calls_still_run() {
+    let source = "LET n = 0\nFUNCTION f()\nLET n = n + 1\nRETURN n\nENDFUNCTION\n\
+                  LET u = f()\nLET w = 2\nPRINT n\n";
+    let directory = scratch("unread");
+    fs::write(directory.join("test.teeny"), source).unwrap();
+    for flags in [&[][..], &["-O"]] {
+        assert_eq!(run(&directory, flags, ""), "1\n");
+        let c = fs::read_to_string(directory.join("out.c")).unwrap();
+        assert!(c.contains("static float n;"), "no n in\n{}", c);
+        assert!(c.contains("    tt_fn_f();"), "no call to f in\n{}", c);
+        for unread in ["float u", "float w", "u =", "w ="] {
+            assert!(!c.contains(unread), "{} in\n{}", unread, c);
+        }
+    }
+    fs::remove_dir_all(&directory).unwrap();
+}